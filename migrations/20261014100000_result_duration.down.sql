@@ -0,0 +1 @@
+ALTER TABLE email_results DROP COLUMN duration_ms;
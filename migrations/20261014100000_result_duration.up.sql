@@ -0,0 +1 @@
+ALTER TABLE email_results ADD COLUMN duration_ms INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE bulk_jobs ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,11 @@
+CREATE TABLE bulk_exports (
+    id SERIAL PRIMARY KEY,
+    job_id INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    format TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'running',
+    total_records INTEGER NOT NULL DEFAULT 0,
+    processed_records INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    FOREIGN KEY (job_id) REFERENCES bulk_jobs(id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+DROP INDEX email_results_job_id_processed_at;
+ALTER TABLE email_results DROP COLUMN processed_at;
@@ -0,0 +1,13 @@
+-- Existing results were processed some time after their job was created,
+-- which is all that's known. They get that time rather than the time of the
+-- migration, so that they don't look fresher than they are. The results
+-- without a job get the time of the migration.
+ALTER TABLE email_results ADD COLUMN processed_at TIMESTAMPTZ;
+UPDATE email_results r SET processed_at = j.created_at
+FROM bulk_jobs j
+WHERE j.id = r.job_id;
+UPDATE email_results SET processed_at = NOW() WHERE processed_at IS NULL;
+ALTER TABLE email_results
+    ALTER COLUMN processed_at SET DEFAULT NOW(),
+    ALTER COLUMN processed_at SET NOT NULL;
+CREATE INDEX email_results_job_id_processed_at ON email_results (job_id, processed_at);
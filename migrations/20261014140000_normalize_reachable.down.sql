@@ -0,0 +1 @@
+DROP FUNCTION normalize_reachable(TEXT);
@@ -0,0 +1,9 @@
+-- Map the `is_reachable` values written by older versions, with another
+-- casing or spelling, to the current buckets: safe, risky, invalid, unknown.
+CREATE FUNCTION normalize_reachable(is_reachable TEXT) RETURNS TEXT AS $$
+    SELECT CASE lower(trim(is_reachable))
+        WHEN 'deliverable' THEN 'safe'
+        WHEN 'undeliverable' THEN 'invalid'
+        ELSE lower(trim(is_reachable))
+    END
+$$ LANGUAGE SQL IMMUTABLE;
@@ -0,0 +1,7 @@
+CREATE TABLE bulk_job_summaries (
+    job_id INTEGER PRIMARY KEY,
+    refreshed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    total_processed INTEGER NOT NULL,
+    summary JSONB NOT NULL,
+    FOREIGN KEY (job_id) REFERENCES bulk_jobs(id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+DROP TRIGGER email_results_deleted ON email_results;
+DROP TRIGGER email_results_inserted ON email_results;
+DROP FUNCTION count_deleted_results();
+DROP FUNCTION count_inserted_results();
+ALTER TABLE bulk_jobs DROP COLUMN processed_count;
@@ -0,0 +1,36 @@
+-- Number of results of each job, kept up to date by the triggers below so
+-- that the status of a job doesn't count its results.
+ALTER TABLE bulk_jobs ADD COLUMN processed_count INTEGER NOT NULL DEFAULT 0;
+
+UPDATE bulk_jobs j
+SET processed_count = (SELECT COUNT(*) FROM email_results r WHERE r.job_id = j.id);
+
+CREATE FUNCTION count_inserted_results() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE bulk_jobs j
+    SET processed_count = j.processed_count + n.count
+    FROM (SELECT job_id, COUNT(*) AS count FROM new_results GROUP BY job_id) n
+    WHERE j.id = n.job_id;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION count_deleted_results() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE bulk_jobs j
+    SET processed_count = j.processed_count - o.count
+    FROM (SELECT job_id, COUNT(*) AS count FROM old_results GROUP BY job_id) o
+    WHERE j.id = o.job_id;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER email_results_inserted
+    AFTER INSERT ON email_results
+    REFERENCING NEW TABLE AS new_results
+    FOR EACH STATEMENT EXECUTE FUNCTION count_inserted_results();
+
+CREATE TRIGGER email_results_deleted
+    AFTER DELETE ON email_results
+    REFERENCING OLD TABLE AS old_results
+    FOR EACH STATEMENT EXECUTE FUNCTION count_deleted_results();
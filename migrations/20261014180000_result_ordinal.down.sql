@@ -0,0 +1,3 @@
+DROP INDEX email_results_job_id_ordinal;
+
+ALTER TABLE email_results DROP COLUMN ordinal;
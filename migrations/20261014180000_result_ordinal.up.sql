@@ -0,0 +1,5 @@
+-- Position of the input in the submitted list, so that downloads can follow
+-- the original order. Results written before this column existed have none.
+ALTER TABLE email_results ADD COLUMN ordinal INTEGER;
+
+CREATE INDEX email_results_job_id_ordinal ON email_results (job_id, ordinal);
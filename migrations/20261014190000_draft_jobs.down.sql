@@ -0,0 +1,4 @@
+DROP TABLE bulk_job_draft_inputs;
+
+ALTER TABLE bulk_jobs DROP COLUMN draft_options;
+ALTER TABLE bulk_jobs DROP COLUMN draft;
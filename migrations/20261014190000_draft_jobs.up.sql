@@ -0,0 +1,13 @@
+-- Jobs submitted in several requests are drafts until finalized: their
+-- emails are staged in `bulk_job_draft_inputs`, and enqueued with the
+-- request options kept in `draft_options` on finalize.
+ALTER TABLE bulk_jobs ADD COLUMN draft BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE bulk_jobs ADD COLUMN draft_options JSONB;
+
+CREATE TABLE bulk_job_draft_inputs (
+    id SERIAL PRIMARY KEY,
+    job_id INTEGER NOT NULL REFERENCES bulk_jobs(id) ON DELETE CASCADE,
+    email TEXT NOT NULL
+);
+
+CREATE INDEX bulk_job_draft_inputs_job_id ON bulk_job_draft_inputs (job_id, id);
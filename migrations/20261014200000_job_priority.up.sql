@@ -0,0 +1,4 @@
+-- Operators can prioritize some jobs over others, higher first. The range
+-- is also checked by the API, see `MAX_JOB_PRIORITY`.
+ALTER TABLE bulk_jobs ADD COLUMN priority INTEGER NOT NULL DEFAULT 0
+    CHECK (priority BETWEEN 0 AND 10);
@@ -0,0 +1,5 @@
+DROP INDEX bulk_jobs_pending;
+DROP INDEX bulk_jobs_api_key_id;
+
+ALTER TABLE bulk_jobs DROP COLUMN pending;
+ALTER TABLE bulk_jobs DROP COLUMN api_key_id;
@@ -0,0 +1,9 @@
+-- The owner of a job is the API key it was submitted with, to limit the
+-- number of jobs each owner runs at once. Jobs over the limit may be pending:
+-- their emails are staged as for drafts, and enqueued once the owner is
+-- under the limit again.
+ALTER TABLE bulk_jobs ADD COLUMN api_key_id INTEGER REFERENCES api_keys(id) ON DELETE SET NULL;
+ALTER TABLE bulk_jobs ADD COLUMN pending BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX bulk_jobs_api_key_id ON bulk_jobs (api_key_id);
+CREATE INDEX bulk_jobs_pending ON bulk_jobs (id) WHERE pending;
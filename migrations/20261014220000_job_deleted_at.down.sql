@@ -0,0 +1,3 @@
+DROP INDEX bulk_jobs_deleted_at;
+
+ALTER TABLE bulk_jobs DROP COLUMN deleted_at;
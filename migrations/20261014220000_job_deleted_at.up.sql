@@ -0,0 +1,6 @@
+-- With `RCH_JOB_DELETE_MODE=soft`, deleting a job only sets `deleted_at`,
+-- keeping the job and its results for auditing until an admin purges them.
+-- Soft-deleted jobs are hidden from all the read endpoints.
+ALTER TABLE bulk_jobs ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX bulk_jobs_deleted_at ON bulk_jobs (id) WHERE deleted_at IS NOT NULL;
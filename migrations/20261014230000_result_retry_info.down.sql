@@ -0,0 +1,2 @@
+ALTER TABLE email_results DROP COLUMN last_error;
+ALTER TABLE email_results DROP COLUMN retry_count;
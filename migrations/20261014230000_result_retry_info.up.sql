@@ -0,0 +1,4 @@
+-- Number of times the input was requeued by the requeue-unknowns endpoint,
+-- and the error of the attempt it replaced, to tell why a result was retried.
+ALTER TABLE email_results ADD COLUMN retry_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE email_results ADD COLUMN last_error TEXT;
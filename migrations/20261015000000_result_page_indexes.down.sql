@@ -0,0 +1,2 @@
+DROP INDEX email_results_job_id_input;
+DROP INDEX email_results_job_id_id;
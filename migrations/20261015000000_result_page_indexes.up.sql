@@ -0,0 +1,4 @@
+-- Downloads walk the results of a job in id order, from their cursor.
+CREATE INDEX email_results_job_id_id ON email_results (job_id, id);
+-- With latest_only, whether an input has a more recent result.
+CREATE INDEX email_results_job_id_input ON email_results (job_id, (result ->> 'input'), id);
@@ -0,0 +1,3 @@
+-- Instance of the server running the export, see `RCH_INSTANCE_ID`. A
+-- restarted instance only fails the exports it left running.
+ALTER TABLE bulk_exports ADD COLUMN instance_id TEXT;
@@ -0,0 +1,6 @@
+-- Set when the queued tasks of a deleted job are cancelled. Until then, the
+-- job still counts in the running jobs of its owner.
+ALTER TABLE bulk_jobs ADD COLUMN tasks_cancelled BOOLEAN NOT NULL DEFAULT false;
+
+-- The tasks of the jobs deleted so far don't write any result anymore.
+UPDATE bulk_jobs SET tasks_cancelled = true WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,9 @@
+-- Last change of a job listed by `GET /v0/bulk`, for its Last-Modified
+-- header: its tags, its number of records, or its start.
+ALTER TABLE bulk_jobs ADD COLUMN updated_at TIMESTAMPTZ;
+
+UPDATE bulk_jobs SET updated_at = created_at;
+
+ALTER TABLE bulk_jobs
+    ALTER COLUMN updated_at SET DEFAULT NOW(),
+    ALTER COLUMN updated_at SET NOT NULL;
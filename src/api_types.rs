@@ -0,0 +1,23 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Request and response types of the HTTP API, for crates building clients
+//! against it. They have the exact serde shapes used by the endpoints.
+
+pub use crate::routes::bulk::get::{
+	JobResultCsvResponse, JobResultFields, JobResultRequest, JobResultResponseFormat,
+	JobResultShape, JobStatusResponseBody, JobStatusSummaryResponseBody, TimeFormat, ValidStatus,
+};
@@ -0,0 +1,377 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Authentication filters shared by the routes.
+
+use super::errors::{ReacherError, ReacherResponseError};
+use super::settings::{env_flag, Settings};
+use hmac::{Hmac, Mac};
+use moka::sync::Cache;
+use sha2::{Digest, Sha256};
+use sqlx::types::chrono::Utc;
+use sqlx::{Pool, Postgres};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use warp::path::FullPath;
+use warp::{http, Filter};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header holding the admin API key, for admin-only endpoints.
+pub const ADMIN_KEY_HEADER: &str = "x-reacher-admin-key";
+
+/// Whether `key` is the admin API key `expected`, compared in constant time.
+/// Their digests are compared, so that the time doesn't depend on the length
+/// of the key either.
+fn is_admin_key(expected: &str, key: &str) -> bool {
+	!expected.is_empty()
+		&& Sha256::digest(expected.as_bytes())
+			.ct_eq(&Sha256::digest(key.as_bytes()))
+			.into()
+}
+
+/// Only let the request through if it carries the admin API key set in the
+/// `RCH_ADMIN_API_KEY` environment variable. If that variable isn't set,
+/// admin endpoints are disabled altogether.
+pub fn with_admin_key() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+	warp::header::optional::<String>(ADMIN_KEY_HEADER)
+		.and_then(|key: Option<String>| async move {
+			match (env::var("RCH_ADMIN_API_KEY"), key) {
+				(Ok(expected), Some(key)) if is_admin_key(&expected, &key) => Ok(()),
+				_ => Err(warp::reject::custom(ReacherResponseError::new(
+					http::StatusCode::UNAUTHORIZED,
+					format!("Missing or invalid {} header", ADMIN_KEY_HEADER),
+				))),
+			}
+		})
+		.untuple_one()
+}
+
+/// Header holding the API key, if API key auth is enabled.
+pub const API_KEY_HEADER: &str = "x-reacher-api-key";
+
+/// Time to live of a cached API key lookup. A disabled key is rejected after
+/// at most this long.
+pub const API_KEY_CACHE_TTL: Duration = Duration::from_secs(5);
+/// Maximum number of API key lookups kept in the cache.
+const API_KEY_CACHE_CAPACITY: u64 = 10_000;
+
+/// What a key of the `api_keys` table gives access to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ApiKeyScope {
+	/// Only `GET` requests.
+	ReadOnly,
+	Admin,
+}
+
+impl ApiKeyScope {
+	fn parse(scope: &str) -> Option<Self> {
+		match scope {
+			"read_only" => Some(ApiKeyScope::ReadOnly),
+			"admin" => Some(ApiKeyScope::Admin),
+			_ => None,
+		}
+	}
+
+	fn allows(self, method: &http::Method) -> bool {
+		self == ApiKeyScope::Admin || method == http::Method::GET || method == http::Method::HEAD
+	}
+}
+
+/// Cache of the API key lookups, by key hash. Unknown and disabled keys are
+/// cached too, as `None`.
+pub struct ApiKeyCache(Cache<String, Option<ApiKeyScope>>);
+
+impl ApiKeyCache {
+	pub fn new(ttl: Duration) -> Self {
+		ApiKeyCache(
+			Cache::builder()
+				.max_capacity(API_KEY_CACHE_CAPACITY)
+				.time_to_live(ttl)
+				.build(),
+		)
+	}
+}
+
+impl Default for ApiKeyCache {
+	fn default() -> Self {
+		ApiKeyCache::new(API_KEY_CACHE_TTL)
+	}
+}
+
+/// Hex-encoded SHA-256 of an API key, as stored in the `api_keys` table.
+pub fn hash_api_key(key: &str) -> String {
+	hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Scope of an enabled API key, from the cache if it's fresh enough.
+async fn api_key_scope(
+	key: &str,
+	conn_pool: &Pool<Postgres>,
+	cache: &ApiKeyCache,
+) -> Result<Option<ApiKeyScope>, warp::Rejection> {
+	let key_hash = hash_api_key(key);
+	if let Some(scope) = cache.0.get(&key_hash) {
+		return Ok(scope);
+	}
+
+	let rec = sqlx::query!(
+		r#"
+		SELECT scope FROM api_keys
+		WHERE key_hash = $1 AND enabled
+		"#,
+		key_hash
+	)
+	.fetch_optional(conn_pool)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get API key with [error={}]",
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+	let scope = rec.and_then(|rec| ApiKeyScope::parse(&rec.scope));
+	cache.0.insert(key_hash, scope);
+
+	Ok(scope)
+}
+
+/// Whether the request doesn't need an API key: the progress badge, which
+/// is embedded with an `<img>` that can't send the header, and the downloads
+/// of a signed URL, whose token stands in for the key, if `signing_key` is
+/// set.
+fn is_keyless_request(path: &str, query: &str, now: i64, signing_key: Option<&[u8]>) -> bool {
+	let job_path = match path.strip_prefix("/v0/bulk/") {
+		Some(job_path) => job_path,
+		None => return false,
+	};
+	let (job_id, endpoint) = match job_path.split_once('/') {
+		Some(parts) => parts,
+		None => return false,
+	};
+	let job_id = match job_id.parse::<i32>() {
+		Ok(job_id) => job_id,
+		Err(_) => return false,
+	};
+
+	match endpoint {
+		"progress.svg" => true,
+		"download" => {
+			let params: Vec<(String, String)> =
+				serde_urlencoded::from_str(query).unwrap_or_default();
+			let param = |name| {
+				params
+					.iter()
+					.find(|(key, _)| key == name)
+					.map(|(_, value)| value.as_str())
+			};
+			match (signing_key, param("token"), param("expires")) {
+				(Some(key), Some(token), Some(expires)) => expires
+					.parse()
+					.is_ok_and(|expires| verify_download(key, job_id, expires, token, now).is_ok()),
+				_ => false,
+			}
+		}
+		_ => false,
+	}
+}
+
+/// Whether the `/v0` requests need an API key, set by the `RCH_API_KEY_AUTH`
+/// flag, see `with_api_key`.
+///
+/// # Panics
+///
+/// Panics if `RCH_API_KEY_AUTH` is malformed, see [`env_flag`].
+pub fn api_key_auth() -> bool {
+	env_flag("RCH_API_KEY_AUTH")
+}
+
+/// If API key auth is enabled, see [`api_key_auth`], only let the
+/// `/v0` requests through if they carry an enabled key of the `api_keys`
+/// table, and only `GET` requests with a read-only key. The progress badge
+/// and the signed download URLs don't need a key, see `is_keyless_request`.
+/// The status websocket does, so browsers can't open it directly, as they
+/// don't send custom headers on websockets.
+pub fn with_api_key(
+	conn_pool: Pool<Postgres>,
+	cache: Arc<ApiKeyCache>,
+	settings: Settings,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+	warp::path::full()
+		.and(warp::query::raw().or(warp::any().map(String::new)).unify())
+		.and(warp::method())
+		.and(warp::header::optional::<String>(API_KEY_HEADER))
+		.and_then(
+			move |path: FullPath, query: String, method: http::Method, key: Option<String>| {
+				let conn_pool = conn_pool.clone();
+				let cache = cache.clone();
+				let signing_key = settings.url_signing_key.clone();
+				let api_key_auth = settings.api_key_auth;
+				async move {
+					if !api_key_auth || !path.as_str().starts_with("/v0/") {
+						return Ok(());
+					}
+					if is_keyless_request(
+						path.as_str(),
+						&query,
+						Utc::now().timestamp(),
+						signing_key.as_deref(),
+					) {
+						return Ok(());
+					}
+
+					let scope = match key {
+						Some(key) => api_key_scope(&key, &conn_pool, &cache).await?,
+						None => None,
+					};
+					match scope {
+						Some(scope) if scope.allows(&method) => Ok(()),
+						Some(_) => Err(warp::reject::custom(ReacherResponseError::new(
+							http::StatusCode::FORBIDDEN,
+							"This API key is read-only",
+						))),
+						None => Err(warp::reject::custom(ReacherResponseError::new(
+							http::StatusCode::UNAUTHORIZED,
+							format!("Missing or invalid {} header", API_KEY_HEADER),
+						))),
+					}
+				}
+			},
+		)
+		.untuple_one()
+}
+
+/// Key used to sign download URLs, from the `RCH_URL_SIGNING_KEY`
+/// environment variable. Signed URLs are disabled if it isn't set.
+///
+/// # Panics
+///
+/// Panics if `RCH_URL_SIGNING_KEY` is set but empty, rather than signing
+/// with an empty key or silently disabling the URLs.
+pub fn url_signing_key() -> Option<Arc<[u8]>> {
+	env::var("RCH_URL_SIGNING_KEY").ok().map(|key| {
+		if key.is_empty() {
+			panic!("Environment variable RCH_URL_SIGNING_KEY is empty.");
+		}
+		key.into_bytes().into()
+	})
+}
+
+fn download_mac(key: &[u8], job_id: i32, expires: i64) -> HmacSha256 {
+	let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size. qed.");
+	mac.update(format!("{}:{}", job_id, expires).as_bytes());
+	mac
+}
+
+/// Hex-encoded signature of the download URL of a job, valid until the
+/// `expires` unix timestamp.
+pub fn sign_download(key: &[u8], job_id: i32, expires: i64) -> String {
+	hex::encode(download_mac(key, job_id, expires).finalize().into_bytes())
+}
+
+/// Check a download URL signature created by `sign_download`, at the `now`
+/// unix timestamp.
+pub fn verify_download(
+	key: &[u8],
+	job_id: i32,
+	expires: i64,
+	token: &str,
+	now: i64,
+) -> Result<(), ReacherResponseError> {
+	let forbidden = |message| ReacherResponseError::new(http::StatusCode::FORBIDDEN, message);
+
+	let signature = hex::decode(token).map_err(|_| forbidden("Invalid download token"))?;
+	download_mac(key, job_id, expires)
+		.verify_slice(&signature)
+		.map_err(|_| forbidden("Invalid download token"))?;
+	if expires <= now {
+		return Err(forbidden("Expired download token"));
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{is_admin_key, is_keyless_request, sign_download, verify_download};
+
+	const KEY: &[u8] = b"secret";
+
+	#[test]
+	fn test_is_admin_key() {
+		assert!(is_admin_key("admin-secret", "admin-secret"));
+		assert!(!is_admin_key("admin-secret", "admin-secre"));
+		assert!(!is_admin_key("admin-secret", "admin-secreT"));
+		// Admin endpoints are disabled by an empty key.
+		assert!(!is_admin_key("", ""));
+	}
+
+	#[test]
+	fn test_is_keyless_request() {
+		let key = Some(KEY);
+		assert!(is_keyless_request("/v0/bulk/1/progress.svg", "", 0, key));
+		assert!(!is_keyless_request("/v0/bulk/1", "", 0, key));
+		assert!(!is_keyless_request("/v0/bulk/ws", "", 0, key));
+		assert!(!is_keyless_request("/v0/bulk/x/progress.svg", "", 0, key));
+
+		let query = format!("token={}&expires=1000", sign_download(KEY, 1, 1000));
+		assert!(is_keyless_request("/v0/bulk/1/download", &query, 999, key));
+		// Expired, or signed for another job.
+		assert!(!is_keyless_request(
+			"/v0/bulk/1/download",
+			&query,
+			1000,
+			key
+		));
+		assert!(!is_keyless_request("/v0/bulk/2/download", &query, 999, key));
+		assert!(!is_keyless_request(
+			"/v0/bulk/1/download",
+			"expires=1000",
+			999,
+			key
+		));
+		// Only the download.
+		assert!(!is_keyless_request("/v0/bulk/1/count", &query, 999, key));
+		// Signed URLs are disabled.
+		assert!(!is_keyless_request(
+			"/v0/bulk/1/download",
+			&query,
+			999,
+			None
+		));
+	}
+
+	#[test]
+	fn test_verify_download() {
+		let token = sign_download(KEY, 1, 1000);
+
+		assert!(verify_download(KEY, 1, 1000, &token, 999).is_ok());
+		// Expired.
+		assert!(verify_download(KEY, 1, 1000, &token, 1000).is_err());
+		// Tampered job id, expiry or token.
+		assert!(verify_download(KEY, 2, 1000, &token, 999).is_err());
+		assert!(verify_download(KEY, 1, 2000, &token, 999).is_err());
+		assert!(verify_download(KEY, 1, 1000, &token[1..], 999).is_err());
+		assert!(verify_download(KEY, 1, 1000, "not hex", 999).is_err());
+		// Signed with another key.
+		assert!(verify_download(b"other", 1, 1000, &token, 999).is_err());
+	}
+}
@@ -15,14 +15,26 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use reacher_backend::{
-	routes::{bulk::post::email_verification_task, create_routes},
+	bind::bind_addr,
+	db::{endpoint_pool_options, pool_options},
+	routes::{
+		bulk::{
+			expiry::spawn_expiry_task, export::fail_interrupted_exports,
+			owner_limit::spawn_pending_task, post::email_verification_task,
+			provider::provider_domains, summary::spawn_summary_task,
+		},
+		create_routes,
+		health::get::missing_migrations,
+	},
 	sentry_util::{setup_sentry, CARGO_PKG_VERSION},
+	settings::Settings,
+	smtp_errors::smtp_error_classification,
+	tracing_util::setup_tracing,
 };
 
 use dotenv::dotenv;
-use sqlx::postgres::PgPoolOptions;
 use sqlxmq::JobRegistry;
-use std::{env, net::IpAddr};
+use std::env;
 
 /// Run a HTTP server using warp.
 ///
@@ -30,6 +42,7 @@ use std::{env, net::IpAddr};
 ///
 /// The program panics if at least one of the environment variables is
 /// malformed:
+/// - BIND_ADDR,
 /// - RCH_HTTP_HOST,
 /// - PORT.
 #[tokio::main]
@@ -40,15 +53,44 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 	let _ = dotenv();
 
 	env_logger::init();
+	// Load the error classification and the provider domains now, to fail
+	// early if they're malformed.
+	smtp_error_classification();
+	provider_domains();
+	let settings = Settings::from_env();
 	let pg_conn = env::var("DATABASE_URL").unwrap();
 
 	// create connection pool with database
 	// connection pool internally the shared db connection
 	// with arc so it can safely be cloned and shared across threads
-	let pool = PgPoolOptions::new()
-		.max_connections(5)
-		.connect(pg_conn.as_str())
-		.await?;
+	let pool = pool_options().connect(pg_conn.as_str()).await?;
+
+	// Queries fail confusingly on a schema behind the code, keep serving so
+	// that `/health/ready` reports it.
+	match missing_migrations(&pool).await {
+		Ok(migrations) if !migrations.is_empty() => log::error!(
+			target: "reacher",
+			"Database migration needed, missing [migrations={}]",
+			migrations.join(", ")
+		),
+		_ => {}
+	}
+
+	// Exports run in the server process, those left running by the previous
+	// one of this instance won't ever complete.
+	match fail_interrupted_exports(&pool, &settings.instance_id).await {
+		Ok(failed) if failed > 0 => log::warn!(
+			target: "reacher",
+			"Marked [count={}] interrupted exports as failed",
+			failed
+		),
+		Ok(_) => {}
+		Err(e) => log::error!(
+			target: "reacher",
+			"Failed to mark interrupted exports as failed with [error={:?}]",
+			e
+		),
+	}
 
 	// registry needs to be given list of jobs it can accept
 	let registry = JobRegistry::new(&[email_verification_task]);
@@ -68,21 +110,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
 	// Setup warp server
 	let _guard = setup_sentry();
+	setup_tracing()?;
+
+	spawn_expiry_task(pool.clone());
+	spawn_summary_task(pool.clone());
+	spawn_pending_task(pool.clone(), settings.max_running_jobs_per_owner);
+
+	// The queries of the endpoints are bounded, unlike those of the task
+	// queue and of the background tasks, so they get a pool of their own.
+	let endpoint_pool = endpoint_pool_options().connect(pg_conn.as_str()).await?;
+	let routes = create_routes(endpoint_pool);
+
+	// Validate the bind address before starting the server.
+	let addr = bind_addr();
+	log::info!(target: "reacher", "Server is listening on {}.", addr);
 
-	let routes = create_routes(pool);
-
-	let host = env::var("RCH_HTTP_HOST")
-		.unwrap_or_else(|_| "127.0.0.1".into())
-		.parse::<IpAddr>()
-		.expect("Environment variable RCH_HTTP_HOST is malformed.");
-	let port = env::var("PORT")
-		.map(|port| {
-			port.parse::<u16>()
-				.expect("Environment variable PORT is malformed.")
-		})
-		.unwrap_or(8080);
-	log::info!(target: "reacher", "Server is listening on {}:{}.", host, port);
-
-	warp::serve(routes).run((host, port)).await;
+	warp::serve(routes).run(addr).await;
 	Ok(())
 }
@@ -0,0 +1,107 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file reads the address the HTTP server binds to.
+
+use std::env;
+use std::net::{AddrParseError, IpAddr, SocketAddr};
+
+/// Port the HTTP server binds to when none is configured.
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Parse a bind address, either a socket address (`0.0.0.0:8080`,
+/// `[::]:8080`) or a bare IP (`127.0.0.1`, `::`, `[::]`), which then binds
+/// to `default_port`.
+pub fn parse_bind_addr(addr: &str, default_port: u16) -> Result<SocketAddr, AddrParseError> {
+	let addr = addr.trim();
+	if let Ok(addr) = addr.parse::<SocketAddr>() {
+		return Ok(addr);
+	}
+
+	let ip = addr
+		.strip_prefix('[')
+		.and_then(|ip| ip.strip_suffix(']'))
+		.unwrap_or(addr)
+		.parse::<IpAddr>()?;
+	Ok(SocketAddr::new(ip, default_port))
+}
+
+/// Address the HTTP server binds to. `BIND_ADDR` takes precedence, otherwise
+/// it's built from `RCH_HTTP_HOST` and `PORT`.
+///
+/// # Panics
+///
+/// Panics if one of these environment variables is malformed.
+pub fn bind_addr() -> SocketAddr {
+	let port = env::var("PORT")
+		.map(|port| {
+			port.parse::<u16>()
+				.expect("Environment variable PORT is malformed.")
+		})
+		.unwrap_or(DEFAULT_PORT);
+
+	match env::var("BIND_ADDR") {
+		Ok(addr) => parse_bind_addr(&addr, port).unwrap_or_else(|e| {
+			panic!(
+				"Environment variable BIND_ADDR is malformed, expected an IPv4 or IPv6 address with an optional port, e.g. 0.0.0.0:8080 or [::]:8080, got {:?}: {}",
+				addr, e
+			)
+		}),
+		Err(_) => {
+			let host = env::var("RCH_HTTP_HOST")
+				.unwrap_or_else(|_| "127.0.0.1".into())
+				.parse::<IpAddr>()
+				.expect("Environment variable RCH_HTTP_HOST is malformed.");
+			SocketAddr::new(host, port)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::parse_bind_addr;
+	use std::net::{Ipv6Addr, SocketAddr};
+
+	#[test]
+	fn test_parse_ipv6_bind_addr() {
+		assert_eq!(
+			parse_bind_addr("[::]:3000", 8080).unwrap(),
+			SocketAddr::from((Ipv6Addr::UNSPECIFIED, 3000))
+		);
+		assert_eq!(
+			parse_bind_addr("[::]", 8080).unwrap(),
+			SocketAddr::from((Ipv6Addr::UNSPECIFIED, 8080))
+		);
+		assert_eq!(
+			parse_bind_addr("::1", 8080).unwrap(),
+			SocketAddr::from((Ipv6Addr::LOCALHOST, 8080))
+		);
+	}
+
+	#[test]
+	fn test_parse_ipv4_bind_addr() {
+		assert_eq!(
+			parse_bind_addr("0.0.0.0:3000", 8080).unwrap(),
+			SocketAddr::from(([0, 0, 0, 0], 3000))
+		);
+		assert_eq!(
+			parse_bind_addr("127.0.0.1", 8080).unwrap(),
+			SocketAddr::from(([127, 0, 0, 1], 8080))
+		);
+		assert!(parse_bind_addr("localhost:3000", 8080).is_err());
+		assert!(parse_bind_addr("[::]:99999", 8080).is_err());
+	}
+}
@@ -0,0 +1,123 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Gzip compression of responses, negotiated with the `Accept-Encoding`
+//! request header.
+
+use crate::errors::ReacherResponseError;
+use flate2::{write::GzEncoder, Compression};
+use std::io::Write;
+use warp::http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, VARY};
+use warp::hyper::body::HttpBody;
+use warp::{http, Filter, Reply};
+
+/// Whether the `Accept-Encoding` header value accepts gzip.
+fn accepts_gzip(header: &str) -> bool {
+	header.split(',').any(|item| {
+		let mut parts = item.split(';').map(str::trim);
+		let name = parts.next().unwrap_or_default().to_lowercase();
+		let q = parts
+			.find_map(|p| p.strip_prefix("q="))
+			.and_then(|q| q.parse::<f32>().ok())
+			.unwrap_or(1.0);
+
+		(name == "gzip" || name == "*") && q > 0.0
+	})
+}
+
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+	let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+	encoder.write_all(data)?;
+	encoder.finish()
+}
+
+/// Wrap a filter so that its successful replies are gzip-compressed when the
+/// client accepts it. Rejections and streamed replies are left untouched.
+/// All the replies have `Vary: accept-encoding`, so that caches don't serve
+/// a compressed body to clients which don't accept it, or the other way
+/// around.
+pub fn with_gzip<F, R>(
+	filter: F,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone
+where
+	F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send,
+	R: Reply,
+{
+	warp::header::optional::<String>("accept-encoding")
+		.and(filter)
+		.and_then(|accept_encoding: Option<String>, reply: R| {
+			let mut response = reply.into_response();
+			response
+				.headers_mut()
+				.append(VARY, HeaderValue::from_static("accept-encoding"));
+			async move {
+				// Non-2xx responses, e.g. 304 Not Modified, are left as is, and
+				// so are streamed bodies, of unknown size, not to buffer them.
+				let accepted = accept_encoding.as_deref().is_some_and(accepts_gzip);
+				if !accepted
+					|| !response.status().is_success()
+					|| response.headers().contains_key(CONTENT_ENCODING)
+					|| response.body().size_hint().exact().is_none()
+				{
+					return Ok::<_, warp::Rejection>(response);
+				}
+
+				let (mut parts, body) = response.into_parts();
+				let body = match warp::hyper::body::to_bytes(body).await {
+					Ok(body) => body,
+					Err(e) => {
+						log::error!(
+							target:"reacher",
+							"Failed to buffer the response body with [error={}]",
+							e
+						);
+
+						return Err(warp::reject::custom(ReacherResponseError::new(
+							http::StatusCode::INTERNAL_SERVER_ERROR,
+							"Internal server error",
+						)));
+					}
+				};
+				let compressed = match gzip(&body) {
+					Ok(compressed) => compressed,
+					Err(_) => return Ok(warp::reply::Response::from_parts(parts, body.into())),
+				};
+
+				parts
+					.headers
+					.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+				parts
+					.headers
+					.insert(CONTENT_LENGTH, compressed.len().into());
+
+				Ok(warp::reply::Response::from_parts(parts, compressed.into()))
+			}
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::accepts_gzip;
+
+	#[test]
+	fn test_accepts_gzip() {
+		assert!(accepts_gzip("gzip"));
+		assert!(accepts_gzip("deflate, gzip;q=0.5"));
+		assert!(accepts_gzip("*"));
+		assert!(!accepts_gzip("deflate, br"));
+		assert!(!accepts_gzip("gzip;q=0"));
+	}
+}
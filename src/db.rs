@@ -0,0 +1,79 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Options of the database pools. Waiting for a connection of a pool and
+//! running a query on it are bounded separately: the former fails with
+//! `ReacherError::PoolTimedOut`, a 503, the latter with
+//! `ReacherError::QueryTimedOut`, a 504. Only the queries of the endpoints
+//! are bounded, those of the task queue and of the background tasks run on a
+//! pool of their own.
+
+use crate::DB_MAX_CONNECTIONS;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::Executor;
+use std::env;
+use std::time::Duration;
+
+/// Read a duration in milliseconds from the environment variable.
+///
+/// # Panics
+///
+/// Panics if the variable is set but isn't a number.
+fn env_millis(name: &str) -> Option<Duration> {
+	env::var(name).ok().map(|ms| {
+		Duration::from_millis(
+			ms.parse()
+				.unwrap_or_else(|_| panic!("Environment variable {} is malformed.", name)),
+		)
+	})
+}
+
+/// Maximum time to wait for a connection of the pool, read from
+/// `RCH_DB_ACQUIRE_TIMEOUT_MS`. Defaults to the sqlx default of 30 seconds.
+pub fn db_acquire_timeout() -> Duration {
+	env_millis("RCH_DB_ACQUIRE_TIMEOUT_MS").unwrap_or_else(|| Duration::from_secs(30))
+}
+
+/// Maximum time a query of the endpoints may run, read from
+/// `RCH_DB_QUERY_TIMEOUT_MS`, and set as the `statement_timeout` of the
+/// connections of their pool. Queries are unbounded if it's not set.
+pub fn db_query_timeout() -> Option<Duration> {
+	env_millis("RCH_DB_QUERY_TIMEOUT_MS")
+}
+
+/// Options of the database pool, with the acquire timeout.
+pub fn pool_options() -> PgPoolOptions {
+	PgPoolOptions::new()
+		.max_connections(DB_MAX_CONNECTIONS)
+		.connect_timeout(db_acquire_timeout())
+}
+
+/// Options of the database pool of the endpoints, with the acquire and query
+/// timeouts. The timeout is read once, when the pool is created.
+pub fn endpoint_pool_options() -> PgPoolOptions {
+	let options = pool_options();
+
+	match db_query_timeout() {
+		Some(timeout) => options.after_connect(move |conn| {
+			Box::pin(async move {
+				conn.execute(format!("SET statement_timeout = {}", timeout.as_millis()).as_str())
+					.await?;
+				Ok(())
+			})
+		}),
+		None => options,
+	}
+}
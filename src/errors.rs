@@ -17,8 +17,26 @@
 //! Describe a common response error to be used by all routes, should an error
 //! happen.
 
-use serde::Serialize;
-use warp::{http, reject};
+use crate::settings::env_flag;
+use serde::{Deserialize, Serialize};
+use warp::{http, reject, Filter, Reply};
+
+/// Seconds clients should wait before retrying when the database pool is
+/// exhausted.
+const POOL_TIMED_OUT_RETRY_AFTER: u64 = 5;
+/// SQLSTATE of a query cancelled, e.g. by its `statement_timeout`.
+const QUERY_CANCELED: &str = "57014";
+
+/// Whether internal error responses include the underlying cause of the
+/// error, set by the `VERBOSE_ERRORS` environment flag. Only meant for
+/// development, as the cause may leak details of the database.
+///
+/// # Panics
+///
+/// Panics if `VERBOSE_ERRORS` is not a boolean.
+pub fn verbose_errors() -> bool {
+	env_flag("VERBOSE_ERRORS")
+}
 
 /// Struct describing an error response.
 #[derive(Serialize, Debug)]
@@ -26,34 +44,177 @@ pub struct ReacherResponseError {
 	#[serde(skip)]
 	code: http::StatusCode,
 	message: String,
+	/// Underlying cause of an internal error, only set with `VERBOSE_ERRORS`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	detail: Option<String>,
+	/// Seconds after which the client may retry, sent as `Retry-After`.
+	#[serde(skip)]
+	retry_after: Option<u64>,
+}
+
+impl ReacherResponseError {
+	pub fn new<S: Into<String>>(code: http::StatusCode, message: S) -> Self {
+		ReacherResponseError {
+			code,
+			message: message.into(),
+			detail: None,
+			retry_after: None,
+		}
+	}
+
+	/// Tell the client to retry after this many seconds.
+	pub fn with_retry_after(mut self, seconds: u64) -> Self {
+		self.retry_after = Some(seconds);
+		self
+	}
 }
 
 impl reject::Reject for ReacherResponseError {}
 
 /// This function receives a `Rejection` and tries to return a custom value,
-/// otherwise simply passes the rejection along.
-pub async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+/// otherwise simply passes the rejection along. Internal errors include
+/// their cause if `verbose`, see [`verbose_errors`].
+pub async fn handle_rejection(
+	err: warp::Rejection,
+	verbose: bool,
+) -> Result<impl warp::Reply, warp::Rejection> {
 	if let Some(err) = err.find::<ReacherResponseError>() {
-		Ok(warp::reply::with_status(warp::reply::json(err), err.code))
+		let mut response =
+			warp::reply::with_status(warp::reply::json(err), err.code).into_response();
+		if let Some(retry_after) = err.retry_after {
+			response
+				.headers_mut()
+				.insert("Retry-After", retry_after.into());
+		}
+		Ok(response)
+	} else if let Some(ReacherError::PoolTimedOut) = err.find::<ReacherError>() {
+		let err = ReacherResponseError::new(
+			http::StatusCode::SERVICE_UNAVAILABLE,
+			"All database connections are in use, please retry later",
+		);
+		Ok(warp::reply::with_header(
+			warp::reply::with_status(warp::reply::json(&err), err.code),
+			"Retry-After",
+			POOL_TIMED_OUT_RETRY_AFTER,
+		)
+		.into_response())
+	} else if let Some(ReacherError::QueryTimedOut) = err.find::<ReacherError>() {
+		let err = ReacherResponseError::new(
+			http::StatusCode::GATEWAY_TIMEOUT,
+			"The database query timed out",
+		);
+		Ok(warp::reply::with_status(warp::reply::json(&err), err.code).into_response())
+	} else if let Some(internal) = err.find::<ReacherError>() {
+		log::debug!(target: "reacher", "Internal error [error={:?}]", internal);
+		let mut err = ReacherResponseError::new(
+			http::StatusCode::INTERNAL_SERVER_ERROR,
+			"Internal server error",
+		);
+		if verbose {
+			err.detail = Some(internal.detail());
+		}
+		Ok(warp::reply::with_status(warp::reply::json(&err), err.code).into_response())
 	} else {
 		Err(err)
 	}
 }
 
+#[derive(Deserialize)]
+struct ErrorsAs200Query {
+	errors_as_200: Option<bool>,
+}
+
+/// Whether an error response with this status is returned as a 200 in the
+/// `errors_as_200` mode. Authentication errors and 404s are kept as is.
+fn is_wrapped_error(status: http::StatusCode) -> bool {
+	(status.is_client_error() || status.is_server_error())
+		&& ![
+			http::StatusCode::UNAUTHORIZED,
+			http::StatusCode::FORBIDDEN,
+			http::StatusCode::NOT_FOUND,
+		]
+		.contains(&status)
+}
+
+/// NON-STANDARD compatibility mode, for legacy clients treating any non-200
+/// response as a hard failure. With the `errors_as_200=true` query param,
+/// error responses are returned with a 200 status and a
+/// `{"ok": false, "error": ...}` body, where `error` is the original body.
+/// Don't use it in new clients.
+pub fn errors_as_200<F, R>(
+	routes: F,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone
+where
+	F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send,
+	R: Reply,
+{
+	warp::query::<ErrorsAs200Query>()
+		.or(warp::any().map(|| ErrorsAs200Query {
+			errors_as_200: None,
+		}))
+		.unify()
+		.and(routes)
+		.and_then(|query: ErrorsAs200Query, reply: R| {
+			let response = reply.into_response();
+			async move {
+				if !query.errors_as_200.unwrap_or(false) || !is_wrapped_error(response.status()) {
+					return Ok::<_, warp::Rejection>(response);
+				}
+
+				let body = warp::hyper::body::to_bytes(response.into_body())
+					.await
+					.unwrap_or_default();
+				let error = serde_json::from_slice(&body).unwrap_or_else(|_| {
+					serde_json::Value::String(String::from_utf8_lossy(&body).into_owned())
+				});
+
+				Ok(
+					warp::reply::json(&serde_json::json!({ "ok": false, "error": error }))
+						.into_response(),
+				)
+			}
+		})
+}
+
 /// Catch all error struct
 #[derive(Debug)]
 pub enum ReacherError {
 	Db(sqlx::Error),
+	/// No database connection could be acquired from the pool in time, see
+	/// `crate::db::db_acquire_timeout`.
+	PoolTimedOut,
+	/// A query was cancelled by its `statement_timeout`, see
+	/// `crate::db::db_query_timeout`.
+	QueryTimedOut,
 	Csv(),
 	Json(),
 }
 
+impl ReacherError {
+	/// Underlying cause of the error, as returned with `VERBOSE_ERRORS`.
+	fn detail(&self) -> String {
+		match self {
+			ReacherError::Db(e) => e.to_string(),
+			ReacherError::PoolTimedOut => "database pool timed out".into(),
+			ReacherError::QueryTimedOut => "database query timed out".into(),
+			ReacherError::Csv() => "failed to serialize the results to csv".into(),
+			ReacherError::Json() => "failed to serialize the results to json".into(),
+		}
+	}
+}
+
 // Defaults to Internal server error
 impl reject::Reject for ReacherError {}
 
 // wrap sql errors as db errors for reacher
 impl From<sqlx::Error> for ReacherError {
 	fn from(e: sqlx::Error) -> Self {
-		ReacherError::Db(e)
+		match e {
+			sqlx::Error::PoolTimedOut => ReacherError::PoolTimedOut,
+			sqlx::Error::Database(e) if e.code().as_deref() == Some(QUERY_CANCELED) => {
+				ReacherError::QueryTimedOut
+			}
+			e => ReacherError::Db(e),
+		}
 	}
 }
@@ -14,7 +14,19 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod api_types;
+mod auth;
+pub mod bind;
 pub mod check;
+mod compression;
+pub mod db;
 mod errors;
+mod query;
 pub mod routes;
 pub mod sentry_util;
+pub mod settings;
+pub mod smtp_errors;
+pub mod tracing_util;
+
+/// Maximum number of connections in the database pool.
+pub const DB_MAX_CONNECTIONS: u32 = 5;
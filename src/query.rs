@@ -0,0 +1,123 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Extraction of the query params of a request, like `warp::query`, but
+//! rejecting malformed params with a 400 naming the param, e.g.
+//! `limit is invalid: invalid digit found in string`, instead of warp's
+//! generic "Invalid query string".
+
+use crate::errors::ReacherResponseError;
+use serde::de::DeserializeOwned;
+use warp::{http, Filter};
+
+/// Deserialize the query string, finding the culprit param on failure.
+fn parse_query<T: DeserializeOwned>(query: &str) -> Result<T, ReacherResponseError> {
+	let err = match serde_urlencoded::from_str(query) {
+		Ok(query) => return Ok(query),
+		Err(err) => err,
+	};
+
+	// The params are deserialized in order, and the first malformed one
+	// fails, so the culprit is the last param of the shortest prefix of the
+	// query failing with the same error. It's found by bisection, as the
+	// client controls the number of params.
+	let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap_or_default();
+	let fails_like_query = |len: usize| {
+		serde_urlencoded::to_string(&pairs[..len])
+			.map(|prefix| match serde_urlencoded::from_str::<T>(&prefix) {
+				Err(prefix_err) => prefix_err.to_string() == err.to_string(),
+				Ok(_) => false,
+			})
+			.unwrap_or(false)
+	};
+	let (mut low, mut high) = (0, pairs.len());
+	if !fails_like_query(high) {
+		high = 0;
+	}
+	while low + 1 < high {
+		let mid = (low + high) / 2;
+		if fails_like_query(mid) {
+			high = mid;
+		} else {
+			low = mid;
+		}
+	}
+	let message = match high.checked_sub(1).map(|i| &pairs[i].0) {
+		Some(name) => format!("{} is invalid: {}", name, err),
+		None => format!("invalid query string: {}", err),
+	};
+
+	Err(ReacherResponseError::new(
+		http::StatusCode::BAD_REQUEST,
+		message,
+	))
+}
+
+/// Extract the query params into `T`, like `warp::query`. A missing query
+/// string is deserialized as an empty one.
+pub fn with_query<T: DeserializeOwned + Send + 'static>(
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+	warp::query::raw()
+		.or(warp::any().map(String::new))
+		.unify()
+		.and_then(|query: String| async move { parse_query(&query).map_err(warp::reject::custom) })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::parse_query;
+	use serde::Deserialize;
+
+	#[derive(Debug, Deserialize)]
+	#[allow(dead_code)]
+	struct Query {
+		limit: Option<u64>,
+		sample: Option<f64>,
+		pretty: Option<bool>,
+	}
+
+	fn message(query: &str) -> String {
+		serde_json::to_value(parse_query::<Query>(query).unwrap_err()).unwrap()["message"]
+			.as_str()
+			.unwrap()
+			.to_string()
+	}
+
+	#[test]
+	fn test_parse_query() {
+		let query: Query = parse_query("limit=10&pretty=true").unwrap();
+		assert_eq!(query.limit, Some(10));
+		assert!(parse_query::<Query>("").is_ok());
+
+		assert_eq!(
+			message("limit=abc"),
+			"limit is invalid: invalid digit found in string"
+		);
+		assert!(message("pretty=true&limit=").starts_with("limit is invalid"));
+		assert!(message("limit=99999999999999999999").starts_with("limit is invalid"));
+		assert!(message("sample=half").starts_with("sample is invalid"));
+		assert!(message("limit=1&pretty=yes&sample=0.5").starts_with("pretty is invalid"));
+		assert_eq!(
+			message("limit=1&limit=2"),
+			"limit is invalid: duplicate field `limit`"
+		);
+
+		// Bisected among many unknown params.
+		let junk: Vec<String> = (0..5000).map(|i| format!("junk{}=1", i)).collect();
+		let query = format!("{}&limit=abc&sample=half", junk.join("&"));
+		assert!(message(&query).starts_with("limit is invalid"));
+	}
+}
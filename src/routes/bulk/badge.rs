@@ -0,0 +1,118 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /v0/bulk/{id}/progress.svg` endpoint, a
+//! small progress badge of a job to embed in status pages and READMEs.
+
+use super::get::{fetch_job_status, ValidStatus};
+use super::status_cache::JobStatusCache;
+use super::{job_id_param, JobId};
+use crate::settings::Settings;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use warp::{http, Filter};
+
+/// Width of the badge, in pixels.
+const BADGE_WIDTH: u32 = 160;
+const RUNNING_COLOR: &str = "#007ec6";
+const COMPLETED_COLOR: &str = "#4c1";
+
+/// Render the badge, with a bar filled in proportion to the processed
+/// records.
+fn render_badge(total_processed: i32, total_records: i32, job_status: &ValidStatus) -> String {
+	let bar_width = if total_records > 0 {
+		(BADGE_WIDTH as f64 * total_processed as f64 / total_records as f64).round() as u32
+	} else {
+		0
+	};
+	let color = match job_status {
+		ValidStatus::Draft | ValidStatus::Pending | ValidStatus::Running => RUNNING_COLOR,
+		ValidStatus::Completed => COMPLETED_COLOR,
+	};
+	let label = format!("{}/{}", total_processed, total_records);
+
+	format!(
+		r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}"><title>{label}</title><rect width="{width}" height="20" rx="3" fill="#555"/><rect width="{bar_width}" height="20" rx="3" fill="{color}"/><text x="{x}" y="14" fill="#fff" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11" text-anchor="middle">{label}</text></svg>"##,
+		width = BADGE_WIDTH,
+		bar_width = bar_width.min(BADGE_WIDTH),
+		color = color,
+		x = BADGE_WIDTH / 2,
+		label = label,
+	)
+}
+
+async fn progress_badge(
+	job_id: JobId,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let status = fetch_job_status(job_id.get(), false, conn_pool, &status_cache, &settings).await?;
+
+	// A completed job doesn't change anymore, a running one should be
+	// refetched every time.
+	let cache_control = match status.job_status {
+		ValidStatus::Draft | ValidStatus::Pending | ValidStatus::Running => "no-cache",
+		ValidStatus::Completed => "public, max-age=3600",
+	};
+
+	Ok(http::Response::builder()
+		.header("Content-Type", "image/svg+xml")
+		.header("Cache-Control", cache_control)
+		.body(render_badge(
+			status.total_processed,
+			status.total_records,
+			&status.job_status,
+		))
+		.expect("All header names and values are valid. qed."))
+}
+
+/// Create the `GET /v0/bulk/{id}/progress.svg` endpoint.
+pub fn get_job_progress_badge(
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "progress.svg")
+		.and(warp::get())
+		.and_then(job_id_param)
+		.and_then(move |job_id| {
+			progress_badge(
+				job_id,
+				conn_pool.clone(),
+				status_cache.clone(),
+				settings.clone(),
+			)
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{render_badge, BADGE_WIDTH};
+	use crate::routes::bulk::get::ValidStatus;
+
+	#[test]
+	fn test_render_badge_bar_width() {
+		let svg = render_badge(1, 4, &ValidStatus::Running);
+		assert!(svg.contains(r##"<rect width="40" height="20" rx="3" fill="#007ec6"/>"##));
+
+		let svg = render_badge(0, 0, &ValidStatus::Completed);
+		assert!(svg.contains(r##"<rect width="0" height="20" rx="3" fill="#4c1"/>"##));
+		assert!(svg.contains(&format!(r#"width="{}""#, BADGE_WIDTH)));
+	}
+}
@@ -0,0 +1,150 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /v0/bulk/csv-schema` endpoint, listing the
+//! default columns of the csv download with their types, for importers to
+//! be set up before the first download.
+
+use super::get::{JobResultCsvResponse, CSV_HEADER};
+use schemars::schema::{InstanceType, Schema, SingleOrVec};
+use schemars::schema_for;
+use serde::{Deserialize, Serialize};
+use warp::Filter;
+
+/// Type of the values of a csv column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CsvColumnType {
+	String,
+	Bool,
+	Integer,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsvColumn {
+	pub name: String,
+	#[serde(rename = "type")]
+	pub column_type: CsvColumnType,
+	/// Whether the column may be empty, for a missing value.
+	pub optional: bool,
+}
+
+/// The columns of `CSV_HEADER`, in the order they're written, typed after
+/// the JSON Schema of `JobResultCsvResponse`.
+///
+/// # Panics
+///
+/// Panics if a column isn't a field of `JobResultCsvResponse`.
+pub fn csv_schema() -> Vec<CsvColumn> {
+	let schema = schema_for!(JobResultCsvResponse);
+	let properties = &schema
+		.schema
+		.object
+		.as_ref()
+		.expect("JobResultCsvResponse is a struct. qed.")
+		.properties;
+
+	CSV_HEADER
+		.iter()
+		.map(|name| {
+			let types = match properties.get(*name) {
+				Some(Schema::Object(object)) => match &object.instance_type {
+					Some(SingleOrVec::Single(instance_type)) => vec![**instance_type],
+					Some(SingleOrVec::Vec(instance_types)) => instance_types.clone(),
+					None => vec![],
+				},
+				_ => panic!("CSV column {} isn't a field of JobResultCsvResponse.", name),
+			};
+			let column_type = if types.contains(&InstanceType::Boolean) {
+				CsvColumnType::Bool
+			} else if types.contains(&InstanceType::Integer) {
+				CsvColumnType::Integer
+			} else {
+				CsvColumnType::String
+			};
+
+			CsvColumn {
+				name: name.to_string(),
+				column_type,
+				optional: types.contains(&InstanceType::Null),
+			}
+		})
+		.collect()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CsvSchemaResponseBody {
+	columns: Vec<CsvColumn>,
+}
+
+async fn get_schema() -> Result<impl warp::Reply, warp::Rejection> {
+	Ok(warp::reply::json(&CsvSchemaResponseBody {
+		columns: csv_schema(),
+	}))
+}
+
+/// Create the `GET /v0/bulk/csv-schema` endpoint.
+pub fn get_csv_schema() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+{
+	warp::path!("v0" / "bulk" / "csv-schema")
+		.and(warp::get())
+		.and_then(get_schema)
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{get_csv_schema, CSV_HEADER};
+	use warp::http::StatusCode;
+	use warp::test::request;
+
+	#[tokio::test]
+	async fn test_get_csv_schema() {
+		let resp = request()
+			.path("/v0/bulk/csv-schema")
+			.method("GET")
+			.reply(&get_csv_schema())
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+		let columns = body["columns"].as_array().unwrap();
+		let names: Vec<&str> = columns
+			.iter()
+			.map(|column| column["name"].as_str().unwrap())
+			.collect();
+		assert_eq!(names, CSV_HEADER);
+
+		let column = |name| {
+			columns
+				.iter()
+				.find(|column| column["name"] == name)
+				.unwrap()
+		};
+		assert_eq!(
+			column("input"),
+			&serde_json::json!({"name": "input", "type": "string", "optional": false})
+		);
+		assert_eq!(column("smtp.is_deliverable")["type"], "bool");
+		assert_eq!(
+			column("error"),
+			&serde_json::json!({"name": "error", "type": "string", "optional": true})
+		);
+		assert_eq!(column("duration_ms")["type"], "integer");
+		assert_eq!(column("duration_ms")["optional"], true);
+	}
+}
@@ -0,0 +1,320 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `DELETE /v0/bulk/{id}` endpoint. Jobs are
+//! deleted along with their results, unless `RCH_JOB_DELETE_MODE` is `soft`:
+//! then only their `deleted_at` is set, and they're hidden from the read
+//! endpoints until the `POST /v0/bulk/purge-deleted` admin endpoint deletes
+//! them for good. Either way, the queued tasks of the job are cancelled.
+
+use super::export::remove_orphan_export_files;
+use super::status_cache::JobStatusCache;
+use super::{job_id_param, JobId};
+use crate::auth::with_admin_key;
+use crate::errors::{ReacherError, ReacherResponseError};
+use crate::settings::Settings;
+use crate::tracing_util::TimedQuery;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Transaction};
+use std::env;
+use std::sync::Arc;
+use warp::{http, Filter};
+
+/// How a job is deleted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobDeleteMode {
+	/// The job and its results are removed.
+	Hard,
+	/// The job is flagged with `deleted_at`, and kept with its results.
+	Soft,
+}
+
+/// Read from `RCH_JOB_DELETE_MODE`, `hard` or `soft`. Defaults to `hard`.
+///
+/// # Panics
+///
+/// Panics if `RCH_JOB_DELETE_MODE` is set to another value.
+pub fn job_delete_mode() -> JobDeleteMode {
+	match env::var("RCH_JOB_DELETE_MODE").as_deref() {
+		Err(_) | Ok("hard") => JobDeleteMode::Hard,
+		Ok("soft") => JobDeleteMode::Soft,
+		Ok(_) => panic!("Environment variable RCH_JOB_DELETE_MODE is malformed."),
+	}
+}
+
+/// Reject with a 404 if the job is soft-deleted, for the read endpoints not
+/// reading the job record otherwise.
+pub(super) async fn check_not_deleted(
+	job_id: i32,
+	conn_pool: &Pool<Postgres>,
+) -> Result<(), warp::Rejection> {
+	let rec = sqlx::query!(
+		r#"
+		SELECT id FROM bulk_jobs
+		WHERE id = $1 AND deleted_at IS NOT NULL
+		"#,
+		job_id
+	)
+	.fetch_optional(conn_pool)
+	.timed("job_deleted", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get job record for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+
+	match rec {
+		Some(_) => Err(job_not_found(job_id).into()),
+		None => Ok(()),
+	}
+}
+
+fn job_not_found(job_id: i32) -> ReacherResponseError {
+	ReacherResponseError::new(
+		http::StatusCode::NOT_FOUND,
+		format!("job {} not found", job_id),
+	)
+}
+
+/// Delete a job which isn't soft-deleted yet, and cancel its queued tasks.
+/// Returns whether there was such a job.
+async fn delete_job(
+	job_id: i32,
+	mode: JobDeleteMode,
+	conn_pool: &Pool<Postgres>,
+) -> Result<bool, ReacherError> {
+	let mut tx = conn_pool.begin().await?;
+
+	let deleted = match mode {
+		JobDeleteMode::Soft => soft_delete_job(&mut tx, job_id).await?,
+		JobDeleteMode::Hard => hard_delete_job(&mut tx, job_id).await?,
+	};
+	if deleted {
+		// Otherwise the emails would still be verified, for results nobody
+		// can read.
+		sqlx::query!(
+			r#"
+			WITH tasks AS (
+				DELETE FROM mq_msgs m
+				USING mq_payloads p
+				WHERE m.id = p.id AND (p.payload_json ->> 'job_id')::int = $1
+				RETURNING m.id
+			),
+			payloads AS (
+				DELETE FROM mq_payloads
+				WHERE id IN (SELECT id FROM tasks)
+			)
+			-- For the running jobs limit of the owner, a no-op if the job
+			-- was hard-deleted.
+			UPDATE bulk_jobs SET tasks_cancelled = true
+			WHERE id = $1
+			"#,
+			job_id
+		)
+		.execute(&mut tx)
+		.timed("cancel_job_tasks", job_id)
+		.await?;
+	}
+
+	tx.commit().await?;
+
+	Ok(deleted)
+}
+
+async fn soft_delete_job(
+	tx: &mut Transaction<'_, Postgres>,
+	job_id: i32,
+) -> Result<bool, ReacherError> {
+	let deleted = sqlx::query!(
+		r#"
+		UPDATE bulk_jobs SET deleted_at = NOW()
+		WHERE id = $1 AND deleted_at IS NULL
+		"#,
+		job_id
+	)
+	.execute(&mut *tx)
+	.timed("delete_job", job_id)
+	.await?
+	.rows_affected();
+
+	Ok(deleted > 0)
+}
+
+async fn hard_delete_job(
+	tx: &mut Transaction<'_, Postgres>,
+	job_id: i32,
+) -> Result<bool, ReacherError> {
+	sqlx::query!(
+		r#"
+		DELETE FROM email_results r
+		USING bulk_jobs j
+		WHERE r.job_id = j.id AND j.id = $1 AND j.deleted_at IS NULL
+		"#,
+		job_id
+	)
+	.execute(&mut *tx)
+	.timed("delete_job_results", job_id)
+	.await?;
+
+	let deleted = sqlx::query!(
+		r#"
+		DELETE FROM bulk_jobs
+		WHERE id = $1 AND deleted_at IS NULL
+		"#,
+		job_id
+	)
+	.execute(&mut *tx)
+	.timed("delete_job", job_id)
+	.await?
+	.rows_affected();
+
+	Ok(deleted > 0)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct DeleteJobResponseBody {
+	job_id: i32,
+	mode: JobDeleteMode,
+}
+
+async fn delete(
+	job_id: JobId,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let mode = settings.job_delete_mode;
+	let deleted = delete_job(job_id.get(), mode, &conn_pool)
+		.await
+		.map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to delete [job_id={}] with [error={:?}]",
+				job_id,
+				e
+			);
+			e
+		})?;
+	if !deleted {
+		return Err(job_not_found(job_id.get()).into());
+	}
+
+	// Otherwise the status would still be served until it expires.
+	status_cache.invalidate(job_id.get());
+	if mode == JobDeleteMode::Hard {
+		remove_orphan_export_files(&conn_pool).await;
+	}
+	log::info!(target:"reacher", "Deleted [job_id={}] with [mode={:?}]", job_id, mode);
+
+	Ok(warp::reply::json(&DeleteJobResponseBody {
+		job_id: job_id.get(),
+		mode,
+	}))
+}
+
+/// Create the `DELETE /v0/bulk/{id}` endpoint.
+pub fn delete_bulk_job(
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32)
+		.and(warp::delete())
+		.and_then(job_id_param)
+		.and_then(move |job_id| {
+			delete(
+				job_id,
+				conn_pool.clone(),
+				status_cache.clone(),
+				settings.clone(),
+			)
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Delete all the soft-deleted jobs, along with their results. Returns the
+/// number of purged jobs.
+pub async fn purge_deleted_jobs(conn_pool: &Pool<Postgres>) -> Result<u64, ReacherError> {
+	let mut tx = conn_pool.begin().await?;
+
+	sqlx::query!(
+		r#"
+		DELETE FROM email_results r
+		USING bulk_jobs j
+		WHERE r.job_id = j.id AND j.deleted_at IS NOT NULL
+		"#
+	)
+	.execute(&mut tx)
+	.timed_for("purge_deleted_results", String::from("deleted jobs"))
+	.await?;
+
+	let purged = sqlx::query!(
+		r#"
+		DELETE FROM bulk_jobs
+		WHERE deleted_at IS NOT NULL
+		"#
+	)
+	.execute(&mut tx)
+	.timed_for("purge_deleted_jobs", String::from("deleted jobs"))
+	.await?
+	.rows_affected();
+
+	tx.commit().await?;
+
+	Ok(purged)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct PurgeDeletedResponseBody {
+	total_purged: u64,
+}
+
+async fn purge_deleted(conn_pool: Pool<Postgres>) -> Result<impl warp::Reply, warp::Rejection> {
+	let purged = purge_deleted_jobs(&conn_pool).await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to purge deleted jobs with [error={:?}]",
+			e
+		);
+		e
+	})?;
+	remove_orphan_export_files(&conn_pool).await;
+	log::info!(target:"reacher", "Purged [count={}] deleted jobs", purged);
+
+	Ok(warp::reply::json(&PurgeDeletedResponseBody {
+		total_purged: purged,
+	}))
+}
+
+/// Create the `POST /v0/bulk/purge-deleted` admin endpoint.
+pub fn purge_deleted_bulk_jobs(
+	conn_pool: Pool<Postgres>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / "purge-deleted")
+		.and(warp::post())
+		.and(with_admin_key())
+		.and_then(move || purge_deleted(conn_pool.clone()))
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
@@ -0,0 +1,160 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /v0/bulk/{id}/distribution` endpoint, the
+//! share of the processed results of a job in each reachability bucket, for
+//! charts.
+
+use super::get::{live_job_summary, reachable_str};
+use super::{job_id_param, JobId};
+use crate::errors::{ReacherError, ReacherResponseError};
+use crate::tracing_util::TimedQuery;
+use check_if_email_exists::Reachable;
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use warp::{http, Filter};
+
+#[derive(Debug, Serialize, PartialEq)]
+struct DistributionShare {
+	count: i64,
+	/// Share of the processed results, in percent. 0 if nothing was
+	/// processed yet.
+	percent: f64,
+}
+
+impl DistributionShare {
+	fn new(count: i64, total_processed: i64) -> Self {
+		let percent = if total_processed > 0 {
+			count as f64 * 100.0 / total_processed as f64
+		} else {
+			0.0
+		};
+
+		DistributionShare { count, percent }
+	}
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct ReachableShare {
+	reachable: String,
+	#[serde(flatten)]
+	share: DistributionShare,
+}
+
+/// The reachability buckets don't overlap, and add up to the processed
+/// results. The others overlap with them.
+#[derive(Debug, Serialize)]
+struct DistributionResponse {
+	total_processed: i64,
+	reachable: Vec<ReachableShare>,
+	catch_all: DistributionShare,
+	/// Results with a transient error, see `crate::smtp_errors`.
+	transient_errors: DistributionShare,
+	/// Results with a permanent error, see `crate::smtp_errors`.
+	permanent_errors: DistributionShare,
+}
+
+async fn job_distribution(
+	job_id: JobId,
+	conn_pool: Pool<Postgres>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let job = sqlx::query!(
+		r#"
+		SELECT
+			j.id,
+			(
+				SELECT COUNT(*) FROM email_results
+				WHERE job_id = j.id
+					AND COALESCE(result -> 'smtp' ->> 'is_catch_all', 'false') = 'true'
+			) AS "catch_all_count!"
+		FROM bulk_jobs j
+		WHERE j.id = $1 AND j.deleted_at IS NULL
+		"#,
+		job_id.get()
+	)
+	.fetch_optional(&conn_pool)
+	.timed("job_distribution", job_id.get())
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get job record for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?
+	.ok_or_else(|| {
+		ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", job_id),
+		)
+	})?;
+
+	let (total_processed, summary) = live_job_summary(job_id.get(), false, &conn_pool).await?;
+	let reachable = [
+		(Reachable::Safe, summary.total_safe),
+		(Reachable::Risky, summary.total_risky),
+		(Reachable::Invalid, summary.total_invalid),
+		(Reachable::Unknown, summary.total_unknown),
+	]
+	.iter()
+	.map(|(reachable, count)| ReachableShare {
+		reachable: reachable_str(reachable),
+		share: DistributionShare::new((*count).into(), total_processed),
+	})
+	.collect();
+
+	Ok(warp::reply::json(&DistributionResponse {
+		total_processed,
+		reachable,
+		catch_all: DistributionShare::new(job.catch_all_count, total_processed),
+		transient_errors: DistributionShare::new(
+			summary.total_transient_errors.into(),
+			total_processed,
+		),
+		permanent_errors: DistributionShare::new(
+			summary.total_permanent_errors.into(),
+			total_processed,
+		),
+	}))
+}
+
+/// Create the `GET /v0/bulk/{id}/distribution` endpoint.
+pub fn get_job_distribution(
+	conn_pool: Pool<Postgres>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "distribution")
+		.and(warp::get())
+		.and_then(job_id_param)
+		.and_then(move |job_id| job_distribution(job_id, conn_pool.clone()))
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::DistributionShare;
+
+	#[test]
+	fn test_distribution_share() {
+		assert_eq!(DistributionShare::new(1, 4).percent, 25.0);
+		assert_eq!(DistributionShare::new(4, 4).percent, 100.0);
+		// Nothing processed yet.
+		assert_eq!(DistributionShare::new(0, 0).percent, 0.0);
+	}
+}
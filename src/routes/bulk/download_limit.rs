@@ -0,0 +1,245 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-job cap on the concurrent downloads of the `GET /v0/bulk/{id}/download`
+//! endpoint. A job shared with many clients, e.g. through a signed URL, could
+//! otherwise have all of them run heavy queries at once. Rejected downloads
+//! are told to retry once the oldest running download of the job is expected
+//! to complete, from the average duration of the previous downloads.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Seconds clients should wait before retrying a download rejected by the
+/// cap, until a download has completed to estimate a better delay.
+pub const DOWNLOAD_RETRY_AFTER: u64 = 2;
+/// Longer estimated delays are truncated to this many seconds, so that a
+/// single slow download doesn't make clients wait needlessly long.
+const MAX_DOWNLOAD_RETRY_AFTER: u64 = 60;
+
+/// Maximum number of concurrent downloads of a single job, read from
+/// `RCH_MAX_DOWNLOADS_PER_JOB`. Unlimited if it's not set.
+///
+/// # Panics
+///
+/// Panics if `RCH_MAX_DOWNLOADS_PER_JOB` is not a positive integer.
+pub fn max_downloads_per_job() -> Option<usize> {
+	env::var("RCH_MAX_DOWNLOADS_PER_JOB").ok().map(|max| {
+		max.parse::<usize>()
+			.ok()
+			.filter(|max| *max > 0)
+			.expect("Environment variable RCH_MAX_DOWNLOADS_PER_JOB is malformed.")
+	})
+}
+
+/// Running downloads, and the average duration of the completed ones.
+#[derive(Default)]
+struct Downloads {
+	/// Start times of the running downloads, keyed by job id and permit id.
+	/// A job is dropped once none of its downloads are running.
+	running: HashMap<i32, HashMap<u64, Instant>>,
+	next_id: u64,
+	/// Moving average of the duration of the downloads.
+	average: Option<Duration>,
+}
+
+impl Downloads {
+	/// Estimated seconds until a download of the job completes, assuming
+	/// the oldest one takes the average duration.
+	fn retry_after(&self, job_id: i32) -> u64 {
+		let average = match self.average {
+			Some(average) => average,
+			None => return DOWNLOAD_RETRY_AFTER,
+		};
+		let oldest = self
+			.running
+			.get(&job_id)
+			.and_then(|running| running.values().min())
+			.map_or(Duration::ZERO, |started_at| started_at.elapsed());
+		let remaining = average.saturating_sub(oldest);
+
+		// Rounded up, as `Retry-After` is in whole seconds.
+		let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+		seconds.clamp(1, MAX_DOWNLOAD_RETRY_AFTER)
+	}
+
+	/// Add the duration of a completed download to the average.
+	fn record(&mut self, duration: Duration) {
+		self.average = Some(match self.average {
+			Some(average) => (average * 7 + duration) / 8,
+			None => duration,
+		});
+	}
+}
+
+/// Tracks the running downloads of each job, to cap them.
+#[derive(Default)]
+pub struct JobDownloadLimiter {
+	max_per_job: Option<usize>,
+	downloads: Arc<Mutex<Downloads>>,
+}
+
+/// Held for the duration of a download.
+pub struct JobDownloadPermit {
+	job_id: i32,
+	/// Set if downloads are capped.
+	id: Option<u64>,
+	downloads: Arc<Mutex<Downloads>>,
+}
+
+impl JobDownloadLimiter {
+	pub fn new(max_per_job: Option<usize>) -> Self {
+		JobDownloadLimiter {
+			max_per_job,
+			downloads: Arc::default(),
+		}
+	}
+
+	/// Start a download of the job. If it already has the maximum number of
+	/// concurrent downloads, returns the estimated seconds until one of them
+	/// completes, at least 1.
+	pub fn try_acquire(&self, job_id: i32) -> Result<JobDownloadPermit, u64> {
+		let id = match self.max_per_job {
+			Some(max_per_job) => {
+				let mut downloads = self
+					.downloads
+					.lock()
+					.expect("The lock is never held across a panic. qed.");
+				if downloads
+					.running
+					.get(&job_id)
+					.is_some_and(|running| running.len() >= max_per_job)
+				{
+					return Err(downloads.retry_after(job_id));
+				}
+
+				let id = downloads.next_id;
+				downloads.next_id += 1;
+				downloads
+					.running
+					.entry(job_id)
+					.or_default()
+					.insert(id, Instant::now());
+				Some(id)
+			}
+			None => None,
+		};
+
+		Ok(JobDownloadPermit {
+			job_id,
+			id,
+			downloads: self.downloads.clone(),
+		})
+	}
+
+	/// Number of jobs with running downloads.
+	pub fn len(&self) -> usize {
+		self.downloads
+			.lock()
+			.expect("The lock is never held across a panic. qed.")
+			.running
+			.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl Drop for JobDownloadPermit {
+	fn drop(&mut self) {
+		let id = match self.id.take() {
+			Some(id) => id,
+			None => return,
+		};
+
+		let mut downloads = self
+			.downloads
+			.lock()
+			.expect("The lock is never held across a panic. qed.");
+		let running = match downloads.running.get_mut(&self.job_id) {
+			Some(running) => running,
+			None => return,
+		};
+		let started_at = running.remove(&id);
+		if running.is_empty() {
+			downloads.running.remove(&self.job_id);
+		}
+		if let Some(started_at) = started_at {
+			downloads.record(started_at.elapsed());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{JobDownloadLimiter, DOWNLOAD_RETRY_AFTER, MAX_DOWNLOAD_RETRY_AFTER};
+	use std::time::Duration;
+
+	#[test]
+	fn test_try_acquire() {
+		let limiter = JobDownloadLimiter::new(Some(2));
+		let first = limiter.try_acquire(1).unwrap();
+		let second = limiter.try_acquire(1).unwrap();
+		assert!(limiter.try_acquire(1).is_err());
+		// Other jobs have their own cap.
+		let other = limiter.try_acquire(2).unwrap();
+
+		drop(first);
+		let third = limiter.try_acquire(1).unwrap();
+		assert_eq!(limiter.len(), 2);
+
+		drop((second, third, other));
+		assert!(limiter.is_empty());
+	}
+
+	#[test]
+	fn test_try_acquire_unlimited() {
+		let limiter = JobDownloadLimiter::new(None);
+		let permits: Vec<_> = (0..100).map(|_| limiter.try_acquire(1).unwrap()).collect();
+		assert_eq!(permits.len(), 100);
+		assert!(limiter.is_empty());
+	}
+
+	#[test]
+	fn test_retry_after() {
+		let limiter = JobDownloadLimiter::new(Some(1));
+		let _first = limiter.try_acquire(1).unwrap();
+		// No download has completed yet.
+		assert_eq!(limiter.try_acquire(1).err(), Some(DOWNLOAD_RETRY_AFTER));
+
+		let set_average = |average| {
+			limiter.downloads.lock().unwrap().average = Some(average);
+		};
+		set_average(Duration::from_millis(9500));
+		assert_eq!(limiter.try_acquire(1).err(), Some(10));
+		set_average(Duration::from_secs(3600));
+		assert_eq!(limiter.try_acquire(1).err(), Some(MAX_DOWNLOAD_RETRY_AFTER));
+		// The oldest download is already late.
+		set_average(Duration::ZERO);
+		assert_eq!(limiter.try_acquire(1).err(), Some(1));
+	}
+
+	#[test]
+	fn test_average_duration() {
+		let limiter = JobDownloadLimiter::new(Some(1));
+		drop(limiter.try_acquire(1).unwrap());
+		let average = limiter.downloads.lock().unwrap().average;
+		assert!(average.is_some_and(|average| average < Duration::from_secs(1)));
+	}
+}
@@ -0,0 +1,82 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Estimated completion time of a running job. Dividing the time since its
+//! creation by the processed records is skewed by the time the job waited in
+//! the queue, so the rate is measured over its latest results only.
+
+use chrono::Duration;
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// Number of latest results over which the processing rate is measured.
+pub const ETA_WINDOW_RESULTS: i64 = 100;
+
+/// Estimated time at which the `remaining` records are processed, at the
+/// rate of the results processed at `latest`, sorted from the most recent.
+/// `None` if nothing remains, or if the rate can't be measured.
+pub fn rolling_eta(latest: &[DateTime<Utc>], remaining: i64) -> Option<DateTime<Utc>> {
+	let (newest, oldest) = match (latest.first(), latest.last()) {
+		(Some(newest), Some(oldest)) => (*newest, *oldest),
+		_ => return None,
+	};
+	let intervals = latest.len() as i64 - 1;
+	let span_ms = (newest - oldest).num_milliseconds();
+	if remaining <= 0 || intervals == 0 || span_ms <= 0 {
+		return None;
+	}
+
+	let remaining_ms = (span_ms as f64 / intervals as f64 * remaining as f64).round();
+	Some(newest + Duration::milliseconds(remaining_ms as i64))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::rolling_eta;
+	use chrono::Duration;
+	use sqlx::types::chrono::{DateTime, TimeZone, Utc};
+
+	#[test]
+	fn test_rolling_eta() {
+		let start = Utc.ymd(2026, 1, 1).and_hms(0, 0, 0);
+		// A result every 10s after a long wait in the queue, then every 1s.
+		let mut processed: Vec<DateTime<Utc>> = (0..10)
+			.map(|i| start + Duration::hours(1) + Duration::seconds(10 * i))
+			.collect();
+		let sped_up = *processed.last().unwrap();
+		processed.extend((1..=10).map(|i| sped_up + Duration::seconds(i)));
+		processed.reverse();
+		let newest = processed[0];
+
+		// Over the latest results, the ETA follows the current rate.
+		assert_eq!(
+			rolling_eta(&processed[..10], 50),
+			Some(newest + Duration::seconds(50))
+		);
+		// Over all of them, it's between the two rates.
+		let eta = rolling_eta(&processed, 50).unwrap();
+		assert!(eta > newest + Duration::seconds(50));
+		assert!(eta < newest + Duration::seconds(500));
+	}
+
+	#[test]
+	fn test_rolling_eta_unknown() {
+		let at = Utc.ymd(2026, 1, 1).and_hms(0, 0, 0);
+		assert_eq!(rolling_eta(&[], 10), None);
+		assert_eq!(rolling_eta(&[at], 10), None);
+		assert_eq!(rolling_eta(&[at, at], 10), None);
+		assert_eq!(rolling_eta(&[at + Duration::seconds(1), at], 0), None);
+	}
+}
@@ -0,0 +1,118 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the auto-expiry of bulk jobs. If
+//! `RCH_JOB_RETENTION_DAYS` is set, a background task periodically deletes
+//! the jobs, and their results, older than this retention period.
+
+use super::export::remove_orphan_export_files;
+use crate::errors::ReacherError;
+use chrono::Duration;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use std::env;
+
+/// Interval between two runs of the expiry task.
+const EXPIRY_TASK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Retention period of bulk jobs, read from `RCH_JOB_RETENTION_DAYS`. Jobs
+/// never expire if it's not set.
+///
+/// # Panics
+///
+/// Panics if `RCH_JOB_RETENTION_DAYS` is not a positive integer.
+pub fn job_retention() -> Option<Duration> {
+	env::var("RCH_JOB_RETENTION_DAYS").ok().map(|days| {
+		let days = days
+			.parse::<u32>()
+			.ok()
+			.filter(|days| *days > 0)
+			.expect("Environment variable RCH_JOB_RETENTION_DAYS is malformed.");
+		Duration::days(days.into())
+	})
+}
+
+/// Time at which a job created at `created_at` expires.
+pub fn expires_at(created_at: DateTime<Utc>, retention: Option<Duration>) -> Option<DateTime<Utc>> {
+	retention.map(|retention| created_at + retention)
+}
+
+/// Delete all the jobs older than `retention`, along with their results.
+/// Returns the number of deleted jobs.
+pub async fn purge_expired_jobs(
+	conn_pool: &Pool<Postgres>,
+	retention: Duration,
+) -> Result<u64, ReacherError> {
+	let created_before = Utc::now() - retention;
+	let mut tx = conn_pool.begin().await?;
+
+	sqlx::query!(
+		r#"
+		DELETE FROM email_results r
+		USING bulk_jobs j
+		WHERE r.job_id = j.id AND j.created_at < $1
+		"#,
+		created_before
+	)
+	.execute(&mut tx)
+	.await?;
+
+	let deleted = sqlx::query!(
+		r#"
+		DELETE FROM bulk_jobs
+		WHERE created_at < $1
+		"#,
+		created_before
+	)
+	.execute(&mut tx)
+	.await?
+	.rows_affected();
+
+	tx.commit().await?;
+
+	Ok(deleted)
+}
+
+/// Spawn the background task purging expired jobs, if a retention period is
+/// configured.
+pub fn spawn_expiry_task(conn_pool: Pool<Postgres>) {
+	let retention = match job_retention() {
+		Some(retention) => retention,
+		None => return,
+	};
+
+	tokio::spawn(async move {
+		let mut interval = tokio::time::interval(EXPIRY_TASK_INTERVAL);
+		loop {
+			interval.tick().await;
+			match purge_expired_jobs(&conn_pool, retention).await {
+				Ok(deleted) => {
+					remove_orphan_export_files(&conn_pool).await;
+					log::info!(
+						target:"reacher",
+						"Purged [count={}] expired jobs",
+						deleted
+					)
+				}
+				Err(e) => log::error!(
+					target:"reacher",
+					"Failed to purge expired jobs with [error={:?}]",
+					e
+				),
+			}
+		}
+	});
+}
@@ -0,0 +1,960 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the asynchronous exports of bulk job results. An
+//! export pages through all the results of a job in the background and
+//! writes them to a file under `RCH_EXPORT_DIR`, which is then served by the
+//! `GET /v0/bulk/{id}/export/{export_id}/download` endpoint. The files are
+//! removed along with the export records, when their job is deleted.
+//!
+//! An export runs in the instance of the server which started it, see
+//! `instance_id`, but may be downloaded from any of them, so with several
+//! instances `RCH_EXPORT_DIR` must be storage they share. On Heroku, the
+//! filesystem of the dynos is neither shared nor kept across restarts.
+//!
+//! It also implements `GET /v0/bulk/export`, which streams the combined
+//! results of several jobs in a single download.
+
+use super::get::{
+	check_sample, inputs_txt, job_result_count, job_result_csv, job_result_json, CsvOptions,
+	CsvWrapper, JobResultCsvResponse, JobResultOrder, JobResultResponseFormat, JobResultSort,
+	PageParams, ResultConfidence, ResultFilter, CSV_HEADER, MAX_DOWNLOAD_LIMIT,
+};
+use super::transform::{ResultTransformer, SharedTransformer};
+use super::{job_id_and_param, job_id_param, JobId};
+use crate::errors::{ReacherError, ReacherResponseError};
+use crate::query::with_query;
+use crate::settings::Settings;
+use crate::tracing_util::TimedQuery;
+use check_if_email_exists::Reachable;
+use futures::future::poll_fn;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::convert::TryFrom;
+use std::{
+	env,
+	path::{Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use warp::{http, Filter};
+
+/// Directory the export files are written to, from `RCH_EXPORT_DIR`.
+/// Defaults to a `reacher-exports` folder in the system's temp directory.
+pub fn export_dir() -> PathBuf {
+	env::var("RCH_EXPORT_DIR")
+		.ok()
+		.filter(|dir| !dir.is_empty())
+		.map(PathBuf::from)
+		.unwrap_or_else(|| env::temp_dir().join("reacher-exports"))
+}
+
+/// Id of this instance of the server, from `RCH_INSTANCE_ID`, or else from
+/// `DYNO` on Heroku, e.g. `web.1`. It should be unique among the running
+/// instances and kept across their restarts, so that a restarted instance
+/// only fails the exports it was running, see `fail_interrupted_exports`.
+/// Defaults to `default`, for a single instance.
+pub fn instance_id() -> String {
+	["RCH_INSTANCE_ID", "DYNO"]
+		.iter()
+		.find_map(|name| env::var(name).ok().filter(|id| !id.is_empty()))
+		.unwrap_or_else(|| "default".into())
+}
+
+fn export_path(export_id: i32, format: &JobResultResponseFormat) -> PathBuf {
+	let extension = match format {
+		JobResultResponseFormat::Csv => "csv",
+		JobResultResponseFormat::Txt => "txt",
+		JobResultResponseFormat::Json | JobResultResponseFormat::JsonArray => "json",
+	};
+	export_dir().join(format!("{}.{}", export_id, extension))
+}
+
+/// Id of the export written to the file, if it's an export file.
+fn export_id_of_path(path: &Path) -> Option<i32> {
+	path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Remove the export files whose export record was deleted, i.e. the files
+/// of deleted jobs, logging the failures.
+pub async fn remove_orphan_export_files(conn_pool: &Pool<Postgres>) {
+	match orphan_export_files(conn_pool).await {
+		Ok(paths) => {
+			for path in paths {
+				if let Err(e) = tokio::fs::remove_file(&path).await {
+					log::error!(
+						target:"reacher",
+						"Failed to remove export file [path={}] with [error={}]",
+						path.display(),
+						e
+					);
+				}
+			}
+		}
+		Err(e) => log::error!(
+			target:"reacher",
+			"Failed to find orphan export files with [error={:?}]",
+			e
+		),
+	}
+}
+
+/// The export files whose export record doesn't exist anymore.
+async fn orphan_export_files(conn_pool: &Pool<Postgres>) -> Result<Vec<PathBuf>, ReacherError> {
+	let mut paths = Vec::new();
+	let mut entries = match tokio::fs::read_dir(export_dir()).await {
+		Ok(entries) => entries,
+		// Nothing was exported yet.
+		Err(_) => return Ok(vec![]),
+	};
+	while let Ok(Some(entry)) = entries.next_entry().await {
+		let path = entry.path();
+		if let Some(export_id) = export_id_of_path(&path) {
+			paths.push((export_id, path));
+		}
+	}
+
+	let export_ids: Vec<i32> = paths.iter().map(|(export_id, _)| *export_id).collect();
+	let existing = sqlx::query!(
+		r#"
+		SELECT id FROM bulk_exports
+		WHERE id = ANY($1)
+		"#,
+		&export_ids
+	)
+	.fetch_all(conn_pool)
+	.await?;
+
+	Ok(paths
+		.into_iter()
+		.filter(|(export_id, _)| existing.iter().all(|rec| rec.id != *export_id))
+		.map(|(_, path)| path)
+		.collect())
+}
+
+/// Mark the exports left running by a previous run of this instance of the
+/// server as failed, and remove their partial files. The exports of the
+/// other instances are left alone, as they may still be running. To be
+/// called at startup, before any new export is started. Returns the number
+/// of failed exports.
+pub async fn fail_interrupted_exports(
+	conn_pool: &Pool<Postgres>,
+	instance_id: &str,
+) -> Result<u64, ReacherError> {
+	// The exports started before their instance was recorded are failed by
+	// any instance.
+	let recs = sqlx::query!(
+		r#"
+		UPDATE bulk_exports
+		SET status = $1, error = 'interrupted by a restart of the server'
+		WHERE status = $2 AND (instance_id = $3 OR instance_id IS NULL)
+		RETURNING id, format
+		"#,
+		ExportStatus::Failed.as_str(),
+		ExportStatus::Running.as_str(),
+		instance_id
+	)
+	.fetch_all(conn_pool)
+	.await?;
+
+	for rec in &recs {
+		if let Ok(format) = serde_json::from_value(serde_json::Value::String(rec.format.clone())) {
+			let _ = tokio::fs::remove_file(export_path(rec.id, &format)).await;
+		}
+	}
+
+	Ok(recs.len() as u64)
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ExportStatus {
+	Running,
+	Completed,
+	Failed,
+}
+
+impl ExportStatus {
+	fn as_str(&self) -> &'static str {
+		match self {
+			ExportStatus::Running => "running",
+			ExportStatus::Completed => "completed",
+			ExportStatus::Failed => "failed",
+		}
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CreateExportRequest {
+	format: Option<JobResultResponseFormat>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CreateExportResponseBody {
+	export_id: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ExportStatusResponseBody {
+	export_id: i32,
+	job_id: i32,
+	format: JobResultResponseFormat,
+	status: ExportStatus,
+	processed_records: i32,
+	total_records: i32,
+	/// Only set once the export is completed.
+	download_url: Option<String>,
+	/// Only set if the export failed.
+	error: Option<String>,
+}
+
+async fn create_export(
+	job_id: JobId,
+	req: CreateExportRequest,
+	conn_pool: Pool<Postgres>,
+	transformer: SharedTransformer,
+	settings: Settings,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let job_id = job_id.get();
+	let format = req.format.unwrap_or(JobResultResponseFormat::Csv);
+	let format_str = serde_json::to_value(&format)
+		.ok()
+		.and_then(|v| v.as_str().map(String::from))
+		.expect("JobResultResponseFormat serializes to a string. qed.");
+
+	let rec = sqlx::query!(
+		r#"
+		INSERT INTO bulk_exports (job_id, format, instance_id)
+		SELECT id, $2, $3 FROM bulk_jobs
+		WHERE id = $1 AND deleted_at IS NULL
+		RETURNING id
+		"#,
+		job_id,
+		format_str,
+		&*settings.instance_id
+	)
+	.fetch_optional(&conn_pool)
+	.timed("create_export", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to create export for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?
+	.ok_or_else(|| {
+		ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", job_id),
+		)
+	})?;
+
+	let export_id = rec.id;
+	log::info!(target:"reacher", "Started export [export_id={}] for [job_id={}]", export_id, job_id);
+	tokio::spawn(run_export(
+		export_id,
+		job_id,
+		format,
+		conn_pool,
+		transformer,
+	));
+
+	Ok(warp::reply::with_status(
+		warp::reply::json(&CreateExportResponseBody { export_id }),
+		http::StatusCode::ACCEPTED,
+	))
+}
+
+/// Write all the results of the job to the export file, then mark the export
+/// as completed, or failed.
+async fn run_export(
+	export_id: i32,
+	job_id: i32,
+	format: JobResultResponseFormat,
+	conn_pool: Pool<Postgres>,
+	transformer: SharedTransformer,
+) {
+	let path = export_path(export_id, &format);
+	let written = write_export(
+		export_id,
+		job_id,
+		&format,
+		&path,
+		&conn_pool,
+		transformer.as_ref(),
+	)
+	.await;
+	let (status, error) = match written {
+		Ok(()) => (ExportStatus::Completed, None),
+		Err(e) => {
+			log::error!(
+				target:"reacher",
+				"Failed to write export [export_id={}] for [job_id={}] with [error={}]",
+				export_id,
+				job_id,
+				e
+			);
+			// Don't leave a partial file behind.
+			let _ = tokio::fs::remove_file(&path).await;
+			(ExportStatus::Failed, Some(e))
+		}
+	};
+
+	if let Err(e) = sqlx::query!(
+		r#"
+		UPDATE bulk_exports
+		SET status = $2, error = $3
+		WHERE id = $1
+		"#,
+		export_id,
+		status.as_str(),
+		error
+	)
+	.execute(&conn_pool)
+	.timed_for("export_status", format!("export_id={}", export_id))
+	.await
+	{
+		log::error!(
+			target:"reacher",
+			"Failed to update status of [export_id={}] with [error={}]",
+			export_id,
+			e
+		);
+	}
+}
+
+async fn write_export(
+	export_id: i32,
+	job_id: i32,
+	format: &JobResultResponseFormat,
+	path: &PathBuf,
+	conn_pool: &Pool<Postgres>,
+	transformer: &dyn ResultTransformer,
+) -> Result<(), String> {
+	let mut filter = ResultFilter {
+		sample: None,
+		sample_by_id: false,
+		latest_only: true,
+		reachable: None,
+		confidence_reachable: None,
+		exclude_catch_all: false,
+		include_domains: None,
+		exclude_domains: None,
+		after: None,
+		sort: JobResultSort::Id,
+		order: JobResultOrder::Asc,
+		include_retry_info: false,
+		stale_before: None,
+	};
+	let total = job_result_count(job_id, &filter, conn_pool)
+		.await
+		.map_err(|e| format!("{:?}", e))?;
+	update_progress(export_id, 0, total, conn_pool).await?;
+
+	tokio::fs::create_dir_all(export_dir())
+		.await
+		.map_err(|e| e.to_string())?;
+	let mut file = tokio::fs::File::create(path)
+		.await
+		.map_err(|e| e.to_string())?;
+
+	match format {
+		JobResultResponseFormat::Json => file.write_all(b"{\"results\":[").await,
+		JobResultResponseFormat::JsonArray => file.write_all(b"[").await,
+		JobResultResponseFormat::Csv | JobResultResponseFormat::Txt => Ok(()),
+	}
+	.map_err(|e| e.to_string())?;
+
+	// Paged along the id cursor, so that each page is read from the index
+	// rather than after all the previous ones.
+	let page_params = PageParams::new(MAX_DOWNLOAD_LIMIT, 0).map_err(|e| format!("{:?}", e))?;
+	let mut processed = 0;
+	loop {
+		let (data, count, last_id) = match format {
+			JobResultResponseFormat::Csv => {
+				let page = job_result_csv(
+					job_id,
+					page_params,
+					&filter,
+					&CsvOptions::default(),
+					// Only the first page carries the header.
+					filter.after.is_none(),
+					transformer,
+					conn_pool.clone(),
+				)
+				.await
+				.map_err(|e| format!("{:?}", e))?;
+				(page.rows, page.count, page.last_id)
+			}
+			JobResultResponseFormat::Txt => {
+				let page = job_result_json(
+					job_id,
+					page_params,
+					&filter,
+					None,
+					None,
+					transformer,
+					conn_pool.clone(),
+				)
+				.await
+				.map_err(|e| format!("{:?}", e))?;
+				(inputs_txt(&page.rows), page.count, page.last_id)
+			}
+			JobResultResponseFormat::Json | JobResultResponseFormat::JsonArray => {
+				let page = job_result_json(
+					job_id,
+					page_params,
+					&filter,
+					None,
+					None,
+					transformer,
+					conn_pool.clone(),
+				)
+				.await
+				.map_err(|e| format!("{:?}", e))?;
+				let mut data = Vec::new();
+				for (i, result) in page.rows.iter().enumerate() {
+					if processed > 0 || i > 0 {
+						data.push(b',');
+					}
+					serde_json::to_writer(&mut data, result).map_err(|e| e.to_string())?;
+				}
+				(data, page.count, page.last_id)
+			}
+		};
+		file.write_all(&data).await.map_err(|e| e.to_string())?;
+
+		processed += count as u64;
+		update_progress(export_id, processed, total.max(processed), conn_pool).await?;
+		// A short page is the last one.
+		match last_id {
+			Some(last_id) if count as u64 == MAX_DOWNLOAD_LIMIT => filter.after = Some(last_id),
+			_ => break,
+		}
+	}
+
+	match format {
+		JobResultResponseFormat::Json => file.write_all(b"]}").await,
+		JobResultResponseFormat::JsonArray => file.write_all(b"]").await,
+		JobResultResponseFormat::Csv | JobResultResponseFormat::Txt => Ok(()),
+	}
+	.map_err(|e| e.to_string())?;
+
+	file.flush().await.map_err(|e| e.to_string())
+}
+
+async fn update_progress(
+	export_id: i32,
+	processed: u64,
+	total: u64,
+	conn_pool: &Pool<Postgres>,
+) -> Result<(), String> {
+	sqlx::query!(
+		r#"
+		UPDATE bulk_exports
+		SET processed_records = $2, total_records = $3
+		WHERE id = $1
+		"#,
+		export_id,
+		processed as i32,
+		total as i32
+	)
+	.execute(conn_pool)
+	.timed_for("export_progress", format!("export_id={}", export_id))
+	.await
+	.map(|_| ())
+	.map_err(|e| e.to_string())
+}
+
+/// Fetch the export record, making sure it belongs to the job.
+async fn fetch_export(
+	job_id: JobId,
+	export_id: i32,
+	conn_pool: &Pool<Postgres>,
+) -> Result<ExportStatusResponseBody, warp::Rejection> {
+	let job_id = job_id.get();
+	let rec = sqlx::query!(
+		r#"
+		SELECT id, job_id, format, status, processed_records, total_records, error
+		FROM bulk_exports
+		WHERE id = $1 AND job_id = $2
+			AND job_id IN (SELECT id FROM bulk_jobs WHERE deleted_at IS NULL)
+		"#,
+		export_id,
+		job_id
+	)
+	.fetch_optional(conn_pool)
+	.timed("job_export", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get [export_id={}] for [job_id={}] with [error={}]",
+			export_id,
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?
+	.ok_or_else(|| {
+		ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("export {} of job {} not found", export_id, job_id),
+		)
+	})?;
+
+	let format = serde_json::from_value(serde_json::Value::String(rec.format)).map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Invalid format of [export_id={}] with [error={}]",
+			export_id,
+			e
+		);
+		ReacherError::Json()
+	})?;
+	let status = serde_json::from_value(serde_json::Value::String(rec.status)).map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Invalid status of [export_id={}] with [error={}]",
+			export_id,
+			e
+		);
+		ReacherError::Json()
+	})?;
+	let download_url = if status == ExportStatus::Completed {
+		Some(format!(
+			"/v0/bulk/{}/export/{}/download",
+			rec.job_id, rec.id
+		))
+	} else {
+		None
+	};
+
+	Ok(ExportStatusResponseBody {
+		export_id: rec.id,
+		job_id: rec.job_id,
+		format,
+		status,
+		processed_records: rec.processed_records,
+		total_records: rec.total_records,
+		download_url,
+		error: rec.error,
+	})
+}
+
+async fn export_status(
+	job_id: JobId,
+	export_id: i32,
+	conn_pool: Pool<Postgres>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let export = fetch_export(job_id, export_id, &conn_pool).await?;
+
+	Ok(warp::reply::json(&export))
+}
+
+async fn export_download(
+	job_id: JobId,
+	export_id: i32,
+	conn_pool: Pool<Postgres>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let export = fetch_export(job_id, export_id, &conn_pool).await?;
+	if export.status != ExportStatus::Completed {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::CONFLICT,
+			format!("export {} is {}", export_id, export.status.as_str()),
+		)
+		.into());
+	}
+
+	let path = export_path(export_id, &export.format);
+	let file = tokio::fs::File::open(&path).await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to open export file [path={}] with [error={}]",
+			path.display(),
+			e
+		);
+		ReacherResponseError::new(
+			http::StatusCode::GONE,
+			format!("the file of export {} is gone", export_id),
+		)
+	})?;
+	let length = file.metadata().await.map(|m| m.len()).ok();
+
+	let content_type = match export.format {
+		JobResultResponseFormat::Csv => "text/csv",
+		JobResultResponseFormat::Txt => "text/plain; charset=utf-8",
+		JobResultResponseFormat::Json | JobResultResponseFormat::JsonArray => "application/json",
+	};
+	let mut response = http::Response::builder()
+		.header("Content-Type", content_type)
+		.header(
+			"Content-Disposition",
+			format!(
+				"attachment; filename=\"{}\"",
+				path.file_name()
+					.and_then(|name| name.to_str())
+					.unwrap_or_default()
+			),
+		);
+	if let Some(length) = length {
+		response = response.header("Content-Length", length);
+	}
+
+	Ok(response
+		.body(warp::hyper::Body::wrap_stream(ReaderStream::new(file)))
+		.expect("All header names and values are valid. qed."))
+}
+
+/// Maximum number of jobs in a combined download.
+pub const MAX_COMBINED_JOBS: usize = 20;
+/// Maximum number of results in a combined download, across all the jobs.
+pub const MAX_COMBINED_RESULTS: u64 = 100_000;
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CombinedExportRequest {
+	/// Comma-separated ids of the jobs.
+	ids: String,
+	format: Option<JobResultResponseFormat>,
+	sample: Option<f64>,
+	reachable: Option<Reachable>,
+	exclude_catch_all: Option<bool>,
+	confidence: Option<ResultConfidence>,
+	include_domains: Option<String>,
+	exclude_domains: Option<String>,
+	latest_only: Option<bool>,
+	header: Option<bool>,
+}
+
+/// Parse the comma-separated job ids, keeping their order and dropping
+/// duplicates.
+fn parse_job_ids(ids: &str) -> Result<Vec<i32>, ReacherResponseError> {
+	let mut job_ids = Vec::new();
+	for id in ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+		let job_id = id.parse::<i32>().map_err(|_| {
+			ReacherResponseError::new(
+				http::StatusCode::BAD_REQUEST,
+				format!("invalid job id {:?}", id),
+			)
+		})?;
+		let job_id = JobId::try_from(job_id)?.get();
+		if !job_ids.contains(&job_id) {
+			job_ids.push(job_id);
+		}
+	}
+
+	if job_ids.is_empty() {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			"ids should list at least one job id",
+		));
+	}
+	if job_ids.len() > MAX_COMBINED_JOBS {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			format!("ids should list at most {} jobs", MAX_COMBINED_JOBS),
+		));
+	}
+
+	Ok(job_ids)
+}
+
+/// Serialize a page of results of one job for the combined download, with
+/// the `job_id` of each row. `first` tracks whether a JSON element was
+/// already written, to place the separators.
+fn combined_page(
+	job_id: i32,
+	rows: Vec<serde_json::Value>,
+	format: &JobResultResponseFormat,
+	first: &mut bool,
+) -> Result<Vec<u8>, String> {
+	match format {
+		JobResultResponseFormat::Csv => {
+			let mut wtr = csv::WriterBuilder::new()
+				.has_headers(false)
+				.from_writer(vec![]);
+			for row in rows {
+				let result_csv = JobResultCsvResponse::try_from(CsvWrapper(row))?;
+				wtr.serialize((job_id, result_csv))
+					.map_err(|e| e.to_string())?;
+			}
+			wtr.into_inner().map_err(|e| e.to_string())
+		}
+		JobResultResponseFormat::Json | JobResultResponseFormat::JsonArray => {
+			let mut page = Vec::new();
+			for mut row in rows {
+				if let Some(row) = row.as_object_mut() {
+					row.insert("job_id".into(), job_id.into());
+				}
+				if !*first {
+					page.push(b',');
+				}
+				*first = false;
+				serde_json::to_writer(&mut page, &row).map_err(|e| e.to_string())?;
+			}
+			Ok(page)
+		}
+		JobResultResponseFormat::Txt => Ok(inputs_txt(&rows)),
+	}
+}
+
+/// Log a client going away before the end of a combined export.
+fn log_disconnect(job_id: i32, rows_sent: usize) {
+	log::info!(
+		target:"reacher",
+		"Client disconnected from combined export at [job_id={}] after [rows_sent={}]",
+		job_id,
+		rows_sent
+	);
+}
+
+async fn combined_export(
+	req: CombinedExportRequest,
+	conn_pool: Pool<Postgres>,
+	transformer: SharedTransformer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let job_ids = parse_job_ids(&req.ids)?;
+	check_sample(req.sample)?;
+	let format = req.format.unwrap_or(JobResultResponseFormat::Csv);
+	// The results are sampled by id, so that the count matches the
+	// streamed results.
+	let filter = ResultFilter {
+		sample: req.sample,
+		sample_by_id: true,
+		..ResultFilter::from_params(
+			req.reachable.as_ref(),
+			req.exclude_catch_all,
+			req.confidence,
+			req.include_domains.as_deref(),
+			req.exclude_domains.as_deref(),
+			req.latest_only,
+		)?
+	};
+
+	let unknown = sqlx::query!(
+		r#"
+		SELECT id FROM UNNEST($1::int4[]) AS id
+		WHERE id NOT IN (SELECT id FROM bulk_jobs WHERE deleted_at IS NULL)
+		"#,
+		&job_ids
+	)
+	.fetch_all(&conn_pool)
+	.timed_for("job_records", format!("job_ids={:?}", job_ids))
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get job records for [job_ids={:?}] with [error={}]",
+			job_ids,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+	if let Some(rec) = unknown.first() {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", rec.id.unwrap_or_default()),
+		)
+		.into());
+	}
+
+	// Check the cap before streaming anything.
+	let mut totals = Vec::with_capacity(job_ids.len());
+	for job_id in &job_ids {
+		totals.push(job_result_count(*job_id, &filter, &conn_pool).await?);
+	}
+	let total: u64 = totals.iter().sum();
+	if total > MAX_COMBINED_RESULTS {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			format!(
+				"the jobs have {} results, a combined download is limited to {}",
+				total, MAX_COMBINED_RESULTS
+			),
+		)
+		.into());
+	}
+
+	let (prefix, suffix): (Vec<u8>, &[u8]) = match format {
+		JobResultResponseFormat::Csv if req.header.unwrap_or(true) => {
+			let mut wtr = csv::WriterBuilder::new().from_writer(vec![]);
+			wtr.write_record(std::iter::once("job_id").chain(CSV_HEADER))
+				.map_err(|_| ReacherError::Csv())?;
+			(wtr.into_inner().map_err(|_| ReacherError::Csv())?, b"")
+		}
+		JobResultResponseFormat::Json => (b"{\"results\":[".to_vec(), b"]}"),
+		JobResultResponseFormat::JsonArray => (b"[".to_vec(), b"]"),
+		_ => (vec![], b""),
+	};
+	let content_type = match format {
+		JobResultResponseFormat::Csv => "text/csv",
+		JobResultResponseFormat::Txt => "text/plain; charset=utf-8",
+		JobResultResponseFormat::Json | JobResultResponseFormat::JsonArray => "application/json",
+	};
+
+	// Stream the results job by job, page by page. Each page is only fetched
+	// once the client is ready to receive it, so that a client going away
+	// stops the stream before the next query. The queries themselves are
+	// never cancelled midway, so their connection goes back to the pool.
+	let (mut sender, body) = warp::hyper::Body::channel();
+	tokio::spawn(async move {
+		if sender.send_data(prefix.into()).await.is_err() {
+			return;
+		}
+
+		let mut first = true;
+		let mut rows_sent = 0;
+		let page_params = PageParams::new(MAX_DOWNLOAD_LIMIT, 0)
+			.expect("MAX_DOWNLOAD_LIMIT fits in an i64. qed.");
+		for job_id in job_ids {
+			// Paged along the id cursor.
+			let mut filter = ResultFilter {
+				after: None,
+				..filter.clone()
+			};
+			loop {
+				if poll_fn(|cx| sender.poll_ready(cx)).await.is_err() {
+					log_disconnect(job_id, rows_sent);
+					return;
+				}
+
+				let page = match job_result_json(
+					job_id,
+					page_params,
+					&filter,
+					None,
+					None,
+					transformer.as_ref(),
+					conn_pool.clone(),
+				)
+				.await
+				{
+					Ok(page) => {
+						let (rows, last_id) = (page.count, page.last_id);
+						combined_page(job_id, page.rows, &format, &mut first)
+							.map(|page| (page, rows, last_id))
+					}
+					Err(e) => Err(format!("{:?}", e)),
+				};
+				let (page, rows, last_id) = match page {
+					Ok(page) => page,
+					Err(e) => {
+						log::error!(
+							target:"reacher",
+							"Failed to stream combined results for [job_id={}] [after={:?}] with [error={}]",
+							job_id,
+							filter.after,
+							e
+						);
+						// Make the client see a truncated body.
+						sender.abort();
+						return;
+					}
+				};
+				if sender.send_data(page.into()).await.is_err() {
+					log_disconnect(job_id, rows_sent);
+					return;
+				}
+				rows_sent += rows;
+				// A short page is the last one.
+				match last_id {
+					Some(last_id) if rows as u64 == MAX_DOWNLOAD_LIMIT => {
+						filter.after = Some(last_id)
+					}
+					_ => break,
+				}
+			}
+		}
+
+		let _ = sender.send_data(suffix.into()).await;
+	});
+
+	Ok(http::Response::builder()
+		.header("Content-Type", content_type)
+		.header("X-Total-Count", total)
+		.body(body)
+		.expect("All header names and values are valid. qed."))
+}
+
+/// Create the `GET /v0/bulk/export` endpoint, streaming the combined
+/// results of several jobs, with the `job_id` of each result.
+pub fn get_combined_export(
+	conn_pool: Pool<Postgres>,
+	transformer: SharedTransformer,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / "export")
+		.and(warp::get())
+		.and(with_query::<CombinedExportRequest>())
+		.and_then(move |req| combined_export(req, conn_pool.clone(), transformer.clone()))
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Create the `POST /v0/bulk/{id}/export` endpoint, starting an export of
+/// all the results of a job in the background.
+pub fn create_job_export(
+	conn_pool: Pool<Postgres>,
+	transformer: SharedTransformer,
+	settings: Settings,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "export")
+		.and(warp::post())
+		.and_then(job_id_param)
+		.and(with_query::<CreateExportRequest>())
+		.and_then(move |job_id, req| {
+			create_export(
+				job_id,
+				req,
+				conn_pool.clone(),
+				transformer.clone(),
+				settings.clone(),
+			)
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Create the `GET /v0/bulk/{id}/export/{export_id}/status` endpoint.
+pub fn get_job_export_status(
+	conn_pool: Pool<Postgres>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "export" / i32 / "status")
+		.and(warp::get())
+		.and_then(job_id_and_param)
+		.untuple_one()
+		.and_then(move |job_id, export_id| export_status(job_id, export_id, conn_pool.clone()))
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Create the `GET /v0/bulk/{id}/export/{export_id}/download` endpoint.
+pub fn get_job_export_download(
+	conn_pool: Pool<Postgres>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "export" / i32 / "download")
+		.and(warp::get())
+		.and_then(job_id_and_param)
+		.untuple_one()
+		.and_then(move |job_id, export_id| export_download(job_id, export_id, conn_pool.clone()))
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
@@ -0,0 +1,51 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Freshness of the results. The MX and SMTP state of a domain changes over
+//! time, so a result processed longer ago than the freshness window is
+//! `stale`, and its email should be verified again before being relied on.
+
+use chrono::Duration;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::env;
+
+/// Default freshness window of the results, in days.
+const DEFAULT_RESULT_FRESHNESS_DAYS: u32 = 30;
+
+/// Freshness window of the results, read from `RCH_RESULT_FRESHNESS_DAYS`.
+///
+/// # Panics
+///
+/// Panics if `RCH_RESULT_FRESHNESS_DAYS` is not a positive integer.
+pub fn result_freshness() -> Duration {
+	let days =
+		env::var("RCH_RESULT_FRESHNESS_DAYS")
+			.ok()
+			.map_or(DEFAULT_RESULT_FRESHNESS_DAYS, |days| {
+				days.parse::<u32>()
+					.ok()
+					.filter(|days| *days > 0)
+					.expect("Environment variable RCH_RESULT_FRESHNESS_DAYS is malformed.")
+			});
+
+	Duration::days(days.into())
+}
+
+/// Time before which the results processed are stale, as of `now`, with the
+/// `freshness` window.
+pub fn stale_before(now: DateTime<Utc>, freshness: Duration) -> DateTime<Utc> {
+	now - freshness
+}
@@ -16,45 +16,749 @@
 
 //! This file implements the `GET /bulk/{id}` endpoint.
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::{TryFrom, TryInto};
+use std::env;
+use std::io::Write;
 
-use crate::errors::ReacherError;
+use super::delete::check_not_deleted;
+use super::download_limit::{JobDownloadLimiter, JobDownloadPermit};
+use super::eta::{rolling_eta, ETA_WINDOW_RESULTS};
+use super::expiry::expires_at;
+use super::freshness::stale_before;
+use super::json_path::{JsonPath, MAX_EXTRA_FIELDS};
+use super::metadata::{attach_result_metadata, result_metadata, ResultMetadata};
+use super::provider::WithProvider;
+use super::status_cache::JobStatusCache;
+use super::summary::precomputed_job_summary;
+use super::transform::{ResultTransformer, SharedTransformer};
+use super::{job_id_and_param, job_id_param, JobId};
+use crate::auth::verify_download;
+use crate::compression::with_gzip;
+use crate::errors::{ReacherError, ReacherResponseError};
+use crate::query::with_query;
+use crate::settings::Settings;
+use crate::smtp_errors::smtp_error_classification;
+use crate::tracing_util::{with_trace_context, ServerTiming, TimedQuery};
+use crate::DB_MAX_CONNECTIONS;
 
-use csv::WriterBuilder;
+use check_if_email_exists::Reachable;
+use csv::{QuoteStyle, WriterBuilder};
+use futures::future::poll_fn;
+use opentelemetry::Context;
+use percent_encoding::percent_decode_str;
+use schemars::JsonSchema;
+use sqlx::postgres::PgArguments;
+use sqlx::query::Query;
 use sqlx::{Executor, Pool, Postgres, Row};
-use warp::Filter;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use warp::{http, Filter};
 
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use sqlx::types::chrono::{DateTime, Utc};
 
-#[derive(Serialize, Deserialize)]
+/// Maximum number of results returned by a single download request.
+pub const MAX_DOWNLOAD_LIMIT: u64 = 10_000;
+/// Deeper CSV offsets are rejected, as the database scans all the skipped
+/// results: clients should page with `after` instead.
+pub const MAX_CSV_OFFSET: u64 = 100_000;
+/// Number of results fetched by each query of a streamed download.
+const STREAM_PAGE_LIMIT: u64 = 1000;
+/// Number of results returned in JSON format when no `limit` is given.
+pub const DEFAULT_JSON_LIMIT: u64 = 50;
+/// Number of results returned in CSV format when no `limit` is given.
+pub const DEFAULT_CSV_LIMIT: u64 = 5000;
+/// When the database pool is saturated, downloads only return this fraction
+/// of the requested limit.
+const THROTTLE_LIMIT_DIVISOR: u64 = 10;
+
+/// Maximum size in bytes of a JSON download, read from
+/// `RCH_MAX_RESPONSE_BYTES`. JSON downloads are unbounded if it's not set.
+///
+/// # Panics
+///
+/// Panics if `RCH_MAX_RESPONSE_BYTES` is not a positive integer.
+pub fn max_response_bytes() -> Option<usize> {
+	env::var("RCH_MAX_RESPONSE_BYTES").ok().map(|bytes| {
+		bytes
+			.parse::<usize>()
+			.ok()
+			.filter(|bytes| *bytes > 0)
+			.expect("Environment variable RCH_MAX_RESPONSE_BYTES is malformed.")
+	})
+}
+
+/// Formatting of the non-integer numbers of the csv download, integers are
+/// written as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvNumberFormat {
+	separator: char,
+	/// Number of decimals, all of them if `None`.
+	precision: Option<usize>,
+}
+
+impl Default for CsvNumberFormat {
+	fn default() -> Self {
+		CsvNumberFormat {
+			separator: '.',
+			precision: None,
+		}
+	}
+}
+
+impl CsvNumberFormat {
+	fn format(&self, number: &serde_json::Number) -> String {
+		if !number.is_f64() {
+			return number.to_string();
+		}
+
+		let formatted = match (number.as_f64(), self.precision) {
+			(Some(float), Some(precision)) => format!("{:.*}", precision, float),
+			_ => number.to_string(),
+		};
+		if self.separator == '.' {
+			formatted
+		} else {
+			formatted.replacen('.', &self.separator.to_string(), 1)
+		}
+	}
+}
+
+/// Number formatting of the csv download, read from
+/// `RCH_CSV_DECIMAL_SEPARATOR` and `RCH_CSV_DECIMAL_PRECISION`.
+///
+/// # Panics
+///
+/// Panics if `RCH_CSV_DECIMAL_SEPARATOR` is not a single character, or
+/// `RCH_CSV_DECIMAL_PRECISION` not a non-negative integer.
+pub fn csv_number_format() -> CsvNumberFormat {
+	let mut format = CsvNumberFormat::default();
+	if let Ok(separator) = env::var("RCH_CSV_DECIMAL_SEPARATOR") {
+		let mut chars = separator.chars();
+		format.separator = match (chars.next(), chars.next()) {
+			(Some(separator), None) => separator,
+			_ => panic!("Environment variable RCH_CSV_DECIMAL_SEPARATOR is malformed."),
+		};
+	}
+	if let Ok(precision) = env::var("RCH_CSV_DECIMAL_PRECISION") {
+		format.precision = Some(
+			precision
+				.parse::<usize>()
+				.expect("Environment variable RCH_CSV_DECIMAL_PRECISION is malformed."),
+		);
+	}
+
+	format
+}
+
+/// Counts the bytes written to it, to measure serialized sizes without
+/// buffering them.
+#[derive(Default)]
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.0 += buf.len();
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
-enum JobResultResponseFormat {
+pub enum JobResultResponseFormat {
 	Json,
 	Csv,
+	/// Same as `Json`, but the results are returned as a top-level array
+	/// instead of being wrapped in `{"results": [...]}`.
+	#[serde(rename = "json_array")]
+	JsonArray,
+	/// Only the `input` of the results, or the given `column`, one per
+	/// line.
+	Txt,
+}
+
+/// Shape of the results in the JSON formats.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobResultShape {
+	Array,
+	/// An object keyed by the `input` field of the results. For duplicate
+	/// inputs, the latest result is kept.
+	Map,
+}
+
+/// Minimum deliverability confidence of the results, from their
+/// `is_reachable` and `smtp.is_catch_all`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultConfidence {
+	/// Only the `safe` results of domains which aren't catch-all.
+	High,
+	/// The `safe` and `risky` results, catch-all domains included.
+	Medium,
+	/// All the results.
+	Low,
+}
+
+impl ResultConfidence {
+	/// `is_reachable` values of the results with this confidence, `None` if
+	/// they're all kept.
+	fn reachable(self) -> Option<Vec<String>> {
+		let reachable: &[Reachable] = match self {
+			ResultConfidence::High => &[Reachable::Safe],
+			ResultConfidence::Medium => &[Reachable::Safe, Reachable::Risky],
+			ResultConfidence::Low => return None,
+		};
+
+		Some(reachable.iter().map(reachable_str).collect())
+	}
+}
+
+/// Order of the downloaded results.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobResultSort {
+	/// Order in which the results were written.
+	Id,
+	/// Order of the inputs in the submitted list. Results written before
+	/// the ordinals were recorded come last.
+	Ordinal,
+}
+
+/// Direction of the `JobResultSort` order.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobResultOrder {
+	#[default]
+	Asc,
+	/// With the default sort, the most recently written results first.
+	Desc,
+}
+
+/// Columns of the CSV download.
+#[derive(Debug, PartialEq, Eq)]
+pub enum JobResultFields {
+	/// The curated columns of `JobResultCsvResponse`.
+	Default,
+	/// Every scalar leaf of the results, flattened with dotted keys. The
+	/// header is the sorted union of the keys of all the returned rows.
+	AllNested,
+	/// Some of the curated columns, in the given order, e.g.
+	/// `smtp.is_deliverable,input`.
+	Columns(Vec<String>),
+}
+
+impl Serialize for JobResultFields {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer,
+	{
+		match self {
+			JobResultFields::Default => serializer.serialize_str("default"),
+			JobResultFields::AllNested => serializer.serialize_str("all_nested"),
+			JobResultFields::Columns(columns) => serializer.serialize_str(&columns.join(",")),
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for JobResultFields {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: serde::Deserializer<'de>,
+	{
+		let fields = String::deserialize(deserializer)?;
+		Ok(match fields.as_str() {
+			"default" => JobResultFields::Default,
+			"all_nested" => JobResultFields::AllNested,
+			// The columns are checked by the endpoint, for a better error.
+			_ => JobResultFields::Columns(fields.split(',').map(str::to_string).collect()),
+		})
+	}
+}
+
+impl JobResultResponseFormat {
+	/// All the formats supported by the download endpoint.
+	pub const ALL: [JobResultResponseFormat; 4] = [
+		JobResultResponseFormat::Json,
+		JobResultResponseFormat::Csv,
+		JobResultResponseFormat::JsonArray,
+		JobResultResponseFormat::Txt,
+	];
 }
 
 // limit and offset are optional in the request
 // if they are unspecified their default values
 // are 50 and 0 respectively
 #[derive(Serialize, Deserialize)]
-struct JobResultRequest {
-	format: Option<JobResultResponseFormat>,
+pub struct JobResultRequest {
+	pub format: Option<JobResultResponseFormat>,
+	pub limit: Option<u64>,
+	pub offset: Option<u64>,
+	/// Fraction of the results, between 0 and 1, to randomly sample. Each row
+	/// is picked independently, so the number of returned rows is only
+	/// approximately `sample` times the total.
+	pub sample: Option<f64>,
+	/// Add a `mx.records` column to the csv download, with the MX hostnames
+	/// joined by semicolons. JSON results always include them.
+	pub include_mx: Option<bool>,
+	/// Add the mailbox provider of each result, inferred from its domain,
+	/// e.g. `gmail`, see `super::provider`. It's a `provider` field of the
+	/// JSON results and a `provider` column of the csv download.
+	pub include_provider: Option<bool>,
+	/// Add the `retry_count` of each result, the number of times its email
+	/// was requeued by `POST /v0/bulk/requeue-unknowns`, and the
+	/// `last_error` of the attempt the requeue replaced. They're fields of
+	/// the JSON results and columns of the csv download.
+	pub include_retry_info: Option<bool>,
+	/// Add whether each result is `stale`, processed longer ago than the
+	/// freshness window, see `super::freshness`. It's a field of the JSON
+	/// results and a column of the csv download.
+	pub include_freshness: Option<bool>,
+	/// Write the header row of the csv download, defaults to true. Useful to
+	/// concatenate paginated downloads.
+	pub header: Option<bool>,
+	/// Only return the results after this cursor, given by the
+	/// `X-Next-After` header of the previous page. Unlike `offset`, the
+	/// results before it aren't scanned, with `latest_only` too.
+	pub after: Option<i32>,
+	/// Only return the results with this `is_reachable` value.
+	pub reachable: Option<Reachable>,
+	/// Leave out the catch-all results, defaults to false.
+	pub exclude_catch_all: Option<bool>,
+	/// Only return the results with at least this deliverability
+	/// confidence, on top of the other filters.
+	pub confidence: Option<ResultConfidence>,
+	/// Comma-separated domains, only return the results of these domains.
+	pub include_domains: Option<String>,
+	/// Comma-separated domains, leave out the results of these domains.
+	/// Can't be combined with `include_domains`.
+	pub exclude_domains: Option<String>,
+	/// Only return the most recent result of each input, defaults to true.
+	/// When false, the full history of results is returned.
+	pub latest_only: Option<bool>,
+	/// Shape of the JSON results, defaults to an array.
+	pub shape: Option<JobResultShape>,
+	/// Order of the results, defaults to `id`. Can't be combined with
+	/// `after` when sorting by `ordinal`.
+	pub sort: Option<JobResultSort>,
+	/// Direction of the sort, defaults to `asc`. The `after` cursor follows
+	/// it, e.g. with `desc` it returns the results with a lower id.
+	pub order: Option<JobResultOrder>,
+	/// Columns of the csv download, defaults to the curated subset. Either
+	/// `default`, `all_nested` or a comma-separated list of the default
+	/// columns, written in that order.
+	pub fields: Option<JobResultFields>,
+	/// Add the most frequent failure reasons of the job, in the
+	/// `X-Processing-Warnings` header and a `warnings` array of the JSON
+	/// download. They're aggregated over all the results of the job, so
+	/// only on request rather than for every page.
+	pub meta: Option<bool>,
+	/// Indent the JSON download, to read it in a browser. Defaults to the
+	/// compact form.
+	pub pretty: Option<bool>,
+	/// Stream all the results of the `json_array` download, from `after` if
+	/// set, as a single array sent in chunks instead of a buffered page.
+	/// Can't be combined with `limit`, `offset`, `sort=ordinal`, `shape` or
+	/// `pretty`.
+	pub stream: Option<bool>,
+	/// Column of the txt download, one of the default csv columns. Defaults
+	/// to `input`.
+	pub column: Option<String>,
+	/// Quoting of the csv fields, defaults to `necessary`.
+	pub quoting: Option<CsvQuoting>,
+	/// Comma-separated paths of fields to add as csv columns, e.g.
+	/// `smtp.details.code`, see `super::json_path`. The column header is the
+	/// path.
+	pub extra_fields: Option<String>,
+	/// End the csv download with a `# total_rows: <count>` line, for clients
+	/// to check they received all the rows. Off by default, as it isn't
+	/// valid csv.
+	pub count_trailer: Option<bool>,
+	/// Add a `meta` object to each result, with the whitelisted columns of
+	/// its row in the metadata table, see `RCH_RESULT_METADATA_TABLE`. The
+	/// csv download gets a `meta.<column>` column for each of them.
+	pub include_meta: Option<bool>,
+	/// Signature of a shared download URL, see `POST /v0/bulk/{id}/download-url`.
+	pub token: Option<String>,
+	/// Unix timestamp after which the `token` is rejected.
+	pub expires: Option<i64>,
+}
+
+/// Which results of a job are downloaded.
+#[derive(Clone)]
+pub(super) struct ResultFilter {
+	pub(super) sample: Option<f64>,
+	/// Sample by a hash of the result ids rather than at random, so that
+	/// every query picks the same results, and they can be counted.
+	pub(super) sample_by_id: bool,
+	pub(super) latest_only: bool,
+	/// `is_reachable` value of the results, as serialized.
+	pub(super) reachable: Option<String>,
+	/// `is_reachable` values of the results of the `confidence` param.
+	pub(super) confidence_reachable: Option<Vec<String>>,
+	pub(super) exclude_catch_all: bool,
+	/// Lowercased domains of the results to keep.
+	pub(super) include_domains: Option<Vec<String>>,
+	/// Lowercased domains of the results to leave out.
+	pub(super) exclude_domains: Option<Vec<String>>,
+	/// Only the results after this id in the `order` direction, see
+	/// `JobResultRequest::after`.
+	pub(super) after: Option<i32>,
+	pub(super) sort: JobResultSort,
+	pub(super) order: JobResultOrder,
+	/// Not a filter, but read by the same queries: add the `retry_count`
+	/// and `last_error` of the results, see
+	/// `JobResultRequest::include_retry_info`.
+	pub(super) include_retry_info: bool,
+	/// Add whether the results are `stale`, i.e. processed before this
+	/// time, see `JobResultRequest::include_freshness`.
+	pub(super) stale_before: Option<DateTime<Utc>>,
+}
+
+impl ResultFilter {
+	/// Build the filter from the query params shared by the downloads, the
+	/// count and the combined export. `sample`, `after`,
+	/// `include_retry_info` and `stale_before` are left unset.
+	pub(super) fn from_params(
+		reachable: Option<&Reachable>,
+		exclude_catch_all: Option<bool>,
+		confidence: Option<ResultConfidence>,
+		include_domains: Option<&str>,
+		exclude_domains: Option<&str>,
+		latest_only: Option<bool>,
+	) -> Result<Self, ReacherResponseError> {
+		let (include_domains, exclude_domains) = domain_filters(include_domains, exclude_domains)?;
+
+		Ok(ResultFilter {
+			sample: None,
+			sample_by_id: false,
+			latest_only: latest_only.unwrap_or(true),
+			reachable: reachable.map(reachable_str),
+			confidence_reachable: confidence.and_then(ResultConfidence::reachable),
+			exclude_catch_all: exclude_catch_all.unwrap_or(false)
+				|| confidence == Some(ResultConfidence::High),
+			include_domains,
+			exclude_domains,
+			after: None,
+			sort: JobResultSort::Id,
+			order: JobResultOrder::Asc,
+			include_retry_info: false,
+			stale_before: None,
+		})
+	}
+}
+
+/// Parse the comma-separated `include_domains` and `exclude_domains`
+/// params, which are mutually exclusive.
+#[allow(clippy::type_complexity)]
+fn domain_filters(
+	include_domains: Option<&str>,
+	exclude_domains: Option<&str>,
+) -> Result<(Option<Vec<String>>, Option<Vec<String>>), ReacherResponseError> {
+	if include_domains.is_some() && exclude_domains.is_some() {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			"include_domains and exclude_domains can't be used together",
+		));
+	}
+
+	let parse = |domains: &str| {
+		domains
+			.split(',')
+			.map(|domain| domain.trim().to_lowercase())
+			.filter(|domain| !domain.is_empty())
+			.collect::<Vec<_>>()
+	};
+
+	Ok((include_domains.map(parse), exclude_domains.map(parse)))
+}
+
+/// Limit and offset of a page of results, checked once to fit the `BIGINT`
+/// parameters of the queries, instead of being cast wherever they're bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct PageParams {
+	limit: i64,
+	offset: i64,
+}
+
+impl PageParams {
+	pub(super) fn new(limit: u64, offset: u64) -> Result<Self, ReacherResponseError> {
+		let to_i64 = |name, value| {
+			i64::try_from(value).map_err(|_| {
+				ReacherResponseError::new(
+					http::StatusCode::BAD_REQUEST,
+					format!("{} should be at most {}", name, i64::MAX),
+				)
+			})
+		};
+
+		Ok(PageParams {
+			limit: to_i64("limit", limit)?,
+			offset: to_i64("offset", offset)?,
+		})
+	}
+
+	pub(super) fn limit(&self) -> u64 {
+		self.limit.unsigned_abs()
+	}
+
+	pub(super) fn offset(&self) -> u64 {
+		self.offset.unsigned_abs()
+	}
+
+	/// Apply the default limit of the requested format, and check the
+	/// limit is at most `MAX_DOWNLOAD_LIMIT`.
+	pub(super) fn from_request(
+		req: &JobResultRequest,
+		defaults: &DownloadDefaults,
+	) -> Result<Self, ReacherResponseError> {
+		let limit = req.limit.unwrap_or(match req.format {
+			Some(JobResultResponseFormat::Csv) | Some(JobResultResponseFormat::Txt) => {
+				defaults.csv_limit
+			}
+			_ => defaults.json_limit,
+		});
+		if limit > MAX_DOWNLOAD_LIMIT {
+			return Err(ReacherResponseError::new(
+				http::StatusCode::BAD_REQUEST,
+				format!("limit should be at most {}", MAX_DOWNLOAD_LIMIT),
+			));
+		}
+
+		PageParams::new(limit, req.offset.unwrap_or(0))
+	}
+}
+
+/// Page sizes of the downloads without a `limit`, read once at startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadDefaults {
+	pub json_limit: u64,
+	pub csv_limit: u64,
+}
+
+impl Default for DownloadDefaults {
+	fn default() -> Self {
+		DownloadDefaults {
+			json_limit: DEFAULT_JSON_LIMIT,
+			csv_limit: DEFAULT_CSV_LIMIT,
+		}
+	}
+}
+
+impl DownloadDefaults {
+	/// Read the page sizes from `DEFAULT_JSON_LIMIT` and `DEFAULT_CSV_LIMIT`,
+	/// the constants of the same name being used for the unset ones.
+	///
+	/// # Panics
+	///
+	/// Panics if either is not an integer between 1 and `MAX_DOWNLOAD_LIMIT`.
+	pub fn from_env() -> Self {
+		let limit = |name: &str, default: u64| {
+			env::var(name).ok().map_or(default, |limit| {
+				limit
+					.parse::<u64>()
+					.ok()
+					.filter(|limit| (1..=MAX_DOWNLOAD_LIMIT).contains(limit))
+					.unwrap_or_else(|| panic!("Environment variable {} is malformed.", name))
+			})
+		};
+
+		DownloadDefaults {
+			json_limit: limit("DEFAULT_JSON_LIMIT", DEFAULT_JSON_LIMIT),
+			csv_limit: limit("DEFAULT_CSV_LIMIT", DEFAULT_CSV_LIMIT),
+		}
+	}
+}
+
+/// A page of results, with the id of its last result, to be used as the
+/// cursor of the next page.
+pub(super) struct ResultPage<T> {
+	pub(super) rows: T,
+	pub(super) last_id: Option<i32>,
+	/// Number of results in the page.
+	pub(super) count: usize,
+	/// Time the queries of the page took, without their serialization.
+	pub(super) query_time: Duration,
+}
+
+/// Character sets the CSV download can be encoded in.
+#[derive(Debug, PartialEq, Eq)]
+enum CsvCharset {
+	Utf8,
+	Latin1,
+}
+
+impl CsvCharset {
+	/// Pick the charset to use from an `Accept-Charset` header, by order of
+	/// preference. Defaults to UTF-8 when there's no header, and returns
+	/// `None` when none of the accepted charsets are supported.
+	fn from_accept_charset(header: Option<&str>) -> Option<Self> {
+		let header = match header {
+			Some(header) => header,
+			None => return Some(CsvCharset::Utf8),
+		};
+
+		let mut best: Option<(f32, CsvCharset)> = None;
+		for item in header.split(',') {
+			let mut parts = item.split(';').map(str::trim);
+			let name = parts.next().unwrap_or_default().to_lowercase();
+			let q = parts
+				.find_map(|p| p.strip_prefix("q="))
+				.and_then(|q| q.parse::<f32>().ok())
+				.unwrap_or(1.0);
+
+			let charset = match name.as_str() {
+				"utf-8" | "utf8" | "*" => CsvCharset::Utf8,
+				"iso-8859-1" | "latin1" | "latin-1" => CsvCharset::Latin1,
+				_ => continue,
+			};
+			if q > 0.0 && best.as_ref().is_none_or(|(best_q, _)| q > *best_q) {
+				best = Some((q, charset));
+			}
+		}
+
+		best.map(|(_, charset)| charset)
+	}
+
+	fn content_type(&self) -> &'static str {
+		match self {
+			CsvCharset::Utf8 => "text/csv; charset=utf-8",
+			CsvCharset::Latin1 => "text/csv; charset=iso-8859-1",
+		}
+	}
+
+	/// Encode the UTF-8 CSV data into this charset. Characters that can't be
+	/// represented in Latin-1 are replaced by `?`.
+	fn encode(&self, data: Vec<u8>) -> Vec<u8> {
+		match self {
+			CsvCharset::Utf8 => data,
+			CsvCharset::Latin1 => String::from_utf8_lossy(&data)
+				.chars()
+				.map(|c| if (c as u32) <= 0xFF { c as u8 } else { b'?' })
+				.collect(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct JobStatusRequest {
+	/// Also count the distinct domains of the job. This is opt-in, as
+	/// `COUNT(DISTINCT ...)` is slow on large jobs.
+	distinct_domains: Option<bool>,
+	/// Format of the timestamps, defaults to RFC 3339.
+	time_format: Option<TimeFormat>,
+	/// Also count the stale results of the job, see `super::freshness`.
+	/// This is opt-in, as the count can't be cached with the status.
+	include_freshness: Option<bool>,
+}
+
+/// Format of the timestamps in status responses.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+	Rfc3339,
+	/// Milliseconds since the Unix epoch.
+	EpochMs,
+}
+
+/// Serializes a status with its timestamps in the given format.
+struct TimeFormatted<'a>(&'a JobStatusResponseBody, TimeFormat);
+
+impl Serialize for TimeFormatted<'_> {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let TimeFormatted(status, time_format) = self;
+		match time_format {
+			TimeFormat::Rfc3339 => status.serialize(serializer),
+			TimeFormat::EpochMs => {
+				let mut value = serde_json::to_value(status).map_err(serde::ser::Error::custom)?;
+				let timestamps = [
+					("created_at", Some(status.created_at)),
+					("expires_at", status.expires_at),
+					("last_processed_at", status.last_processed_at),
+					("eta_rolling", status.eta_rolling),
+					("summary_refreshed_at", status.summary_refreshed_at),
+				];
+				for (key, timestamp) in timestamps {
+					value[key] = serde_json::json!(timestamp.map(|t| t.timestamp_millis()));
+				}
+				value.serialize(serializer)
+			}
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct JobListRequest {
 	limit: Option<u64>,
 	offset: Option<u64>,
+	/// Only list the jobs with this tag.
+	tag: Option<String>,
+}
+
+/// Filters of the `GET /v0/bulk/{id}/count` endpoint, see
+/// `JobResultRequest` for their meaning.
+#[derive(Deserialize)]
+struct JobCountRequest {
+	reachable: Option<Reachable>,
+	exclude_catch_all: Option<bool>,
+	confidence: Option<ResultConfidence>,
+	include_domains: Option<String>,
+	exclude_domains: Option<String>,
+	latest_only: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct JobCountResponse {
+	count: u64,
+}
+
+#[derive(Serialize)]
+struct JobListResponse {
+	jobs: Vec<JobRecord>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct JobResultJsonResponse {
-	results: Vec<serde_json::Value>,
+	results: serde_json::Value,
+	/// Only present if requested with `meta=true`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	warnings: Option<Vec<ProcessingWarning>>,
+}
+
+/// Number of failure reasons returned in the `X-Processing-Warnings` header.
+const MAX_PROCESSING_WARNINGS: i64 = 3;
+/// Failure reasons are truncated to this many characters in the header.
+const MAX_PROCESSING_WARNING_LEN: usize = 100;
+
+/// One of the most frequent failure reasons among the results of a job.
+#[derive(Debug, Deserialize, Serialize)]
+struct ProcessingWarning {
+	/// Step of the verification which failed, `smtp` or `mx`.
+	stage: String,
+	reason: String,
+	count: i64,
+	/// Share of the processed results with this failure, in percent.
+	percent: u32,
 }
 
 /// NOTE: Type conversions from postgres to rust types
 /// are according to the table given by
 /// [sqlx here](https://docs.rs/sqlx/latest/sqlx/postgres/types/index.html)
-#[derive(Debug, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
 pub enum ValidStatus {
+	/// Opened by a chunked submission, and not finalized yet.
+	Draft,
+	/// Submitted while its owner had too many running jobs, see
+	/// `super::owner_limit`.
+	Pending,
 	Running,
 	Completed,
 }
@@ -70,61 +774,278 @@ pub struct JobRecord {
 	id: i32,
 	created_at: DateTime<Utc>,
 	total_records: i32,
+	tags: Vec<String>,
+	priority: i32,
 }
 
 /// Summary of a bulk verification job status
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
 pub struct JobStatusSummaryResponseBody {
-	total_safe: i32,
-	total_risky: i32,
-	total_invalid: i32,
-	total_unknown: i32,
+	pub total_safe: i32,
+	pub total_risky: i32,
+	pub total_invalid: i32,
+	pub total_unknown: i32,
+	pub avg_duration_ms: Option<f64>,
+	pub p95_duration_ms: Option<f64>,
+	/// Results with a transient error, see `crate::smtp_errors`.
+	pub total_transient_errors: i32,
+	/// Results with a permanent error, see `crate::smtp_errors`.
+	pub total_permanent_errors: i32,
+	/// Only present if requested with `distinct_domains=true`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub distinct_domains: Option<i32>,
 }
 
 /// Complete information about a bulk verification job
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct JobStatusResponseBody {
-	job_id: i32,
-	created_at: DateTime<Utc>,
-	total_records: i32,
-	total_processed: i32,
-	summary: JobStatusSummaryResponseBody,
-	job_status: ValidStatus,
+	pub job_id: i32,
+	pub created_at: DateTime<Utc>,
+	/// Time after which the job and its results are deleted, if a retention
+	/// period is configured.
+	pub expires_at: Option<DateTime<Utc>>,
+	pub total_records: i32,
+	pub total_processed: i32,
+	/// Time at which the latest result of the job was written, if any.
+	pub last_processed_at: Option<DateTime<Utc>>,
+	/// Estimated time at which a running job completes, at the rate of its
+	/// latest results, see `super::eta`.
+	pub eta_rolling: Option<DateTime<Utc>>,
+	/// Only set if the job is too large for its summary to be computed on
+	/// each request, time at which the precomputed summary was last
+	/// refreshed.
+	pub summary_refreshed_at: Option<DateTime<Utc>>,
+	/// Set if the job is too large for its summary to be computed on each
+	/// request, and its summary wasn't precomputed yet. The `summary` is
+	/// then empty.
+	#[serde(default)]
+	pub summary_pending: bool,
+	pub tags: Vec<String>,
+	/// Set at submission, see `MAX_JOB_PRIORITY`.
+	pub priority: i32,
+	pub summary: JobStatusSummaryResponseBody,
+	/// Results processed longer ago than the freshness window, see
+	/// `super::freshness`. Only set by `GET /v0/bulk/{id}` with
+	/// `include_freshness=true`, and never cached, as results become stale
+	/// without any write to the job.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub stale_count: Option<i64>,
+	pub job_status: ValidStatus,
 }
 /// Wrapper for serde json value to convert
 /// into a csv response
 #[derive(Debug)]
-struct CsvWrapper(serde_json::Value);
+pub(super) struct CsvWrapper(pub(super) serde_json::Value);
+
+/// Columns of the csv download, in order. `JobResultCsvResponse` is
+/// serialized following it rather than its field declaration order, so that
+/// reordering the fields doesn't change the positions in the csv. It's also
+/// written separately as the header, so that a download without any results
+/// still has one.
+pub(super) const CSV_HEADER: [&str; 16] = [
+	"input",
+	"is_reachable",
+	"misc.is_disposable",
+	"misc.is_role_account",
+	"mx.accepts_mail",
+	"smtp.can_connect",
+	"smtp.has_full_inbox",
+	"smtp.is_catch_all",
+	"smtp.is_deliverable",
+	"smtp.is_disabled",
+	"syntax.is_valid_syntax",
+	"syntax.domain",
+	"syntax.username",
+	"error",
+	"warnings",
+	"duration_ms",
+];
+
+/// Name of the optional column holding the MX records.
+const CSV_MX_RECORDS_COLUMN: &str = "mx.records";
+/// Name of the optional column holding the provider, see `WithProvider`.
+const CSV_PROVIDER_COLUMN: &str = "provider";
+/// Names of the optional columns of `JobResultRequest::include_retry_info`.
+const CSV_RETRY_INFO_COLUMNS: [&str; 2] = ["retry_count", "last_error"];
+/// Name of the optional column of `JobResultRequest::include_freshness`.
+const CSV_STALE_COLUMN: &str = "stale";
+
+/// Quoting of the fields of the csv download.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CsvQuoting {
+	/// Only the fields containing a delimiter, a quote or a newline.
+	#[default]
+	Necessary,
+	Always,
+	/// No field is quoted, the download fails if one of them would need it.
+	Never,
+}
+
+impl CsvQuoting {
+	/// Writer of the csv download. `Never` also writes the necessary quotes,
+	/// which `check` then rejects.
+	fn writer(self) -> csv::Writer<Vec<u8>> {
+		let quote_style = match self {
+			CsvQuoting::Always => QuoteStyle::Always,
+			CsvQuoting::Necessary | CsvQuoting::Never => QuoteStyle::Necessary,
+		};
+
+		WriterBuilder::new()
+			.has_headers(false)
+			.quote_style(quote_style)
+			.from_writer(vec![])
+	}
+
+	/// With `Never`, reject the data if any field was quoted: without its
+	/// quotes, a comma in a field would shift the following columns.
+	/// Otherwise it's the same as unquoted data.
+	fn check(self, data: Vec<u8>) -> Result<Vec<u8>, ReacherResponseError> {
+		if self == CsvQuoting::Never && data.contains(&b'"') {
+			return Err(ReacherResponseError::new(
+				http::StatusCode::BAD_REQUEST,
+				"quoting=never can't be used, a field contains a delimiter, a quote or a newline",
+			));
+		}
+
+		Ok(data)
+	}
+}
+
+/// Options of the csv download. The optional columns are written after
+/// `columns`.
+pub(super) struct CsvOptions<'a> {
+	/// Columns of `CSV_HEADER` to write, in this order.
+	pub(super) columns: &'a [&'static str],
+	/// Add the `CSV_MX_RECORDS_COLUMN` column.
+	pub(super) include_mx: bool,
+	/// Add the `CSV_PROVIDER_COLUMN` column, the transformer adding the
+	/// `provider` field.
+	pub(super) include_provider: bool,
+	/// Add a `meta.<column>` column for each metadata column.
+	pub(super) metadata: Option<&'a ResultMetadata>,
+	/// Add a column with the value at each of these paths.
+	pub(super) extra_fields: &'a [JsonPath],
+	pub(super) quoting: CsvQuoting,
+}
+
+impl Default for CsvOptions<'_> {
+	fn default() -> Self {
+		CsvOptions {
+			columns: &CSV_HEADER,
+			include_mx: false,
+			include_provider: false,
+			metadata: None,
+			extra_fields: &[],
+			quoting: CsvQuoting::default(),
+		}
+	}
+}
 
 /// Simplified output of `CheckEmailOutput` struct
 /// for csv fields
-#[derive(Debug, Serialize)]
-struct JobResultCsvResponse {
-	input: String,
-	is_reachable: String,
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct JobResultCsvResponse {
+	pub input: String,
+	pub is_reachable: String,
 	#[serde(rename = "misc.is_disposable")]
-	misc_is_disposable: bool,
+	pub misc_is_disposable: bool,
 	#[serde(rename = "misc.is_role_account")]
-	misc_is_role_account: bool,
+	pub misc_is_role_account: bool,
 	#[serde(rename = "mx.accepts_mail")]
-	mx_accepts_mail: bool,
+	pub mx_accepts_mail: bool,
 	#[serde(rename = "smtp.can_connect")]
-	smtp_can_connect: bool,
+	pub smtp_can_connect: bool,
 	#[serde(rename = "smtp.has_full_inbox")]
-	smtp_has_full_inbox: bool,
+	pub smtp_has_full_inbox: bool,
 	#[serde(rename = "smtp.is_catch_all")]
-	smtp_is_catch_all: bool,
+	pub smtp_is_catch_all: bool,
 	#[serde(rename = "smtp.is_deliverable")]
-	smtp_is_deliverable: bool,
+	pub smtp_is_deliverable: bool,
 	#[serde(rename = "smtp.is_disabled")]
-	smtp_is_disabled: bool,
+	pub smtp_is_disabled: bool,
 	#[serde(rename = "syntax.is_valid_syntax")]
-	syntax_is_valid_syntax: bool,
+	pub syntax_is_valid_syntax: bool,
 	#[serde(rename = "syntax.domain")]
-	syntax_domain: String,
+	pub syntax_domain: String,
 	#[serde(rename = "syntax.username")]
-	syntax_username: String,
-	error: Option<String>,
+	pub syntax_username: String,
+	pub error: Option<String>,
+	/// Non-fatal warnings of the verification, joined by semicolons. Empty
+	/// if there are none.
+	#[serde(default)]
+	pub warnings: String,
+	pub duration_ms: Option<i64>,
+	/// Only written when requested, see `CSV_MX_RECORDS_COLUMN`.
+	#[serde(skip)]
+	pub(crate) mx_records: Vec<String>,
+}
+
+impl Serialize for JobResultCsvResponse {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer,
+	{
+		self.serialize_columns(&CSV_HEADER, serializer)
+	}
+}
+
+impl JobResultCsvResponse {
+	/// Serialize these columns of `CSV_HEADER`, in this order.
+	fn serialize_columns<S>(
+		&self,
+		columns: &[&'static str],
+		serializer: S,
+	) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer,
+	{
+		use serde::ser::{Error, SerializeStruct};
+
+		let mut state = serializer.serialize_struct("JobResultCsvResponse", columns.len())?;
+		for &column in columns {
+			match column {
+				"input" => state.serialize_field(column, &self.input)?,
+				"is_reachable" => state.serialize_field(column, &self.is_reachable)?,
+				"misc.is_disposable" => state.serialize_field(column, &self.misc_is_disposable)?,
+				"misc.is_role_account" => {
+					state.serialize_field(column, &self.misc_is_role_account)?
+				}
+				"mx.accepts_mail" => state.serialize_field(column, &self.mx_accepts_mail)?,
+				"smtp.can_connect" => state.serialize_field(column, &self.smtp_can_connect)?,
+				"smtp.has_full_inbox" => {
+					state.serialize_field(column, &self.smtp_has_full_inbox)?
+				}
+				"smtp.is_catch_all" => state.serialize_field(column, &self.smtp_is_catch_all)?,
+				"smtp.is_deliverable" => {
+					state.serialize_field(column, &self.smtp_is_deliverable)?
+				}
+				"smtp.is_disabled" => state.serialize_field(column, &self.smtp_is_disabled)?,
+				"syntax.is_valid_syntax" => {
+					state.serialize_field(column, &self.syntax_is_valid_syntax)?
+				}
+				"syntax.domain" => state.serialize_field(column, &self.syntax_domain)?,
+				"syntax.username" => state.serialize_field(column, &self.syntax_username)?,
+				"error" => state.serialize_field(column, &self.error)?,
+				"warnings" => state.serialize_field(column, &self.warnings)?,
+				"duration_ms" => state.serialize_field(column, &self.duration_ms)?,
+				_ => return Err(S::Error::custom(format!("unknown csv column {}", column))),
+			}
+		}
+		state.end()
+	}
+}
+
+/// Some columns of a csv record, see `JobResultCsvResponse::serialize_columns`.
+struct CsvColumns<'a>(&'a JobResultCsvResponse, &'a [&'static str]);
+
+impl Serialize for CsvColumns<'_> {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer,
+	{
+		self.0.serialize_columns(self.1, serializer)
+	}
 }
 
 /// Convert csv wrapper to csv response
@@ -148,6 +1069,9 @@ impl TryFrom<CsvWrapper> for JobResultCsvResponse {
 		let mut syntax_domain: String = String::default();
 		let mut syntax_username: String = String::default();
 		let mut error: Option<String> = None;
+		let mut warnings: String = String::default();
+		let mut duration_ms: Option<i64> = None;
+		let mut mx_records: Vec<String> = vec![];
 
 		let top_level = value
 			.0
@@ -162,6 +1086,23 @@ impl TryFrom<CsvWrapper> for JobResultCsvResponse {
 						.ok_or("is_reachable should be a string")?
 						.to_string()
 				}
+				"duration_ms" => duration_ms = val.as_i64(),
+				"warnings" if val.is_null() => {}
+				"warnings" => {
+					warnings = val
+						.as_array()
+						.ok_or("warnings should be an array")?
+						.iter()
+						.map(|warning| match warning {
+							serde_json::Value::String(warning) => warning.clone(),
+							warning => warning.to_string(),
+						})
+						.collect::<Vec<_>>()
+						.join(";")
+				}
+				// A stage that was skipped is serialized as `null`, in which
+				// case we keep the default values for all its fields.
+				"misc" | "mx" | "smtp" | "syntax" if val.is_null() => {}
 				"misc" => {
 					let misc_obj = val.as_object().ok_or("misc field should be an object")?;
 					for (key, val) in misc_obj.keys().zip(misc_obj.values()) {
@@ -188,6 +1129,19 @@ impl TryFrom<CsvWrapper> for JobResultCsvResponse {
 								mx_accepts_mail =
 									val.as_bool().ok_or("accepts_email should be a boolean")?
 							}
+							"records" => {
+								mx_records = val
+									.as_array()
+									.ok_or("records should be an array")?
+									.iter()
+									.map(|record| {
+										record
+											.as_str()
+											.map(str::to_string)
+											.ok_or("records should be strings")
+									})
+									.collect::<Result<_, _>>()?
+							}
 							_ => {}
 						}
 					}
@@ -265,265 +1219,2381 @@ impl TryFrom<CsvWrapper> for JobResultCsvResponse {
 			syntax_is_valid_syntax,
 			syntax_username,
 			error,
+			warnings,
+			duration_ms,
+			mx_records,
 		})
 	}
 }
 
 async fn job_result(
-	job_id: i32,
+	job_id: JobId,
 	req: JobResultRequest,
+	accept_charset: Option<String>,
 	conn_pool: Pool<Postgres>,
+	transformer: SharedTransformer,
+	download_limiter: Arc<JobDownloadLimiter>,
+	settings: Settings,
 	// ) -> Either<Result<impl warp::Reply, warp::Rejection>, Result<impl warp::Reply, warp::Rejection>> {
 ) -> Result<impl warp::Reply, warp::Rejection> {
+	let job_id = job_id.get();
+	check_param_conflicts(&req)?;
+	check_sample(req.sample)?;
+	let page_params = PageParams::from_request(&req, &settings.download_defaults)?;
+	if let Some(column) = &req.column {
+		check_txt_column(column)?;
+	}
+	let extra_fields = match &req.extra_fields {
+		Some(extra_fields) => parse_extra_fields(extra_fields)?,
+		None => vec![],
+	};
+	let columns = match &req.fields {
+		Some(JobResultFields::Columns(columns)) => csv_columns(columns)?,
+		_ => CSV_HEADER.to_vec(),
+	};
+
+	if let Some(token) = &req.token {
+		let key = settings.url_signing_key.as_deref().ok_or_else(|| {
+			ReacherResponseError::new(
+				http::StatusCode::FORBIDDEN,
+				"Signed download URLs are disabled",
+			)
+		})?;
+		let expires = req.expires.unwrap_or(0);
+		verify_download(key, job_id, expires, token, Utc::now().timestamp())?;
+	}
+
+	// Held until the response is built, the body being buffered, or until
+	// the end of a streamed body.
+	let permit = download_limiter
+		.try_acquire(job_id)
+		.map_err(|retry_after| {
+			ReacherResponseError::new(
+				http::StatusCode::TOO_MANY_REQUESTS,
+				format!("Too many concurrent downloads of job {}", job_id),
+			)
+			.with_retry_after(retry_after)
+		})?;
+
+	let mut timing = ServerTiming::default();
+	let start = Instant::now();
+	let job_progress_rec = job_download_progress(job_id, &conn_pool).await?;
+	timing.record("db_record", start);
+	if job_progress_rec.as_ref().is_some_and(|rec| rec.deleted) {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", job_id),
+		)
+		.into());
+	}
+	let is_expired = job_progress_rec.as_ref().is_some_and(|rec| {
+		expires_at(rec.created_at, settings.job_retention)
+			.is_some_and(|expires_at| expires_at <= Utc::now())
+	});
+	if is_expired {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::GONE,
+			"the job and its results have expired",
+		)
+		.into());
+	}
+
+	let metadata = match req.include_meta {
+		Some(true) => Some(result_metadata().ok_or_else(|| {
+			ReacherResponseError::new(
+				http::StatusCode::BAD_REQUEST,
+				"include_meta needs a result metadata table to be configured",
+			)
+		})?),
+		_ => None,
+	};
+
+	let include_provider = req.include_provider.unwrap_or(false);
+	let transformer: SharedTransformer = if include_provider {
+		Arc::new(WithProvider(transformer))
+	} else {
+		transformer
+	};
+
 	let format = req.format.unwrap_or(JobResultResponseFormat::Json);
-	match format {
-		JobResultResponseFormat::Json => {
-			let data = job_result_json(
+	let offset = page_params.offset();
+	if matches!(format, JobResultResponseFormat::Csv) && offset > MAX_CSV_OFFSET {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			format!(
+				"offset should be at most {} for csv downloads, page through deeper results with after=<X-Next-After header of the previous page> instead",
+				MAX_CSV_OFFSET
+			),
+		)
+		.into());
+	}
+	let (limit, throttled) =
+		throttle_limit(page_params.limit(), conn_pool.size(), conn_pool.num_idle());
+	let page_params = PageParams::new(limit, offset)?;
+	if throttled {
+		log::warn!(
+			target:"reacher",
+			"Database pool saturated, reducing limit to [limit={}] for [job_id={}]",
+			limit,
+			job_id,
+		);
+	}
+
+	let sort = req.sort.unwrap_or(JobResultSort::Id);
+	let filter = ResultFilter {
+		sample: req.sample,
+		after: req.after,
+		sort,
+		order: req.order.unwrap_or_default(),
+		include_retry_info: req.include_retry_info.unwrap_or(false),
+		stale_before: req
+			.include_freshness
+			.unwrap_or(false)
+			.then(|| stale_before(Utc::now(), settings.result_freshness)),
+		..ResultFilter::from_params(
+			req.reachable.as_ref(),
+			req.exclude_catch_all,
+			req.confidence,
+			req.include_domains.as_deref(),
+			req.exclude_domains.as_deref(),
+			req.latest_only,
+		)?
+	};
+	let start = Instant::now();
+	let total = job_result_count(job_id, &filter, &conn_pool).await?;
+	let meta = req.meta.unwrap_or(false);
+	let warnings = match &job_progress_rec {
+		Some(rec) if meta && rec.total_processed > 0 => {
+			job_processing_warnings(job_id, rec.total_processed, &conn_pool).await?
+		}
+		_ => vec![],
+	};
+	timing.record("db_aggregate", start);
+	let warnings_header = (!warnings.is_empty()).then(|| processing_warnings_header(&warnings));
+
+	if req.stream == Some(true) {
+		let body = json_array_stream(job_id, filter, metadata, transformer, conn_pool, permit);
+		// The pages of the stream aren't throttled, see `STREAM_PAGE_LIMIT`.
+		let response = download_headers(
+			http::Response::builder().header("Content-Type", "application/json"),
+			total,
+			&timing,
+			job_progress_rec,
+			warnings_header,
+			None,
+		);
+
+		return Ok(response
+			.body(body)
+			.expect("All header names and values are valid. qed."));
+	}
+
+	// The queries of the page are timed apart from the serialization.
+	let start = Instant::now();
+	let last_id;
+	let query_time;
+	let (data, content_type) = match format {
+		JobResultResponseFormat::Json | JobResultResponseFormat::JsonArray => {
+			let page = job_result_json(
 				job_id,
-				req.limit.unwrap_or(50),
-				req.offset.unwrap_or(0),
+				page_params,
+				&filter,
+				settings.max_response_bytes,
+				metadata.as_ref(),
+				transformer.as_ref(),
 				conn_pool,
 			)
 			.await?;
+			last_id = page.last_id;
+			query_time = page.query_time;
+			let data = page.rows;
+			let results = match req.shape.unwrap_or(JobResultShape::Array) {
+				JobResultShape::Array => serde_json::Value::Array(data),
+				JobResultShape::Map => serde_json::Value::Object(results_by_input(data)),
+			};
 
-			let reply =
-				serde_json::to_vec(&JobResultJsonResponse { results: data }).map_err(|e| {
-					log::error!(
-						target:"reacher",
-						"Failed to convert json results to string for [job_id={}] with [error={}]",
-						job_id,
-						e
-					);
+			let pretty = req.pretty.unwrap_or(false);
+			let serialized = match format {
+				JobResultResponseFormat::JsonArray => json_to_vec(&results, pretty),
+				_ => json_to_vec(
+					&JobResultJsonResponse {
+						results,
+						warnings: meta.then_some(warnings),
+					},
+					pretty,
+				),
+			};
+			let reply = serialized.map_err(|e| {
+				log::error!(
+					target:"reacher",
+					"Failed to convert json results to string for [job_id={}] with [error={}]",
+					job_id,
+					e
+				);
 
-					ReacherError::Json()
-				})?;
+				ReacherError::Json()
+			})?;
 
-			Ok(warp::reply::with_header(
-				reply,
-				"Content-Type",
-				"application/json",
-			))
+			(reply, "application/json")
 		}
 		JobResultResponseFormat::Csv => {
-			let data = job_result_csv(
+			let charset =
+				CsvCharset::from_accept_charset(accept_charset.as_deref()).ok_or_else(|| {
+					ReacherResponseError::new(
+						http::StatusCode::NOT_ACCEPTABLE,
+						"Only the utf-8 and iso-8859-1 charsets are supported",
+					)
+				})?;
+
+			let header = req.header.unwrap_or(true);
+			let quoting = req.quoting.unwrap_or_default();
+			let (data, count) = match req.fields.unwrap_or(JobResultFields::Default) {
+				JobResultFields::Default | JobResultFields::Columns(_) => {
+					let options = CsvOptions {
+						columns: &columns,
+						include_mx: req.include_mx.unwrap_or(false),
+						include_provider,
+						metadata: metadata.as_ref(),
+						extra_fields: &extra_fields,
+						quoting,
+					};
+					let page = job_result_csv(
+						job_id,
+						page_params,
+						&filter,
+						&options,
+						header,
+						transformer.as_ref(),
+						conn_pool,
+					)
+					.await?;
+					last_id = page.last_id;
+					query_time = page.query_time;
+					(page.rows, page.count)
+				}
+				JobResultFields::AllNested => {
+					let page = job_result_json(
+						job_id,
+						page_params,
+						&filter,
+						None,
+						metadata.as_ref(),
+						transformer.as_ref(),
+						conn_pool,
+					)
+					.await?;
+					last_id = page.last_id;
+					query_time = page.query_time;
+					let data = nested_csv(&page.rows, header, &settings.csv_number_format, quoting)
+						.map_err(|e| {
+							log::error!(
+								target:"reacher",
+								"Failed to convert results for [job_id={}] [limit={}] [offset={}] to nested csv with [error={}]",
+								job_id,
+								limit,
+								offset,
+								e
+							);
+
+							ReacherError::Csv()
+						})?;
+					(data, page.count)
+				}
+			};
+			let mut data = quoting.check(data)?;
+			if req.count_trailer.unwrap_or(false) {
+				data.extend_from_slice(format!("# total_rows: {}\n", count).as_bytes());
+			}
+
+			(charset.encode(data), charset.content_type())
+		}
+		JobResultResponseFormat::Txt => {
+			let page = job_result_json(
 				job_id,
-				req.limit.unwrap_or(5000),
-				req.offset.unwrap_or(0),
+				page_params,
+				&filter,
+				None,
+				None,
+				transformer.as_ref(),
 				conn_pool,
 			)
 			.await?;
+			last_id = page.last_id;
+			query_time = page.query_time;
+			let data = match &req.column {
+				Some(column) => column_txt(job_id, &page.rows, column)?,
+				None => inputs_txt(&page.rows),
+			};
 
-			Ok(warp::reply::with_header(data, "Content-Type", "text/csv"))
+			(data, "text/plain; charset=utf-8")
 		}
+	};
+
+	timing.add("db_results", query_time);
+	timing.add("serialize", start.elapsed().saturating_sub(query_time));
+
+	// The whole body is buffered, so its length is known upfront.
+	let mut response = download_headers(
+		http::Response::builder()
+			.header("Content-Type", content_type)
+			.header("Content-Length", data.len()),
+		total,
+		&timing,
+		job_progress_rec,
+		warnings_header,
+		throttled.then_some(limit),
+	);
+	// The cursor follows the id order.
+	if let Some(last_id) = last_id.filter(|_| filter.sort == JobResultSort::Id) {
+		response = response.header("X-Next-After", last_id);
+	}
+	if offset > 0 && offset >= total {
+		response = response
+			.header("X-Pagination-Overflow", "true")
+			.header("X-Pagination-Max-Offset", last_page_offset(total, limit));
+	}
+
+	Ok(response
+		.body(data.into())
+		.expect("All header names and values are valid. qed."))
+}
+
+/// Add the headers shared by the buffered and the streamed downloads:
+/// `X-Total-Count`, `Server-Timing`, the progress of the job, the processing
+/// warnings, and a `Warning` if the limit of the page was reduced to
+/// `throttled_limit` under load.
+fn download_headers(
+	mut response: http::response::Builder,
+	total: u64,
+	timing: &ServerTiming,
+	job_progress_rec: Option<JobDownloadProgress>,
+	warnings_header: Option<String>,
+	throttled_limit: Option<u64>,
+) -> http::response::Builder {
+	response = response
+		.header("X-Total-Count", total)
+		.header("Server-Timing", timing.header_value());
+	if let Some(rec) = job_progress_rec {
+		// Tells clients whether the results are complete.
+		let (total_processed, job_status) = job_progress(rec.total_processed, rec.total_records);
+		let job_status = match job_status {
+			ValidStatus::Draft => "draft",
+			ValidStatus::Pending => "pending",
+			ValidStatus::Running => "running",
+			ValidStatus::Completed => "completed",
+		};
+		response = response
+			.header("X-Job-Status", job_status)
+			.header("X-Total-Records", rec.total_records)
+			.header("X-Total-Processed", total_processed);
+	}
+	if let Some(warnings_header) = warnings_header {
+		response = response.header("X-Processing-Warnings", warnings_header);
+	}
+	if let Some(limit) = throttled_limit {
+		response = response.header(
+			"Warning",
+			format!(
+				"199 reacher \"Server under load, limit reduced to {}\"",
+				limit
+			),
+		);
 	}
+
+	response
 }
 
-async fn job_result_csv(
+/// Stream all the results of the job as a JSON array, page by page along
+/// the id cursor. As in the combined export, each page is only fetched once
+/// the client is ready to receive it, so that huge jobs are never buffered.
+/// Each page starts its index scan at the cursor, see `result_filter_sql`,
+/// so the stream reads the results of the job once overall.
+fn json_array_stream(
 	job_id: i32,
-	limit: u64,
-	offset: u64,
+	mut filter: ResultFilter,
+	metadata: Option<ResultMetadata>,
+	transformer: SharedTransformer,
 	conn_pool: Pool<Postgres>,
-) -> Result<Vec<u8>, warp::Rejection> {
-	let query = sqlx::query!(
-		r#"
-		SELECT result FROM email_results
-		WHERE job_id = $1
-		ORDER BY id
-		LIMIT $2 OFFSET $3
+	permit: JobDownloadPermit,
+) -> warp::hyper::Body {
+	let (mut sender, body) = warp::hyper::Body::channel();
+	tokio::spawn(async move {
+		// The download counts as running until the end of the stream.
+		let _permit = permit;
+		if sender.send_data("[".into()).await.is_err() {
+			return;
+		}
+
+		let mut first = true;
+		loop {
+			if poll_fn(|cx| sender.poll_ready(cx)).await.is_err() {
+				log::info!(
+					target:"reacher",
+					"Client disconnected from streamed download of [job_id={}]",
+					job_id
+				);
+				return;
+			}
+
+			let page_params = PageParams::new(STREAM_PAGE_LIMIT, 0)
+				.expect("STREAM_PAGE_LIMIT fits in an i64. qed.");
+			let page = match job_result_json(
+				job_id,
+				page_params,
+				&filter,
+				None,
+				metadata.as_ref(),
+				transformer.as_ref(),
+				conn_pool.clone(),
+			)
+			.await
+			{
+				Ok(page) => json_array_chunk(&page.rows, &mut first)
+					.map(|chunk| (chunk, page.count, page.last_id))
+					.map_err(|e| e.to_string()),
+				Err(e) => Err(format!("{:?}", e)),
+			};
+			let (chunk, count, last_id) = match page {
+				Ok(page) => page,
+				Err(e) => {
+					log::error!(
+						target:"reacher",
+						"Failed to stream results for [job_id={}] [after={:?}] with [error={}]",
+						job_id,
+						filter.after,
+						e
+					);
+					// Make the client see a truncated body.
+					sender.abort();
+					return;
+				}
+			};
+			if !chunk.is_empty() && sender.send_data(chunk.into()).await.is_err() {
+				return;
+			}
+
+			// A short page is the last one.
+			match last_id {
+				Some(last_id) if count as u64 == STREAM_PAGE_LIMIT => filter.after = Some(last_id),
+				_ => break,
+			}
+		}
+
+		let _ = sender.send_data("]".into()).await;
+	});
+
+	body
+}
+
+/// Serialize the rows of a streamed JSON array, each preceded by a comma but
+/// the very first one of the array.
+fn json_array_chunk(rows: &[serde_json::Value], first: &mut bool) -> serde_json::Result<Vec<u8>> {
+	let mut chunk = Vec::new();
+	for row in rows {
+		if !*first {
+			chunk.push(b',');
+		}
+		*first = false;
+		serde_json::to_writer(&mut chunk, row)?;
+	}
+
+	Ok(chunk)
+}
+
+/// The most frequent failure reasons among all the results of the job, most
+/// frequent first.
+async fn job_processing_warnings(
+	job_id: i32,
+	total_processed: i64,
+	conn_pool: &Pool<Postgres>,
+) -> Result<Vec<ProcessingWarning>, warp::Rejection> {
+	let recs = sqlx::query!(
+		r#"
+		SELECT e.stage, concat_ws(': ', e.error ->> 'type', e.error ->> 'message') AS reason,
+			COUNT(*) AS count
+		FROM email_results r,
+			LATERAL (VALUES
+				('smtp', r.result -> 'smtp' -> 'error'),
+				('mx', r.result -> 'mx' -> 'error')
+			) AS e(stage, error)
+		WHERE r.job_id = $1 AND jsonb_typeof(e.error) = 'object'
+		GROUP BY 1, 2
+		ORDER BY 3 DESC, 1, 2
+		LIMIT $2
+		"#,
+		job_id,
+		MAX_PROCESSING_WARNINGS
+	)
+	.fetch_all(conn_pool)
+	.timed("job_processing_warnings", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get processing warnings for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+
+	Ok(recs
+		.into_iter()
+		.map(|rec| {
+			let count = rec.count.unwrap_or(0);
+			ProcessingWarning {
+				stage: rec.stage.unwrap_or_default(),
+				reason: rec.reason.unwrap_or_default(),
+				count,
+				percent: (count as f64 * 100.0 / total_processed as f64).round() as u32,
+			}
+		})
+		.collect())
+}
+
+/// Value of the `X-Processing-Warnings` header, e.g. `80% smtp: TimeoutError:
+/// future has timed out; 5% mx: ...`. Characters which aren't allowed in a
+/// header are replaced.
+fn processing_warnings_header(warnings: &[ProcessingWarning]) -> String {
+	warnings
+		.iter()
+		.map(|warning| {
+			let reason: String = warning
+				.reason
+				.chars()
+				.take(MAX_PROCESSING_WARNING_LEN)
+				.map(|c| {
+					if c == ' ' || (c.is_ascii_graphic() && c != ';') {
+						c
+					} else {
+						'?'
+					}
+				})
+				.collect();
+			format!("{}% {}: {}", warning.percent, warning.stage, reason)
+		})
+		.collect::<Vec<_>>()
+		.join("; ")
+}
+
+/// Key results by their `input` field. Results are ordered by id, so later
+/// duplicates overwrite earlier ones.
+fn results_by_input(results: Vec<serde_json::Value>) -> serde_json::Map<String, serde_json::Value> {
+	results
+		.into_iter()
+		.filter_map(|result| {
+			let input = result.get("input")?.as_str()?.to_string();
+			Some((input, result))
+		})
+		.collect()
+}
+
+/// Some ingestion bugs stored results as a JSON-encoded string instead of
+/// an object. Decode such a string a second time, and add the row's
+/// `duration_ms` like the queries do for objects. Any other value is left
+/// as it is.
+fn recover_double_encoded(
+	job_id: i32,
+	result: serde_json::Value,
+	duration_ms: Option<i32>,
+) -> serde_json::Value {
+	let recovered = match &result {
+		serde_json::Value::String(s) => serde_json::from_str::<serde_json::Value>(s)
+			.ok()
+			.filter(|value| value.is_object()),
+		_ => None,
+	};
+	match recovered {
+		Some(mut value) => {
+			log::warn!(
+				target:"reacher",
+				"Recovered a double-encoded result of [job_id={}]",
+				job_id
+			);
+			value["duration_ms"] = serde_json::json!(duration_ms);
+			value
+		}
+		None => result,
+	}
+}
+
+/// Flatten the scalar leaves of `value` into `columns`, keyed by their
+/// dotted path. Arrays are joined by semicolons, and nulls are left empty.
+fn flatten_json(
+	prefix: &str,
+	value: &serde_json::Value,
+	number_format: &CsvNumberFormat,
+	columns: &mut BTreeMap<String, String>,
+) {
+	let key = |name: &str| {
+		if prefix.is_empty() {
+			name.to_string()
+		} else {
+			format!("{}.{}", prefix, name)
+		}
+	};
+
+	match value {
+		serde_json::Value::Object(map) => {
+			for (name, value) in map {
+				flatten_json(&key(name), value, number_format, columns);
+			}
+		}
+		serde_json::Value::Array(values) => {
+			let joined = values
+				.iter()
+				.map(|value| match value {
+					serde_json::Value::String(s) => s.clone(),
+					serde_json::Value::Number(n) => number_format.format(n),
+					value => value.to_string(),
+				})
+				.collect::<Vec<_>>()
+				.join(";");
+			columns.insert(prefix.to_string(), joined);
+		}
+		serde_json::Value::String(s) => {
+			columns.insert(prefix.to_string(), s.clone());
+		}
+		serde_json::Value::Null => {
+			columns.insert(prefix.to_string(), String::new());
+		}
+		serde_json::Value::Number(n) => {
+			columns.insert(prefix.to_string(), number_format.format(n));
+		}
+		value => {
+			columns.insert(prefix.to_string(), value.to_string());
+		}
+	}
+}
+
+/// Write the results as a CSV with a column for every scalar leaf found in
+/// any of the rows. Leaves missing from a row are left empty.
+fn nested_csv(
+	rows: &[serde_json::Value],
+	header: bool,
+	number_format: &CsvNumberFormat,
+	quoting: CsvQuoting,
+) -> Result<Vec<u8>, String> {
+	let flattened: Vec<BTreeMap<String, String>> = rows
+		.iter()
+		.map(|row| {
+			let mut columns = BTreeMap::new();
+			flatten_json("", row, number_format, &mut columns);
+			columns
+		})
+		.collect();
+	let keys: BTreeSet<&String> = flattened
+		.iter()
+		.flat_map(|columns| columns.keys())
+		.collect();
+
+	let mut wtr = quoting.writer();
+	if header {
+		wtr.write_record(&keys).map_err(|e| e.to_string())?;
+	}
+	for columns in &flattened {
+		wtr.write_record(
+			keys.iter()
+				.map(|key| columns.get(*key).map(String::as_str).unwrap_or_default()),
+		)
+		.map_err(|e| e.to_string())?;
+	}
+
+	wtr.into_inner().map_err(|e| e.to_string())
+}
+
+/// Write the `input` of each result on its own line.
+pub(super) fn inputs_txt(rows: &[serde_json::Value]) -> Vec<u8> {
+	let mut data = Vec::new();
+	for input in rows.iter().filter_map(|row| row.get("input")?.as_str()) {
+		data.extend_from_slice(input.as_bytes());
+		data.push(b'\n');
+	}
+
+	data
+}
+
+/// Reject the download params which can't be used together, naming the
+/// conflict, rather than ignoring one of them. It runs before any query.
+fn check_param_conflicts(req: &JobResultRequest) -> Result<(), ReacherResponseError> {
+	let format = req
+		.format
+		.as_ref()
+		.unwrap_or(&JobResultResponseFormat::Json);
+	let is_csv = matches!(format, JobResultResponseFormat::Csv);
+	let is_json = matches!(
+		format,
+		JobResultResponseFormat::Json | JobResultResponseFormat::JsonArray
+	);
+	let conflicts = [
+		(
+			req.after.is_some() && req.offset.is_some(),
+			"after can't be used with offset, page with one or the other",
+		),
+		(
+			req.after.is_some() && req.sort == Some(JobResultSort::Ordinal),
+			"after can't be used with sort=ordinal, page with offset instead",
+		),
+		(
+			req.include_domains.is_some() && req.exclude_domains.is_some(),
+			"include_domains and exclude_domains can't be used together",
+		),
+		(
+			req.column.is_some() && !matches!(format, JobResultResponseFormat::Txt),
+			"column is only supported by the txt format",
+		),
+		(
+			req.extra_fields.is_some()
+				&& (!is_csv || req.fields == Some(JobResultFields::AllNested)),
+			"extra_fields is only supported by the csv format, with the default fields",
+		),
+		(
+			!is_csv && (req.fields.is_some() || req.quoting.is_some()),
+			"fields and quoting are only supported by the csv format",
+		),
+		(
+			!is_csv && req.count_trailer.is_some(),
+			"count_trailer is only supported by the csv format",
+		),
+		(
+			matches!(format, JobResultResponseFormat::Txt) && req.include_provider.is_some(),
+			"include_provider isn't supported by the txt format",
+		),
+		(
+			matches!(format, JobResultResponseFormat::Txt) && req.include_retry_info.is_some(),
+			"include_retry_info isn't supported by the txt format",
+		),
+		(
+			matches!(format, JobResultResponseFormat::Txt) && req.include_freshness.is_some(),
+			"include_freshness isn't supported by the txt format",
+		),
+		(
+			!is_json && (req.shape.is_some() || req.pretty.is_some()),
+			"shape and pretty are only supported by the json formats",
+		),
+		(
+			req.stream.is_some() && !matches!(format, JobResultResponseFormat::JsonArray),
+			"stream is only supported by the json_array format",
+		),
+		(
+			req.stream == Some(true)
+				&& (req.limit.is_some()
+					|| req.offset.is_some()
+					|| req.sort == Some(JobResultSort::Ordinal)
+					|| req.shape.is_some()
+					|| req.pretty.is_some()),
+			"stream can't be used with limit, offset, sort=ordinal, shape or pretty",
+		),
+	];
+
+	match conflicts.iter().find(|(conflict, _)| *conflict) {
+		Some((_, message)) => Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			*message,
+		)),
+		None => Ok(()),
+	}
+}
+
+/// Reject a txt `column` that isn't one of `CSV_HEADER`.
+fn check_txt_column(column: &str) -> Result<(), ReacherResponseError> {
+	csv_column(column).map(|_| ())
+}
+
+/// The `CSV_HEADER` column with this name.
+fn csv_column(column: &str) -> Result<&'static str, ReacherResponseError> {
+	CSV_HEADER
+		.iter()
+		.find(|name| **name == column)
+		.copied()
+		.ok_or_else(|| {
+			ReacherResponseError::new(
+				http::StatusCode::BAD_REQUEST,
+				format!(
+					"unknown column {}, expected one of {}",
+					column,
+					CSV_HEADER.join(", ")
+				),
+			)
+		})
+}
+
+/// Parse the columns of a csv download with a list of `fields`, keeping
+/// their order.
+fn csv_columns(fields: &[String]) -> Result<Vec<&'static str>, ReacherResponseError> {
+	let mut columns = Vec::with_capacity(fields.len());
+	for field in fields {
+		let column = csv_column(field)?;
+		if columns.contains(&column) {
+			return Err(ReacherResponseError::new(
+				http::StatusCode::BAD_REQUEST,
+				format!("column {} is requested more than once", column),
+			));
+		}
+		columns.push(column);
+	}
+
+	Ok(columns)
+}
+
+/// A JSON value as a csv field: strings without their quotes, and nothing
+/// for null.
+fn csv_cell(value: &serde_json::Value) -> String {
+	match value {
+		serde_json::Value::String(s) => s.clone(),
+		serde_json::Value::Null => String::new(),
+		value => value.to_string(),
+	}
+}
+
+/// Parse the `extra_fields` of a csv download.
+fn parse_extra_fields(extra_fields: &str) -> Result<Vec<JsonPath>, ReacherResponseError> {
+	let exprs: Vec<&str> = extra_fields.split(',').collect();
+	if exprs.len() > MAX_EXTRA_FIELDS {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			format!(
+				"extra_fields should have at most {} paths",
+				MAX_EXTRA_FIELDS
+			),
+		));
+	}
+
+	exprs
+		.into_iter()
+		.map(|expr| {
+			JsonPath::parse(expr).ok_or_else(|| {
+				ReacherResponseError::new(
+					http::StatusCode::BAD_REQUEST,
+					format!("invalid extra_fields path {}", expr),
+				)
+			})
+		})
+		.collect()
+}
+
+/// Write the value of `column`, one of `CSV_HEADER`, of each result on its
+/// own line, formatted as in the csv download. Empty values are left out.
+fn column_txt(
+	job_id: i32,
+	rows: &[serde_json::Value],
+	column: &str,
+) -> Result<Vec<u8>, ReacherError> {
+	let mut data = Vec::new();
+	for row in rows {
+		let result_csv = JobResultCsvResponse::try_from(CsvWrapper(row.clone())).map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to convert json to csv output struct for [job_id={}] with [error={}]",
+				job_id,
+				e
+			);
+
+			ReacherError::Csv()
+		})?;
+		// Keyed by the csv columns.
+		let value = serde_json::to_value(&result_csv)
+			.expect("Serializing to JSON doesn't fail. qed.")
+			.get(column)
+			.cloned();
+		let value = match value {
+			None | Some(serde_json::Value::Null) => continue,
+			Some(serde_json::Value::String(s)) if s.is_empty() => continue,
+			Some(serde_json::Value::String(s)) => s,
+			Some(value) => value.to_string(),
+		};
+		data.extend_from_slice(value.as_bytes());
+		data.push(b'\n');
+	}
+
+	Ok(data)
+}
+
+/// Reject a `sample` that isn't a fraction.
+pub(super) fn check_sample(sample: Option<f64>) -> Result<(), ReacherResponseError> {
+	match sample {
+		Some(sample) if !(sample > 0.0 && sample <= 1.0) => Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			"sample should be a fraction between 0 and 1",
+		)),
+		_ => Ok(()),
+	}
+}
+
+/// Serialized `is_reachable` value, as stored in the results.
+pub(super) fn reachable_str(reachable: &Reachable) -> String {
+	serde_json::to_value(reachable)
+		.ok()
+		.and_then(|value| value.as_str().map(String::from))
+		.expect("Reachable serializes to a string. qed.")
+}
+
+fn json_to_vec<T: Serialize>(value: &T, pretty: bool) -> serde_json::Result<Vec<u8>> {
+	if pretty {
+		serde_json::to_vec_pretty(value)
+	} else {
+		serde_json::to_vec(value)
+	}
+}
+
+/// Number of processed records and status of a job. The job record and the
+/// results are read by separate queries, so results landing in between can
+/// make the count exceed `total_records`: it's clamped to keep the status
+/// consistent.
+fn job_progress(total_processed: i64, total_records: i32) -> (i32, ValidStatus) {
+	let total_processed = total_processed.clamp(0, total_records.max(0).into()) as i32;
+	let job_status = if total_processed < total_records {
+		ValidStatus::Running
+	} else {
+		ValidStatus::Completed
+	};
+
+	(total_processed, job_status)
+}
+
+/// Reduce the download limit when all the connections of a full pool are
+/// in use, to shed load. Returns the effective limit, and whether it was
+/// reduced.
+fn throttle_limit(limit: u64, pool_size: u32, num_idle: usize) -> (u64, bool) {
+	if limit > 0 && pool_size >= DB_MAX_CONNECTIONS && num_idle == 0 {
+		((limit / THROTTLE_LIMIT_DIVISOR).max(1), true)
+	} else {
+		(limit, false)
+	}
+}
+
+/// Offset of the last page of `total` results, for pages of `limit` results.
+fn last_page_offset(total: u64, limit: u64) -> u64 {
+	if total == 0 || limit == 0 {
+		0
+	} else {
+		(total - 1) / limit * limit
+	}
+}
+
+/// Job record and number of results, read by downloads to check the expiry
+/// and report the progress of the job.
+struct JobDownloadProgress {
+	created_at: DateTime<Utc>,
+	total_records: i32,
+	total_processed: i64,
+	/// Whether the job is soft-deleted, see `delete::JobDeleteMode`.
+	deleted: bool,
+}
+
+/// Progress of the job, `None` if there's no such job.
+async fn job_download_progress(
+	job_id: i32,
+	conn_pool: &Pool<Postgres>,
+) -> Result<Option<JobDownloadProgress>, warp::Rejection> {
+	let rec = sqlx::query!(
+		r#"
+		SELECT
+			created_at,
+			total_records,
+			processed_count,
+			deleted_at IS NOT NULL AS "deleted!"
+		FROM bulk_jobs
+		WHERE id = $1
+		"#,
+		job_id
+	)
+	.fetch_optional(conn_pool)
+	.timed("job_download_progress", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get job record for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+
+	Ok(rec.map(|rec| JobDownloadProgress {
+		created_at: rec.created_at,
+		total_records: rec.total_records,
+		total_processed: rec.processed_count.into(),
+		deleted: rec.deleted,
+	}))
+}
+
+/// Conditions on the `email_results r` of the job selected by the filter,
+/// with the parameters bound by `bind_result_filter`. The variants of
+/// `latest_only` and `order` are spelled out rather than switched on
+/// parameters, so that the planner walks the `(job_id, id)` index from the
+/// cursor.
+fn result_filter_sql(filter: &ResultFilter) -> String {
+	let cursor = match filter.order {
+		JobResultOrder::Asc => "r.id > $2",
+		JobResultOrder::Desc => "r.id < $2",
+	};
+	// With latest_only, keep the most recent result of each input. A
+	// correlated max() is looked up in the `(job_id, input, id)` index
+	// whatever the estimated size of the job. Double-encoded results have
+	// no input to compare, they're all kept.
+	let latest_only = if filter.latest_only {
+		r#"AND (r.result ->> 'input' IS NULL OR r.id = (
+			SELECT max(n.id) FROM email_results n
+			WHERE n.job_id = r.job_id AND n.result ->> 'input' = r.result ->> 'input'
+		))"#
+	} else {
+		""
+	};
+
+	// Hashes are spread over the int4 range, scaled down to [0, 1).
+	let sample = if filter.sample_by_id {
+		"(hashint4(r.id)::float8 + 2147483648) / 4294967296"
+	} else {
+		"random()"
+	};
+
+	format!(
+		r#"r.job_id = $1 AND {} {}
+		AND ($8::float8 IS NULL OR {} < $8)
+		AND ($3::text IS NULL OR normalize_reachable(r.result ->> 'is_reachable') = $3)
+		AND NOT ($4 AND COALESCE(r.result -> 'smtp' ->> 'is_catch_all', 'false') = 'true')
+		AND ($5::text[] IS NULL OR lower(r.result -> 'syntax' ->> 'domain') = ANY($5))
+		AND ($6::text[] IS NULL OR COALESCE(lower(r.result -> 'syntax' ->> 'domain'), '') <> ALL($6))
+		AND ($7::text[] IS NULL OR normalize_reachable(r.result ->> 'is_reachable') = ANY($7))"#,
+		cursor, latest_only, sample
+	)
+}
+
+/// Bind the parameters of `result_filter_sql`, sampling the results with
+/// `sample`.
+fn bind_result_filter<'q>(
+	query: Query<'q, Postgres, PgArguments>,
+	job_id: i32,
+	filter: &ResultFilter,
+	sample: Option<f64>,
+) -> Query<'q, Postgres, PgArguments> {
+	let cursor = filter.after.unwrap_or(match filter.order {
+		JobResultOrder::Asc => 0,
+		JobResultOrder::Desc => i32::MAX,
+	});
+
+	query
+		.bind(job_id)
+		.bind(cursor)
+		.bind(filter.reachable.clone())
+		.bind(filter.exclude_catch_all)
+		.bind(filter.include_domains.clone())
+		.bind(filter.exclude_domains.clone())
+		.bind(filter.confidence_reachable.clone())
+		.bind(sample)
+}
+
+/// Query of a page of results selected by the filter, returning their `id`,
+/// `duration_ms` and `result`. Its parameters are bound by
+/// `bind_result_page`.
+fn result_page_sql(filter: &ResultFilter) -> String {
+	// Double-encoded results are decoded in recover_double_encoded.
+	let result = r#"CASE WHEN jsonb_typeof(r.result) = 'string' THEN r.result ELSE r.result
+		|| jsonb_strip_nulls(jsonb_build_object('is_reachable', normalize_reachable(r.result ->> 'is_reachable')))
+		|| jsonb_build_object('duration_ms', r.duration_ms)
+		|| CASE WHEN $11 THEN jsonb_build_object('retry_count', r.retry_count, 'last_error', r.last_error)
+			ELSE '{}' END
+		|| CASE WHEN $12::timestamptz IS NOT NULL THEN jsonb_build_object('stale', r.processed_at < $12)
+			ELSE '{}' END END"#;
+	// Results without an ordinal come last in both directions.
+	let order = match (filter.sort, filter.order) {
+		(JobResultSort::Id, JobResultOrder::Asc) => "r.id",
+		(JobResultSort::Id, JobResultOrder::Desc) => "r.id DESC",
+		(JobResultSort::Ordinal, JobResultOrder::Asc) => "r.ordinal, r.id",
+		(JobResultSort::Ordinal, JobResultOrder::Desc) => "r.ordinal DESC NULLS LAST, r.id DESC",
+	};
+
+	format!(
+		r#"
+		SELECT {} AS result, r.id, r.duration_ms
+		FROM email_results r
+		WHERE {}
+		ORDER BY {}
+		LIMIT $9 OFFSET $10
+		"#,
+		result,
+		result_filter_sql(filter),
+		order
+	)
+}
+
+/// Bind the parameters of `result_page_sql`.
+fn bind_result_page<'q>(
+	query: Query<'q, Postgres, PgArguments>,
+	job_id: i32,
+	page: PageParams,
+	filter: &ResultFilter,
+) -> Query<'q, Postgres, PgArguments> {
+	bind_result_filter(query, job_id, filter, filter.sample)
+		.bind(page.limit)
+		.bind(page.offset)
+		.bind(filter.include_retry_info)
+		.bind(filter.stale_before)
+}
+
+/// Number of results of the job, ignoring random sampling, as it would pick
+/// other results than the page query.
+pub(super) async fn job_result_count(
+	job_id: i32,
+	filter: &ResultFilter,
+	conn_pool: &Pool<Postgres>,
+) -> Result<u64, warp::Rejection> {
+	let sql = format!(
+		"SELECT COUNT(*) FROM email_results r WHERE {}",
+		result_filter_sql(filter)
+	);
+	let sample = filter.sample.filter(|_| filter.sample_by_id);
+	let rec = bind_result_filter(sqlx::query(&sql), job_id, filter, sample)
+		.fetch_one(conn_pool)
+		.timed("job_result_count", job_id)
+		.await
+		.map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to count results for [job_id={}] with [error={}]",
+				job_id,
+				e
+			);
+
+			ReacherError::from(e)
+		})?;
+
+	Ok(rec.get::<i64, _>(0) as u64)
+}
+
+pub(super) async fn job_result_csv(
+	job_id: i32,
+	page: PageParams,
+	filter: &ResultFilter,
+	options: &CsvOptions<'_>,
+	header: bool,
+	transformer: &dyn ResultTransformer,
+	conn_pool: Pool<Postgres>,
+) -> Result<ResultPage<Vec<u8>>, warp::Rejection> {
+	let sql = result_page_sql(filter);
+	let query = bind_result_page(sqlx::query(&sql), job_id, page, filter);
+
+	let mut wtr = options.quoting.writer();
+	if header {
+		let meta_columns = options
+			.metadata
+			.map(ResultMetadata::columns)
+			.unwrap_or_default()
+			.iter()
+			.map(|column| format!("meta.{}", column));
+		let header = options
+			.columns
+			.iter()
+			.map(|column| column.to_string())
+			.chain(
+				options
+					.include_mx
+					.then(|| CSV_MX_RECORDS_COLUMN.to_string()),
+			)
+			.chain(
+				options
+					.include_provider
+					.then(|| CSV_PROVIDER_COLUMN.to_string()),
+			)
+			.chain(
+				filter
+					.include_retry_info
+					.then_some(CSV_RETRY_INFO_COLUMNS)
+					.into_iter()
+					.flatten()
+					.map(|column| column.to_string()),
+			)
+			.chain(filter.stale_before.map(|_| CSV_STALE_COLUMN.to_string()))
+			.chain(meta_columns)
+			.chain(options.extra_fields.iter().map(JsonPath::to_string));
+		wtr.write_record(header).map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to write csv header for [job_id={}] with [error={}]",
+				job_id,
+				e
+			);
+
+			ReacherError::Csv()
+		})?;
+	}
+
+	// A limit of 0 only asks for the headers, skip the query.
+	let start = Instant::now();
+	let rows = if page.limit == 0 {
+		vec![]
+	} else {
+		conn_pool
+			.fetch_all(query)
+			.timed("job_result_csv", job_id)
+			.await
+			.map_err(|e| {
+				log::error!(
+					target:"reacher",
+					"Failed to get results for [job_id={}] with [error={}]",
+					job_id,
+					e
+				);
+
+				ReacherError::from(e)
+			})?
+	};
+
+	let mut query_time = start.elapsed();
+	let mut results: Vec<serde_json::Value> = rows
+		.iter()
+		.map(|row| recover_double_encoded(job_id, row.get("result"), row.get("duration_ms")))
+		.collect();
+	if let Some(metadata) = options.metadata {
+		let start = Instant::now();
+		attach_result_metadata(&mut results, metadata, &conn_pool)
+			.await
+			.map_err(|e| {
+				log::error!(
+					target:"reacher",
+					"Failed to get result metadata for [job_id={}] with [error={}]",
+					job_id,
+					e
+				);
+
+				ReacherError::from(e)
+			})?;
+		query_time += start.elapsed();
+	}
+
+	for json_value in results.iter().map(|result| transformer.transform(result)) {
+		let meta_values: Vec<String> = options
+			.metadata
+			.map(ResultMetadata::columns)
+			.unwrap_or_default()
+			.iter()
+			.map(|column| csv_cell(&json_value["meta"][column]))
+			.collect();
+		let provider_value = options
+			.include_provider
+			.then(|| csv_cell(&json_value["provider"]));
+		let retry_values: Vec<String> = filter
+			.include_retry_info
+			.then_some(CSV_RETRY_INFO_COLUMNS)
+			.into_iter()
+			.flatten()
+			.map(|column| csv_cell(&json_value[column]))
+			.collect();
+		let stale_value = filter
+			.stale_before
+			.map(|_| csv_cell(&json_value[CSV_STALE_COLUMN]));
+		let extra_values: Vec<String> = options
+			.extra_fields
+			.iter()
+			.map(|path| path.get(&json_value).map(csv_cell).unwrap_or_default())
+			.collect();
+		let result_csv: JobResultCsvResponse = CsvWrapper(json_value).try_into().map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to convert json to csv output struct for [job_id={}] [limit={}] [offset={}] to csv with [error={}]",
+				job_id,
+				page.limit,
+				page.offset,
+				e
+			);
+
+			ReacherError::Csv()
+		})?;
+		let extra_columns: Vec<String> = options
+			.include_mx
+			.then(|| result_csv.mx_records.join(";"))
+			.into_iter()
+			.chain(provider_value)
+			.chain(retry_values)
+			.chain(stale_value)
+			.chain(meta_values)
+			.chain(extra_values)
+			.collect();
+		wtr.serialize((CsvColumns(&result_csv, options.columns), extra_columns))
+			.map_err(|e| {
+				log::error!(
+					target:"reacher",
+					"Failed to serialize result for [job_id={}] [limit={}] [offset={}] to csv with [error={}]",
+					job_id,
+					page.limit,
+					page.offset,
+					e
+				);
+
+				ReacherError::Csv()
+			})?;
+	}
+
+	let data = wtr.into_inner().map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to convert results for [job_id={}] [limit={}] [offset={}] to csv with [error={}]",
+			job_id,
+			page.limit,
+			page.offset,
+			e
+		);
+
+		ReacherError::Csv()
+	})?;
+
+	Ok(ResultPage {
+		rows: data,
+		last_id: rows.last().map(|row| row.get("id")),
+		count: rows.len(),
+		query_time,
+	})
+}
+
+pub(super) async fn job_result_json(
+	job_id: i32,
+	page: PageParams,
+	filter: &ResultFilter,
+	max_bytes: Option<usize>,
+	metadata: Option<&ResultMetadata>,
+	transformer: &dyn ResultTransformer,
+	conn_pool: Pool<Postgres>,
+) -> Result<ResultPage<Vec<serde_json::Value>>, warp::Rejection> {
+	// A limit of 0 only asks for the headers, skip the query.
+	if page.limit == 0 {
+		return Ok(ResultPage {
+			rows: vec![],
+			last_id: None,
+			count: 0,
+			query_time: Duration::ZERO,
+		});
+	}
+
+	let sql = result_page_sql(filter);
+	let query = bind_result_page(sqlx::query(&sql), job_id, page, filter);
+
+	let start = Instant::now();
+	let pg_rows = conn_pool
+		.fetch_all(query)
+		.timed("job_result_json", job_id)
+		.await
+		.map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to get results for [job_id={}] [limit={}] [offset={}] with [error={}]",
+				job_id,
+				page.limit,
+				page.offset,
+				e
+			);
+
+			ReacherError::from(e)
+		})?;
+	let mut query_time = start.elapsed();
+	let mut results: Vec<serde_json::Value> = pg_rows
+		.iter()
+		.map(|row| recover_double_encoded(job_id, row.get("result"), row.get("duration_ms")))
+		.collect();
+	if let Some(metadata) = metadata {
+		let start = Instant::now();
+		attach_result_metadata(&mut results, metadata, &conn_pool)
+			.await
+			.map_err(|e| {
+				log::error!(
+					target:"reacher",
+					"Failed to get result metadata for [job_id={}] with [error={}]",
+					job_id,
+					e
+				);
+
+				ReacherError::from(e)
+			})?;
+		query_time += start.elapsed();
+	}
+	let rows: Vec<serde_json::Value> = results
+		.iter()
+		.map(|result| transformer.transform(result))
+		.collect();
+
+	if let Some(max_bytes) = max_bytes {
+		// The enclosing brackets and the separators.
+		let mut size = ByteCounter(rows.len() + 1);
+		for row in &rows {
+			serde_json::to_writer(&mut size, row).map_err(|_| ReacherError::Json())?;
+			if size.0 > max_bytes {
+				return Err(ReacherResponseError::new(
+					http::StatusCode::PAYLOAD_TOO_LARGE,
+					format!(
+						"the response would exceed {} bytes, use a smaller limit and paginate with offset",
+						max_bytes
+					),
+				)
+				.into());
+			}
+		}
+	}
+
+	Ok(ResultPage {
+		count: rows.len(),
+		rows,
+		last_id: pg_rows.last().map(|row| row.get("id")),
+		query_time,
+	})
+}
+
+/// Number of results of the job matching the filters, without fetching them.
+async fn job_count(
+	job_id: JobId,
+	req: JobCountRequest,
+	conn_pool: Pool<Postgres>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let job_id = job_id.get();
+	let filter = ResultFilter::from_params(
+		req.reachable.as_ref(),
+		req.exclude_catch_all,
+		req.confidence,
+		req.include_domains.as_deref(),
+		req.exclude_domains.as_deref(),
+		req.latest_only,
+	)?;
+
+	let progress = job_download_progress(job_id, &conn_pool).await?;
+	if progress.is_none_or(|rec| rec.deleted) {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", job_id),
+		)
+		.into());
+	}
+	let count = job_result_count(job_id, &filter, &conn_pool).await?;
+
+	Ok(warp::reply::json(&JobCountResponse { count }))
+}
+
+async fn job_status(
+	job_id: JobId,
+	req: JobStatusRequest,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let with_distinct_domains = req.distinct_domains.unwrap_or(false);
+	let time_format = req.time_format.unwrap_or(TimeFormat::Rfc3339);
+	let mut timing = ServerTiming::default();
+	let mut status = fetch_job_status_timed(
+		job_id.get(),
+		with_distinct_domains,
+		conn_pool.clone(),
+		&status_cache,
+		&settings,
+		&mut timing,
+	)
+	.await?;
+
+	if req.include_freshness.unwrap_or(false) {
+		let start = Instant::now();
+		let stale_before = stale_before(Utc::now(), settings.result_freshness);
+		status.stale_count = Some(job_stale_count(job_id.get(), stale_before, &conn_pool).await?);
+		timing.record("db_stale", start);
+	}
+
+	let start = Instant::now();
+	let reply = warp::reply::json(&TimeFormatted(&status, time_format));
+	timing.record("serialize", start);
+
+	Ok(warp::reply::with_header(
+		reply,
+		"Server-Timing",
+		timing.header_value(),
+	))
+}
+
+/// Status of a job, from the cache if it's fresh enough.
+pub(super) async fn fetch_job_status(
+	job_id: i32,
+	with_distinct_domains: bool,
+	conn_pool: Pool<Postgres>,
+	status_cache: &JobStatusCache,
+	settings: &Settings,
+) -> Result<JobStatusResponseBody, warp::Rejection> {
+	fetch_job_status_timed(
+		job_id,
+		with_distinct_domains,
+		conn_pool,
+		status_cache,
+		settings,
+		&mut ServerTiming::default(),
+	)
+	.await
+}
+
+/// Same as `fetch_job_status`, recording the time spent reading the cache,
+/// the job record and aggregating its results.
+async fn fetch_job_status_timed(
+	job_id: i32,
+	with_distinct_domains: bool,
+	conn_pool: Pool<Postgres>,
+	status_cache: &JobStatusCache,
+	settings: &Settings,
+	timing: &mut ServerTiming,
+) -> Result<JobStatusResponseBody, warp::Rejection> {
+	let start = Instant::now();
+	let cached = status_cache.get(job_id, with_distinct_domains);
+	timing.record("cache", start);
+	if let Some(status) = cached {
+		return Ok(status);
+	}
+
+	let start = Instant::now();
+	let job_rec = sqlx::query!(
+		r#"
+		SELECT id, created_at, total_records, tags, priority, processed_count, draft, pending,
+			deleted_at IS NOT NULL AS "deleted!"
+		FROM bulk_jobs
+		WHERE id = $1
+		LIMIT 1
+		"#,
+		job_id
+	)
+	.fetch_one(&conn_pool)
+	.timed("job_record", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get job record for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+	timing.record("db_record", start);
+	if job_rec.deleted {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", job_id),
+		)
+		.into());
+	}
+
+	// The summary of a large job is precomputed, so that polling its status
+	// doesn't scan all of its results.
+	let start = Instant::now();
+	let (summary, summary_refreshed_at, summary_pending) = match settings.summary_min_records {
+		Some(min_records) if job_rec.total_records > min_records => {
+			match precomputed_job_summary(job_id, &conn_pool).await? {
+				Some(precomputed) => {
+					let mut summary = precomputed.summary;
+					if !with_distinct_domains {
+						summary.distinct_domains = None;
+					}
+					(summary, Some(precomputed.refreshed_at), false)
+				}
+				// Left to the background task, see `spawn_summary_task`.
+				None => (JobStatusSummaryResponseBody::default(), None, true),
+			}
+		}
+		_ => {
+			let (_, summary) = live_job_summary(job_id, with_distinct_domains, &conn_pool).await?;
+			(summary, None, false)
+		}
+	};
+
+	// Served by the (job_id, processed_at) index.
+	let latest_processed_at: Vec<DateTime<Utc>> = sqlx::query!(
+		r#"
+		SELECT processed_at FROM email_results
+		WHERE job_id = $1
+		ORDER BY processed_at DESC
+		LIMIT $2
+		"#,
+		job_id,
+		ETA_WINDOW_RESULTS
+	)
+	.fetch_all(&conn_pool)
+	.timed("job_last_processed_at", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get last processed time for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?
+	.into_iter()
+	.map(|rec| rec.processed_at)
+	.collect();
+	let last_processed_at = latest_processed_at.first().copied();
+	timing.record("db_aggregate", start);
+
+	// Read from the counter, rather than counting the results.
+	let (total_processed, job_status) =
+		job_progress(job_rec.processed_count.into(), job_rec.total_records);
+	let job_status = if job_rec.draft {
+		ValidStatus::Draft
+	} else if job_rec.pending {
+		ValidStatus::Pending
+	} else {
+		job_status
+	};
+
+	let status = JobStatusResponseBody {
+		job_id: job_rec.id,
+		created_at: job_rec.created_at,
+		expires_at: expires_at(job_rec.created_at, settings.job_retention),
+		total_records: job_rec.total_records,
+		total_processed,
+		last_processed_at,
+		eta_rolling: match job_status {
+			ValidStatus::Running => rolling_eta(
+				&latest_processed_at,
+				i64::from(job_rec.total_records - total_processed),
+			),
+			_ => None,
+		},
+		tags: job_rec.tags,
+		priority: job_rec.priority,
+		summary,
+		summary_refreshed_at,
+		summary_pending,
+		stale_count: None,
+		job_status,
+	};
+	status_cache.insert(job_id, with_distinct_domains, status.clone());
+
+	Ok(status)
+}
+
+/// Number of processed records of the job, and its summary, computed from
+/// all of its results.
+pub(super) async fn live_job_summary(
+	job_id: i32,
+	with_distinct_domains: bool,
+	conn_pool: &Pool<Postgres>,
+) -> Result<(i64, JobStatusSummaryResponseBody), warp::Rejection> {
+	let classification = smtp_error_classification();
+	let agg_info = sqlx::query!(
+		r#"
+		SELECT
+			COUNT(*) as total_processed,
+			COUNT(CASE WHEN normalize_reachable(result ->> 'is_reachable') = 'safe' THEN 1 END) as safe_count,
+			COUNT(CASE WHEN normalize_reachable(result ->> 'is_reachable') = 'risky' THEN 1 END) as risky_count,
+			COUNT(CASE WHEN normalize_reachable(result ->> 'is_reachable') = 'invalid' THEN 1 END) as invalid_count,
+			COUNT(CASE WHEN normalize_reachable(result ->> 'is_reachable') = 'unknown' THEN 1 END) as unknown_count,
+			AVG(duration_ms)::float8 as avg_duration_ms,
+			PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY duration_ms) as p95_duration_ms,
+			COUNT(CASE WHEN e.error ILIKE ANY($2) THEN 1 END) as transient_errors_count,
+			COUNT(CASE WHEN NOT e.error ILIKE ANY($2) AND e.error ILIKE ANY($3) THEN 1 END) as permanent_errors_count
+		FROM email_results,
+			LATERAL (SELECT concat_ws(': ',
+				result -> 'smtp' -> 'error' ->> 'type',
+				result -> 'smtp' -> 'error' ->> 'message',
+				result -> 'mx' -> 'error' ->> 'type',
+				result -> 'mx' -> 'error' ->> 'message'
+			) AS error) e
+		WHERE job_id = $1
 		"#,
 		job_id,
-		limit as i64,
-		offset as i64
-	);
-
-	let mut wtr = WriterBuilder::new().has_headers(true).from_writer(vec![]);
-
-	for json_value in conn_pool
-		.fetch_all(query)
-		.await
-		.map_err(|e| {
-			log::error!(
-				target:"reacher",
-				"Failed to get results for [job_id={}] with [error={}]",
-				job_id,
-				e
-			);
+		&classification.transient_patterns(),
+		&classification.permanent_patterns(),
+	)
+	.fetch_one(conn_pool)
+	.timed("job_aggregate", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher/v0/bulk/",
+			"Failed to get aggregate info for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
 
-			ReacherError::from(e)
-		})?
-		.iter()
-		.map(|row| row.get("result"))
-	{
-		let result_csv: JobResultCsvResponse = CsvWrapper(json_value).try_into().map_err(|e| {
-			log::error!(
-				target:"reacher",
-				"Failed to convert json to csv output struct for [job_id={}] [limit={}] [offset={}] to csv with [error={}]",
-				job_id,
-				limit,
-				offset,
-				e
-			);
+	let distinct_domains = if with_distinct_domains {
+		Some(job_distinct_domains(job_id, conn_pool).await?)
+	} else {
+		None
+	};
 
-			ReacherError::Csv()
-		})?;
-		wtr.serialize(result_csv).map_err(|e| {
-			log::error!(
-				target:"reacher",
-				"Failed to serialize result for [job_id={}] [limit={}] [offset={}] to csv with [error={}]",
-				job_id,
-				limit,
-				offset,
-				e
-			);
+	let summary = JobStatusSummaryResponseBody {
+		total_safe: agg_info.safe_count.unwrap() as i32,
+		total_risky: agg_info.risky_count.unwrap() as i32,
+		total_invalid: agg_info.invalid_count.unwrap() as i32,
+		total_unknown: agg_info.unknown_count.unwrap() as i32,
+		avg_duration_ms: agg_info.avg_duration_ms,
+		p95_duration_ms: agg_info.p95_duration_ms,
+		total_transient_errors: agg_info.transient_errors_count.unwrap() as i32,
+		total_permanent_errors: agg_info.permanent_errors_count.unwrap() as i32,
+		distinct_domains,
+	};
 
-			ReacherError::Csv()
-		})?;
-	}
+	Ok((agg_info.total_processed.unwrap(), summary))
+}
 
-	let data = wtr.into_inner().map_err(|e| {
+/// Number of stale results of the job, served by the (job_id, processed_at)
+/// index.
+async fn job_stale_count(
+	job_id: i32,
+	stale_before: DateTime<Utc>,
+	conn_pool: &Pool<Postgres>,
+) -> Result<i64, warp::Rejection> {
+	let rec = sqlx::query!(
+		r#"
+		SELECT COUNT(*) AS "count!" FROM email_results
+		WHERE job_id = $1 AND processed_at < $2
+		"#,
+		job_id,
+		stale_before
+	)
+	.fetch_one(conn_pool)
+	.timed("job_stale_count", job_id)
+	.await
+	.map_err(|e| {
 		log::error!(
-			target:"reacher",
-			"Failed to convert results for [job_id={}] [limit={}] [offset={}] to csv with [error={}]",
+			target:"reacher/v0/bulk/",
+			"Failed to count stale results for [job_id={}] with [error={}]",
 			job_id,
-			limit,
-			offset,
 			e
 		);
-
-		ReacherError::Csv()
+		ReacherError::from(e)
 	})?;
 
-	Ok(data)
+	Ok(rec.count)
 }
 
-async fn job_result_json(
+async fn job_distinct_domains(
 	job_id: i32,
-	limit: u64,
-	offset: u64,
-	conn_pool: Pool<Postgres>,
-) -> Result<Vec<serde_json::Value>, warp::Rejection> {
-	let query = sqlx::query!(
+	conn_pool: &Pool<Postgres>,
+) -> Result<i32, warp::Rejection> {
+	let rec = sqlx::query!(
 		r#"
-		SELECT result FROM email_results
+		SELECT COUNT(DISTINCT result -> 'syntax' ->> 'domain') as distinct_domains
+		FROM email_results
 		WHERE job_id = $1
-		ORDER BY id
-		LIMIT $2 OFFSET $3
 		"#,
-		job_id,
-		limit as i64,
-		offset as i64
-	);
-
-	let rows: Vec<serde_json::Value> = conn_pool
-		.fetch_all(query)
-		.await
-		.map_err(|e| {
-			log::error!(
-				target:"reacher",
-				"Failed to get results for [job_id={}] [limit={}] [offset={}] with [error={}]",
-				job_id,
-				limit,
-				offset,
-				e
-			);
+		job_id
+	)
+	.fetch_one(conn_pool)
+	.timed("job_distinct_domains", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to count distinct domains for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
 
-			ReacherError::from(e)
-		})?
-		.iter()
-		.map(|row| row.get("result"))
-		.collect();
+		ReacherError::from(e)
+	})?;
 
-	Ok(rows)
+	Ok(rec.distinct_domains.unwrap_or(0) as i32)
 }
 
-async fn job_status(
-	job_id: i32,
+/// Latest result of a single input of the job. The input comes percent-encoded
+/// from the URL path, where `+` is a literal plus sign.
+async fn job_input_result(
+	job_id: JobId,
+	input: String,
 	conn_pool: Pool<Postgres>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-	let job_rec = sqlx::query_as!(
-		JobRecord,
+	let job_id = job_id.get();
+	let input = percent_decode_str(&input).decode_utf8().map_err(|_| {
+		ReacherResponseError::new(http::StatusCode::BAD_REQUEST, "input is not valid UTF-8")
+	})?;
+	check_not_deleted(job_id, &conn_pool).await?;
+
+	// A number is the id of the result row rather than an input, for stable
+	// links to a result.
+	if let Ok(row_id) = input.parse::<i32>() {
+		return job_row_result(job_id, row_id, &conn_pool).await;
+	}
+
+	let rec = sqlx::query!(
 		r#"
-		SELECT id, created_at, total_records FROM bulk_jobs
-		WHERE id = $1
+		SELECT result
+			|| jsonb_strip_nulls(jsonb_build_object('is_reachable', normalize_reachable(result ->> 'is_reachable')))
+			|| jsonb_build_object('duration_ms', duration_ms) AS result
+		FROM email_results
+		WHERE job_id = $1 AND result ->> 'input' = $2
+		ORDER BY id DESC
 		LIMIT 1
 		"#,
-		job_id
+		job_id,
+		input.as_ref()
 	)
-	.fetch_one(&conn_pool)
+	.fetch_optional(&conn_pool)
+	.timed("job_input_result", job_id)
 	.await
 	.map_err(|e| {
 		log::error!(
 			target:"reacher",
-			"Failed to get job record for [job_id={}] with [error={}]",
+			"Failed to get result of [input={}] for [job_id={}] with [error={}]",
+			input,
 			job_id,
 			e
 		);
+
 		ReacherError::from(e)
 	})?;
 
-	let agg_info = sqlx::query!(
+	match rec.and_then(|rec| rec.result) {
+		Some(result) => Ok(warp::reply::json(&result)),
+		None => Err(ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("{} is not part of job {}", input, job_id),
+		)
+		.into()),
+	}
+}
+
+/// Result stored in the `email_results` row `row_id`, which should belong to
+/// the job.
+async fn job_row_result(
+	job_id: i32,
+	row_id: i32,
+	conn_pool: &Pool<Postgres>,
+) -> Result<warp::reply::Json, warp::Rejection> {
+	let rec = sqlx::query!(
 		r#"
-		SELECT
-			COUNT(*) as total_processed,
-			COUNT(CASE WHEN result ->> 'is_reachable' LIKE 'safe' THEN 1 END) as safe_count,
-			COUNT(CASE WHEN result ->> 'is_reachable' LIKE 'risky' THEN 1 END) as risky_count,
-			COUNT(CASE WHEN result ->> 'is_reachable' LIKE 'invalid' THEN 1 END) as invalid_count,
-			COUNT(CASE WHEN result ->> 'is_reachable' LIKE 'unknown' THEN 1 END) as unknown_count
+		SELECT result
+			|| jsonb_strip_nulls(jsonb_build_object('is_reachable', normalize_reachable(result ->> 'is_reachable')))
+			|| jsonb_build_object('duration_ms', duration_ms) AS result
 		FROM email_results
-		WHERE job_id = $1
+		WHERE id = $1 AND job_id = $2
 		"#,
+		row_id,
 		job_id
 	)
-	.fetch_one(&conn_pool)
+	.fetch_optional(conn_pool)
+	.timed("job_row_result", job_id)
 	.await
 	.map_err(|e| {
 		log::error!(
-			target:"reacher/v0/bulk/",
-			"Failed to get aggregate info for [job_id={}] with [error={}]",
+			target:"reacher",
+			"Failed to get result [row_id={}] for [job_id={}] with [error={}]",
+			row_id,
 			job_id,
 			e
 		);
+
 		ReacherError::from(e)
 	})?;
 
-	let job_status = if (agg_info.total_processed.unwrap() as i32) < job_rec.total_records {
-		ValidStatus::Running
-	} else {
-		ValidStatus::Completed
-	};
+	match rec.and_then(|rec| rec.result) {
+		Some(result) => Ok(warp::reply::json(&result)),
+		None => Err(ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("result {} is not part of job {}", row_id, job_id),
+		)
+		.into()),
+	}
+}
 
-	Ok(warp::reply::json(&JobStatusResponseBody {
-		job_id: job_rec.id,
-		created_at: job_rec.created_at,
-		total_records: job_rec.total_records,
-		total_processed: agg_info.total_processed.unwrap() as i32,
-		summary: JobStatusSummaryResponseBody {
-			total_safe: agg_info.safe_count.unwrap() as i32,
-			total_risky: agg_info.risky_count.unwrap() as i32,
-			total_invalid: agg_info.invalid_count.unwrap() as i32,
-			total_unknown: agg_info.unknown_count.unwrap() as i32,
-		},
-		job_status,
-	}))
+/// Format a timestamp as an HTTP-date, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`.
+fn to_http_date(date: DateTime<Utc>) -> String {
+	date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Whether a resource last modified at `last_modified` has changed since the
+/// `If-Modified-Since` header value. HTTP-dates only have a precision of one
+/// second, so sub-second changes are ignored. An unparseable header counts as
+/// modified.
+fn is_modified_since(last_modified: DateTime<Utc>, if_modified_since: &str) -> bool {
+	match DateTime::parse_from_rfc2822(if_modified_since) {
+		Ok(since) => last_modified.timestamp() > since.timestamp(),
+		Err(_) => true,
+	}
+}
+
+async fn job_list(
+	req: JobListRequest,
+	if_modified_since: Option<String>,
+	conn_pool: Pool<Postgres>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+	let limit = req.limit.unwrap_or(DEFAULT_JSON_LIMIT);
+	if limit > MAX_DOWNLOAD_LIMIT {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			format!("limit should be at most {}", MAX_DOWNLOAD_LIMIT),
+		)
+		.into());
+	}
+	let page_params = PageParams::new(limit, req.offset.unwrap_or(0))?;
+
+	// The listed fields of a job bump its `updated_at`, set at creation too,
+	// so the most recent update or deletion date is the last time the list
+	// changed.
+	let last_modified = sqlx::query!(
+		r#"
+		SELECT GREATEST(MAX(updated_at), MAX(deleted_at)) as last_modified FROM bulk_jobs
+		"#
+	)
+	.fetch_one(&conn_pool)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get last modified date of jobs with [error={}]",
+			e
+		);
+		ReacherError::from(e)
+	})?
+	.last_modified;
+
+	if let (Some(last_modified), Some(since)) = (last_modified, &if_modified_since) {
+		if !is_modified_since(last_modified, since) {
+			return Ok(Box::new(warp::reply::with_status(
+				warp::reply(),
+				http::StatusCode::NOT_MODIFIED,
+			)));
+		}
+	}
+
+	let jobs = sqlx::query_as!(
+		JobRecord,
+		r#"
+		SELECT id, created_at, total_records, tags, priority FROM bulk_jobs
+		WHERE deleted_at IS NULL AND ($3::text IS NULL OR $3 = ANY(tags))
+		ORDER BY id DESC
+		LIMIT $1 OFFSET $2
+		"#,
+		page_params.limit,
+		page_params.offset,
+		req.tag
+	)
+	.fetch_all(&conn_pool)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to list jobs with [error={}]",
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	let reply = warp::reply::json(&JobListResponse { jobs });
+	match last_modified {
+		Some(last_modified) => Ok(Box::new(warp::reply::with_header(
+			reply,
+			"Last-Modified",
+			to_http_date(last_modified),
+		))),
+		None => Ok(Box::new(reply)),
+	}
+}
+
+/// Create the `GET /v0/bulk` endpoint, listing jobs from newest to oldest.
+pub fn get_job_list(
+	conn_pool: Pool<Postgres>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	with_gzip(
+		warp::path!("v0" / "bulk")
+			.and(warp::get())
+			.and(with_query::<JobListRequest>())
+			.and(warp::header::optional::<String>("if-modified-since"))
+			.and_then(move |req, if_modified_since| {
+				job_list(req, if_modified_since, conn_pool.clone())
+			}),
+	)
+	// View access logs by setting `RUST_LOG=reacher`.
+	.with(warp::log("reacher"))
 }
 
 pub fn get_job_status(
 	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	with_gzip(
+		warp::path!("v0" / "bulk" / i32)
+			.and(warp::get())
+			.and_then(job_id_param)
+			.and(with_query::<JobStatusRequest>())
+			.and(with_trace_context())
+			.and_then(move |job_id: JobId, req, cx: Context| {
+				let span = tracing::info_span!("job_status", job_id = job_id.get());
+				span.set_parent(cx);
+				job_status(
+					job_id,
+					req,
+					conn_pool.clone(),
+					status_cache.clone(),
+					settings.clone(),
+				)
+				.instrument(span)
+			}),
+	)
+	// View access logs by setting `RUST_LOG=reacher`.
+	.with(warp::log("reacher"))
+}
+
+pub fn get_job_result(
+	conn_pool: Pool<Postgres>,
+	transformer: SharedTransformer,
+	download_limiter: Arc<JobDownloadLimiter>,
+	settings: Settings,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	with_gzip(
+		warp::path!("v0" / "bulk" / i32 / "download")
+			.and(warp::get())
+			.and_then(job_id_param)
+			.and(with_query::<JobResultRequest>())
+			.and(warp::header::optional::<String>("accept-charset"))
+			.and(with_trace_context())
+			.and_then(move |job_id: JobId, req, accept_charset, cx: Context| {
+				let span = tracing::info_span!("job_result", job_id = job_id.get());
+				span.set_parent(cx);
+				job_result(
+					job_id,
+					req,
+					accept_charset,
+					conn_pool.clone(),
+					transformer.clone(),
+					download_limiter.clone(),
+					settings.clone(),
+				)
+				.instrument(span)
+			}),
+	)
+	// View access logs by setting `RUST_LOG=reacher`.
+	.with(warp::log("reacher"))
+}
+
+/// Create the `GET /v0/bulk/{id}/count` endpoint.
+pub fn get_job_result_count(
+	conn_pool: Pool<Postgres>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-	warp::path!("v0" / "bulk" / i32)
+	warp::path!("v0" / "bulk" / i32 / "count")
 		.and(warp::get())
-		.and_then(move |job_id| job_status(job_id, conn_pool.clone()))
+		.and_then(job_id_param)
+		.and(with_query::<JobCountRequest>())
+		.and_then(move |job_id, req| job_count(job_id, req, conn_pool.clone()))
 		// View access logs by setting `RUST_LOG=reacher`.
 		.with(warp::log("reacher"))
 }
 
-pub fn get_job_result(
+/// Create the `GET /v0/bulk/{id}/result/{input}` endpoint. `{input}` can also
+/// be the id of the result row.
+pub fn get_job_input_result(
 	conn_pool: Pool<Postgres>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-	warp::path!("v0" / "bulk" / i32 / "download")
+	warp::path!("v0" / "bulk" / i32 / "result" / String)
 		.and(warp::get())
-		.and(warp::query::<JobResultRequest>())
-		.and_then(move |job_id, req| job_result(job_id, req, conn_pool.clone()))
+		.and_then(job_id_and_param)
+		.untuple_one()
+		.and_then(move |job_id, input| job_input_result(job_id, input, conn_pool.clone()))
 		// View access logs by setting `RUST_LOG=reacher`.
 		.with(warp::log("reacher"))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		is_modified_since, job_progress, last_page_offset, nested_csv, processing_warnings_header,
+		result_page_sql, results_by_input, throttle_limit, to_http_date, CsvCharset,
+		CsvNumberFormat, CsvQuoting, CsvWrapper, DownloadDefaults, JobResultCsvResponse,
+		JobResultOrder, JobResultRequest, JobResultSort, PageParams, ProcessingWarning,
+		ResultFilter, ValidStatus, CSV_HEADER, DEFAULT_CSV_LIMIT, DEFAULT_JSON_LIMIT,
+		MAX_DOWNLOAD_LIMIT,
+	};
+	use csv::WriterBuilder;
+	use sqlx::types::chrono::{TimeZone, Utc};
+	use std::convert::TryInto;
+
+	#[test]
+	fn test_processing_warnings_header() {
+		let warning = |reason: &str, percent| ProcessingWarning {
+			stage: "smtp".into(),
+			reason: reason.into(),
+			count: 1,
+			percent,
+		};
+		let header = processing_warnings_header(&[
+			warning("TimeoutError: timed out", 80),
+			warning("RcptToError: 550 5.1.1\r\nusér; unknown", 5),
+		]);
+
+		assert_eq!(
+			header,
+			"80% smtp: TimeoutError: timed out; 5% smtp: RcptToError: 550 5.1.1??us?r? unknown"
+		);
+	}
+
+	#[test]
+	fn test_csv_header_matches_fields() {
+		let value = serde_json::json!({"input": "foo@bar.baz", "is_reachable": "safe"});
+		let csv: JobResultCsvResponse = CsvWrapper(value).try_into().unwrap();
+
+		let mut wtr = WriterBuilder::new().has_headers(true).from_writer(vec![]);
+		wtr.serialize(csv).unwrap();
+		let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+
+		assert_eq!(data.lines().next().unwrap(), CSV_HEADER.join(","));
+	}
+
+	#[test]
+	fn test_csv_column_order() {
+		// Downstream parsers rely on the positions of the columns, changing
+		// them is a breaking change of the csv download.
+		assert_eq!(
+			CSV_HEADER.join(","),
+			"input,is_reachable,misc.is_disposable,misc.is_role_account,mx.accepts_mail,smtp.can_connect,smtp.has_full_inbox,smtp.is_catch_all,smtp.is_deliverable,smtp.is_disabled,syntax.is_valid_syntax,syntax.domain,syntax.username,error,warnings,duration_ms"
+		);
+
+		let value = serde_json::json!({
+			"input": "foo@bar.baz",
+			"is_reachable": "safe",
+			"syntax": {"domain": "bar.baz", "username": "foo", "is_valid_syntax": true},
+		});
+		let csv: JobResultCsvResponse = CsvWrapper(value).try_into().unwrap();
+		let mut wtr = WriterBuilder::new().has_headers(false).from_writer(vec![]);
+		wtr.serialize(csv).unwrap();
+		let row = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+		assert_eq!(
+			row,
+			"foo@bar.baz,safe,false,false,false,false,false,false,false,false,true,bar.baz,foo,,,\n"
+		);
+	}
+
+	#[test]
+	fn test_csv_round_trip() {
+		let value = serde_json::json!({"input": "foo@bar.baz", "is_reachable": "risky"});
+		let csv: JobResultCsvResponse = CsvWrapper(value).try_into().unwrap();
+
+		let mut wtr = WriterBuilder::new().has_headers(true).from_writer(vec![]);
+		wtr.serialize(csv).unwrap();
+		let data = wtr.into_inner().unwrap();
+
+		let mut rdr = csv::Reader::from_reader(data.as_slice());
+		let parsed: JobResultCsvResponse = rdr.deserialize().next().unwrap().unwrap();
+		assert_eq!(parsed.input, "foo@bar.baz");
+		assert_eq!(parsed.is_reachable, "risky");
+	}
+
+	#[test]
+	fn test_nested_csv_unions_keys() {
+		let rows = vec![
+			serde_json::json!({"input": "foo@bar.baz", "mx": {"records": ["a.mx", "b.mx"]}}),
+			serde_json::json!({"input": "bar@bar.baz", "misc": {"gravatar_url": null}, "error": 1}),
+		];
+		let data = String::from_utf8(
+			nested_csv(
+				&rows,
+				true,
+				&CsvNumberFormat::default(),
+				CsvQuoting::default(),
+			)
+			.unwrap(),
+		)
+		.unwrap();
+		let lines: Vec<&str> = data.lines().collect();
+
+		assert_eq!(lines[0], "error,input,misc.gravatar_url,mx.records");
+		assert_eq!(lines[1], ",foo@bar.baz,,a.mx;b.mx");
+		assert_eq!(lines[2], "1,bar@bar.baz,,");
+	}
+
+	#[test]
+	fn test_nested_csv_number_format() {
+		let rows = vec![serde_json::json!({"score": 0.123456, "duration_ms": 1200})];
+		let format = CsvNumberFormat {
+			separator: ',',
+			precision: Some(2),
+		};
+		let data =
+			String::from_utf8(nested_csv(&rows, true, &format, CsvQuoting::default()).unwrap())
+				.unwrap();
+		let lines: Vec<&str> = data.lines().collect();
+
+		assert_eq!(lines[0], "duration_ms,score");
+		assert_eq!(lines[1], "1200,\"0,12\"");
+
+		// Full precision by default.
+		let number = serde_json::Number::from_f64(0.123456).unwrap();
+		assert_eq!(CsvNumberFormat::default().format(&number), "0.123456");
+	}
+
+	#[test]
+	fn test_is_modified_since() {
+		let date = Utc.ymd(2015, 10, 21).and_hms_milli(7, 28, 0, 500);
+
+		assert_eq!(to_http_date(date), "Wed, 21 Oct 2015 07:28:00 GMT");
+		assert!(!is_modified_since(date, "Wed, 21 Oct 2015 07:28:00 GMT"));
+		assert!(is_modified_since(date, "Wed, 21 Oct 2015 07:27:59 GMT"));
+		assert!(is_modified_since(date, "not a date"));
+	}
+
+	#[test]
+	fn test_job_progress() {
+		assert_eq!(job_progress(5, 10), (5, ValidStatus::Running));
+		assert_eq!(job_progress(10, 10), (10, ValidStatus::Completed));
+		// More results than records, when the two queries race.
+		assert_eq!(job_progress(12, 10), (10, ValidStatus::Completed));
+	}
+
+	#[test]
+	fn test_results_by_input() {
+		let results = vec![
+			serde_json::json!({"input": "foo@bar.baz", "is_reachable": "unknown"}),
+			serde_json::json!({"input": "bar@bar.baz", "is_reachable": "invalid"}),
+			serde_json::json!({"input": "foo@bar.baz", "is_reachable": "safe"}),
+		];
+
+		let map = results_by_input(results);
+		assert_eq!(map.len(), 2);
+		assert_eq!(map["foo@bar.baz"]["is_reachable"], "safe");
+		assert_eq!(map["bar@bar.baz"]["is_reachable"], "invalid");
+	}
+
+	#[test]
+	fn test_throttle_limit() {
+		// Pool still has room, or idle connections.
+		assert_eq!(throttle_limit(5000, 2, 0), (5000, false));
+		assert_eq!(throttle_limit(5000, 5, 1), (5000, false));
+		// All connections busy.
+		assert_eq!(throttle_limit(5000, 5, 0), (500, true));
+		assert_eq!(throttle_limit(5, 5, 0), (1, true));
+		// Metadata-only requests have nothing to reduce.
+		assert_eq!(throttle_limit(0, 5, 0), (0, false));
+	}
+
+	#[test]
+	fn test_page_params() {
+		let page = PageParams::new(50, 100).unwrap();
+		assert_eq!((page.limit(), page.offset()), (50, 100));
+		assert!(PageParams::new(50, i64::MAX as u64).is_ok());
+		assert!(PageParams::new(50, i64::MAX as u64 + 1).is_err());
+		assert!(PageParams::new(u64::MAX, 0).is_err());
+	}
+
+	#[test]
+	fn test_page_params_from_request() {
+		let page = |req: serde_json::Value| {
+			let req: JobResultRequest = serde_json::from_value(req).unwrap();
+			PageParams::from_request(&req, &DownloadDefaults::default())
+		};
+
+		assert_eq!(
+			page(serde_json::json!({})).unwrap().limit(),
+			DEFAULT_JSON_LIMIT
+		);
+		assert_eq!(
+			page(serde_json::json!({"format": "csv"})).unwrap().limit(),
+			DEFAULT_CSV_LIMIT
+		);
+		let params = page(serde_json::json!({"limit": 10, "offset": 20})).unwrap();
+		assert_eq!((params.limit(), params.offset()), (10, 20));
+		assert!(page(serde_json::json!({ "limit": MAX_DOWNLOAD_LIMIT + 1 })).is_err());
+		assert!(page(serde_json::json!({ "offset": u64::MAX })).is_err());
+
+		let req: JobResultRequest = serde_json::from_value(serde_json::json!({})).unwrap();
+		let defaults = DownloadDefaults {
+			json_limit: 7,
+			csv_limit: 8,
+		};
+		assert_eq!(
+			PageParams::from_request(&req, &defaults).unwrap().limit(),
+			7
+		);
+	}
+
+	#[test]
+	fn test_result_page_sql() {
+		let mut filter = ResultFilter::from_params(None, None, None, None, None, None).unwrap();
+		let sql = result_page_sql(&filter);
+		assert!(sql.contains("r.id > $2"), "{}", sql);
+		assert!(sql.contains("max(n.id)"), "{}", sql);
+		assert!(sql.contains("ORDER BY r.id\n"), "{}", sql);
+
+		filter.latest_only = false;
+		filter.order = JobResultOrder::Desc;
+		let sql = result_page_sql(&filter);
+		assert!(sql.contains("r.id < $2"), "{}", sql);
+		assert!(!sql.contains("max(n.id)"), "{}", sql);
+		assert!(sql.contains("ORDER BY r.id DESC\n"), "{}", sql);
+
+		filter.sort = JobResultSort::Ordinal;
+		let sql = result_page_sql(&filter);
+		assert!(
+			sql.contains("ORDER BY r.ordinal DESC NULLS LAST, r.id DESC\n"),
+			"{}",
+			sql
+		);
+	}
+
+	#[test]
+	fn test_last_page_offset() {
+		assert_eq!(last_page_offset(0, 50), 0);
+		assert_eq!(last_page_offset(50, 50), 0);
+		assert_eq!(last_page_offset(51, 50), 50);
+		assert_eq!(last_page_offset(120, 50), 100);
+	}
+
+	#[test]
+	fn test_csv_charset() {
+		assert_eq!(
+			CsvCharset::from_accept_charset(None),
+			Some(CsvCharset::Utf8)
+		);
+		assert_eq!(
+			CsvCharset::from_accept_charset(Some("iso-8859-1, utf-8;q=0.5")),
+			Some(CsvCharset::Latin1)
+		);
+		assert_eq!(
+			CsvCharset::from_accept_charset(Some("latin1;q=0.2, *;q=0.8")),
+			Some(CsvCharset::Utf8)
+		);
+		assert_eq!(CsvCharset::from_accept_charset(Some("utf-16")), None);
+		assert_eq!(CsvCharset::from_accept_charset(Some("utf-8;q=0")), None);
+
+		assert_eq!(
+			CsvCharset::Latin1.encode("café,日本".as_bytes().to_vec()),
+			b"caf\xe9,??".to_vec()
+		);
+	}
+
+	#[test]
+	fn test_csv_mx_records() {
+		let value = serde_json::json!({
+			"input": "foo@bar.baz",
+			"mx": {"accepts_mail": true, "records": ["mx1.bar.baz.", "mx2.bar.baz."]},
+		});
+		let csv: JobResultCsvResponse = CsvWrapper(value).try_into().unwrap();
+		assert_eq!(csv.mx_records, vec!["mx1.bar.baz.", "mx2.bar.baz."]);
+
+		let value = serde_json::json!({"input": "foo@bar.baz", "mx": {"accepts_mail": false}});
+		let csv: JobResultCsvResponse = CsvWrapper(value).try_into().unwrap();
+		assert!(csv.mx_records.is_empty());
+	}
+
+	#[test]
+	fn test_csv_null_smtp() {
+		let value = serde_json::json!({
+			"input": "foo@bar.baz",
+			"is_reachable": "unknown",
+			"misc": {"is_disposable": false, "is_role_account": true},
+			"mx": {"accepts_email": true, "records": ["mx.bar.baz."]},
+			"smtp": null,
+			"syntax": {
+				"address": "foo@bar.baz",
+				"domain": "bar.baz",
+				"is_valid_syntax": true,
+				"username": "foo"
+			}
+		});
+
+		let csv: JobResultCsvResponse = CsvWrapper(value).try_into().unwrap();
+
+		assert_eq!(csv.input, "foo@bar.baz");
+		assert!(csv.misc_is_role_account);
+		assert!(csv.mx_accepts_mail);
+		assert!(!csv.smtp_can_connect);
+		assert!(!csv.smtp_is_deliverable);
+		assert_eq!(csv.syntax_domain, "bar.baz");
+		assert_eq!(csv.error, None);
+	}
+}
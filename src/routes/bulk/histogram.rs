@@ -0,0 +1,240 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /v0/bulk/{id}/histogram` endpoint, the
+//! number of results of a job processed in each time interval, to chart the
+//! throughput of the workers.
+
+use super::{job_id_param, JobId};
+use crate::errors::{ReacherError, ReacherResponseError};
+use crate::query::with_query;
+use crate::tracing_util::TimedQuery;
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use warp::{http, Filter};
+
+/// Maximum number of buckets of a histogram, empty ones included.
+pub const MAX_HISTOGRAM_BUCKETS: i64 = 10_000;
+
+/// Width of the buckets of the histogram.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HistogramBucket {
+	#[serde(rename = "1m")]
+	OneMinute,
+	#[serde(rename = "5m")]
+	FiveMinutes,
+	#[serde(rename = "1h")]
+	OneHour,
+}
+
+impl HistogramBucket {
+	fn width(&self) -> Duration {
+		match self {
+			HistogramBucket::OneMinute => Duration::minutes(1),
+			HistogramBucket::FiveMinutes => Duration::minutes(5),
+			HistogramBucket::OneHour => Duration::hours(1),
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct HistogramRequest {
+	/// Defaults to `1m`.
+	bucket: Option<HistogramBucket>,
+	/// Only count the results processed at or after this time.
+	from: Option<DateTime<Utc>>,
+	/// Only count the results processed before this time.
+	to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct HistogramBucketCount {
+	/// Start of the bucket, aligned on its width.
+	start: DateTime<Utc>,
+	count: i64,
+}
+
+#[derive(Serialize)]
+struct HistogramResponse {
+	bucket: HistogramBucket,
+	/// From the first to the last bucket with results, without gaps.
+	buckets: Vec<HistogramBucketCount>,
+}
+
+/// Add the empty buckets between the ones of `counts`, sorted by start.
+fn fill_buckets(
+	counts: Vec<(DateTime<Utc>, i64)>,
+	width: Duration,
+) -> Result<Vec<HistogramBucketCount>, ReacherResponseError> {
+	let (first, last) = match (counts.first(), counts.last()) {
+		(Some(first), Some(last)) => (first.0, last.0),
+		_ => return Ok(vec![]),
+	};
+	let total = (last - first).num_seconds() / width.num_seconds() + 1;
+	if total > MAX_HISTOGRAM_BUCKETS {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			format!(
+				"the histogram would have {} buckets, at most {} are allowed, use a wider bucket or a shorter time range",
+				total, MAX_HISTOGRAM_BUCKETS
+			),
+		));
+	}
+
+	let mut counts = counts.into_iter().peekable();
+	Ok((0..total)
+		.map(|i| {
+			let start = first + width * i as i32;
+			let count = match counts.next_if(|(bucket, _)| *bucket == start) {
+				Some((_, count)) => count,
+				None => 0,
+			};
+			HistogramBucketCount { start, count }
+		})
+		.collect())
+}
+
+async fn job_histogram(
+	job_id: JobId,
+	req: HistogramRequest,
+	conn_pool: Pool<Postgres>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let job_id = job_id.get();
+	let bucket = req.bucket.unwrap_or(HistogramBucket::OneMinute);
+
+	let job = sqlx::query!(
+		r#"
+		SELECT id FROM bulk_jobs
+		WHERE id = $1 AND deleted_at IS NULL
+		"#,
+		job_id
+	)
+	.fetch_optional(&conn_pool)
+	.timed("job_record", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get job record for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+	if job.is_none() {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", job_id),
+		)
+		.into());
+	}
+
+	// Buckets are aligned on the Unix epoch, which works for widths that
+	// date_trunc doesn't support.
+	let recs = sqlx::query!(
+		r#"
+		SELECT
+			to_timestamp(floor(extract(epoch FROM processed_at)::float8 / $2::int8) * $2::int8) AS "start!",
+			COUNT(*) AS "count!"
+		FROM email_results
+		WHERE job_id = $1
+			AND ($3::timestamptz IS NULL OR processed_at >= $3)
+			AND ($4::timestamptz IS NULL OR processed_at < $4)
+		GROUP BY 1
+		ORDER BY 1
+		"#,
+		job_id,
+		bucket.width().num_seconds(),
+		req.from,
+		req.to
+	)
+	.fetch_all(&conn_pool)
+	.timed("job_histogram", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get histogram for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+
+	let buckets = fill_buckets(
+		recs.into_iter().map(|rec| (rec.start, rec.count)).collect(),
+		bucket.width(),
+	)?;
+
+	Ok(warp::reply::json(&HistogramResponse { bucket, buckets }))
+}
+
+/// Create the `GET /v0/bulk/{id}/histogram` endpoint.
+pub fn get_job_histogram(
+	conn_pool: Pool<Postgres>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "histogram")
+		.and(warp::get())
+		.and_then(job_id_param)
+		.and(with_query::<HistogramRequest>())
+		.and_then(move |job_id, req| job_histogram(job_id, req, conn_pool.clone()))
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{fill_buckets, HistogramBucketCount, MAX_HISTOGRAM_BUCKETS};
+	use chrono::Duration;
+	use sqlx::types::chrono::{TimeZone, Utc};
+
+	#[test]
+	fn test_fill_buckets() {
+		let at = |minute| Utc.ymd(2026, 1, 1).and_hms(0, minute, 0);
+		let buckets = fill_buckets(vec![(at(0), 2), (at(3), 1)], Duration::minutes(1)).unwrap();
+		assert_eq!(
+			buckets,
+			vec![
+				HistogramBucketCount {
+					start: at(0),
+					count: 2
+				},
+				HistogramBucketCount {
+					start: at(1),
+					count: 0
+				},
+				HistogramBucketCount {
+					start: at(2),
+					count: 0
+				},
+				HistogramBucketCount {
+					start: at(3),
+					count: 1
+				},
+			]
+		);
+		assert!(fill_buckets(vec![], Duration::minutes(1))
+			.unwrap()
+			.is_empty());
+
+		let too_far = at(0) + Duration::minutes(MAX_HISTOGRAM_BUCKETS);
+		assert!(fill_buckets(vec![(at(0), 1), (too_far, 1)], Duration::minutes(1)).is_err());
+	}
+}
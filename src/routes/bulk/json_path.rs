@@ -0,0 +1,123 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the expressions of the `extra_fields` parameter of
+//! the csv download, a limited subset of JSONPath to pull fields of the
+//! results that the curated columns don't expose, e.g.
+//! `smtp.details.code` or `$.mx.records[0]`.
+
+use serde_json::Value;
+use std::fmt;
+
+/// Maximum number of expressions of a download.
+pub const MAX_EXTRA_FIELDS: usize = 20;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+	Key(String),
+	Index(usize),
+}
+
+/// A path into a result: keys made of ASCII letters, digits and underscores,
+/// separated by dots, and array indices in brackets. The root can be written
+/// `$`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonPath(Vec<Segment>);
+
+impl JsonPath {
+	/// `None` if the expression isn't part of the supported subset.
+	pub fn parse(expr: &str) -> Option<Self> {
+		let expr = expr.strip_prefix("$.").unwrap_or(expr);
+		let mut segments = vec![];
+		for part in expr.split('.') {
+			let (key, indices) = part.split_once('[').unwrap_or((part, ""));
+			if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+				return None;
+			}
+			segments.push(Segment::Key(key.to_string()));
+			if !indices.is_empty() {
+				// What follows the first `[`, e.g. `0][1]`.
+				for index in indices.strip_suffix(']')?.split("][") {
+					if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+						return None;
+					}
+					segments.push(Segment::Index(index.parse().ok()?));
+				}
+			}
+		}
+
+		Some(JsonPath(segments))
+	}
+
+	/// The value at the path, if any.
+	pub fn get<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+		self.0
+			.iter()
+			.try_fold(value, |value, segment| match segment {
+				Segment::Key(key) => value.get(key),
+				Segment::Index(index) => value.get(index),
+			})
+	}
+}
+
+/// The normalized expression, used as the csv header.
+impl fmt::Display for JsonPath {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, segment) in self.0.iter().enumerate() {
+			match segment {
+				Segment::Key(key) if i == 0 => write!(f, "{}", key)?,
+				Segment::Key(key) => write!(f, ".{}", key)?,
+				Segment::Index(index) => write!(f, "[{}]", index)?,
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::JsonPath;
+
+	#[test]
+	fn test_json_path() {
+		let value = serde_json::json!({
+			"smtp": {"details": {"code": 550}},
+			"mx": {"records": ["a.io", "b.io"]},
+		});
+
+		let path = JsonPath::parse("smtp.details.code").unwrap();
+		assert_eq!(path.get(&value), Some(&serde_json::json!(550)));
+		let path = JsonPath::parse("$.mx.records[1]").unwrap();
+		assert_eq!(path.get(&value), Some(&serde_json::json!("b.io")));
+		assert_eq!(path.to_string(), "mx.records[1]");
+		assert_eq!(JsonPath::parse("mx.records[2]").unwrap().get(&value), None);
+		assert_eq!(JsonPath::parse("smtp.missing").unwrap().get(&value), None);
+
+		for expr in [
+			"",
+			"smtp..code",
+			"smtp.details.code;",
+			"mx.records[]",
+			"mx.records[-1]",
+			"mx.records[0",
+			"$..smtp",
+			"mx[0]records",
+		] {
+			assert_eq!(JsonPath::parse(expr), None, "{}", expr);
+		}
+	}
+}
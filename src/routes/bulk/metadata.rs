@@ -0,0 +1,164 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the optional metadata of bulk results. Deployments
+//! storing per-address metadata (e.g. source campaign, external id) in a
+//! side table can have whitelisted columns of that table joined, on
+//! `input`, into the downloads requested with `include_meta=true`.
+
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::env;
+
+/// Side table joined into the results, and its columns exposed in
+/// downloads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultMetadata {
+	table: String,
+	columns: Vec<String>,
+}
+
+impl ResultMetadata {
+	/// Columns of the metadata table exposed in downloads.
+	pub fn columns(&self) -> &[String] {
+		&self.columns
+	}
+}
+
+/// Only lowercase unquoted identifiers are accepted, so that they can be
+/// safely interpolated in the query.
+fn is_identifier(name: &str) -> bool {
+	let mut chars = name.chars();
+	chars
+		.next()
+		.is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+		&& chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Parse the table, optionally schema-qualified, and its comma-separated
+/// columns. Returns `None` if any of them isn't a valid identifier.
+fn parse_result_metadata(table: &str, columns: &str) -> Option<ResultMetadata> {
+	let table_is_valid = table.split('.').count() <= 2 && table.split('.').all(is_identifier);
+	let columns = columns
+		.split(',')
+		.map(str::trim)
+		.map(|column| is_identifier(column).then(|| column.to_string()))
+		.collect::<Option<Vec<_>>>()?;
+
+	table_is_valid.then(|| ResultMetadata {
+		table: table.to_string(),
+		columns,
+	})
+}
+
+/// Metadata table joined into the results, read from
+/// `RCH_RESULT_METADATA_TABLE`, with the columns whitelisted by
+/// `RCH_RESULT_METADATA_COLUMNS`. `None` if no table is configured.
+///
+/// # Panics
+///
+/// Panics if the table or the columns aren't valid lowercase identifiers,
+/// or if no columns are set with the table.
+pub fn result_metadata() -> Option<ResultMetadata> {
+	env::var("RCH_RESULT_METADATA_TABLE").ok().map(|table| {
+		let columns = env::var("RCH_RESULT_METADATA_COLUMNS")
+			.expect("Environment variable RCH_RESULT_METADATA_COLUMNS must be set with RCH_RESULT_METADATA_TABLE.");
+		parse_result_metadata(&table, &columns).expect(
+			"Environment variables RCH_RESULT_METADATA_TABLE or RCH_RESULT_METADATA_COLUMNS are malformed.",
+		)
+	})
+}
+
+/// Add a `meta` object to each result, with the whitelisted columns of the
+/// metadata row of its `input`. The columns are null if there's no such row.
+pub(super) async fn attach_result_metadata(
+	rows: &mut [Value],
+	metadata: &ResultMetadata,
+	conn_pool: &Pool<Postgres>,
+) -> Result<(), sqlx::Error> {
+	let inputs: Vec<String> = rows
+		.iter()
+		.filter_map(|row| row.get("input")?.as_str().map(str::to_string))
+		.collect();
+	if inputs.is_empty() {
+		return Ok(());
+	}
+
+	let table = metadata
+		.table
+		.split('.')
+		.map(|part| format!("\"{}\"", part))
+		.collect::<Vec<_>>()
+		.join(".");
+	let fields = metadata
+		.columns
+		.iter()
+		.map(|column| format!("'{0}', m.\"{0}\"", column))
+		.collect::<Vec<_>>()
+		.join(", ");
+	// An input with several metadata rows gets the first one.
+	let query = format!(
+		r#"
+		SELECT DISTINCT ON (i.input) i.input, jsonb_build_object({}) AS meta
+		FROM UNNEST($1::text[]) AS i(input)
+		LEFT JOIN {} AS m ON m.input = i.input
+		ORDER BY i.input
+		"#,
+		fields, table
+	);
+
+	let metas: HashMap<String, Value> = sqlx::query_as::<_, (String, Value)>(&query)
+		.bind(&inputs)
+		.fetch_all(conn_pool)
+		.await?
+		.into_iter()
+		.collect();
+
+	for row in rows.iter_mut() {
+		let meta = row
+			.get("input")
+			.and_then(Value::as_str)
+			.and_then(|input| metas.get(input))
+			.cloned();
+		if let (Some(meta), Some(row)) = (meta, row.as_object_mut()) {
+			row.insert("meta".into(), meta);
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_result_metadata() {
+		assert_eq!(
+			parse_result_metadata("crm.contacts", "campaign, external_id"),
+			Some(ResultMetadata {
+				table: "crm.contacts".into(),
+				columns: vec!["campaign".into(), "external_id".into()],
+			})
+		);
+		assert_eq!(parse_result_metadata("contacts", "campaign\""), None);
+		assert_eq!(parse_result_metadata("contacts; DROP", "campaign"), None);
+		assert_eq!(parse_result_metadata("a.b.c", "campaign"), None);
+		assert_eq!(parse_result_metadata("Contacts", "campaign"), None);
+		assert_eq!(parse_result_metadata("contacts", ""), None);
+	}
+}
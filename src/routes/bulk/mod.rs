@@ -14,5 +14,90 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod badge;
+pub mod csv_schema;
+pub mod delete;
+pub mod distribution;
+pub mod download_limit;
+pub mod eta;
+pub mod expiry;
+pub mod export;
+pub mod freshness;
 pub mod get;
+pub mod histogram;
+pub mod json_path;
+pub mod metadata;
+pub mod notify;
+pub mod owner_limit;
 pub mod post;
+pub mod processed_count;
+pub mod provider;
+pub mod status_cache;
+pub mod status_ws;
+pub mod summary;
+pub mod tags;
+pub mod transform;
+
+use crate::errors::ReacherResponseError;
+use std::convert::TryFrom;
+use std::fmt;
+use warp::http;
+
+/// Id of a bulk job, which is always positive. Ids from requests are parsed
+/// into it before querying the database, as other ids can't match any job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JobId(i32);
+
+impl JobId {
+	pub fn get(self) -> i32 {
+		self.0
+	}
+}
+
+impl TryFrom<i32> for JobId {
+	type Error = ReacherResponseError;
+
+	fn try_from(job_id: i32) -> Result<Self, Self::Error> {
+		if job_id > 0 {
+			Ok(JobId(job_id))
+		} else {
+			Err(ReacherResponseError::new(
+				http::StatusCode::BAD_REQUEST,
+				"job id should be a positive integer",
+			))
+		}
+	}
+}
+
+impl fmt::Display for JobId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+/// Parse the job id segment of a path, for the filters of the endpoints.
+async fn job_id_param(job_id: i32) -> Result<JobId, warp::Rejection> {
+	Ok(JobId::try_from(job_id)?)
+}
+
+/// Same as `job_id_param`, for the paths with another segment after the job
+/// id, e.g. `/v0/bulk/{id}/tags/{tag}`. Use with `.untuple_one()`.
+async fn job_id_and_param<T>(job_id: i32, param: T) -> Result<(JobId, T), warp::Rejection> {
+	Ok((JobId::try_from(job_id)?, param))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::JobId;
+	use std::convert::TryFrom;
+
+	#[test]
+	fn test_job_id() {
+		assert_eq!(JobId::try_from(1).unwrap().get(), 1);
+		assert_eq!(JobId::try_from(i32::MAX).unwrap().get(), i32::MAX);
+		assert!(JobId::try_from(0).is_err());
+		assert!(JobId::try_from(-1).is_err());
+		assert!(JobId::try_from(i32::MIN).is_err());
+		assert_eq!(JobId::try_from(42).unwrap().to_string(), "42");
+	}
+}
@@ -0,0 +1,79 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Notification of the workers when the emails of a job are enqueued, so
+//! that they can react without polling the database. With
+//! `RCH_NOTIFY_NEW_JOBS` set to `true`, the job id is sent on the `new_bulk_job`
+//! channel with `NOTIFY`, delivered once the enqueuing transaction commits.
+//! Workers subscribe to it with `subscribe_new_jobs`.
+
+use crate::settings::env_flag;
+use futures::{Stream, StreamExt};
+use sqlx::postgres::PgListener;
+use sqlx::{Pool, Postgres, Transaction};
+use std::sync::OnceLock;
+
+/// Channel of the notifications, with the job id as payload.
+pub const NEW_BULK_JOB_CHANNEL: &str = "new_bulk_job";
+
+static NOTIFY_NEW_JOBS: OnceLock<bool> = OnceLock::new();
+
+/// Whether jobs are notified, set by the `RCH_NOTIFY_NEW_JOBS` flag. It's
+/// read once, on the first call, as jobs are also started by the background
+/// tasks.
+///
+/// # Panics
+///
+/// Panics if `RCH_NOTIFY_NEW_JOBS` is malformed, see [`env_flag`]. Call it at
+/// startup to fail early.
+pub fn notify_new_jobs() -> bool {
+	*NOTIFY_NEW_JOBS.get_or_init(|| env_flag("RCH_NOTIFY_NEW_JOBS"))
+}
+
+/// Notify the job on `NEW_BULK_JOB_CHANNEL` when the transaction commits.
+pub(super) async fn notify_new_job(
+	tx: &mut Transaction<'_, Postgres>,
+	job_id: i32,
+) -> Result<(), sqlx::Error> {
+	sqlx::query("SELECT pg_notify($1, $2)")
+		.bind(NEW_BULK_JOB_CHANNEL)
+		.bind(job_id.to_string())
+		.execute(&mut *tx)
+		.await?;
+
+	Ok(())
+}
+
+/// Listen to `NEW_BULK_JOB_CHANNEL` on a dedicated connection, yielding the
+/// ids of the notified jobs. Notifications sent before the subscription, or
+/// while the connection is lost, are missed, so workers should still poll
+/// from time to time.
+pub async fn subscribe_new_jobs(
+	conn_pool: &Pool<Postgres>,
+) -> Result<impl Stream<Item = Result<i32, sqlx::Error>>, sqlx::Error> {
+	let mut listener = PgListener::connect_with(conn_pool).await?;
+	listener.listen(NEW_BULK_JOB_CHANNEL).await?;
+
+	Ok(listener
+		.into_stream()
+		.filter_map(|notification| async move {
+			match notification {
+				// Payloads notified by other senders are skipped.
+				Ok(notification) => notification.payload().parse().ok().map(Ok),
+				Err(e) => Some(Err(e)),
+			}
+		}))
+}
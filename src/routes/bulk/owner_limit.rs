@@ -0,0 +1,263 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the limit on the number of jobs an owner runs at
+//! once. The owner of a job is the enabled API key it was submitted with, in
+//! the `x-reacher-api-key` header. If `RCH_MAX_RUNNING_JOBS_PER_OWNER` is
+//! set, a submission of an owner with as many running jobs is rejected, or
+//! created as `pending` if `RCH_OWNER_LIMIT_ACTION` is `queue`. So is the
+//! finalize of a draft, which starts it. A background task starts the
+//! pending jobs once their owner is under the limit again.
+
+use super::post::start_staged_job;
+use crate::auth::hash_api_key;
+use crate::errors::ReacherError;
+use crate::tracing_util::TimedQuery;
+use sqlx::{Pool, Postgres, Transaction};
+use std::env;
+
+/// Interval between two runs of the task starting pending jobs.
+const PENDING_TASK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Maximum number of running jobs of an owner, read from
+/// `RCH_MAX_RUNNING_JOBS_PER_OWNER`. There's no limit if it's not set.
+///
+/// # Panics
+///
+/// Panics if `RCH_MAX_RUNNING_JOBS_PER_OWNER` is not a positive integer.
+pub fn max_running_jobs_per_owner() -> Option<i64> {
+	env::var("RCH_MAX_RUNNING_JOBS_PER_OWNER").ok().map(|max| {
+		max.parse::<i64>()
+			.ok()
+			.filter(|max| *max > 0)
+			.expect("Environment variable RCH_MAX_RUNNING_JOBS_PER_OWNER is malformed.")
+	})
+}
+
+/// What happens to a submission over the limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OwnerLimitAction {
+	/// Rejected with a 429.
+	Reject,
+	/// Created as `pending`, and started later.
+	Queue,
+}
+
+/// Read from `RCH_OWNER_LIMIT_ACTION`, `reject` or `queue`. Defaults to
+/// `reject`.
+///
+/// # Panics
+///
+/// Panics if `RCH_OWNER_LIMIT_ACTION` is set to another value.
+pub fn owner_limit_action() -> OwnerLimitAction {
+	match env::var("RCH_OWNER_LIMIT_ACTION").as_deref() {
+		Err(_) | Ok("reject") => OwnerLimitAction::Reject,
+		Ok("queue") => OwnerLimitAction::Queue,
+		Ok(_) => panic!("Environment variable RCH_OWNER_LIMIT_ACTION is malformed."),
+	}
+}
+
+/// Id of the enabled API key, if any.
+pub(super) async fn owner_id(
+	tx: &mut Transaction<'_, Postgres>,
+	api_key: &str,
+) -> Result<Option<i32>, ReacherError> {
+	let rec = sqlx::query!(
+		r#"
+		SELECT id FROM api_keys
+		WHERE key_hash = $1 AND enabled
+		"#,
+		hash_api_key(api_key)
+	)
+	.fetch_optional(&mut *tx)
+	.timed_for("api_key_owner", String::from("api_key"))
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get the owner of the API key with [error={}]",
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	Ok(rec.map(|rec| rec.id))
+}
+
+/// Number of running jobs of the owner. Submissions of the owner are
+/// serialized until the end of the transaction, so that concurrent ones
+/// can't both get under the limit.
+pub(super) async fn running_jobs(
+	tx: &mut Transaction<'_, Postgres>,
+	owner_id: i32,
+) -> Result<i64, ReacherError> {
+	sqlx::query!("SELECT pg_advisory_xact_lock($1)", i64::from(owner_id))
+		.execute(&mut *tx)
+		.timed_for("owner_lock", format!("owner={}", owner_id))
+		.await
+		.map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to lock the jobs of [owner={}] with [error={}]",
+				owner_id,
+				e
+			);
+			ReacherError::from(e)
+		})?;
+
+	let rec = sqlx::query!(
+		r#"
+		SELECT COUNT(*) AS "count!" FROM bulk_jobs
+		WHERE api_key_id = $1
+			AND NOT draft
+			AND NOT pending
+			AND processed_count < total_records
+			-- A deleted job still runs until its tasks are cancelled.
+			AND (deleted_at IS NULL OR NOT tasks_cancelled)
+		"#,
+		owner_id
+	)
+	.fetch_one(&mut *tx)
+	.timed_for("owner_running_jobs", format!("owner={}", owner_id))
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to count the running jobs of [owner={}] with [error={}]",
+			owner_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	Ok(rec.count)
+}
+
+/// Start the pending job if its owner is under the limit. Returns whether
+/// it was started.
+async fn start_pending_job(
+	conn_pool: &Pool<Postgres>,
+	job_id: i32,
+	max_running_jobs: Option<i64>,
+) -> Result<bool, warp::Rejection> {
+	let mut tx = conn_pool.begin().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to start transaction to start pending [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	let rec = sqlx::query!(
+		r#"
+		SELECT api_key_id, draft_options FROM bulk_jobs
+		WHERE id = $1 AND pending AND deleted_at IS NULL
+		FOR UPDATE
+		"#,
+		job_id
+	)
+	.fetch_optional(&mut tx)
+	.timed("pending_job", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to lock pending [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+	// Started by another instance in the meantime.
+	let (owner_id, options) = match rec {
+		Some(rec) => (rec.api_key_id, rec.draft_options.unwrap_or_default()),
+		None => return Ok(false),
+	};
+
+	if let (Some(owner_id), Some(max_running_jobs)) = (owner_id, max_running_jobs) {
+		if running_jobs(&mut tx, owner_id).await? >= max_running_jobs {
+			return Ok(false);
+		}
+	}
+
+	start_staged_job(&mut tx, job_id, options).await?;
+	tx.commit().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to commit start of pending [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	Ok(true)
+}
+
+/// Start the pending jobs, oldest first, as long as their owner is under the
+/// limit. Returns the number of started jobs.
+pub async fn start_pending_jobs(
+	conn_pool: &Pool<Postgres>,
+	max_running_jobs: Option<i64>,
+) -> Result<u64, ReacherError> {
+	let job_ids = sqlx::query!(
+		r#"
+		SELECT id FROM bulk_jobs
+		WHERE pending AND deleted_at IS NULL
+		ORDER BY id
+		"#
+	)
+	.fetch_all(conn_pool)
+	.await?;
+
+	let mut started = 0;
+	for rec in job_ids {
+		match start_pending_job(conn_pool, rec.id, max_running_jobs).await {
+			Ok(true) => started += 1,
+			Ok(false) => continue,
+			// Already logged, try the next jobs.
+			Err(_) => continue,
+		}
+	}
+
+	Ok(started)
+}
+
+/// Spawn the background task starting the pending jobs. It also runs without
+/// a limit configured, so that the jobs left pending by a previous
+/// configuration are started.
+pub fn spawn_pending_task(conn_pool: Pool<Postgres>, max_running_jobs: Option<i64>) {
+	tokio::spawn(async move {
+		let mut interval = tokio::time::interval(PENDING_TASK_INTERVAL);
+		loop {
+			interval.tick().await;
+			match start_pending_jobs(&conn_pool, max_running_jobs).await {
+				Ok(started) => log::debug!(
+					target:"reacher",
+					"Started [count={}] pending jobs",
+					started
+				),
+				Err(e) => log::error!(
+					target:"reacher",
+					"Failed to start pending jobs with [error={:?}]",
+					e
+				),
+			}
+		}
+	});
+}
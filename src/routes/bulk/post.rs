@@ -16,12 +16,31 @@
 
 //! This file implements the `POST /bulk` endpoint.
 
+use super::notify::{notify_new_job, notify_new_jobs};
+use super::owner_limit::{owner_id, running_jobs, OwnerLimitAction};
+use super::status_cache::JobStatusCache;
+use super::tags::check_tags;
+use super::{job_id_param, JobId};
+use crate::auth::{sign_download, with_admin_key, API_KEY_HEADER};
 use crate::check::{check_email, SMTP_TIMEOUT};
-use crate::errors::ReacherError;
+use crate::errors::{ReacherError, ReacherResponseError};
+use crate::query::with_query;
+use crate::routes::MAX_BODY_BYTES;
+use crate::settings::Settings;
+use crate::smtp_errors::smtp_error_classification;
 use check_if_email_exists::{CheckEmailInput, CheckEmailInputProxy};
-use sqlx::{Pool, Postgres};
-use std::{cmp::min, error::Error, time::Duration};
-use warp::Filter;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres, Transaction};
+use std::{
+	cmp::min,
+	collections::{BTreeMap, BTreeSet},
+	env,
+	error::Error,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+use warp::{http, Filter};
 
 use serde::{Deserialize, Serialize};
 use sqlxmq::{job, CurrentJob};
@@ -34,21 +53,71 @@ use sqlxmq::{job, CurrentJob};
 // outputs and commit them to the database.
 const EMAIL_TASK_BATCH_SIZE: usize = 1;
 
+/// Number of tasks inserted in the queue with a single statement.
+pub const SUBMISSION_BATCH_SIZE: usize = 500;
+
+/// Highest priority of a job, jobs are submitted with priority 0 by default.
+pub const MAX_JOB_PRIORITY: i32 = 10;
+
+/// Maximum size in bytes of a submission body when
+/// `RCH_MAX_SUBMISSION_BYTES` is not set.
+pub const DEFAULT_MAX_SUBMISSION_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Maximum size in bytes of the body of a bulk submission, or of emails
+/// appended to a draft job, read from `RCH_MAX_SUBMISSION_BYTES`. Larger
+/// bodies are rejected with a 413.
+///
+/// # Panics
+///
+/// Panics if `RCH_MAX_SUBMISSION_BYTES` is not a positive integer.
+pub fn max_submission_bytes() -> u64 {
+	env::var("RCH_MAX_SUBMISSION_BYTES")
+		.map(|bytes| {
+			bytes
+				.parse::<u64>()
+				.ok()
+				.filter(|bytes| *bytes > 0)
+				.expect("Environment variable RCH_MAX_SUBMISSION_BYTES is malformed.")
+		})
+		.unwrap_or(DEFAULT_MAX_SUBMISSION_BYTES)
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct TaskInput {
 	job_id: i32,
 	input: CheckEmailInput,
+	/// Position of the email in the submitted list. Missing from the tasks
+	/// enqueued before it was added.
+	#[serde(default)]
+	ordinal: Option<i32>,
+	/// Number of times the email was requeued, see `requeue_unknowns`.
+	#[serde(default)]
+	retry_count: i32,
+	/// Error of the result the requeue replaced.
+	#[serde(default)]
+	last_error: Option<String>,
 }
 
 /// Endpoint request body.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct CreateBulkRequestBody {
 	input_type: String,
+	#[serde(default)]
 	input: Vec<String>,
 	proxy: Option<CheckEmailInputProxy>,
 	hello_name: Option<String>,
 	from_email: Option<String>,
 	smtp_port: Option<u16>,
+	/// Labels to organize jobs, e.g. `campaign-q3`, see
+	/// `super::tags::check_tags`.
+	tags: Option<Vec<String>>,
+	/// Open a draft job, for lists too large for a single request: more
+	/// emails are added with `POST /v0/bulk/{id}/append`, and nothing is
+	/// verified until `POST /v0/bulk/{id}/finalize`.
+	draft: Option<bool>,
+	/// Between 0 and `MAX_JOB_PRIORITY`, higher priority jobs are meant to
+	/// be processed first.
+	priority: Option<i32>,
 }
 
 struct CreateBulkRequestBodyIterator {
@@ -112,6 +181,45 @@ struct CreateBulkResponseBody {
 	job_id: i32,
 }
 
+/// Request body of the append endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct AppendBulkRequestBody {
+	input: Vec<String>,
+}
+
+/// Response body of the append endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct AppendBulkResponseBody {
+	job_id: i32,
+	/// Number of emails added to the draft so far.
+	total_appended: i64,
+}
+
+/// Request body of the requeue endpoint. Only results of jobs created in the
+/// given time window are requeued, both bounds are optional.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct RequeueUnknownsRequestBody {
+	created_after: Option<DateTime<Utc>>,
+	created_before: Option<DateTime<Utc>>,
+	/// Requeue the results of completed jobs too, which are otherwise
+	/// skipped.
+	force: Option<bool>,
+}
+
+/// Completed job whose results weren't requeued, for lack of `force`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct SkippedRequeueJob {
+	job_id: i32,
+	total_skipped: usize,
+}
+
+/// Response body of the requeue endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct RequeueUnknownsResponseBody {
+	total_requeued: usize,
+	skipped_jobs: Vec<SkippedRequeueJob>,
+}
+
 /// Arguments to the `#[job]` attribute allow setting default job options.
 /// This task tries to verify the given email and inserts the results
 /// into the email verification db table
@@ -133,7 +241,9 @@ pub async fn email_verification_task(
 		current_job.id(),
 	);
 
+	let now = Instant::now();
 	let response = check_email(&task_input.input).await;
+	let duration_ms = now.elapsed().as_millis() as i32;
 
 	log::debug!(
 		target:"reacher",
@@ -147,11 +257,18 @@ pub async fn email_verification_task(
 	#[allow(unused_variables)]
 	let rec = sqlx::query!(
 		r#"
-			INSERT INTO email_results (job_id, result)
-			VALUES ($1, $2)
+			INSERT INTO email_results (job_id, result, duration_ms, processed_at, ordinal,
+				retry_count, last_error)
+			SELECT $1, $2, $3, NOW(), $4, $5, $6
+			-- Dropped if the job was deleted during the verification.
+			WHERE EXISTS (SELECT 1 FROM bulk_jobs WHERE id = $1 AND deleted_at IS NULL)
 			"#,
 		task_input.job_id,
-		serde_json::json!(response)
+		serde_json::json!(response),
+		duration_ms,
+		task_input.ordinal,
+		task_input.retry_count,
+		task_input.last_error
 	)
 	// TODO: This is a simplified solution and will work when
 	// the task queue and email results tables are in the same
@@ -186,21 +303,117 @@ pub async fn email_verification_task(
 	Ok(())
 }
 
+// Options of the email verification tasks. They're inserted by batches in
+// `submit_tasks` rather than spawned one by one with
+// `email_verification_task.builder()`, with the defaults of the builder,
+// which `test_submitted_task_options` checks.
+const TASK_DELAY: Duration = Duration::from_secs(0);
+const TASK_RETRIES: i32 = 4;
+const TASK_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const TASK_CHANNEL_NAME: &str = "";
+const TASK_CHANNEL_ARGS: &str = "";
+const TASK_COMMIT_INTERVAL: Option<Duration> = None;
+const TASK_ORDERED: bool = false;
+
+/// Time after which a client should retry a submission over the running jobs
+/// limit of its owner, in seconds.
+const OWNER_LIMIT_RETRY_AFTER: u64 = 60;
+
+/// Whether a job of the owner, about to run, should wait as pending because
+/// the owner is at its running jobs limit. Rejects with a 429 instead if
+/// such jobs aren't queued, see `Settings::owner_limit_action`.
+async fn over_owner_limit(
+	tx: &mut Transaction<'_, Postgres>,
+	owner_id: Option<i32>,
+	settings: &Settings,
+) -> Result<bool, warp::Rejection> {
+	let (owner_id, max) = match (owner_id, settings.max_running_jobs_per_owner) {
+		(Some(owner_id), Some(max)) => (owner_id, max),
+		_ => return Ok(false),
+	};
+	if running_jobs(tx, owner_id).await? < max {
+		return Ok(false);
+	}
+
+	match settings.owner_limit_action {
+		OwnerLimitAction::Reject => Err(ReacherResponseError::new(
+			http::StatusCode::TOO_MANY_REQUESTS,
+			format!("at most {} jobs can run at once, retry later", max),
+		)
+		.with_retry_after(OWNER_LIMIT_RETRY_AFTER)
+		.into()),
+		OwnerLimitAction::Queue => Ok(true),
+	}
+}
+
 /// handles input, creates db entry for job and tasks for verification
+///
+/// The job record and all its tasks are created inside one transaction, so
+/// if submitting any batch fails, the whole job is rolled back.
+///
+/// The job is owned by the API key, if it's an enabled one, see
+/// `super::owner_limit`.
 async fn create_bulk_request(
 	body: CreateBulkRequestBody,
+	api_key: Option<String>,
 	conn_pool: Pool<Postgres>,
+	settings: Settings,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-	// create job entry
+	let priority = body.priority.unwrap_or(0);
+	if !(0..=MAX_JOB_PRIORITY).contains(&priority) {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			format!("priority should be between 0 and {}", MAX_JOB_PRIORITY),
+		)
+		.into());
+	}
+
+	let tags = check_tags(body.tags.as_deref().unwrap_or_default())?;
+
+	let mut tx = conn_pool.begin().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to start transaction for [body={:?}] with [error={}]",
+			&body,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	let draft = body.draft.unwrap_or(false);
+	let owner_id = match &api_key {
+		Some(api_key) => owner_id(&mut tx, api_key).await?,
+		None => None,
+	};
+	// A draft doesn't run until it's finalized.
+	let pending = !draft && over_owner_limit(&mut tx, owner_id, &settings).await?;
+
+	// The options are kept to enqueue the emails of a draft on finalize, and
+	// of a pending job once it's started.
+	let draft_options = if draft || pending {
+		Some(serde_json::json!(CreateBulkRequestBody {
+			input: vec![],
+			..body.clone()
+		}))
+	} else {
+		None
+	};
+
+	// create job entry, its total_records is set once its tasks are submitted
 	let rec = sqlx::query!(
 		r#"
-		INSERT INTO bulk_jobs (total_records)
-		VALUES ($1)
+		INSERT INTO bulk_jobs (total_records, tags, draft, draft_options, priority, api_key_id, pending)
+		VALUES (0, $1, $2, $3, $4, $5, $6)
 		RETURNING id
 		"#,
-		body.input.len() as i32
+		&tags,
+		draft,
+		draft_options,
+		priority,
+		owner_id,
+		pending
 	)
-	.fetch_one(&conn_pool)
+	.fetch_one(&mut tx)
 	.await
 	.map_err(|e| {
 		log::error!(
@@ -212,23 +425,601 @@ async fn create_bulk_request(
 		ReacherError::from(e)
 	})?;
 
-	for task_input in body.into_iter() {
+	if draft || pending {
+		stage_draft_emails(&mut tx, rec.id, &body.input).await?;
+	} else {
+		submit_tasks(&mut tx, rec.id, body).await?;
+	}
+
+	tx.commit().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to commit job for [job={}] with [error={}]",
+			rec.id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+
+	Ok(warp::reply::json(&CreateBulkResponseBody {
+		job_id: rec.id,
+	}))
+}
+
+/// Enqueue a task for each email of the body on the job, by batches of
+/// `SUBMISSION_BATCH_SIZE` tasks inserted with a single statement each. The
+/// tasks and `total_records` are written in the transaction of the caller,
+/// so they all become visible when it commits, and a failed batch rolls the
+/// whole job back. The workers are then notified of the job, see
+/// `super::notify`.
+async fn submit_tasks(
+	tx: &mut Transaction<'_, Postgres>,
+	job_id: i32,
+	body: CreateBulkRequestBody,
+) -> Result<(), warp::Rejection> {
+	let tasks: Vec<CheckEmailInput> = body.into_iter().collect();
+	let mut ordinal = 0;
+	for batch in tasks.chunks(SUBMISSION_BATCH_SIZE) {
+		let mut payloads = Vec::with_capacity(batch.len());
+		for task_input in batch {
+			let task = TaskInput {
+				input: task_input.clone(),
+				job_id,
+				ordinal: Some(ordinal),
+				retry_count: 0,
+				last_error: None,
+			};
+			payloads.push(serde_json::to_string(&task).map_err(|e| {
+				log::error!(
+					target:"reacher",
+					"Failed to serialize task for [job={}] with [error={}]",
+					job_id,
+					e
+				);
+				ReacherError::Json()
+			})?);
+			ordinal += task_input.to_emails.len() as i32;
+		}
+		let ids: Vec<Uuid> = payloads.iter().map(|_| Uuid::new_v4()).collect();
+
+		// Same as `JobBuilder::spawn` of sqlxmq, for the whole batch.
+		sqlx::query(
+			r#"
+			SELECT mq_insert(ARRAY(
+				SELECT (id, $3, $4, $5, $6, $7, $8, $9, $10, payload, NULL)::mq_new_t
+				FROM UNNEST($1::uuid[], $2::text[]) AS t(id, payload)
+			))
+			"#,
+		)
+		.bind(&ids)
+		.bind(&payloads)
+		.bind(TASK_DELAY)
+		.bind(TASK_RETRIES)
+		.bind(TASK_RETRY_BACKOFF)
+		.bind(TASK_CHANNEL_NAME)
+		.bind(TASK_CHANNEL_ARGS)
+		.bind(TASK_COMMIT_INTERVAL)
+		.bind(TASK_ORDERED)
+		.bind(email_verification_task.name())
+		.execute(&mut *tx)
+		.await
+		.map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to submit tasks for [job={}] with [error={}]",
+				job_id,
+				e
+			);
+
+			ReacherError::from(e)
+		})?;
+
+		log::debug!(
+			target:"reacher",
+			"Submitted [count={}] tasks to sqlxmq for [job={}]",
+			ids.len(),
+			job_id
+		);
+	}
+
+	sqlx::query!(
+		r#"
+		UPDATE bulk_jobs
+		SET total_records = total_records + $2, updated_at = NOW()
+		WHERE id = $1
+		"#,
+		job_id,
+		ordinal
+	)
+	.execute(&mut *tx)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to update total records for [job={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+
+	if notify_new_jobs() {
+		notify_new_job(tx, job_id).await.map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to notify [job={}] with [error={}]",
+				job_id,
+				e
+			);
+
+			ReacherError::from(e)
+		})?;
+	}
+
+	Ok(())
+}
+
+/// Add emails to a draft job, they're enqueued in the same order on
+/// finalize.
+async fn stage_draft_emails(
+	tx: &mut Transaction<'_, Postgres>,
+	job_id: i32,
+	emails: &[String],
+) -> Result<(), warp::Rejection> {
+	sqlx::query!(
+		r#"
+		INSERT INTO bulk_job_draft_inputs (job_id, email)
+		SELECT $1, email FROM UNNEST($2::text[]) WITH ORDINALITY AS t(email, n)
+		ORDER BY n
+		"#,
+		job_id,
+		emails
+	)
+	.execute(&mut *tx)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to stage emails for [job={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+
+	Ok(())
+}
+
+/// A draft job, see `lock_draft_job`.
+struct DraftJob {
+	options: serde_json::Value,
+	owner_id: Option<i32>,
+}
+
+/// Lock the job record until the end of the transaction, and return its
+/// draft options and owner. Fails if there's no such job, or if it isn't a
+/// draft.
+async fn lock_draft_job(
+	tx: &mut Transaction<'_, Postgres>,
+	job_id: JobId,
+) -> Result<DraftJob, warp::Rejection> {
+	let rec = sqlx::query!(
+		r#"
+		SELECT draft, draft_options, api_key_id FROM bulk_jobs
+		WHERE id = $1 AND deleted_at IS NULL
+		FOR UPDATE
+		"#,
+		job_id.get()
+	)
+	.fetch_optional(&mut *tx)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to lock job record for [job={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?
+	.ok_or_else(|| {
+		ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", job_id),
+		)
+	})?;
+
+	match rec.draft_options {
+		Some(options) if rec.draft => Ok(DraftJob {
+			options,
+			owner_id: rec.api_key_id,
+		}),
+		_ => Err(ReacherResponseError::new(
+			http::StatusCode::CONFLICT,
+			format!("job {} is not a draft", job_id),
+		)
+		.into()),
+	}
+}
+
+/// Add the emails of the body to a draft job.
+async fn append_bulk_request(
+	job_id: JobId,
+	body: AppendBulkRequestBody,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let mut tx = conn_pool.begin().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to start transaction to append to [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	lock_draft_job(&mut tx, job_id).await?;
+	stage_draft_emails(&mut tx, job_id.get(), &body.input).await?;
+
+	let total_appended = sqlx::query!(
+		r#"
+		SELECT COUNT(*) AS "count!" FROM bulk_job_draft_inputs
+		WHERE job_id = $1
+		"#,
+		job_id.get()
+	)
+	.fetch_one(&mut tx)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to count staged emails for [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?
+	.count;
+
+	tx.commit().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to commit append to [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+	status_cache.invalidate(job_id.get());
+
+	Ok(warp::reply::json(&AppendBulkResponseBody {
+		job_id: job_id.get(),
+		total_appended,
+	}))
+}
+
+/// Enqueue the staged emails of a draft job, in the order they were
+/// appended, and start processing it. The job can't be appended to anymore.
+///
+/// Like a submission, the job is pending instead if its owner is at the
+/// running jobs limit, or the finalize is rejected, see `over_owner_limit`.
+async fn finalize_bulk_request(
+	job_id: JobId,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let mut tx = conn_pool.begin().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to start transaction to finalize [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	let draft = lock_draft_job(&mut tx, job_id).await?;
+	if over_owner_limit(&mut tx, draft.owner_id, &settings).await? {
+		// Its options and staged emails are kept until it's started.
+		sqlx::query!(
+			r#"
+			UPDATE bulk_jobs
+			SET draft = false, pending = true, updated_at = NOW()
+			WHERE id = $1
+			"#,
+			job_id.get()
+		)
+		.execute(&mut tx)
+		.await
+		.map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to mark pending [job={}] with [error={}]",
+				job_id,
+				e
+			);
+			ReacherError::from(e)
+		})?;
+	} else {
+		start_staged_job(&mut tx, job_id.get(), draft.options).await?;
+	}
+
+	tx.commit().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to commit finalize of [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+	status_cache.invalidate(job_id.get());
+
+	Ok(warp::reply::json(&CreateBulkResponseBody {
+		job_id: job_id.get(),
+	}))
+}
+
+/// Enqueue the staged emails of a draft or pending job with its kept
+/// options, in the order they were staged. The job record should be locked.
+pub(super) async fn start_staged_job(
+	tx: &mut Transaction<'_, Postgres>,
+	job_id: i32,
+	draft_options: serde_json::Value,
+) -> Result<(), warp::Rejection> {
+	let mut body: CreateBulkRequestBody = serde_json::from_value(draft_options).map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to read draft options of [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::Json()
+	})?;
+
+	body.input = sqlx::query!(
+		r#"
+		DELETE FROM bulk_job_draft_inputs
+		WHERE job_id = $1
+		RETURNING id, email
+		"#,
+		job_id
+	)
+	.fetch_all(&mut *tx)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to read staged emails for [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})
+	.map(|mut recs| {
+		// RETURNING doesn't follow any order.
+		recs.sort_by_key(|rec| rec.id);
+		recs.into_iter().map(|rec| rec.email).collect()
+	})?;
+
+	sqlx::query!(
+		r#"
+		UPDATE bulk_jobs
+		SET draft = false, pending = false, draft_options = NULL, updated_at = NOW()
+		WHERE id = $1
+		"#,
+		job_id
+	)
+	.execute(&mut *tx)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to start job record for [job={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	submit_tasks(tx, job_id, body).await
+}
+
+/// Create the `POST /bulk` endpoint.
+/// The endpoint accepts list of email address and creates
+/// a new job to check them.
+pub fn create_bulk_email_vrfy_job(
+	conn_pool: Pool<Postgres>,
+	settings: Settings,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk")
+		.and(warp::post())
+		// When accepting a body, we want a JSON body (and to reject huge
+		// payloads)...
+		.and(warp::body::content_length_limit(max_submission_bytes()))
+		.and(warp::body::json())
+		.and(warp::header::optional::<String>(API_KEY_HEADER))
+		.and_then(move |body: CreateBulkRequestBody, api_key| {
+			create_bulk_request(body, api_key, conn_pool.clone(), settings.clone())
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Create the `POST /v0/bulk/{id}/append` endpoint, adding emails to a draft
+/// job.
+pub fn append_bulk_email_vrfy_job(
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "append")
+		.and(warp::post())
+		.and_then(job_id_param)
+		.and(warp::body::content_length_limit(max_submission_bytes()))
+		.and(warp::body::json())
+		.and_then(move |job_id, body: AppendBulkRequestBody| {
+			append_bulk_request(job_id, body, conn_pool.clone(), status_cache.clone())
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Create the `POST /v0/bulk/{id}/finalize` endpoint, starting to process a
+/// draft job.
+pub fn finalize_bulk_email_vrfy_job(
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "finalize")
+		.and(warp::post())
+		.and_then(job_id_param)
+		.and_then(move |job_id| {
+			finalize_bulk_request(
+				job_id,
+				conn_pool.clone(),
+				status_cache.clone(),
+				settings.clone(),
+			)
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Finds all `unknown` results caused by a transient error (timeouts, IO
+/// errors and 4xx replies by default, see `crate::smtp_errors`) in the jobs
+/// created in the given window and not deleted, removes them and enqueues
+/// their email again on the same job.
+///
+/// The original request options (proxy, hello name...) aren't stored with
+/// the results, so requeued emails are verified with the default options.
+///
+/// The results of a completed job are final, so they are skipped and
+/// reported per job, unless `force` is set. The requeue is refused with a 409
+/// if it only found results of completed jobs.
+async fn requeue_unknowns(
+	body: RequeueUnknownsRequestBody,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let mut tx = conn_pool.begin().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to start transaction for requeue [body={:?}] with [error={}]",
+			&body,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	let recs = sqlx::query!(
+		r#"
+		SELECT r.id, r.job_id, r.result ->> 'input' AS input, r.ordinal, r.retry_count,
+			concat_ws(': ',
+				r.result -> 'smtp' -> 'error' ->> 'type',
+				r.result -> 'smtp' -> 'error' ->> 'message',
+				r.result -> 'mx' -> 'error' ->> 'type',
+				r.result -> 'mx' -> 'error' ->> 'message'
+			) AS last_error,
+			(j.processed_count >= j.total_records AND NOT j.draft) AS "completed!"
+		FROM email_results r
+		JOIN bulk_jobs j ON r.job_id = j.id
+		WHERE j.deleted_at IS NULL
+			AND ($1::timestamptz IS NULL OR j.created_at >= $1)
+			AND ($2::timestamptz IS NULL OR j.created_at < $2)
+			AND normalize_reachable(r.result ->> 'is_reachable') = 'unknown'
+			AND concat_ws(': ',
+				r.result -> 'smtp' -> 'error' ->> 'type',
+				r.result -> 'smtp' -> 'error' ->> 'message',
+				r.result -> 'mx' -> 'error' ->> 'type',
+				r.result -> 'mx' -> 'error' ->> 'message'
+			) ILIKE ANY($3)
+		FOR UPDATE OF r
+		"#,
+		body.created_after,
+		body.created_before,
+		&smtp_error_classification().transient_patterns()
+	)
+	.fetch_all(&mut tx)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to find unknown results for [body={:?}] with [error={}]",
+			&body,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+
+	let force = body.force.unwrap_or(false);
+	let (recs, skipped): (Vec<_>, Vec<_>) =
+		recs.into_iter().partition(|rec| force || !rec.completed);
+	let mut skipped_jobs: BTreeMap<i32, usize> = BTreeMap::new();
+	for job_id in skipped.iter().filter_map(|rec| rec.job_id) {
+		*skipped_jobs.entry(job_id).or_default() += 1;
+	}
+	if recs.is_empty() && !skipped_jobs.is_empty() {
+		let job_ids: Vec<String> = skipped_jobs.keys().map(i32::to_string).collect();
+		return Err(ReacherResponseError::new(
+			http::StatusCode::CONFLICT,
+			format!(
+				"jobs {} are completed, their results can only be requeued with force",
+				job_ids.join(", ")
+			),
+		)
+		.into());
+	}
+
+	let ids: Vec<i32> = recs.iter().map(|rec| rec.id).collect();
+	sqlx::query!("DELETE FROM email_results WHERE id = ANY($1)", &ids)
+		.execute(&mut tx)
+		.await
+		.map_err(|e| {
+			log::error!(
+				target:"reacher",
+				"Failed to delete unknown results for [body={:?}] with [error={}]",
+				&body,
+				e
+			);
+			ReacherError::from(e)
+		})?;
+
+	let mut total_requeued = 0;
+	for rec in &recs {
+		let (job_id, email) = match (rec.job_id, &rec.input) {
+			(Some(job_id), Some(email)) => (job_id, email),
+			_ => continue,
+		};
+
+		let mut input = CheckEmailInput::new(vec![email.clone()]);
+		input.set_smtp_timeout(Duration::from_secs(SMTP_TIMEOUT));
+		// Keep the position of the email, for downloads sorted by ordinal.
 		let task = TaskInput {
-			input: task_input,
-			job_id: rec.id,
+			input,
+			job_id,
+			ordinal: rec.ordinal,
+			retry_count: rec.retry_count + 1,
+			last_error: rec.last_error.clone(),
 		};
 
 		let task_uuid = email_verification_task
 			.builder()
 			.set_json(&task)
 			.unwrap()
-			.spawn(&conn_pool)
+			.spawn(&mut tx)
 			.await
 			.map_err(|e| {
 				log::error!(
 					target:"reacher",
-					"Failed to submit task for [job={}] with [error={}]",
-					rec.id,
+					"Failed to requeue task for [job={}] with [error={}]",
+					job_id,
 					e
 				);
 
@@ -237,31 +1028,145 @@ async fn create_bulk_request(
 
 		log::debug!(
 			target:"reacher",
-			"Submitted task to sqlxmq for [job={}] with [uuid={}]",
-			rec.id,
+			"Requeued task to sqlxmq for [job={}] with [uuid={}]",
+			job_id,
 			task_uuid
 		);
+		total_requeued += 1;
 	}
 
-	Ok(warp::reply::json(&CreateBulkResponseBody {
-		job_id: rec.id,
+	tx.commit().await.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to commit requeue for [body={:?}] with [error={}]",
+			&body,
+			e
+		);
+		ReacherError::from(e)
+	})?;
+	let job_ids: BTreeSet<i32> = recs.iter().filter_map(|rec| rec.job_id).collect();
+	for job_id in job_ids {
+		status_cache.invalidate(job_id);
+	}
+
+	log::info!(target:"reacher", "Requeued {} unknown results", total_requeued);
+
+	Ok(warp::reply::json(&RequeueUnknownsResponseBody {
+		total_requeued,
+		skipped_jobs: skipped_jobs
+			.into_iter()
+			.map(|(job_id, total_skipped)| SkippedRequeueJob {
+				job_id,
+				total_skipped,
+			})
+			.collect(),
 	}))
 }
 
-/// Create the `POST /bulk` endpoint.
-/// The endpoint accepts list of email address and creates
-/// a new job to check them.
-pub fn create_bulk_email_vrfy_job(
+/// Create the `POST /v0/bulk/requeue-unknowns` admin endpoint.
+pub fn requeue_unknowns_job(
 	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-	warp::path!("v0" / "bulk")
+	warp::path!("v0" / "bulk" / "requeue-unknowns")
 		.and(warp::post())
-		// When accepting a body, we want a JSON body (and to reject huge
-		// payloads)...
-		// TODO: Configure max size limit for a bulk job
-		.and(warp::body::content_length_limit(1024 * 16))
+		.and(with_admin_key())
+		.and(warp::body::content_length_limit(MAX_BODY_BYTES))
 		.and(warp::body::json())
-		.and_then(move |body: CreateBulkRequestBody| create_bulk_request(body, conn_pool.clone()))
+		.and_then(move |body: RequeueUnknownsRequestBody| {
+			requeue_unknowns(body, conn_pool.clone(), status_cache.clone())
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Lifetime of a signed download URL when none is requested.
+const DEFAULT_DOWNLOAD_URL_EXPIRES_IN: u64 = 24 * 60 * 60;
+/// Longer lifetimes are truncated to this value.
+const MAX_DOWNLOAD_URL_EXPIRES_IN: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct DownloadUrlRequest {
+	/// Lifetime of the URL in seconds.
+	expires_in: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct DownloadUrlResponseBody {
+	url: String,
+	expires_at: DateTime<Utc>,
+}
+
+/// Sign a download URL of the job, with a 404 if it doesn't exist or was
+/// deleted, rather than a URL that would fail.
+async fn download_url(
+	job_id: JobId,
+	req: DownloadUrlRequest,
+	conn_pool: Pool<Postgres>,
+	settings: Settings,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let job_id = job_id.get();
+	let key = settings.url_signing_key.ok_or_else(|| {
+		ReacherResponseError::new(
+			http::StatusCode::FORBIDDEN,
+			"Signed download URLs are disabled",
+		)
+	})?;
+
+	sqlx::query!(
+		r#"
+		SELECT id FROM bulk_jobs
+		WHERE id = $1 AND deleted_at IS NULL
+		"#,
+		job_id
+	)
+	.fetch_optional(&conn_pool)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?
+	.ok_or_else(|| {
+		ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", job_id),
+		)
+	})?;
+
+	let expires_in = req.expires_in.unwrap_or(DEFAULT_DOWNLOAD_URL_EXPIRES_IN);
+	let expires_at =
+		Utc::now() + chrono::Duration::seconds(min(expires_in, MAX_DOWNLOAD_URL_EXPIRES_IN) as i64);
+	let expires = expires_at.timestamp();
+
+	Ok(warp::reply::json(&DownloadUrlResponseBody {
+		url: format!(
+			"/v0/bulk/{}/download?expires={}&token={}",
+			job_id,
+			expires,
+			sign_download(&key, job_id, expires)
+		),
+		expires_at,
+	}))
+}
+
+/// Create the `POST /v0/bulk/{id}/download-url` admin endpoint, minting a
+/// signed URL to share the results of a job without the admin key.
+pub fn create_download_url(
+	conn_pool: Pool<Postgres>,
+	settings: Settings,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "download-url")
+		.and(warp::post())
+		.and(with_admin_key())
+		.and_then(job_id_param)
+		.and(with_query::<DownloadUrlRequest>())
+		.and_then(move |job_id, req| download_url(job_id, req, conn_pool.clone(), settings.clone()))
 		// View access logs by setting `RUST_LOG=reacher`.
 		.with(warp::log("reacher"))
 }
@@ -0,0 +1,45 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the repair of the `processed_count` counter of bulk
+//! jobs. The counter is maintained by triggers on `email_results`, so that
+//! the job status doesn't count the results on each request; this recounts
+//! them, in case the counter drifted (e.g. after results were copied with
+//! triggers disabled).
+
+use crate::errors::ReacherError;
+use sqlx::{Pool, Postgres};
+
+/// Reset the `processed_count` of the job to its actual number of results.
+/// Returns the repaired count, or `None` if there's no such job.
+pub async fn reconcile_processed_count(
+	conn_pool: &Pool<Postgres>,
+	job_id: i32,
+) -> Result<Option<i32>, ReacherError> {
+	let rec = sqlx::query!(
+		r#"
+		UPDATE bulk_jobs
+		SET processed_count = (SELECT COUNT(*) FROM email_results WHERE job_id = $1)
+		WHERE id = $1
+		RETURNING processed_count
+		"#,
+		job_id
+	)
+	.fetch_optional(conn_pool)
+	.await?;
+
+	Ok(rec.map(|rec| rec.processed_count))
+}
@@ -0,0 +1,172 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `provider` field of the downloads requested with
+//! `include_provider=true`, e.g. `gmail` for `foo@gmail.com`, to segment
+//! lists by mailbox provider. Domains of no known provider are `other`.
+//!
+//! The built-in table of free providers can be extended with a JSON file,
+//! whose path is set in the `RCH_PROVIDER_DOMAINS` environment variable. Its
+//! entries take precedence over the built-in ones:
+//!
+//! ```json
+//! { "example.org": "example", "yahoo.fr": "yahoo" }
+//! ```
+
+use super::transform::{ResultTransformer, SharedTransformer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::{env, fs, sync::OnceLock};
+
+/// Provider of the domains missing from the table.
+pub const OTHER_PROVIDER: &str = "other";
+
+/// Built-in domains of the free mailbox providers.
+const KNOWN_PROVIDERS: [(&str, &str); 20] = [
+	("gmail.com", "gmail"),
+	("googlemail.com", "gmail"),
+	("yahoo.com", "yahoo"),
+	("yahoo.co.uk", "yahoo"),
+	("ymail.com", "yahoo"),
+	("outlook.com", "outlook"),
+	("hotmail.com", "outlook"),
+	("live.com", "outlook"),
+	("msn.com", "outlook"),
+	("icloud.com", "icloud"),
+	("me.com", "icloud"),
+	("mac.com", "icloud"),
+	("aol.com", "aol"),
+	("proton.me", "proton"),
+	("protonmail.com", "proton"),
+	("gmx.com", "gmx"),
+	("gmx.de", "gmx"),
+	("yandex.ru", "yandex"),
+	("mail.ru", "mailru"),
+	("zoho.com", "zoho"),
+];
+
+/// Provider of each domain, keyed by lowercased domain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderDomains(HashMap<String, String>);
+
+impl Default for ProviderDomains {
+	fn default() -> Self {
+		ProviderDomains(
+			KNOWN_PROVIDERS
+				.iter()
+				.map(|(domain, provider)| (domain.to_string(), provider.to_string()))
+				.collect(),
+		)
+	}
+}
+
+impl ProviderDomains {
+	/// Provider of the domain, `OTHER_PROVIDER` if it's unknown.
+	pub fn provider(&self, domain: &str) -> &str {
+		self.0
+			.get(&domain.to_lowercase())
+			.map_or(OTHER_PROVIDER, String::as_str)
+	}
+}
+
+static PROVIDER_DOMAINS: OnceLock<ProviderDomains> = OnceLock::new();
+
+/// The domain to provider table, loaded on first use.
+///
+/// # Panics
+///
+/// Panics if the file set in `RCH_PROVIDER_DOMAINS` can't be read or
+/// parsed. Call it at startup to fail early.
+pub fn provider_domains() -> &'static ProviderDomains {
+	PROVIDER_DOMAINS.get_or_init(|| {
+		let mut domains = ProviderDomains::default();
+		if let Ok(path) = env::var("RCH_PROVIDER_DOMAINS") {
+			let content =
+				fs::read_to_string(&path).expect("Failed to read the RCH_PROVIDER_DOMAINS file.");
+			let overrides: HashMap<String, String> = serde_json::from_str(&content)
+				.expect("The RCH_PROVIDER_DOMAINS file is malformed.");
+			domains.0.extend(
+				overrides
+					.into_iter()
+					.map(|(domain, provider)| (domain.to_lowercase(), provider)),
+			);
+		}
+
+		domains
+	})
+}
+
+/// Domain of a raw result, from its syntax check or else from its input.
+fn result_domain(raw: &Value) -> Option<&str> {
+	raw["syntax"]["domain"]
+		.as_str()
+		.filter(|domain| !domain.is_empty())
+		.or_else(|| {
+			raw["input"]
+				.as_str()?
+				.rsplit_once('@')
+				.map(|(_, domain)| domain)
+		})
+}
+
+/// Wraps the transformer of a download, adding a `provider` field to each
+/// of its results.
+pub struct WithProvider(pub SharedTransformer);
+
+impl ResultTransformer for WithProvider {
+	fn transform(&self, raw: &Value) -> Value {
+		let mut result = self.0.transform(raw);
+		let provider =
+			result_domain(raw).map_or(OTHER_PROVIDER, |domain| provider_domains().provider(domain));
+		if let Some(result) = result.as_object_mut() {
+			result.insert("provider".into(), provider.into());
+		}
+
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::routes::bulk::transform::IdentityTransformer;
+	use std::sync::Arc;
+
+	#[test]
+	fn test_provider() {
+		let domains = ProviderDomains::default();
+		assert_eq!(domains.provider("gmail.com"), "gmail");
+		assert_eq!(domains.provider("Hotmail.COM"), "outlook");
+		assert_eq!(domains.provider("reacher.email"), OTHER_PROVIDER);
+	}
+
+	#[test]
+	fn test_with_provider() {
+		let transformer = WithProvider(Arc::new(IdentityTransformer));
+		let result = transformer.transform(&serde_json::json!({
+			"input": "foo@gmail.com",
+			"syntax": {"domain": "gmail.com"}
+		}));
+		assert_eq!(result["provider"], "gmail");
+		assert_eq!(result["input"], "foo@gmail.com");
+
+		// Without a syntax check, e.g. for an invalid result.
+		let result = transformer.transform(&serde_json::json!({"input": "foo@ymail.com"}));
+		assert_eq!(result["provider"], "yahoo");
+		let result = transformer.transform(&serde_json::json!({"input": "foo"}));
+		assert_eq!(result["provider"], OTHER_PROVIDER);
+	}
+}
@@ -0,0 +1,119 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! In-memory cache of job statuses, in front of the `GET /v0/bulk/{id}`
+//! endpoint. Some jobs are polled much more often than others, e.g. the ones
+//! shown on dashboards.
+
+use super::get::{JobStatusResponseBody, ValidStatus};
+use moka::sync::Cache;
+use sqlx::types::chrono::Utc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Default time to live of the status of a running job.
+pub const RUNNING_TTL: Duration = Duration::from_secs(2);
+/// Default time to live of the status of a completed job, which doesn't
+/// change anymore.
+pub const COMPLETED_TTL: Duration = Duration::from_secs(10 * 60);
+/// Maximum number of statuses kept in each cache.
+const MAX_CAPACITY: u64 = 10_000;
+
+/// Cached statuses are keyed by job id, and whether the distinct domains
+/// were requested.
+type CacheKey = (i32, bool);
+
+pub struct JobStatusCache {
+	running: Cache<CacheKey, JobStatusResponseBody>,
+	completed: Cache<CacheKey, JobStatusResponseBody>,
+	hits: AtomicU64,
+	misses: AtomicU64,
+}
+
+impl JobStatusCache {
+	pub fn new(running_ttl: Duration, completed_ttl: Duration) -> Self {
+		let cache = |ttl| {
+			Cache::builder()
+				.max_capacity(MAX_CAPACITY)
+				.time_to_live(ttl)
+				.build()
+		};
+
+		JobStatusCache {
+			running: cache(running_ttl),
+			completed: cache(completed_ttl),
+			hits: AtomicU64::new(0),
+			misses: AtomicU64::new(0),
+		}
+	}
+
+	/// Get the cached status of a job, and record the cache hit or miss.
+	/// Expired jobs are purged by a background task which doesn't share the
+	/// cache, so their statuses are never returned.
+	pub fn get(&self, job_id: i32, distinct_domains: bool) -> Option<JobStatusResponseBody> {
+		let key = (job_id, distinct_domains);
+		let status = self
+			.completed
+			.get(&key)
+			.or_else(|| self.running.get(&key))
+			.filter(|status| {
+				status
+					.expires_at
+					.is_none_or(|expires_at| expires_at > Utc::now())
+			});
+
+		let counter = if status.is_some() {
+			&self.hits
+		} else {
+			&self.misses
+		};
+		counter.fetch_add(1, Ordering::Relaxed);
+
+		status
+	}
+
+	/// Cache the status of a job, with a TTL depending on the job status.
+	pub fn insert(&self, job_id: i32, distinct_domains: bool, status: JobStatusResponseBody) {
+		let cache = match status.job_status {
+			ValidStatus::Draft | ValidStatus::Pending | ValidStatus::Running => &self.running,
+			ValidStatus::Completed => &self.completed,
+		};
+
+		cache.insert((job_id, distinct_domains), status);
+	}
+
+	/// Drop the cached statuses of a job, after it was changed.
+	pub fn invalidate(&self, job_id: i32) {
+		for distinct_domains in [false, true] {
+			self.running.invalidate(&(job_id, distinct_domains));
+			self.completed.invalidate(&(job_id, distinct_domains));
+		}
+	}
+
+	pub fn hits(&self) -> u64 {
+		self.hits.load(Ordering::Relaxed)
+	}
+
+	pub fn misses(&self) -> u64 {
+		self.misses.load(Ordering::Relaxed)
+	}
+}
+
+impl Default for JobStatusCache {
+	fn default() -> Self {
+		JobStatusCache::new(RUNNING_TTL, COMPLETED_TTL)
+	}
+}
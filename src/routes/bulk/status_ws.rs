@@ -0,0 +1,223 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /v0/bulk/ws` WebSocket endpoint, pushing
+//! the statuses of a set of jobs to a dashboard as they change.
+//!
+//! The client subscribes and unsubscribes with text messages:
+//! `{"subscribe": [1, 2]}` or `{"unsubscribe": [2]}`. The server sends the
+//! `JobStatusResponseBody` of a subscribed job whenever it changes, and
+//! closes the socket once all the subscribed jobs are completed. A message
+//! subscribing past `MAX_WS_SUBSCRIPTIONS` jobs is rejected.
+
+use super::get::{fetch_job_status, JobStatusResponseBody, ValidStatus};
+use super::status_cache::JobStatusCache;
+use super::JobId;
+use crate::settings::Settings;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+use warp::ws::{Message, WebSocket};
+use warp::Filter;
+
+/// Interval between two polls of the subscribed jobs.
+const WS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Maximum number of jobs subscribed to by a connection, as each of them is
+/// polled every `WS_POLL_INTERVAL`.
+pub const MAX_WS_SUBSCRIPTIONS: usize = 100;
+
+/// Message sent by the client.
+#[derive(Debug, Deserialize, Serialize)]
+struct WsRequest {
+	#[serde(default)]
+	subscribe: Vec<i32>,
+	#[serde(default)]
+	unsubscribe: Vec<i32>,
+}
+
+/// Sent instead of a status when a message or a job can't be handled.
+#[derive(Debug, Deserialize, Serialize)]
+struct WsError {
+	job_id: Option<i32>,
+	error: String,
+}
+
+fn ws_message<T: Serialize>(body: &T) -> Message {
+	Message::text(serde_json::to_string(body).expect("Serializing to JSON doesn't fail. qed."))
+}
+
+/// Subscribed jobs, with the last status sent for each of them.
+type Subscriptions = HashMap<i32, Option<JobStatusResponseBody>>;
+
+/// Fetch the status of the subscribed jobs, and return the messages for
+/// those which changed. Completed and failing jobs are unsubscribed.
+async fn poll_statuses(
+	subscriptions: &mut Subscriptions,
+	conn_pool: &Pool<Postgres>,
+	status_cache: &JobStatusCache,
+	settings: &Settings,
+) -> Vec<Message> {
+	let mut messages = Vec::new();
+	let mut done = Vec::new();
+
+	for (job_id, last_status) in subscriptions.iter_mut() {
+		match fetch_job_status(*job_id, false, conn_pool.clone(), status_cache, settings).await {
+			Ok(status) => {
+				let changed = last_status.as_ref().is_none_or(|last| {
+					serde_json::to_value(last).ok() != serde_json::to_value(&status).ok()
+				});
+				if changed {
+					messages.push(ws_message(&status));
+				}
+				if status.job_status == ValidStatus::Completed {
+					done.push(*job_id);
+				}
+				*last_status = Some(status);
+			}
+			Err(e) => {
+				log::error!(
+					target:"reacher",
+					"Failed to get status for websocket of [job_id={}] with [error={:?}]",
+					job_id,
+					e
+				);
+				messages.push(ws_message(&WsError {
+					job_id: Some(*job_id),
+					error: "failed to get the job status".into(),
+				}));
+				done.push(*job_id);
+			}
+		}
+	}
+
+	for job_id in done {
+		subscriptions.remove(&job_id);
+	}
+
+	messages
+}
+
+/// Apply a client message to the subscriptions. Returns an error message to
+/// send back if it's malformed.
+fn handle_request(subscriptions: &mut Subscriptions, message: &Message) -> Option<Message> {
+	let text = message.to_str().ok()?;
+	let req: WsRequest = match serde_json::from_str(text) {
+		Ok(req) => req,
+		Err(e) => {
+			return Some(ws_message(&WsError {
+				job_id: None,
+				error: format!("invalid message: {}", e),
+			}))
+		}
+	};
+
+	if let Some(job_id) = req
+		.subscribe
+		.iter()
+		.find(|job_id| JobId::try_from(**job_id).is_err())
+	{
+		return Some(ws_message(&WsError {
+			job_id: Some(*job_id),
+			error: "job id should be a positive integer".into(),
+		}));
+	}
+
+	for job_id in req.unsubscribe {
+		subscriptions.remove(&job_id);
+	}
+	let new = req
+		.subscribe
+		.iter()
+		.collect::<HashSet<_>>()
+		.into_iter()
+		.filter(|job_id| !subscriptions.contains_key(job_id))
+		.count();
+	if subscriptions.len() + new > MAX_WS_SUBSCRIPTIONS {
+		return Some(ws_message(&WsError {
+			job_id: None,
+			error: format!("at most {} jobs can be subscribed to", MAX_WS_SUBSCRIPTIONS),
+		}));
+	}
+	for job_id in req.subscribe {
+		subscriptions.entry(job_id).or_insert(None);
+	}
+
+	None
+}
+
+async fn status_socket(
+	ws: WebSocket,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) {
+	let (mut tx, mut rx) = ws.split();
+	let mut subscriptions = Subscriptions::new();
+	let mut has_subscribed = false;
+	let mut interval = tokio::time::interval(WS_POLL_INTERVAL);
+
+	loop {
+		let messages = tokio::select! {
+			message = rx.next() => match message {
+				Some(Ok(message)) if message.is_close() => break,
+				Some(Ok(message)) => {
+					let error = handle_request(&mut subscriptions, &message);
+					has_subscribed |= !subscriptions.is_empty();
+					// Reply to new subscriptions right away.
+					let mut messages: Vec<Message> = error.into_iter().collect();
+					messages.extend(poll_statuses(&mut subscriptions, &conn_pool, &status_cache, &settings).await);
+					messages
+				}
+				_ => break,
+			},
+			_ = interval.tick() => poll_statuses(&mut subscriptions, &conn_pool, &status_cache, &settings).await,
+		};
+
+		for message in messages {
+			if tx.send(message).await.is_err() {
+				return;
+			}
+		}
+
+		if has_subscribed && subscriptions.is_empty() {
+			break;
+		}
+	}
+
+	let _ = tx.send(Message::close()).await;
+}
+
+/// Create the `GET /v0/bulk/ws` endpoint.
+pub fn get_job_status_ws(
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+	settings: Settings,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / "ws")
+		.and(warp::ws())
+		.map(move |ws: warp::ws::Ws| {
+			let conn_pool = conn_pool.clone();
+			let status_cache = status_cache.clone();
+			let settings = settings.clone();
+			ws.on_upgrade(move |socket| status_socket(socket, conn_pool, status_cache, settings))
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
@@ -0,0 +1,283 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the precomputed summaries of large bulk jobs. If
+//! `RCH_SUMMARY_MIN_RECORDS` is set, the status of a job with more records
+//! serves a stored summary instead of aggregating all of its results on each
+//! request, and a background task periodically refreshes the summaries of
+//! these jobs whenever their number of results changed. Their results are
+//! never aggregated on request: until the task first stores the summary, the
+//! status has an empty one. Admins can also recompute the summary of a job
+//! with `POST /v0/bulk/{id}/recompute-summary`.
+
+use super::get::{live_job_summary, JobStatusSummaryResponseBody};
+use super::status_cache::JobStatusCache;
+use super::{job_id_param, JobId};
+use crate::auth::with_admin_key;
+use crate::errors::{ReacherError, ReacherResponseError};
+use crate::tracing_util::TimedQuery;
+use serde::Serialize;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use std::env;
+use std::sync::Arc;
+use warp::{http, Filter};
+
+/// Interval between two runs of the summary refresh task.
+const SUMMARY_TASK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Number of records above which the summary of a job is precomputed, read
+/// from `RCH_SUMMARY_MIN_RECORDS`. Summaries are always computed on request
+/// if it's not set.
+///
+/// # Panics
+///
+/// Panics if `RCH_SUMMARY_MIN_RECORDS` is not a non-negative integer.
+pub fn summary_min_records() -> Option<i32> {
+	env::var("RCH_SUMMARY_MIN_RECORDS").ok().map(|min_records| {
+		min_records
+			.parse::<i32>()
+			.ok()
+			.filter(|min_records| *min_records >= 0)
+			.expect("Environment variable RCH_SUMMARY_MIN_RECORDS is malformed.")
+	})
+}
+
+/// The stored summary of a job.
+pub(super) struct PrecomputedSummary {
+	pub(super) summary: JobStatusSummaryResponseBody,
+	pub(super) refreshed_at: DateTime<Utc>,
+}
+
+/// Get the stored summary of a job, if the background task stored one yet.
+pub(super) async fn precomputed_job_summary(
+	job_id: i32,
+	conn_pool: &Pool<Postgres>,
+) -> Result<Option<PrecomputedSummary>, warp::Rejection> {
+	let rec = sqlx::query!(
+		r#"
+		SELECT refreshed_at, summary FROM bulk_job_summaries
+		WHERE job_id = $1
+		"#,
+		job_id
+	)
+	.fetch_optional(conn_pool)
+	.timed("job_summary", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get summary for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+
+	match rec {
+		Some(rec) => {
+			let summary = serde_json::from_value(rec.summary).map_err(|e| {
+				log::error!(
+					target:"reacher",
+					"Failed to parse summary for [job_id={}] with [error={}]",
+					job_id,
+					e
+				);
+
+				ReacherError::Json()
+			})?;
+
+			Ok(Some(PrecomputedSummary {
+				summary,
+				refreshed_at: rec.refreshed_at,
+			}))
+		}
+		None => Ok(None),
+	}
+}
+
+/// Compute the summary of a job, with its distinct domains, and store it.
+pub(super) async fn refresh_job_summary(
+	job_id: i32,
+	conn_pool: &Pool<Postgres>,
+) -> Result<PrecomputedSummary, warp::Rejection> {
+	let (total_processed, summary) = live_job_summary(job_id, true, conn_pool).await?;
+
+	let rec = sqlx::query!(
+		r#"
+		INSERT INTO bulk_job_summaries (job_id, refreshed_at, total_processed, summary)
+		VALUES ($1, NOW(), $2, $3)
+		ON CONFLICT (job_id) DO UPDATE
+		SET refreshed_at = EXCLUDED.refreshed_at,
+			total_processed = EXCLUDED.total_processed,
+			summary = EXCLUDED.summary
+		RETURNING refreshed_at
+		"#,
+		job_id,
+		total_processed as i32,
+		serde_json::json!(summary)
+	)
+	.fetch_one(conn_pool)
+	.timed("store_job_summary", job_id)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to store summary for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+
+	Ok(PrecomputedSummary {
+		summary,
+		refreshed_at: rec.refreshed_at,
+	})
+}
+
+/// Refresh the stored summaries of the large jobs whose number of results
+/// changed since their last refresh, e.g. with requeued results. Returns the
+/// number of refreshed summaries.
+pub async fn refresh_job_summaries(
+	conn_pool: &Pool<Postgres>,
+	min_records: i32,
+) -> Result<u64, ReacherError> {
+	let job_ids = sqlx::query!(
+		r#"
+		SELECT j.id FROM bulk_jobs j
+		LEFT JOIN bulk_job_summaries s ON s.job_id = j.id
+		WHERE j.total_records > $1
+			AND j.deleted_at IS NULL
+			AND (s.job_id IS NULL OR s.total_processed <> j.processed_count)
+		ORDER BY j.id
+		"#,
+		min_records
+	)
+	.fetch_all(conn_pool)
+	.await?;
+
+	let mut refreshed = 0;
+	for rec in job_ids {
+		match refresh_job_summary(rec.id, conn_pool).await {
+			Ok(_) => refreshed += 1,
+			// Already logged, try the next jobs.
+			Err(_) => continue,
+		}
+	}
+
+	Ok(refreshed)
+}
+
+#[derive(Serialize)]
+struct RecomputeSummaryResponseBody {
+	job_id: i32,
+	refreshed_at: DateTime<Utc>,
+	summary: JobStatusSummaryResponseBody,
+}
+
+/// Aggregate the results of a job again, and overwrite its stored summary,
+/// e.g. after its results were fixed by hand. The background task only
+/// refreshes the summaries whose number of results changed.
+async fn recompute_summary(
+	job_id: JobId,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let job = sqlx::query!(
+		r#"
+		SELECT id FROM bulk_jobs
+		WHERE id = $1
+		"#,
+		job_id.get()
+	)
+	.fetch_optional(&conn_pool)
+	.timed("job_record", job_id.get())
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to get job record for [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+
+		ReacherError::from(e)
+	})?;
+	if job.is_none() {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} not found", job_id),
+		)
+		.into());
+	}
+
+	let precomputed = refresh_job_summary(job_id.get(), &conn_pool).await?;
+	// Otherwise the status of a completed job would serve the previous
+	// summary until it expires from the cache.
+	status_cache.invalidate(job_id.get());
+	log::info!(target:"reacher", "Recomputed the summary of [job_id={}]", job_id);
+
+	Ok(warp::reply::json(&RecomputeSummaryResponseBody {
+		job_id: job_id.get(),
+		refreshed_at: precomputed.refreshed_at,
+		summary: precomputed.summary,
+	}))
+}
+
+/// Create the `POST /v0/bulk/{id}/recompute-summary` admin endpoint.
+pub fn recompute_job_summary(
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "recompute-summary")
+		.and(warp::post())
+		.and(with_admin_key())
+		.and_then(job_id_param)
+		.and_then(move |job_id| recompute_summary(job_id, conn_pool.clone(), status_cache.clone()))
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Spawn the background task refreshing the summaries of large jobs, if a
+/// threshold is configured.
+pub fn spawn_summary_task(conn_pool: Pool<Postgres>) {
+	let min_records = match summary_min_records() {
+		Some(min_records) => min_records,
+		None => return,
+	};
+
+	tokio::spawn(async move {
+		let mut interval = tokio::time::interval(SUMMARY_TASK_INTERVAL);
+		loop {
+			interval.tick().await;
+			match refresh_job_summaries(&conn_pool, min_records).await {
+				Ok(refreshed) => log::debug!(
+					target:"reacher",
+					"Refreshed [count={}] job summaries",
+					refreshed
+				),
+				Err(e) => log::error!(
+					target:"reacher",
+					"Failed to refresh job summaries with [error={:?}]",
+					e
+				),
+			}
+		}
+	});
+}
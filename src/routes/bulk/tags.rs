@@ -0,0 +1,234 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `POST /v0/bulk/{id}/tags` and
+//! `DELETE /v0/bulk/{id}/tags/{tag}` endpoints, to organize jobs after they
+//! were submitted. Both return the updated tags of the job. The tags given
+//! at submission are checked the same way, see `check_tags`.
+
+use super::status_cache::JobStatusCache;
+use super::{job_id_and_param, job_id_param, JobId};
+use crate::errors::{ReacherError, ReacherResponseError};
+use crate::routes::MAX_BODY_BYTES;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use warp::{http, Filter};
+
+/// Maximum length of a tag, in characters.
+pub const MAX_TAG_LEN: usize = 64;
+
+/// Reject the tags which are empty, longer than `MAX_TAG_LEN`, or with other
+/// characters than ASCII letters, digits, `-`, `_` and `.`, which are safe to
+/// use unescaped in a path.
+fn check_tag(tag: &str) -> Result<(), ReacherResponseError> {
+	let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+	if tag.is_empty() || tag.len() > MAX_TAG_LEN || !tag.chars().all(allowed) {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::BAD_REQUEST,
+			format!(
+				"tag should have between 1 and {} letters, digits, '-', '_' or '.'",
+				MAX_TAG_LEN
+			),
+		));
+	}
+
+	Ok(())
+}
+
+/// Check the tags of a submitted job, and drop the duplicates, keeping the
+/// first of them.
+pub(super) fn check_tags(tags: &[String]) -> Result<Vec<String>, ReacherResponseError> {
+	let mut checked: Vec<String> = Vec::with_capacity(tags.len());
+	for tag in tags {
+		check_tag(tag)?;
+		if !checked.contains(tag) {
+			checked.push(tag.clone());
+		}
+	}
+
+	Ok(checked)
+}
+
+fn job_not_found(job_id: JobId) -> ReacherResponseError {
+	ReacherResponseError::new(
+		http::StatusCode::NOT_FOUND,
+		format!("job {} not found", job_id),
+	)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct AddTagRequestBody {
+	tag: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct JobTagsResponseBody {
+	job_id: i32,
+	tags: Vec<String>,
+}
+
+/// Add a tag to a job, if it doesn't have it already.
+async fn add_tag(
+	job_id: JobId,
+	body: AddTagRequestBody,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	check_tag(&body.tag)?;
+
+	let rec = sqlx::query!(
+		r#"
+		UPDATE bulk_jobs
+		SET tags = CASE WHEN $2 = ANY(tags) THEN tags ELSE array_append(tags, $2) END,
+			updated_at = NOW()
+		WHERE id = $1 AND deleted_at IS NULL
+		RETURNING tags
+		"#,
+		job_id.get(),
+		body.tag
+	)
+	.fetch_optional(&conn_pool)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to add tag to [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?
+	.ok_or_else(|| job_not_found(job_id))?;
+
+	// The tags are part of the cached status.
+	status_cache.invalidate(job_id.get());
+
+	Ok(warp::reply::json(&JobTagsResponseBody {
+		job_id: job_id.get(),
+		tags: rec.tags,
+	}))
+}
+
+/// Remove a tag from a job, with a 404 if the job doesn't have it. The tag
+/// isn't checked, so that the tags of the jobs submitted before they were
+/// can still be removed.
+async fn remove_tag(
+	job_id: JobId,
+	tag: String,
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let rec = sqlx::query!(
+		r#"
+		WITH job AS (
+			SELECT id, $2 = ANY(tags) AS tagged FROM bulk_jobs
+			WHERE id = $1 AND deleted_at IS NULL
+			FOR UPDATE
+		)
+		UPDATE bulk_jobs j
+		SET tags = array_remove(j.tags, $2), updated_at = NOW()
+		FROM job
+		WHERE j.id = job.id
+		RETURNING j.tags, job.tagged AS "tagged!"
+		"#,
+		job_id.get(),
+		tag
+	)
+	.fetch_optional(&conn_pool)
+	.await
+	.map_err(|e| {
+		log::error!(
+			target:"reacher",
+			"Failed to remove tag from [job_id={}] with [error={}]",
+			job_id,
+			e
+		);
+		ReacherError::from(e)
+	})?
+	.ok_or_else(|| job_not_found(job_id))?;
+	if !rec.tagged {
+		return Err(ReacherResponseError::new(
+			http::StatusCode::NOT_FOUND,
+			format!("job {} has no tag {}", job_id, tag),
+		)
+		.into());
+	}
+
+	status_cache.invalidate(job_id.get());
+
+	Ok(warp::reply::json(&JobTagsResponseBody {
+		job_id: job_id.get(),
+		tags: rec.tags,
+	}))
+}
+
+/// Create the `POST /v0/bulk/{id}/tags` endpoint.
+pub fn add_job_tag(
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "tags")
+		.and(warp::post())
+		.and_then(job_id_param)
+		.and(warp::body::content_length_limit(MAX_BODY_BYTES))
+		.and(warp::body::json())
+		.and_then(move |job_id, body| {
+			add_tag(job_id, body, conn_pool.clone(), status_cache.clone())
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+/// Create the `DELETE /v0/bulk/{id}/tags/{tag}` endpoint.
+pub fn remove_job_tag(
+	conn_pool: Pool<Postgres>,
+	status_cache: Arc<JobStatusCache>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "bulk" / i32 / "tags" / String)
+		.and(warp::delete())
+		.and_then(job_id_and_param)
+		.untuple_one()
+		.and_then(move |job_id, tag| {
+			remove_tag(job_id, tag, conn_pool.clone(), status_cache.clone())
+		})
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log("reacher"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{check_tag, check_tags, MAX_TAG_LEN};
+
+	#[test]
+	fn test_check_tag() {
+		assert!(check_tag("campaign-q3").is_ok());
+		assert!(check_tag("v1.2_signup").is_ok());
+		assert!(check_tag(&"a".repeat(MAX_TAG_LEN)).is_ok());
+		assert!(check_tag("").is_err());
+		assert!(check_tag(&"a".repeat(MAX_TAG_LEN + 1)).is_err());
+		assert!(check_tag("two words").is_err());
+		assert!(check_tag("a/b").is_err());
+		assert!(check_tag("é").is_err());
+	}
+
+	#[test]
+	fn test_check_tags() {
+		let tags = ["b", "a", "b"].map(String::from);
+		assert_eq!(check_tags(&tags).unwrap(), vec!["b", "a"]);
+		assert!(check_tags(&["a".into(), "a b".into()]).is_err());
+	}
+}
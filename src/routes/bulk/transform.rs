@@ -0,0 +1,38 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Hook to rewrite the results of a bulk job before they are serialized in
+//! the downloads and exports, e.g. to add derived fields or rename keys.
+
+use std::sync::Arc;
+
+/// Rewrites one raw result, as stored in the database, before it's
+/// serialized to json or csv.
+pub trait ResultTransformer: Send + Sync {
+	fn transform(&self, raw: &serde_json::Value) -> serde_json::Value;
+}
+
+/// The default transformer, which returns the results unchanged.
+pub struct IdentityTransformer;
+
+impl ResultTransformer for IdentityTransformer {
+	fn transform(&self, raw: &serde_json::Value) -> serde_json::Value {
+		raw.clone()
+	}
+}
+
+/// Transformer shared by all the endpoints of the server.
+pub type SharedTransformer = Arc<dyn ResultTransformer>;
@@ -17,6 +17,7 @@
 //! This file implements the `POST /check_email` endpoint.
 
 use crate::check::check_email;
+use crate::routes::MAX_BODY_BYTES;
 use check_if_email_exists::{CheckEmailInput, CheckEmailInputProxy};
 use serde::{Deserialize, Serialize};
 use std::env;
@@ -24,7 +25,7 @@ use warp::Filter;
 
 /// Endpoint request body.
 #[derive(Clone, Debug, Deserialize, Serialize)]
-struct EndpointRequest {
+pub struct EndpointRequest {
 	from_email: Option<String>,
 	hello_name: Option<String>,
 	proxy: Option<CheckEmailInputProxy>,
@@ -67,7 +68,7 @@ pub fn post_check_email() -> impl Filter<Extract = impl warp::Reply, Error = war
 		.and(warp::post())
 		// When accepting a body, we want a JSON body (and to reject huge
 		// payloads)...
-		.and(warp::body::content_length_limit(1024 * 16))
+		.and(warp::body::content_length_limit(MAX_BODY_BYTES))
 		.and(warp::body::json())
 		.and_then(handler)
 		// View access logs by setting `RUST_LOG=reacher`.
@@ -0,0 +1,102 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /v0/config` endpoint.
+
+use crate::check::SMTP_TIMEOUT;
+use crate::routes::bulk::get::{DownloadDefaults, JobResultResponseFormat, MAX_DOWNLOAD_LIMIT};
+use check_if_email_exists::CheckEmailInput;
+use serde::Serialize;
+use warp::Filter;
+
+/// SMTP settings used for bulk verifications when the request doesn't
+/// override them.
+#[derive(Debug, Serialize)]
+struct SmtpDefaults {
+	from_email: String,
+	hello_name: String,
+	smtp_port: u16,
+	smtp_timeout_secs: u64,
+}
+
+/// Optional features of the server.
+#[derive(Debug, Serialize)]
+struct Features {
+	webhooks: bool,
+}
+
+/// Endpoint response body.
+#[derive(Debug, Serialize)]
+struct EndpointConfig {
+	formats: &'static [JobResultResponseFormat],
+	max_download_limit: u64,
+	default_json_limit: u64,
+	default_csv_limit: u64,
+	smtp: SmtpDefaults,
+	features: Features,
+}
+
+fn config(download_defaults: DownloadDefaults) -> EndpointConfig {
+	let input = CheckEmailInput::default();
+
+	EndpointConfig {
+		formats: &JobResultResponseFormat::ALL,
+		max_download_limit: MAX_DOWNLOAD_LIMIT,
+		default_json_limit: download_defaults.json_limit,
+		default_csv_limit: download_defaults.csv_limit,
+		smtp: SmtpDefaults {
+			from_email: input.from_email,
+			hello_name: input.hello_name,
+			smtp_port: input.smtp_port,
+			smtp_timeout_secs: SMTP_TIMEOUT,
+		},
+		features: Features { webhooks: false },
+	}
+}
+
+/// Create the `GET /v0/config` endpoint.
+pub fn get_config(
+	download_defaults: DownloadDefaults,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "config")
+		.and(warp::get())
+		.map(move || warp::reply::json(&config(download_defaults)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::get_config;
+	use crate::routes::bulk::get::DownloadDefaults;
+	use warp::http::StatusCode;
+	use warp::test::request;
+
+	#[tokio::test]
+	async fn test_get_config() {
+		let resp = request()
+			.path("/v0/config")
+			.method("GET")
+			.reply(&get_config(DownloadDefaults::default()))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+		assert_eq!(
+			body["formats"],
+			serde_json::json!(["json", "csv", "json_array", "txt"])
+		);
+		assert_eq!(body["max_download_limit"], 10_000);
+	}
+}
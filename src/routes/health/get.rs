@@ -0,0 +1,94 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /health/ready` endpoint, reporting whether
+//! the database has been migrated to what the code expects.
+
+use crate::errors::ReacherError;
+use serde::{Deserialize, Serialize};
+use sqlx::migrate::Migrator;
+use sqlx::{Pool, Postgres};
+use warp::http::StatusCode;
+use warp::Filter;
+
+/// The migrations of the `migrations` folder, embedded at build time. The
+/// sqlxmq ones, in a subfolder, aren't part of it.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Endpoint response body.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ReadyResponseBody {
+	/// `ready`, or `migration needed` if some migrations weren't applied.
+	status: String,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	missing_migrations: Vec<String>,
+}
+
+/// The embedded migrations which weren't applied successfully, as
+/// `{version} {description}`, according to the `_sqlx_migrations` table
+/// `sqlx migrate run` keeps. Without that table, none were applied.
+pub async fn missing_migrations(conn_pool: &Pool<Postgres>) -> Result<Vec<String>, ReacherError> {
+	// The table may not exist, so it's not checked at compile time.
+	let applied: Vec<i64> =
+		match sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+			.fetch_all(conn_pool)
+			.await
+		{
+			Ok(applied) => applied,
+			// undefined_table
+			Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("42P01") => vec![],
+			Err(e) => {
+				log::error!(
+					target:"reacher",
+					"Failed to get the applied migrations with [error={}]",
+					e
+				);
+				return Err(e.into());
+			}
+		};
+
+	Ok(MIGRATOR
+		.iter()
+		.filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+		.map(|m| format!("{} {}", m.version, m.description))
+		.collect())
+}
+
+async fn ready(conn_pool: Pool<Postgres>) -> Result<impl warp::Reply, warp::Rejection> {
+	let missing_migrations = missing_migrations(&conn_pool).await?;
+	let (status, code) = if missing_migrations.is_empty() {
+		("ready", StatusCode::OK)
+	} else {
+		("migration needed", StatusCode::SERVICE_UNAVAILABLE)
+	};
+
+	Ok(warp::reply::with_status(
+		warp::reply::json(&ReadyResponseBody {
+			status: status.into(),
+			missing_migrations,
+		}),
+		code,
+	))
+}
+
+/// Create the `GET /health/ready` endpoint.
+pub fn get_ready(
+	conn_pool: Pool<Postgres>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("health" / "ready")
+		.and(warp::get())
+		.and_then(move || ready(conn_pool.clone()))
+}
@@ -0,0 +1,84 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /metrics` endpoint, in the Prometheus text
+//! format.
+
+use crate::routes::bulk::status_cache::JobStatusCache;
+use std::fmt::Write;
+use std::sync::Arc;
+use warp::Filter;
+
+fn metrics(status_cache: &JobStatusCache) -> String {
+	let mut out = String::new();
+	for (name, help, value) in [
+		(
+			"reacher_job_status_cache_hits_total",
+			"Job status requests served from the cache.",
+			status_cache.hits(),
+		),
+		(
+			"reacher_job_status_cache_misses_total",
+			"Job status requests not found in the cache.",
+			status_cache.misses(),
+		),
+	] {
+		let _ = writeln!(out, "# HELP {} {}", name, help);
+		let _ = writeln!(out, "# TYPE {} counter", name);
+		let _ = writeln!(out, "{} {}", name, value);
+	}
+
+	out
+}
+
+/// Create the `GET /metrics` endpoint.
+pub fn get_metrics(
+	status_cache: Arc<JobStatusCache>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("metrics").and(warp::get()).map(move || {
+		warp::reply::with_header(
+			metrics(&status_cache),
+			"Content-Type",
+			"text/plain; version=0.0.4",
+		)
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::get_metrics;
+	use crate::routes::bulk::status_cache::JobStatusCache;
+	use std::sync::Arc;
+	use warp::http::StatusCode;
+	use warp::test::request;
+
+	#[tokio::test]
+	async fn test_get_metrics() {
+		let status_cache = Arc::new(JobStatusCache::default());
+		status_cache.get(1, false);
+
+		let resp = request()
+			.path("/metrics")
+			.method("GET")
+			.reply(&get_metrics(status_cache))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body = String::from_utf8(resp.body().to_vec()).unwrap();
+		assert!(body.contains("\nreacher_job_status_cache_hits_total 0\n"));
+		assert!(body.contains("\nreacher_job_status_cache_misses_total 1\n"));
+	}
+}
@@ -16,19 +16,132 @@
 
 pub mod bulk;
 pub mod check_email;
+pub mod config;
+pub mod health;
+pub mod metrics;
+pub mod schema;
 pub mod version;
 
+use super::auth::{with_api_key, ApiKeyCache};
 use super::errors;
+use super::settings::Settings;
+use bulk::download_limit::{max_downloads_per_job, JobDownloadLimiter};
+use bulk::status_cache::JobStatusCache;
+use bulk::transform::{IdentityTransformer, SharedTransformer};
 use sqlx::{Pool, Postgres};
+use std::sync::Arc;
 use warp::Filter;
 
+/// Maximum size in bytes of the JSON body of the POST endpoints, except the
+/// bulk submissions, see `bulk::post::max_submission_bytes`.
+pub const MAX_BODY_BYTES: u64 = 1024 * 16;
+
 pub fn create_routes(
 	conn_pool: Pool<Postgres>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-	version::get::get_version()
+	create_routes_with_transformer(conn_pool, Arc::new(IdentityTransformer))
+}
+
+/// Same as [`create_routes`], with a transformer applied to the bulk results
+/// before they're downloaded or exported.
+pub fn create_routes_with_transformer(
+	conn_pool: Pool<Postgres>,
+	transformer: SharedTransformer,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	let status_cache = Arc::new(JobStatusCache::default());
+
+	let api_key_cache = Arc::new(ApiKeyCache::default());
+
+	let download_limiter = Arc::new(JobDownloadLimiter::new(max_downloads_per_job()));
+	let settings = Settings::from_env();
+
+	let endpoints = version::get::get_version()
+		.or(config::get::get_config(settings.download_defaults))
+		.or(metrics::get::get_metrics(status_cache.clone()))
+		.or(health::get::get_ready(conn_pool.clone()))
+		.or(schema::get::get_schema())
 		.or(check_email::post::post_check_email())
-		.or(bulk::post::create_bulk_email_vrfy_job(conn_pool.clone()))
-		.or(bulk::get::get_job_status(conn_pool.clone()))
-		.or(bulk::get::get_job_result(conn_pool))
-		.recover(errors::handle_rejection)
+		.or(bulk::post::create_bulk_email_vrfy_job(
+			conn_pool.clone(),
+			settings.clone(),
+		))
+		.or(bulk::post::append_bulk_email_vrfy_job(
+			conn_pool.clone(),
+			status_cache.clone(),
+		))
+		.or(bulk::post::finalize_bulk_email_vrfy_job(
+			conn_pool.clone(),
+			status_cache.clone(),
+			settings.clone(),
+		))
+		.or(bulk::post::requeue_unknowns_job(
+			conn_pool.clone(),
+			status_cache.clone(),
+		))
+		.or(bulk::post::create_download_url(
+			conn_pool.clone(),
+			settings.clone(),
+		))
+		.or(bulk::csv_schema::get_csv_schema())
+		.or(bulk::delete::purge_deleted_bulk_jobs(conn_pool.clone()))
+		.or(bulk::get::get_job_list(conn_pool.clone()))
+		.or(bulk::status_ws::get_job_status_ws(
+			conn_pool.clone(),
+			status_cache.clone(),
+			settings.clone(),
+		))
+		.or(bulk::badge::get_job_progress_badge(
+			conn_pool.clone(),
+			status_cache.clone(),
+			settings.clone(),
+		))
+		.or(bulk::summary::recompute_job_summary(
+			conn_pool.clone(),
+			status_cache.clone(),
+		))
+		.or(bulk::delete::delete_bulk_job(
+			conn_pool.clone(),
+			status_cache.clone(),
+			settings.clone(),
+		))
+		.or(bulk::tags::add_job_tag(
+			conn_pool.clone(),
+			status_cache.clone(),
+		))
+		.or(bulk::tags::remove_job_tag(
+			conn_pool.clone(),
+			status_cache.clone(),
+		))
+		.or(bulk::get::get_job_status(
+			conn_pool.clone(),
+			status_cache,
+			settings.clone(),
+		))
+		.or(bulk::get::get_job_result(
+			conn_pool.clone(),
+			transformer.clone(),
+			download_limiter,
+			settings.clone(),
+		))
+		.or(bulk::get::get_job_result_count(conn_pool.clone()))
+		.or(bulk::histogram::get_job_histogram(conn_pool.clone()))
+		.or(bulk::distribution::get_job_distribution(conn_pool.clone()))
+		.or(bulk::get::get_job_input_result(conn_pool.clone()))
+		.or(bulk::export::create_job_export(
+			conn_pool.clone(),
+			transformer.clone(),
+			settings.clone(),
+		))
+		.or(bulk::export::get_job_export_status(conn_pool.clone()))
+		.or(bulk::export::get_job_export_download(conn_pool.clone()))
+		.or(bulk::export::get_combined_export(
+			conn_pool.clone(),
+			transformer,
+		));
+	let verbose_errors = settings.verbose_errors;
+	let routes = with_api_key(conn_pool, api_key_cache, settings)
+		.and(endpoints)
+		.recover(move |err| errors::handle_rejection(err, verbose_errors));
+
+	errors::errors_as_200(routes)
 }
@@ -0,0 +1,86 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /v0/schema/{resource}` endpoint, returning
+//! the JSON Schema of the API payloads.
+
+use crate::errors::ReacherResponseError;
+use crate::routes::bulk::get::{JobResultCsvResponse, JobStatusResponseBody};
+use schemars::schema_for;
+use warp::{http, Filter};
+
+async fn schema(resource: String) -> Result<impl warp::Reply, warp::Rejection> {
+	let schema = match resource.as_str() {
+		"status" => schema_for!(JobStatusResponseBody),
+		"result" => schema_for!(JobResultCsvResponse),
+		_ => {
+			return Err(ReacherResponseError::new(
+				http::StatusCode::NOT_FOUND,
+				"resource should be one of: status, result",
+			)
+			.into())
+		}
+	};
+
+	Ok(warp::reply::json(&schema))
+}
+
+/// Create the `GET /v0/schema/{resource}` endpoint.
+pub fn get_schema() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+	warp::path!("v0" / "schema" / String)
+		.and(warp::get())
+		.and_then(schema)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::get_schema;
+	use crate::errors::handle_rejection;
+	use warp::http::StatusCode;
+	use warp::test::request;
+	use warp::Filter;
+
+	#[tokio::test]
+	async fn test_get_schema() {
+		let resp = request()
+			.path("/v0/schema/status")
+			.method("GET")
+			.reply(&get_schema())
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK);
+		let schema: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+		for property in ["job_id", "created_at", "job_status", "summary"] {
+			assert!(schema["properties"].get(property).is_some(), "{}", property);
+		}
+
+		let resp = request()
+			.path("/v0/schema/result")
+			.method("GET")
+			.reply(&get_schema())
+			.await;
+		let schema: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+		assert!(schema["properties"].get("smtp.is_deliverable").is_some());
+		assert!(schema["properties"].get("mx_records").is_none());
+
+		let resp = request()
+			.path("/v0/schema/foo")
+			.method("GET")
+			.reply(&get_schema().recover(|err| handle_rejection(err, false)))
+			.await;
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	}
+}
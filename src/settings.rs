@@ -0,0 +1,134 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Settings of the endpoints read from the environment. They're read once
+//! when the routes are created, so that a malformed variable fails at
+//! startup rather than in the middle of a request, and passed to the filters
+//! needing them.
+
+use crate::auth::{api_key_auth, url_signing_key};
+use crate::errors::verbose_errors;
+use crate::routes::bulk::delete::{job_delete_mode, JobDeleteMode};
+use crate::routes::bulk::expiry::job_retention;
+use crate::routes::bulk::export::instance_id;
+use crate::routes::bulk::freshness::result_freshness;
+use crate::routes::bulk::get::{
+	csv_number_format, max_response_bytes, CsvNumberFormat, DownloadDefaults,
+};
+use crate::routes::bulk::notify::notify_new_jobs;
+use crate::routes::bulk::owner_limit::{
+	max_running_jobs_per_owner, owner_limit_action, OwnerLimitAction,
+};
+use crate::routes::bulk::summary::summary_min_records;
+use crate::tracing_util::slow_query_threshold;
+use chrono::Duration;
+use std::env;
+use std::sync::Arc;
+
+/// Whether the flag environment variable `name` is set to `true` or `1`. An
+/// unset flag is false.
+///
+/// # Panics
+///
+/// Panics if the variable is set to anything but `true`, `false`, `1` or
+/// `0`.
+pub fn env_flag(name: &str) -> bool {
+	match env::var(name).as_deref() {
+		Err(_) | Ok("false") | Ok("0") => false,
+		Ok("true") | Ok("1") => true,
+		Ok(_) => panic!("Environment variable {} is malformed.", name),
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+	/// See [`DownloadDefaults::from_env`].
+	pub download_defaults: DownloadDefaults,
+	/// See [`job_retention`].
+	pub job_retention: Option<Duration>,
+	/// See [`max_response_bytes`].
+	pub max_response_bytes: Option<usize>,
+	/// See [`csv_number_format`].
+	pub csv_number_format: CsvNumberFormat,
+	/// See [`summary_min_records`].
+	pub summary_min_records: Option<i32>,
+	/// See [`result_freshness`].
+	pub result_freshness: Duration,
+	/// See [`job_delete_mode`].
+	pub job_delete_mode: JobDeleteMode,
+	/// See [`url_signing_key`].
+	pub url_signing_key: Option<Arc<[u8]>>,
+	/// See [`instance_id`].
+	pub instance_id: Arc<str>,
+	/// See [`api_key_auth`].
+	pub api_key_auth: bool,
+	/// See [`verbose_errors`].
+	pub verbose_errors: bool,
+	/// See [`max_running_jobs_per_owner`].
+	pub max_running_jobs_per_owner: Option<i64>,
+	/// See [`owner_limit_action`].
+	pub owner_limit_action: OwnerLimitAction,
+}
+
+impl Settings {
+	/// Read all the settings from the environment.
+	///
+	/// # Panics
+	///
+	/// Panics if one of the variables is malformed.
+	pub fn from_env() -> Self {
+		// The timed queries read it on their own, deep down the handlers.
+		slow_query_threshold();
+		// Jobs are also started by the background tasks, which read it on
+		// their own.
+		notify_new_jobs();
+
+		Settings {
+			download_defaults: DownloadDefaults::from_env(),
+			job_retention: job_retention(),
+			max_response_bytes: max_response_bytes(),
+			csv_number_format: csv_number_format(),
+			summary_min_records: summary_min_records(),
+			result_freshness: result_freshness(),
+			job_delete_mode: job_delete_mode(),
+			url_signing_key: url_signing_key(),
+			instance_id: instance_id().into(),
+			api_key_auth: api_key_auth(),
+			verbose_errors: verbose_errors(),
+			max_running_jobs_per_owner: max_running_jobs_per_owner(),
+			owner_limit_action: owner_limit_action(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::env_flag;
+	use std::env;
+
+	#[test]
+	fn test_env_flag() {
+		let name = "RCH_TEST_ENV_FLAG";
+		assert!(!env_flag(name));
+		for (value, flag) in [("true", true), ("1", true), ("false", false), ("0", false)] {
+			env::set_var(name, value);
+			assert_eq!(env_flag(name), flag, "{}", value);
+		}
+
+		env::set_var(name, "yes");
+		assert!(std::panic::catch_unwind(|| env_flag(name)).is_err());
+	}
+}
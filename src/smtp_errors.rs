@@ -0,0 +1,106 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Classification of verification errors as transient or permanent, used to
+//! requeue transient errors and to split errors in the job summary.
+//!
+//! An error is classified from its `"{type}: {message}"` string, e.g.
+//! `"TimeoutError: future has timed out"`, by matching case-insensitive
+//! substrings. Transient substrings are checked first. The table can be
+//! overridden with a JSON file, whose path is set in the
+//! `RCH_SMTP_ERROR_CLASSIFICATION` environment variable:
+//!
+//! ```json
+//! { "transient": ["TimeoutError", "transient:"], "permanent": ["permanent:"] }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::{env, fs, sync::OnceLock};
+
+/// Substrings of error strings identifying each class: transient errors
+/// are worth retrying later, e.g. timeouts or greylisting, permanent ones
+/// aren't, e.g. a domain that doesn't exist.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SmtpErrorClassification {
+	pub transient: Vec<String>,
+	pub permanent: Vec<String>,
+}
+
+impl Default for SmtpErrorClassification {
+	fn default() -> Self {
+		let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect();
+
+		SmtpErrorClassification {
+			transient: strings(&["TimeoutError", "transient:", "timeout:", "io:"]),
+			permanent: strings(&["permanent:", "NXDOMAIN"]),
+		}
+	}
+}
+
+/// Turn a substring into an `ILIKE` pattern matching it anywhere.
+fn like_pattern(substring: &str) -> String {
+	let escaped = substring
+		.replace('\\', "\\\\")
+		.replace('%', "\\%")
+		.replace('_', "\\_");
+
+	format!("%{}%", escaped)
+}
+
+impl SmtpErrorClassification {
+	/// `ILIKE` patterns of the transient errors, for SQL queries.
+	pub fn transient_patterns(&self) -> Vec<String> {
+		self.transient.iter().map(|s| like_pattern(s)).collect()
+	}
+
+	/// `ILIKE` patterns of the permanent errors, for SQL queries. As
+	/// transient substrings are checked first, queries should only apply
+	/// them to non-transient errors.
+	pub fn permanent_patterns(&self) -> Vec<String> {
+		self.permanent.iter().map(|s| like_pattern(s)).collect()
+	}
+}
+
+static CLASSIFICATION: OnceLock<SmtpErrorClassification> = OnceLock::new();
+
+/// The error classification, loaded on first use.
+///
+/// # Panics
+///
+/// Panics if the file set in `RCH_SMTP_ERROR_CLASSIFICATION` can't be read
+/// or parsed. Call it at startup to fail early.
+pub fn smtp_error_classification() -> &'static SmtpErrorClassification {
+	CLASSIFICATION.get_or_init(|| match env::var("RCH_SMTP_ERROR_CLASSIFICATION") {
+		Ok(path) => {
+			let content = fs::read_to_string(&path)
+				.expect("Failed to read the RCH_SMTP_ERROR_CLASSIFICATION file.");
+			serde_json::from_str(&content)
+				.expect("The RCH_SMTP_ERROR_CLASSIFICATION file is malformed.")
+		}
+		Err(_) => SmtpErrorClassification::default(),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::like_pattern;
+
+	#[test]
+	fn test_like_pattern() {
+		assert_eq!(like_pattern("io:"), "%io:%");
+		assert_eq!(like_pattern("100%_done"), "%100\\%\\_done%");
+	}
+}
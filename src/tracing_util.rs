@@ -0,0 +1,246 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Helper functions for distributed tracing with OpenTelemetry.
+//!
+//! Incoming requests can carry a W3C trace context in their `traceparent` and
+//! `tracestate` headers, which is used as parent of the spans we create. If
+//! `RCH_OTLP_ENDPOINT` is set, spans are exported to that OTLP collector.
+//!
+//! Database queries are also timed, and logged when they're slower than
+//! `SLOW_QUERY_MS`. Some responses break their time down in a
+//! `Server-Timing` header, shown by browser devtools.
+
+use futures::future::{BoxFuture, Future, FutureExt};
+use opentelemetry::{
+	global,
+	propagation::Extractor,
+	sdk::{propagation::TraceContextPropagator, trace, Resource},
+	Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use std::{
+	convert::Infallible,
+	env,
+	sync::OnceLock,
+	time::{Duration, Instant},
+};
+use tracing::Instrument;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use warp::{http::HeaderMap, Filter};
+
+/// Setup tracing. This always registers the W3C trace context propagator,
+/// and only exports spans if `RCH_OTLP_ENDPOINT` is set.
+pub fn setup_tracing() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+	global::set_text_map_propagator(TraceContextPropagator::new());
+
+	let endpoint = match env::var("RCH_OTLP_ENDPOINT") {
+		Ok(endpoint) => endpoint,
+		Err(_) => return Ok(()),
+	};
+
+	let tracer = opentelemetry_otlp::new_pipeline()
+		.tracing()
+		.with_exporter(
+			opentelemetry_otlp::new_exporter()
+				.tonic()
+				.with_endpoint(endpoint.as_str()),
+		)
+		.with_trace_config(
+			trace::config().with_resource(Resource::new(vec![KeyValue::new(
+				"service.name",
+				"reacher",
+			)])),
+		)
+		.install_batch(opentelemetry::runtime::Tokio)?;
+
+	tracing_subscriber::registry()
+		.with(tracing_opentelemetry::layer().with_tracer(tracer))
+		.try_init()?;
+
+	log::info!(target: "reacher", "Exporting traces to {}.", endpoint);
+
+	Ok(())
+}
+
+/// Reads the trace context from HTTP headers.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+	fn get(&self, key: &str) -> Option<&str> {
+		self.0.get(key).and_then(|value| value.to_str().ok())
+	}
+
+	fn keys(&self) -> Vec<&str> {
+		self.0.keys().map(|key| key.as_str()).collect()
+	}
+}
+
+/// Warp filter extracting the trace context of the incoming request. The
+/// context is empty if the request doesn't have a valid `traceparent`.
+pub fn with_trace_context() -> impl Filter<Extract = (Context,), Error = Infallible> + Clone {
+	warp::header::headers_cloned().map(|headers: HeaderMap| {
+		global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(&headers)))
+	})
+}
+
+static SLOW_QUERY_THRESHOLD: OnceLock<Option<Duration>> = OnceLock::new();
+
+/// Queries taking longer than this are logged, if set with `SLOW_QUERY_MS`.
+/// It's read once, on the first call, as the queries are timed deep down the
+/// handlers.
+///
+/// # Panics
+///
+/// Panics if `SLOW_QUERY_MS` is not an integer. Call it at startup to fail
+/// early.
+pub fn slow_query_threshold() -> Option<Duration> {
+	*SLOW_QUERY_THRESHOLD.get_or_init(|| {
+		env::var("SLOW_QUERY_MS").ok().map(|ms| {
+			Duration::from_millis(
+				ms.parse()
+					.expect("Environment variable SLOW_QUERY_MS is malformed."),
+			)
+		})
+	})
+}
+
+/// Log a warning if the query took longer than the threshold. Returns
+/// whether it did.
+fn log_slow_query(
+	query: &str,
+	subject: &str,
+	elapsed: Duration,
+	threshold: Option<Duration>,
+) -> bool {
+	match threshold {
+		Some(threshold) if elapsed > threshold => {
+			log::warn!(
+				target: "reacher",
+				"Slow query [query={}] for [{}] took [elapsed_ms={}]",
+				query,
+				subject,
+				elapsed.as_millis()
+			);
+			true
+		}
+		_ => false,
+	}
+}
+
+/// Timing of the database queries, see `slow_query_threshold`.
+pub trait TimedQuery: Future + Send + Sized {
+	/// Run the query in a `db.query` span named after it, and log it if it's
+	/// slow.
+	fn timed<'a>(self, query: &'static str, job_id: i32) -> BoxFuture<'a, Self::Output>
+	where
+		Self: 'a,
+	{
+		self.timed_for(query, format!("job_id={}", job_id))
+	}
+
+	/// Same as `timed`, for the queries not about a single job. The subject
+	/// is logged as is, e.g. `owner=1`.
+	fn timed_for<'a>(self, query: &'static str, subject: String) -> BoxFuture<'a, Self::Output>
+	where
+		Self: 'a,
+	{
+		async move {
+			let start = Instant::now();
+			let output = self
+				.instrument(tracing::info_span!("db.query", query))
+				.await;
+			log_slow_query(query, &subject, start.elapsed(), slow_query_threshold());
+			output
+		}
+		.boxed()
+	}
+}
+
+impl<F: Future + Send> TimedQuery for F {}
+
+/// Durations of the phases of a request, for its `Server-Timing` header.
+#[derive(Debug, Default)]
+pub struct ServerTiming(Vec<(&'static str, Duration)>);
+
+impl ServerTiming {
+	/// Add the time elapsed since `start` to the phase, e.g. `db_record`.
+	pub fn record(&mut self, phase: &'static str, start: Instant) {
+		self.add(phase, start.elapsed());
+	}
+
+	/// Add the duration to the phase, for the phases not timed in one go.
+	pub fn add(&mut self, phase: &'static str, elapsed: Duration) {
+		match self.0.iter_mut().find(|(name, _)| *name == phase) {
+			Some((_, duration)) => *duration += elapsed,
+			None => self.0.push((phase, elapsed)),
+		}
+	}
+
+	/// The header value, e.g. `db_record;dur=1.250, serialize;dur=0.031`,
+	/// durations being in milliseconds.
+	pub fn header_value(&self) -> String {
+		self.0
+			.iter()
+			.map(|(phase, duration)| {
+				format!("{};dur={:.3}", phase, duration.as_secs_f64() * 1000.0)
+			})
+			.collect::<Vec<_>>()
+			.join(", ")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{log_slow_query, ServerTiming};
+	use std::time::{Duration, Instant};
+
+	#[test]
+	fn test_server_timing() {
+		let mut timing = ServerTiming::default();
+		let start = Instant::now() - Duration::from_millis(2);
+		timing.record("db_record", start);
+		timing.record("serialize", Instant::now());
+		timing.record("db_record", start);
+
+		let value = timing.header_value();
+		let phases: Vec<&str> = value.split(", ").collect();
+		assert_eq!(phases.len(), 2, "{}", value);
+		assert!(phases[0].starts_with("db_record;dur="), "{}", value);
+		let dur: f64 = phases[0]["db_record;dur=".len()..].parse().unwrap();
+		assert!(dur >= 4.0, "{}", value);
+		assert!(phases[1].starts_with("serialize;dur="), "{}", value);
+	}
+
+	#[test]
+	fn test_log_slow_query() {
+		let ms = Duration::from_millis;
+		assert!(log_slow_query(
+			"job_record",
+			"job_id=1",
+			ms(20),
+			Some(ms(10))
+		));
+		assert!(!log_slow_query(
+			"job_record",
+			"job_id=1",
+			ms(10),
+			Some(ms(10))
+		));
+		// Disabled without a threshold.
+		assert!(!log_slow_query("job_record", "job_id=1", ms(20), None));
+	}
+}
@@ -0,0 +1,2857 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the `/v0/bulk` endpoints. These tests need a
+//! Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::bulk::post::email_verification_task;
+use reacher_backend::routes::bulk::processed_count::reconcile_processed_count;
+use reacher_backend::routes::bulk::transform::ResultTransformer;
+use reacher_backend::routes::{create_routes, create_routes_with_transformer};
+use serde_json::Value;
+use sqlx::postgres::PgPoolOptions;
+use std::{
+	env,
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_download_sample() {
+	let pool = pool().await;
+	let results: Vec<Value> = (0..2000)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?sample=0.1&limit=5000",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let count = body["results"].as_array().unwrap().len();
+	// Expect ~200 rows, the bounds are more than 5 standard deviations away.
+	assert!((120..280).contains(&count), "got {} rows", count);
+}
+
+#[tokio::test]
+async fn test_download_sample_out_of_range() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?sample=1.5", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_create_job_in_batches() {
+	let pool = pool().await;
+	// More than two submission batches, while staying under the body limit.
+	let emails: Vec<String> = (0..1200).map(|i| format!("{}@a.io", i)).collect();
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({"input_type": "array", "input": emails}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let job_id = body["job_id"].as_i64().unwrap() as i32;
+
+	let total_records: i32 =
+		sqlx::query_scalar("SELECT total_records FROM bulk_jobs WHERE id = $1")
+			.bind(job_id)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+	assert_eq!(total_records, 1200);
+
+	let (tasks, distinct_emails): (i64, i64) = sqlx::query_as(
+		r#"
+		SELECT COUNT(*), COUNT(DISTINCT payload_json->'input'->'to_emails'->>0)
+		FROM mq_payloads
+		WHERE (payload_json->>'job_id')::int = $1
+		"#,
+	)
+	.bind(job_id)
+	.fetch_one(&pool)
+	.await
+	.unwrap();
+	assert_eq!(tasks, 1200);
+	assert_eq!(distinct_emails, 1200);
+
+	// Each task records the position of its email in the submitted list.
+	let in_order: i64 = sqlx::query_scalar(
+		r#"
+		SELECT COUNT(*) FROM mq_payloads
+		WHERE (payload_json->>'job_id')::int = $1
+			AND (payload_json->>'ordinal')::int = split_part(payload_json->'input'->'to_emails'->>0, '@', 1)::int
+		"#,
+	)
+	.bind(job_id)
+	.fetch_one(&pool)
+	.await
+	.unwrap();
+	assert_eq!(in_order, 1200);
+}
+
+/// Number of tasks enqueued for the job, and how many of them have the
+/// ordinal given by their email.
+async fn enqueued_tasks(pool: &sqlx::PgPool, job_id: i32) -> (i64, i64) {
+	sqlx::query_as(
+		r#"
+		SELECT COUNT(*), COUNT(*) FILTER (
+			WHERE (payload_json->>'ordinal')::int = split_part(payload_json->'input'->'to_emails'->>0, '@', 1)::int
+		)
+		FROM mq_payloads
+		WHERE (payload_json->>'job_id')::int = $1
+		"#,
+	)
+	.bind(job_id)
+	.fetch_one(pool)
+	.await
+	.unwrap()
+}
+
+#[tokio::test]
+async fn test_create_draft_job() {
+	let pool = pool().await;
+	let emails: Vec<String> = (0..30).map(|i| format!("{}@a.io", i)).collect();
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({"input_type": "array", "input": emails[..10], "draft": true}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let job_id = body["job_id"].as_i64().unwrap() as i32;
+
+	for (i, batch) in emails[10..].chunks(10).enumerate() {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/append", job_id))
+			.method("POST")
+			.json(&serde_json::json!({ "input": batch }))
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		assert_eq!(body["total_appended"], 20 + 10 * i);
+	}
+
+	// Nothing is verified before the job is finalized.
+	assert_eq!(enqueued_tasks(&pool, job_id).await, (0, 0));
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["job_status"], "Draft");
+	assert_eq!(body["total_records"], 0);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/finalize", job_id))
+		.method("POST")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	let total_records: i32 =
+		sqlx::query_scalar("SELECT total_records FROM bulk_jobs WHERE id = $1")
+			.bind(job_id)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+	assert_eq!(total_records, 30);
+	// The ordinals follow the order in which the emails were appended.
+	assert_eq!(enqueued_tasks(&pool, job_id).await, (30, 30));
+}
+
+#[tokio::test]
+async fn test_append_after_finalize() {
+	let pool = pool().await;
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({"input_type": "array", "draft": true}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let job_id = body["job_id"].as_i64().unwrap() as i32;
+
+	// Finalizing twice is rejected too.
+	for expected in [StatusCode::OK, StatusCode::CONFLICT] {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/finalize", job_id))
+			.method("POST")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), expected);
+	}
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/append", job_id))
+		.method("POST")
+		.json(&serde_json::json!({"input": ["foo@bar.baz"]}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::CONFLICT);
+	assert_eq!(enqueued_tasks(&pool, job_id).await, (0, 0));
+
+	// Jobs created in a single request can't be appended to either.
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/append", job_id))
+		.method("POST")
+		.json(&serde_json::json!({"input": ["bar@bar.baz"]}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_status_duration_percentiles() {
+	let pool = pool().await;
+	let results: Vec<Value> = (1..=100)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+	// Durations from 1ms to 100ms, in insertion order.
+	sqlx::query(
+		r#"
+		UPDATE email_results SET duration_ms = sub.rank
+		FROM (SELECT id, ROW_NUMBER() OVER (ORDER BY id) AS rank FROM email_results WHERE job_id = $1) sub
+		WHERE email_results.id = sub.id
+		"#,
+	)
+	.bind(job_id)
+	.execute(&pool)
+	.await
+	.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["summary"]["avg_duration_ms"], 50.5);
+	let p95 = body["summary"]["p95_duration_ms"].as_f64().unwrap();
+	assert!((p95 - 95.05).abs() < 1e-9, "got p95 {}", p95);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?limit=1", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["results"][0]["duration_ms"], 1);
+}
+
+#[tokio::test]
+async fn test_status_distinct_domains() {
+	let pool = pool().await;
+	let results: Vec<Value> = [
+		"a@foo.com",
+		"b@foo.com",
+		"c@bar.com",
+		"d@bar.com",
+		"e@baz.com",
+	]
+	.iter()
+	.map(|input| result(input, "safe"))
+	.collect();
+	let job_id = insert_job(&pool, &results).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body["summary"].get("distinct_domains").is_none());
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}?distinct_domains=true", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["summary"]["distinct_domains"], 3);
+}
+
+#[tokio::test]
+async fn test_status_is_cached() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let routes = create_routes(pool.clone());
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["total_processed"], 1);
+
+	// A result written in between isn't seen by a poll within the TTL.
+	sqlx::query("INSERT INTO email_results (job_id, result) VALUES ($1, $2)")
+		.bind(job_id)
+		.bind(result("bar@bar.baz", "safe"))
+		.execute(&pool)
+		.await
+		.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["total_processed"], 1);
+	assert_eq!(body["job_status"], "Completed");
+
+	let resp = request()
+		.path("/metrics")
+		.method("GET")
+		.reply(&routes)
+		.await;
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	assert!(body.contains("\nreacher_job_status_cache_hits_total 1\n"));
+	assert!(body.contains("\nreacher_job_status_cache_misses_total 1\n"));
+}
+
+#[tokio::test]
+async fn test_status_cache_invalidated_by_finalize() {
+	let pool = pool().await;
+	let routes = create_routes(pool.clone());
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({"input_type": "array", "input": ["foo@bar.baz"], "draft": true}))
+		.reply(&routes)
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let job_id = body["job_id"].as_i64().unwrap() as i32;
+
+	let status = |routes| async move {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}", job_id))
+			.method("GET")
+			.reply(routes)
+			.await;
+		serde_json::from_slice::<Value>(resp.body()).unwrap()
+	};
+	assert_eq!(status(&routes).await["job_status"], "Draft");
+
+	for path in ["append", "finalize"] {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/{}", job_id, path))
+			.method("POST")
+			.json(&serde_json::json!({"input": ["bar@bar.baz"]}))
+			.reply(&routes)
+			.await;
+		assert_eq!(resp.status(), StatusCode::OK);
+	}
+
+	// The cached draft status isn't returned within its TTL.
+	let body = status(&routes).await;
+	assert_eq!(body["job_status"], "Running");
+	assert_eq!(body["total_records"], 2);
+}
+
+#[tokio::test]
+async fn test_download_csv_charset() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["Content-Type"], "text/csv; charset=utf-8");
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.header("Accept-Charset", "utf-16")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_ACCEPTABLE);
+}
+
+#[tokio::test]
+async fn test_download_empty_job() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=json", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.body(), r#"{"results":[]}"#);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	assert_eq!(body.lines().count(), 1);
+	assert!(body.starts_with("input,is_reachable,"));
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["job_status"], "Completed");
+	assert_eq!(body["total_processed"], 0);
+}
+
+#[tokio::test]
+async fn test_download_csv_include_mx() {
+	let pool = pool().await;
+	let mut value = result("foo@bar.baz", "safe");
+	value["mx"]["records"] = serde_json::json!(["mx1.bar.baz.", "mx2.bar.baz."]);
+	let job_id = insert_job(&pool, &[value]).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&include_mx=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	let lines: Vec<&str> = body.lines().collect();
+	assert!(lines[0].ends_with(",mx.records"));
+	assert!(lines[1].ends_with(",mx1.bar.baz.;mx2.bar.baz."));
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	assert!(!body.contains("mx.records"));
+}
+
+#[tokio::test]
+async fn test_download_csv_without_header() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&header=false",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	let lines: Vec<&str> = body.lines().collect();
+	assert_eq!(lines.len(), 2);
+	assert!(lines[0].starts_with("foo@bar.baz,safe,"));
+}
+
+#[tokio::test]
+async fn test_download_csv_all_nested() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&fields=all_nested",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let mut rdr = csv::Reader::from_reader(resp.body().as_ref());
+	let headers = rdr.headers().unwrap().clone();
+	let record = rdr.records().next().unwrap().unwrap();
+	// `syntax.address` is not part of the curated columns.
+	let address = headers.iter().position(|h| h == "syntax.address").unwrap();
+	assert_eq!(&record[address], "foo@bar.baz");
+	let can_connect = headers
+		.iter()
+		.position(|h| h == "smtp.can_connect_smtp")
+		.unwrap();
+	assert_eq!(&record[can_connect], "true");
+}
+
+#[tokio::test]
+async fn test_download_txt_safe() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+			result("baz@bar.baz", "safe"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=txt&reachable=safe",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["Content-Type"], "text/plain; charset=utf-8");
+	assert_eq!(resp.headers()["X-Total-Count"], "2");
+	assert_eq!(resp.body().as_ref(), b"foo@bar.baz\nbaz@bar.baz\n");
+}
+
+#[tokio::test]
+async fn test_download_txt_column() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@a.io", "invalid"),
+			result("bar@b.io", "safe"),
+			result("baz@c.io", "invalid"),
+		],
+	)
+	.await;
+
+	let download = |query: &str| {
+		request()
+			.path(&format!("/v0/bulk/{}/download?{}", job_id, query))
+			.method("GET")
+	};
+	let resp = download("format=txt&column=syntax.domain&reachable=invalid")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["Content-Type"], "text/plain; charset=utf-8");
+	assert_eq!(resp.body().as_ref(), b"a.io\nc.io\n");
+
+	for query in [
+		"format=txt&column=syntax.address",
+		"format=csv&column=syntax.domain",
+	] {
+		let resp = download(query).reply(&create_routes(pool.clone())).await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", query);
+	}
+}
+
+#[tokio::test]
+async fn test_download_csv_quoting() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("a,b@bar.baz", "safe")]).await;
+
+	let download = |quoting: &str| {
+		request()
+			.path(&format!(
+				"/v0/bulk/{}/download?format=csv&header=false{}",
+				job_id, quoting
+			))
+			.method("GET")
+	};
+
+	for quoting in ["", "&quoting=necessary"] {
+		let resp = download(quoting).reply(&create_routes(pool.clone())).await;
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body = String::from_utf8(resp.body().to_vec()).unwrap();
+		assert!(body.starts_with("\"a,b@bar.baz\",safe,"), "{}", body);
+	}
+
+	let resp = download("&quoting=always")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	assert!(
+		body.starts_with("\"a,b@bar.baz\",\"safe\",\"false\","),
+		"{}",
+		body
+	);
+
+	// Unquoted, the comma would shift the columns.
+	let resp = download("&quoting=never")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&header=false&quoting=never",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	assert!(body.starts_with("foo@bar.baz,safe,"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_download_csv_count_trailer() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+			result("baz@bar.baz", "risky"),
+		],
+	)
+	.await;
+
+	for fields in ["", "&fields=all_nested"] {
+		let resp = request()
+			.path(&format!(
+				"/v0/bulk/{}/download?format=csv&count_trailer=true&limit=2{}",
+				job_id, fields
+			))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body = String::from_utf8(resp.body().to_vec()).unwrap();
+		let lines: Vec<&str> = body.lines().collect();
+		// The header, the two rows of the page and the trailer.
+		assert_eq!(lines.len(), 4, "{}", body);
+		assert_eq!(lines[3], "# total_rows: 2");
+	}
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	assert!(!body.contains("total_rows"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_download_csv_extra_fields() {
+	let pool = pool().await;
+	let mut value = result("foo@bar.baz", "invalid");
+	value["smtp"]["details"] = serde_json::json!({"code": 550});
+	let job_id = insert_job(&pool, &[value, result("bar@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&extra_fields=$.smtp.details.code,syntax.domain",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	let lines: Vec<&str> = body.lines().collect();
+	assert!(
+		lines[0].ends_with(",smtp.details.code,syntax.domain"),
+		"{}",
+		body
+	);
+	assert!(lines[1].starts_with("foo@bar.baz,"), "{}", body);
+	assert!(lines[1].ends_with(",550,bar.baz"), "{}", body);
+	assert!(lines[2].ends_with(",,bar.baz"), "{}", body);
+
+	for query in [
+		"format=csv&extra_fields=smtp..code",
+		"format=json&extra_fields=smtp.details.code",
+	] {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/download?{}", job_id, query))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+	}
+}
+
+#[tokio::test]
+async fn test_non_positive_job_id() {
+	let pool = pool().await;
+
+	for path in [
+		"/v0/bulk/-5",
+		"/v0/bulk/0",
+		"/v0/bulk/-5/download",
+		"/v0/bulk/0/download",
+		"/v0/bulk/0/result/foo@bar.baz",
+		"/v0/bulk/0/count",
+		"/v0/bulk/-5/histogram",
+		"/v0/bulk/0/progress.svg",
+		"/v0/bulk/0/export/1/status",
+	] {
+		let resp = request()
+			.path(path)
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", path);
+	}
+}
+
+#[tokio::test]
+async fn test_rejected_job_id_message() {
+	let pool = pool().await;
+
+	for path in ["/v0/bulk/-1", "/v0/bulk/0/download?format=csv"] {
+		let resp = request()
+			.path(path)
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", path);
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		assert_eq!(
+			body["message"], "job id should be a positive integer",
+			"{}",
+			path
+		);
+	}
+}
+
+#[tokio::test]
+async fn test_result_processed_at() {
+	let pool = pool().await;
+	let before = chrono::Utc::now() - chrono::Duration::seconds(5);
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let processed_at: Option<chrono::DateTime<chrono::Utc>> =
+		sqlx::query_scalar("SELECT processed_at FROM email_results WHERE job_id = $1")
+			.bind(job_id)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+	let processed_at = processed_at.expect("processed_at is set on insert");
+	assert!(processed_at >= before);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let last_processed_at = body["last_processed_at"]
+		.as_str()
+		.unwrap()
+		.parse::<chrono::DateTime<chrono::Utc>>()
+		.unwrap();
+	assert_eq!(last_processed_at, processed_at);
+
+	let empty_job_id = insert_job(&pool, &[]).await;
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", empty_job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["last_processed_at"], Value::Null);
+}
+
+#[tokio::test]
+async fn test_normalize_reachable() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "SAFE"),
+			result("bar@bar.baz", " Invalid "),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["summary"]["total_safe"], 1);
+	assert_eq!(body["summary"]["total_invalid"], 1);
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=json&reachable=safe",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let results = body["results"].as_array().unwrap();
+	assert_eq!(results.len(), 1);
+	assert_eq!(results[0]["is_reachable"], "safe");
+}
+
+#[tokio::test]
+async fn test_download_job_status_headers() {
+	let pool = pool().await;
+	let completed_job = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let running_job = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	sqlx::query("UPDATE bulk_jobs SET total_records = 3 WHERE id = $1")
+		.bind(running_job)
+		.execute(&pool)
+		.await
+		.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", completed_job))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.headers()["X-Job-Status"], "completed");
+	assert_eq!(resp.headers()["X-Total-Records"], "1");
+	assert_eq!(resp.headers()["X-Total-Processed"], "1");
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", running_job))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.headers()["X-Job-Status"], "running");
+	assert_eq!(resp.headers()["X-Total-Records"], "3");
+	assert_eq!(resp.headers()["X-Total-Processed"], "1");
+}
+
+#[tokio::test]
+async fn test_download_exclude_catch_all() {
+	let pool = pool().await;
+	let mut catch_all = result("foo@catch.all", "risky");
+	catch_all["smtp"]["is_catch_all"] = true.into();
+	let job_id = insert_job(
+		&pool,
+		&[
+			catch_all,
+			result("bar@bar.baz", "safe"),
+			result("baz@bar.baz", "invalid"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=txt&exclude_catch_all=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.headers()["X-Total-Count"], "2");
+	assert_eq!(resp.body().as_ref(), b"bar@bar.baz\nbaz@bar.baz\n");
+
+	// Composes with the other filters.
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=txt&exclude_catch_all=true&reachable=safe",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.body().as_ref(), b"bar@bar.baz\n");
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=txt", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.headers()["X-Total-Count"], "3");
+}
+
+#[tokio::test]
+async fn test_status_time_format() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let created_at = body["created_at"]
+		.as_str()
+		.unwrap()
+		.parse::<chrono::DateTime<chrono::Utc>>()
+		.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}?time_format=epoch_ms", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["created_at"], created_at.timestamp_millis());
+	assert!(body["last_processed_at"].is_i64());
+	assert_eq!(body["expires_at"], Value::Null);
+}
+
+#[tokio::test]
+async fn test_download_csv_warnings() {
+	let pool = pool().await;
+	let mut value = result("foo@bar.baz", "risky");
+	value["warnings"] = serde_json::json!(["greylisted, result may be incomplete", "slow mx"]);
+	let job_id = insert_job(&pool, &[value, result("bar@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	let mut rdr = csv::Reader::from_reader(resp.body().as_ref());
+	let headers = rdr.headers().unwrap().clone();
+	let warnings = headers.iter().position(|h| h == "warnings").unwrap();
+	let error = headers.iter().position(|h| h == "error").unwrap();
+	let records: Vec<csv::StringRecord> = rdr.records().map(Result::unwrap).collect();
+	assert_eq!(
+		&records[0][warnings],
+		"greylisted, result may be incomplete;slow mx"
+	);
+	assert_eq!(&records[0][error], "");
+	assert_eq!(&records[1][warnings], "");
+}
+
+#[tokio::test]
+async fn test_status_websocket() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let mut client = warp::test::ws()
+		.path("/v0/bulk/ws")
+		.handshake(create_routes(pool))
+		.await
+		.unwrap();
+
+	client.send_text("not json").await;
+	let msg = client.recv().await.unwrap();
+	let body: Value = serde_json::from_str(msg.to_str().unwrap()).unwrap();
+	assert!(body["error"]
+		.as_str()
+		.unwrap()
+		.starts_with("invalid message"));
+
+	// Past the cap, nothing is subscribed to.
+	let ids: Vec<String> = (0..101).map(|i| (job_id + i).to_string()).collect();
+	client
+		.send_text(format!("{{\"subscribe\": [{}]}}", ids.join(",")))
+		.await;
+	let msg = client.recv().await.unwrap();
+	let body: Value = serde_json::from_str(msg.to_str().unwrap()).unwrap();
+	assert_eq!(body["error"], "at most 100 jobs can be subscribed to");
+
+	client
+		.send_text(format!("{{\"subscribe\": [{}]}}", job_id))
+		.await;
+	let msg = client.recv().await.unwrap();
+	let body: Value = serde_json::from_str(msg.to_str().unwrap()).unwrap();
+	assert_eq!(body["job_id"], job_id);
+	assert_eq!(body["job_status"], "Completed");
+	assert_eq!(body["summary"]["total_safe"], 1);
+
+	// All the subscribed jobs are completed.
+	client.recv_closed().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_csv_deep_offset() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&offset=100001",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body["message"].as_str().unwrap().contains("after="));
+}
+
+#[tokio::test]
+async fn test_download_after_cursor() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[result("foo@bar.baz", "safe"), result("bar@bar.baz", "safe")],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=txt&limit=1", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.body().as_ref(), b"foo@bar.baz\n");
+	let after = resp.headers()["X-Next-After"].to_str().unwrap().to_string();
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=txt&limit=1&after={}",
+			job_id, after
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.body().as_ref(), b"bar@bar.baz\n");
+	assert_eq!(resp.headers()["X-Total-Count"], "1");
+}
+
+#[tokio::test]
+async fn test_download_conflicting_params() {
+	let pool = pool().await;
+
+	for (query, conflict) in [
+		("after=1&offset=2", "after can't be used with offset"),
+		(
+			"after=1&sort=ordinal",
+			"after can't be used with sort=ordinal",
+		),
+		(
+			"include_domains=a.io&exclude_domains=b.io",
+			"include_domains and exclude_domains",
+		),
+		("format=csv&column=input", "column"),
+		("format=txt&extra_fields=smtp.details", "extra_fields"),
+		(
+			"format=csv&fields=all_nested&extra_fields=smtp.details",
+			"extra_fields",
+		),
+		("format=txt&fields=all_nested", "fields and quoting"),
+		("format=json&quoting=always", "fields and quoting"),
+		("format=json&count_trailer=true", "count_trailer"),
+		("format=csv&shape=map", "shape and pretty"),
+		("format=txt&pretty=true", "shape and pretty"),
+	] {
+		// Rejected before looking the job up.
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/download?{}", i32::MAX, query))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", query);
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		assert!(
+			body["message"].as_str().unwrap().contains(conflict),
+			"{}: {}",
+			query,
+			body
+		);
+	}
+}
+
+#[tokio::test]
+async fn test_download_descending_order() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "safe"),
+			result("baz@bar.baz", "safe"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=txt&limit=2&order=desc",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.body().as_ref(), b"baz@bar.baz\nbar@bar.baz\n");
+	let after = resp.headers()["X-Next-After"].to_str().unwrap().to_string();
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=txt&order=desc&after={}",
+			job_id, after
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.body().as_ref(), b"foo@bar.baz\n");
+	assert_eq!(resp.headers()["X-Total-Count"], "1");
+}
+
+#[tokio::test]
+async fn test_download_offset_overflow() {
+	let pool = pool().await;
+	let results: Vec<Value> = (0..5)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?limit=2&offset=2", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.headers()["X-Total-Count"], "5");
+	assert!(resp.headers().get("X-Pagination-Overflow").is_none());
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?limit=2&offset=10", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["X-Pagination-Overflow"], "true");
+	assert_eq!(resp.headers()["X-Pagination-Max-Offset"], "4");
+	assert_eq!(resp.body(), r#"{"results":[]}"#);
+}
+
+#[tokio::test]
+async fn test_download_json_array() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=json_array", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["Content-Type"], "application/json");
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let results = body.as_array().unwrap();
+	assert_eq!(results.len(), 1);
+	assert_eq!(results[0]["input"], "foo@bar.baz");
+}
+
+#[tokio::test]
+async fn test_download_json_pretty() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let download = |pretty: &str| {
+		request()
+			.path(&format!(
+				"/v0/bulk/{}/download?format=json{}",
+				job_id, pretty
+			))
+			.method("GET")
+	};
+	let compact = download("").reply(&create_routes(pool.clone())).await;
+	let pretty = download("&pretty=true")
+		.reply(&create_routes(pool.clone()))
+		.await;
+
+	assert_eq!(pretty.status(), StatusCode::OK);
+	assert!(!compact.body().contains(&b'\n'));
+	assert!(pretty.body().windows(3).any(|w| w == b"\n  "));
+	// Both hold the same results.
+	assert_eq!(
+		serde_json::from_slice::<Value>(compact.body()).unwrap(),
+		serde_json::from_slice::<Value>(pretty.body()).unwrap()
+	);
+}
+
+#[tokio::test]
+async fn test_download_latest_only() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "unknown"),
+			result("bar@bar.baz", "invalid"),
+			result("foo@bar.baz", "safe"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.headers()["X-Total-Count"], "2");
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let results: Vec<(&str, &str)> = body["results"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|r| {
+			(
+				r["input"].as_str().unwrap(),
+				r["is_reachable"].as_str().unwrap(),
+			)
+		})
+		.collect();
+	assert_eq!(
+		results,
+		[("bar@bar.baz", "invalid"), ("foo@bar.baz", "safe")]
+	);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?latest_only=false", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.headers()["X-Total-Count"], "3");
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["results"].as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_pool_exhausted() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let tiny_pool = PgPoolOptions::new()
+		.max_connections(1)
+		.connect_timeout(Duration::from_millis(100))
+		.connect(&env::var("DATABASE_URL").unwrap())
+		.await
+		.unwrap();
+	let _conn = tiny_pool.acquire().await.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(tiny_pool.clone()))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+	assert_eq!(resp.headers()["Retry-After"], "5");
+}
+
+#[tokio::test]
+async fn test_input_result() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo+tag@bar.baz", "safe"),
+			result("foo@bar.baz", "invalid"),
+		],
+	)
+	.await;
+
+	for path in ["foo+tag@bar.baz", "foo%2Btag%40bar.baz"] {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/result/{}", job_id, path))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		assert_eq!(body["input"], "foo+tag@bar.baz");
+		assert_eq!(body["is_reachable"], "safe");
+	}
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/result/foo%20tag@bar.baz", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_row_result() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let other_job_id = insert_job(&pool, &[result("bar@bar.baz", "invalid")]).await;
+	let row_id = |job_id| {
+		sqlx::query_scalar::<_, i32>("SELECT id FROM email_results WHERE job_id = $1")
+			.bind(job_id)
+			.fetch_one(&pool)
+	};
+	let (row_id, other_row_id) = (
+		row_id(job_id).await.unwrap(),
+		row_id(other_job_id).await.unwrap(),
+	);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/result/{}", job_id, row_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["input"], "foo@bar.baz");
+	assert_eq!(body["is_reachable"], "safe");
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/result/{}", job_id, other_row_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_download_map_shape() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?shape=map", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let results = body["results"].as_object().unwrap();
+	assert_eq!(results.len(), 2);
+	assert_eq!(results["foo@bar.baz"]["is_reachable"], "safe");
+	assert_eq!(results["bar@bar.baz"]["input"], "bar@bar.baz");
+}
+
+#[tokio::test]
+async fn test_download_content_length() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+		],
+	)
+	.await;
+
+	for format in ["json", "csv"] {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/download?format={}", job_id, format))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert_eq!(
+			resp.headers()["Content-Length"],
+			resp.body().len().to_string().as_str()
+		);
+	}
+}
+
+#[tokio::test]
+async fn test_job_list_by_tag() {
+	let pool = pool().await;
+	// Unique across test runs sharing the database.
+	let tag = format!(
+		"campaign-{}",
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_nanos()
+	);
+	let mut job_ids = vec![];
+	// The duplicate tags are dropped.
+	for tags in [vec![tag.clone(), "signup-list".into(), tag.clone()], vec![]] {
+		let resp = request()
+			.path("/v0/bulk")
+			.method("POST")
+			.json(&serde_json::json!({
+				"input_type": "array",
+				"input": ["foo@bar.baz"],
+				"tags": tags,
+			}))
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		job_ids.push(body["job_id"].clone());
+	}
+
+	let resp = request()
+		.path(&format!("/v0/bulk?tag={}", tag))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let jobs = body["jobs"].as_array().unwrap();
+	assert_eq!(jobs.len(), 1);
+	assert_eq!(jobs[0]["id"], job_ids[0]);
+	assert_eq!(jobs[0]["tags"], serde_json::json!([tag, "signup-list"]));
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_ids[1]))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["tags"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn test_job_priority() {
+	let pool = pool().await;
+	// Unique across test runs sharing the database.
+	let tag = format!(
+		"priority-{}",
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_nanos()
+	);
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({
+			"input_type": "array",
+			"input": ["foo@bar.baz"],
+			"priority": 11,
+		}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({
+			"input_type": "array",
+			"input": ["foo@bar.baz"],
+			"priority": 7,
+			"tags": [&tag],
+		}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let job_id = body["job_id"].clone();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["priority"], 7);
+
+	let resp = request()
+		.path(&format!("/v0/bulk?tag={}", tag))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["jobs"][0]["id"], job_id);
+	assert_eq!(body["jobs"][0]["priority"], 7);
+}
+
+#[tokio::test]
+async fn test_errors_as_200() {
+	let pool = pool().await;
+	// Not convertible to csv, as input should be a string.
+	let job_id = insert_job(&pool, &[serde_json::json!({"input": 42})]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&errors_as_200=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["ok"], false);
+	assert_eq!(body["error"]["message"], "Internal server error");
+}
+
+#[tokio::test]
+async fn test_status_error_classes() {
+	let pool = pool().await;
+	let mut timeout = result("timeout@a.io", "unknown");
+	timeout["smtp"] =
+		serde_json::json!({"error": {"type": "TimeoutError", "message": "future has timed out"}});
+	let mut nxdomain = result("nxdomain@a.io", "invalid");
+	nxdomain["mx"] = serde_json::json!({"error": {"type": "ResolveError", "message": "no record found, response_code: NXDomain"}});
+	// Transient substrings are checked first.
+	let mut both = result("both@a.io", "unknown");
+	both["smtp"] =
+		serde_json::json!({"error": {"type": "TimeoutError", "message": "permanent: NXDOMAIN"}});
+	let mut other = result("other@a.io", "unknown");
+	other["smtp"] = serde_json::json!({"error": {"type": "SomeError", "message": "oops"}});
+	let job_id = insert_job(
+		&pool,
+		&[timeout, nxdomain, both, other, result("safe@a.io", "safe")],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["summary"]["total_transient_errors"], 2);
+	assert_eq!(body["summary"]["total_permanent_errors"], 1);
+}
+
+#[tokio::test]
+async fn test_download_limit_zero() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?limit=0", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["X-Total-Count"], "2");
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["results"], serde_json::json!([]));
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv&limit=0", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["X-Total-Count"], "2");
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	assert_eq!(body.lines().count(), 1);
+	assert!(body.starts_with("input,is_reachable,"));
+}
+
+#[tokio::test]
+async fn test_status_gzip() {
+	use flate2::read::GzDecoder;
+	use std::io::Read;
+
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.header("Accept-Encoding", "gzip, deflate")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["Content-Encoding"], "gzip");
+	assert_eq!(resp.headers()["Vary"], "accept-encoding");
+	let mut decoded = String::new();
+	GzDecoder::new(resp.body().as_ref())
+		.read_to_string(&mut decoded)
+		.unwrap();
+	let body: Value = serde_json::from_str(&decoded).unwrap();
+	assert_eq!(body["job_id"], job_id);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert!(resp.headers().get("Content-Encoding").is_none());
+	// The uncompressed reply varies too, not to be served to gzip clients.
+	assert_eq!(resp.headers()["Vary"], "accept-encoding");
+}
+
+/// Adds the domain of the input to each result.
+struct DomainTransformer;
+
+impl ResultTransformer for DomainTransformer {
+	fn transform(&self, raw: &Value) -> Value {
+		let mut result = raw.clone();
+		let domain = raw["input"]
+			.as_str()
+			.and_then(|input| input.split('@').nth(1))
+			.map(String::from);
+		result["domain"] = serde_json::json!(domain);
+		result
+	}
+}
+
+#[tokio::test]
+async fn test_download_result_transformer() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let routes = create_routes_with_transformer(pool, Arc::new(DomainTransformer));
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["results"][0]["domain"], "bar.baz");
+	assert_eq!(body["results"][0]["input"], "foo@bar.baz");
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&fields=all_nested",
+			job_id
+		))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let mut rdr = csv::Reader::from_reader(resp.body().as_ref());
+	let headers = rdr.headers().unwrap().clone();
+	let record = rdr.records().next().unwrap().unwrap();
+	let domain = headers.iter().position(|h| h == "domain").unwrap();
+	assert_eq!(&record[domain], "bar.baz");
+}
+
+#[tokio::test]
+async fn test_progress_badge() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	sqlx::query("UPDATE bulk_jobs SET total_records = 4 WHERE id = $1")
+		.bind(job_id)
+		.execute(&pool)
+		.await
+		.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/progress.svg", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["Content-Type"], "image/svg+xml");
+	assert_eq!(resp.headers()["Cache-Control"], "no-cache");
+	let svg = std::str::from_utf8(resp.body()).unwrap();
+	assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+	assert!(svg.ends_with("</svg>"));
+	assert!(svg.contains(">1/4</text>"));
+}
+
+#[tokio::test]
+async fn test_download_include_domains() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("foo@example.com", "safe"),
+			result("foo@example.org", "safe"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?include_domains=Bar.baz,%20example.org",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["X-Total-Count"], "2");
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let inputs: Vec<&str> = body["results"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|r| r["input"].as_str().unwrap())
+		.collect();
+	assert_eq!(inputs, ["foo@bar.baz", "foo@example.org"]);
+}
+
+#[tokio::test]
+async fn test_download_exclude_domains() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("foo@example.com", "safe"),
+			result("foo@example.org", "safe"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&exclude_domains=EXAMPLE.com,example.org",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["X-Total-Count"], "1");
+	let mut rdr = csv::Reader::from_reader(resp.body().as_ref());
+	let inputs: Vec<String> = rdr
+		.records()
+		.map(|record| record.unwrap()[0].to_string())
+		.collect();
+	assert_eq!(inputs, ["foo@bar.baz"]);
+}
+
+#[tokio::test]
+async fn test_download_include_and_exclude_domains() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?include_domains=bar.baz&exclude_domains=example.com",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body["message"]
+		.as_str()
+		.unwrap()
+		.contains("include_domains and exclude_domains"));
+}
+
+#[tokio::test]
+async fn test_download_processing_warnings() {
+	let pool = pool().await;
+	let mut results: Vec<Value> = (0..8)
+		.map(|i| {
+			let mut value = result(&format!("user{}@bar.baz", i), "unknown");
+			value["smtp"] = serde_json::json!({"error": {"type": "TimeoutError", "message": "future has timed out"}});
+			value
+		})
+		.collect();
+	let mut blocked = result("blocked@bar.baz", "invalid");
+	blocked["smtp"] = serde_json::json!({"error": {"type": "RcptToError", "message": "blocked"}});
+	results.push(blocked);
+	results.push(result("foo@bar.baz", "safe"));
+	let job_id = insert_job(&pool, &results).await;
+
+	// Not aggregated for every page.
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert!(resp.headers().get("X-Processing-Warnings").is_none());
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?meta=true", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.headers()["X-Processing-Warnings"],
+		"80% smtp: TimeoutError: future has timed out; 10% smtp: RcptToError: blocked"
+	);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["warnings"][0]["count"], 8);
+	assert_eq!(body["warnings"][0]["percent"], 80);
+	assert_eq!(body["warnings"].as_array().unwrap().len(), 2);
+}
+
+async fn processed_counts(pool: &sqlx::Pool<sqlx::Postgres>, job_id: i32) -> (i32, i64) {
+	sqlx::query_as(
+		r#"
+		SELECT processed_count, (SELECT COUNT(*) FROM email_results WHERE job_id = $1)
+		FROM bulk_jobs
+		WHERE id = $1
+		"#,
+	)
+	.bind(job_id)
+	.fetch_one(pool)
+	.await
+	.unwrap()
+}
+
+#[tokio::test]
+async fn test_processed_count() {
+	let pool = pool().await;
+	let results: Vec<Value> = (0..3)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+	assert_eq!(processed_counts(&pool, job_id).await, (3, 3));
+
+	sqlx::query("INSERT INTO email_results (job_id, result) VALUES ($1, $2)")
+		.bind(job_id)
+		.bind(result("foo@bar.baz", "invalid"))
+		.execute(&pool)
+		.await
+		.unwrap();
+	assert_eq!(processed_counts(&pool, job_id).await, (4, 4));
+
+	sqlx::query("DELETE FROM email_results WHERE job_id = $1 AND result->>'input' = 'foo@bar.baz'")
+		.bind(job_id)
+		.execute(&pool)
+		.await
+		.unwrap();
+	assert_eq!(processed_counts(&pool, job_id).await, (3, 3));
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["total_processed"], 3);
+	assert_eq!(body["job_status"], "Completed");
+}
+
+#[tokio::test]
+async fn test_reconcile_processed_count() {
+	let pool = pool().await;
+	let results: Vec<Value> = (0..3)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+	sqlx::query("UPDATE bulk_jobs SET processed_count = 1 WHERE id = $1")
+		.bind(job_id)
+		.execute(&pool)
+		.await
+		.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["total_processed"], 1);
+
+	assert_eq!(
+		reconcile_processed_count(&pool, job_id).await.unwrap(),
+		Some(3)
+	);
+	assert_eq!(processed_counts(&pool, job_id).await, (3, 3));
+	assert_eq!(
+		reconcile_processed_count(&pool, i32::MAX).await.unwrap(),
+		None
+	);
+}
+
+#[tokio::test]
+async fn test_result_count() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+			result("baz@other.baz", "invalid"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/count?reachable=invalid", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body, serde_json::json!({"count": 2}));
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/count?reachable=invalid&include_domains=other.baz",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["count"], 1);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/count", i32::MAX))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_download_sort_ordinal() {
+	let pool = pool().await;
+	// Written in a different order than submitted.
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("third@bar.baz", "safe"),
+			result("first@bar.baz", "safe"),
+			result("second@bar.baz", "safe"),
+		],
+	)
+	.await;
+	sqlx::query(
+		r#"
+		UPDATE email_results
+		SET ordinal = CASE result->>'input' WHEN 'first@bar.baz' THEN 0 WHEN 'second@bar.baz' THEN 1 ELSE 2 END
+		WHERE job_id = $1
+		"#,
+	)
+	.bind(job_id)
+	.execute(&pool)
+	.await
+	.unwrap();
+
+	let inputs = |body: &[u8]| -> Vec<String> {
+		let body: Value = serde_json::from_slice(body).unwrap();
+		body["results"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|result| result["input"].as_str().unwrap().to_string())
+			.collect()
+	};
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?sort=ordinal", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		inputs(resp.body()),
+		vec!["first@bar.baz", "second@bar.baz", "third@bar.baz"]
+	);
+	assert!(resp.headers().get("X-Next-After").is_none());
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(
+		inputs(resp.body()),
+		vec!["third@bar.baz", "first@bar.baz", "second@bar.baz"]
+	);
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?sort=ordinal&after=1",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_job_distribution() {
+	let pool = pool().await;
+	let mut catch_all = result("baz@bar.baz", "risky");
+	catch_all["smtp"]["is_catch_all"] = true.into();
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "safe"),
+			result("qux@bar.baz", "invalid"),
+			result("quux@bar.baz", "unknown"),
+			result("corge@bar.baz", "risky"),
+			catch_all,
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/distribution", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+
+	assert_eq!(body["total_processed"], 6);
+	let reachable = body["reachable"].as_array().unwrap();
+	assert_eq!(reachable[0]["reachable"], "safe");
+	assert_eq!(reachable[0]["count"], 2);
+	let total_percent: f64 = reachable
+		.iter()
+		.map(|bucket| bucket["percent"].as_f64().unwrap())
+		.sum();
+	assert!((total_percent - 100.0).abs() < 1e-6, "{}", total_percent);
+	assert_eq!(body["catch_all"]["count"], 1);
+
+	// A job without results doesn't divide by zero.
+	let job_id = insert_job(&pool, &[]).await;
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/distribution", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["reachable"][0]["percent"], 0.0);
+
+	let resp = request()
+		.path("/v0/bulk/2147483647/distribution")
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_job_histogram() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("a@bar.baz", "safe"),
+			result("b@bar.baz", "safe"),
+			result("c@bar.baz", "safe"),
+			result("d@bar.baz", "safe"),
+		],
+	)
+	.await;
+	sqlx::query(
+		r#"
+		UPDATE email_results
+		SET processed_at = CASE result->>'input'
+			WHEN 'a@bar.baz' THEN '2026-01-01T00:00:10Z'::timestamptz
+			WHEN 'b@bar.baz' THEN '2026-01-01T00:00:50Z'::timestamptz
+			WHEN 'c@bar.baz' THEN '2026-01-01T00:01:30Z'::timestamptz
+			ELSE '2026-01-01T00:03:00Z'::timestamptz
+		END
+		WHERE job_id = $1
+		"#,
+	)
+	.bind(job_id)
+	.execute(&pool)
+	.await
+	.unwrap();
+
+	let histogram = |query: String| {
+		let pool = pool.clone();
+		async move {
+			let resp = request()
+				.path(&format!("/v0/bulk/{}/histogram{}", job_id, query))
+				.method("GET")
+				.reply(&create_routes(pool))
+				.await;
+			assert_eq!(resp.status(), StatusCode::OK);
+			let body: Value = serde_json::from_slice(resp.body()).unwrap();
+			body["buckets"]
+				.as_array()
+				.unwrap()
+				.iter()
+				.map(|bucket| bucket["count"].as_i64().unwrap())
+				.collect::<Vec<_>>()
+		}
+	};
+
+	assert_eq!(histogram(String::new()).await, vec![2, 1, 0, 1]);
+	assert_eq!(histogram("?bucket=5m".into()).await, vec![4]);
+	assert_eq!(
+		histogram("?bucket=1m&from=2026-01-01T00:01:00Z&to=2026-01-01T00:03:00Z".into()).await,
+		vec![1]
+	);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/histogram", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["bucket"], "1m");
+	assert_eq!(body["buckets"][0]["start"], "2026-01-01T00:00:00Z");
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/histogram", i32::MAX))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_status_server_timing() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let timing = resp.headers()["Server-Timing"].to_str().unwrap();
+	let phases: Vec<&str> = timing
+		.split(", ")
+		.map(|metric| {
+			let (phase, dur) = metric.split_once(";dur=").unwrap();
+			assert!(dur.parse::<f64>().unwrap() >= 0.0, "{}", timing);
+			phase
+		})
+		.collect();
+	assert!(phases.contains(&"db_record"), "{}", timing);
+	assert!(phases.contains(&"db_aggregate"), "{}", timing);
+	assert!(phases.contains(&"serialize"), "{}", timing);
+}
+
+#[tokio::test]
+async fn test_download_server_timing() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let timing = resp.headers()["Server-Timing"].to_str().unwrap();
+	let phases: Vec<&str> = timing
+		.split(", ")
+		.map(|metric| metric.split_once(";dur=").unwrap().0)
+		.collect();
+	// The page query is timed apart from the serialization.
+	assert_eq!(
+		phases,
+		["db_record", "db_aggregate", "db_results", "serialize"],
+		"{}",
+		timing
+	);
+}
+
+#[tokio::test]
+async fn test_download_double_encoded_result() {
+	let pool = pool().await;
+	let encoded = Value::String(result("foo@bar.baz", "safe").to_string());
+	let job_id = insert_job(&pool, &[encoded, result("baz@bar.baz", "invalid")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=json", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["results"][0]["input"], "foo@bar.baz");
+	assert_eq!(body["results"][0]["syntax"]["domain"], "bar.baz");
+	assert!(body["results"][0].get("duration_ms").is_some());
+	assert_eq!(body["results"][1]["input"], "baz@bar.baz");
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	let rows: Vec<&str> = body.lines().collect();
+	assert_eq!(rows.len(), 3, "{}", body);
+	assert!(rows[1].starts_with("foo@bar.baz,safe,"), "{}", body);
+	assert!(rows[2].starts_with("baz@bar.baz,invalid,"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_download_csv_fields_order() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&fields=smtp.is_deliverable,input,is_reachable",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	let rows: Vec<&str> = body.lines().collect();
+	assert_eq!(
+		rows,
+		[
+			"smtp.is_deliverable,input,is_reachable",
+			"true,foo@bar.baz,safe"
+		]
+	);
+
+	for fields in ["input,foo", "input,input"] {
+		let resp = request()
+			.path(&format!(
+				"/v0/bulk/{}/download?format=csv&fields={}",
+				job_id, fields
+			))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", fields);
+	}
+}
+
+#[tokio::test]
+async fn test_delete_job() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("DELETE")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["mode"], "hard");
+
+	let remaining: i64 = sqlx::query_scalar(
+		"SELECT (SELECT COUNT(*) FROM bulk_jobs WHERE id = $1) + (SELECT COUNT(*) FROM email_results WHERE job_id = $1)",
+	)
+	.bind(job_id)
+	.fetch_one(&pool)
+	.await
+	.unwrap();
+	assert_eq!(remaining, 0);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("DELETE")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_download_include_provider() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@gmail.com", "safe"),
+			result("foo@reacher.email", "safe"),
+		],
+	)
+	.await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?include_provider=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["results"][0]["provider"], "gmail");
+	assert_eq!(body["results"][1]["provider"], "other");
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&include_mx=true&include_provider=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	let lines: Vec<&str> = body.lines().collect();
+	assert!(lines[0].ends_with(",mx.records,provider"), "{}", body);
+	assert!(lines[1].ends_with(",gmail"), "{}", body);
+	assert!(lines[2].ends_with(",other"), "{}", body);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body["results"][0].get("provider").is_none());
+}
+
+#[tokio::test]
+async fn test_download_json_array_stream() {
+	let pool = pool().await;
+	// More than two pages of the stream.
+	let results: Vec<Value> = (0..2500)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=json_array&stream=true",
+			job_id
+		))
+		.method("GET")
+		.header("accept-encoding", "gzip")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert!(resp.headers().get("content-encoding").is_none());
+	assert_eq!(resp.headers()["x-total-count"], "2500");
+	// Same headers as a buffered download.
+	assert_eq!(resp.headers()["X-Job-Status"], "completed");
+	assert_eq!(resp.headers()["X-Total-Processed"], "2500");
+	let timing = resp.headers()["Server-Timing"].to_str().unwrap();
+	assert!(timing.contains("db_aggregate;dur="), "{}", timing);
+	let body: Vec<Value> = serde_json::from_slice(resp.body()).unwrap();
+	let inputs: Vec<&str> = body.iter().map(|r| r["input"].as_str().unwrap()).collect();
+	let expected: Vec<String> = (0..2500)
+		.map(|i| format!("user{}@example.com", i))
+		.collect();
+	assert_eq!(inputs, expected);
+
+	// Retrying the first input makes its earlier result, a couple of pages
+	// before the cursor, superseded.
+	sqlx::query("INSERT INTO email_results (job_id, result) VALUES ($1, $2)")
+		.bind(job_id)
+		.bind(result("user0@example.com", "invalid"))
+		.execute(&pool)
+		.await
+		.unwrap();
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=json_array&stream=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Vec<Value> = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body.len(), 2500);
+	assert_eq!(body[0]["input"], "user1@example.com");
+	assert_eq!(body[2499]["input"], "user0@example.com");
+	assert_eq!(body[2499]["is_reachable"], "invalid");
+
+	let job_id = insert_job(&pool, &[]).await;
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=json_array&stream=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.body(), "[]");
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=json_array&stream=true&limit=10",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_download_confidence() {
+	let pool = pool().await;
+	let catch_all = |input, is_reachable| {
+		let mut value = result(input, is_reachable);
+		value["smtp"]["is_catch_all"] = true.into();
+		value
+	};
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("safe@a.io", "safe"),
+			catch_all("safe@catch-all.io", "safe"),
+			result("risky@a.io", "risky"),
+			catch_all("risky@catch-all.io", "risky"),
+			result("unknown@a.io", "unknown"),
+			result("invalid@a.io", "invalid"),
+		],
+	)
+	.await;
+
+	for (confidence, expected) in [
+		("high", vec!["safe@a.io"]),
+		(
+			"medium",
+			vec![
+				"safe@a.io",
+				"safe@catch-all.io",
+				"risky@a.io",
+				"risky@catch-all.io",
+			],
+		),
+		(
+			"low",
+			vec![
+				"safe@a.io",
+				"safe@catch-all.io",
+				"risky@a.io",
+				"risky@catch-all.io",
+				"unknown@a.io",
+				"invalid@a.io",
+			],
+		),
+	] {
+		let resp = request()
+			.path(&format!(
+				"/v0/bulk/{}/download?confidence={}",
+				job_id, confidence
+			))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::OK, "{}", confidence);
+		assert_eq!(
+			resp.headers()["X-Total-Count"],
+			expected.len().to_string().as_str()
+		);
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		let inputs: Vec<&str> = body["results"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|r| r["input"].as_str().unwrap())
+			.collect();
+		assert_eq!(inputs, expected, "{}", confidence);
+
+		let resp = request()
+			.path(&format!(
+				"/v0/bulk/{}/download?format=csv&confidence={}",
+				job_id, confidence
+			))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		let body = String::from_utf8(resp.body().to_vec()).unwrap();
+		assert_eq!(body.lines().count(), expected.len() + 1, "{}", body);
+	}
+
+	// On top of the other filters.
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?confidence=medium&reachable=risky",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.headers()["X-Total-Count"], "2");
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?confidence=best", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_download_malformed_query() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	for (query, message) in [
+		(
+			"limit=abc",
+			"limit is invalid: invalid digit found in string",
+		),
+		(
+			"format=csv&include_mx=yes",
+			"include_mx is invalid: provided string was not `true` or `false`",
+		),
+	] {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/download?{}", job_id, query))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", query);
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		assert_eq!(body["message"], message);
+	}
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_download_freshness() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[result("old@a.io", "safe"), result("fresh@a.io", "safe")],
+	)
+	.await;
+	sqlx::query(
+		"UPDATE email_results SET processed_at = NOW() - interval '60 days' WHERE job_id = $1 AND result ->> 'input' = 'old@a.io'",
+	)
+	.bind(job_id)
+	.execute(&pool)
+	.await
+	.unwrap();
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?include_freshness=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let stale: Vec<(&str, bool)> = body["results"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|r| (r["input"].as_str().unwrap(), r["stale"].as_bool().unwrap()))
+		.collect();
+	assert_eq!(stale, [("old@a.io", true), ("fresh@a.io", false)]);
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&include_freshness=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	let mut lines = body.lines();
+	assert!(lines.next().unwrap().ends_with(",stale"));
+	assert!(lines.next().unwrap().ends_with(",true"));
+	assert!(lines.next().unwrap().ends_with(",false"));
+
+	// Not added by default.
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body["results"][0].get("stale").is_none());
+
+	let routes = create_routes(pool.clone());
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body.get("stale_count").is_none());
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}?include_freshness=true", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["stale_count"], 1);
+	assert!(body["summary"].get("stale_count").is_none());
+
+	// Results become stale without any write to the job, the count isn't
+	// cached with the status.
+	sqlx::query(
+		"UPDATE email_results SET processed_at = NOW() - interval '60 days' WHERE job_id = $1",
+	)
+	.bind(job_id)
+	.execute(&pool)
+	.await
+	.unwrap();
+	let resp = request()
+		.path(&format!("/v0/bulk/{}?include_freshness=true", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["stale_count"], 2);
+}
+
+#[tokio::test]
+async fn test_status_eta_rolling() {
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("a@a.io", "safe"),
+			result("b@a.io", "safe"),
+			result("c@a.io", "safe"),
+		],
+	)
+	.await;
+	// The first result after a long wait, then one every 2s.
+	sqlx::query(
+		r#"
+		UPDATE email_results r SET processed_at = TIMESTAMPTZ '2026-01-01 00:00:00+00' + s.offset_s * interval '1 second'
+		FROM (
+			SELECT id, CASE WHEN ROW_NUMBER() OVER (ORDER BY id) = 1 THEN -3600 ELSE 2 * ROW_NUMBER() OVER (ORDER BY id) END AS offset_s
+			FROM email_results WHERE job_id = $1
+		) s
+		WHERE r.id = s.id
+		"#,
+	)
+	.bind(job_id)
+	.execute(&pool)
+	.await
+	.unwrap();
+	sqlx::query("UPDATE bulk_jobs SET total_records = 10 WHERE id = $1")
+		.bind(job_id)
+		.execute(&pool)
+		.await
+		.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	// Over the 3 latest results, 7 remaining records take well over an hour.
+	let eta = body["eta_rolling"]
+		.as_str()
+		.unwrap()
+		.parse::<chrono::DateTime<chrono::Utc>>()
+		.unwrap();
+	let last_processed_at = body["last_processed_at"]
+		.as_str()
+		.unwrap()
+		.parse::<chrono::DateTime<chrono::Utc>>()
+		.unwrap();
+	assert!(eta - last_processed_at > chrono::Duration::hours(1));
+
+	// Completed jobs have no ETA.
+	let completed_job_id = insert_job(&pool, &[result("a@a.io", "safe")]).await;
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", completed_job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["eta_rolling"], Value::Null);
+}
+
+#[tokio::test]
+async fn test_job_tags() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	// Adding a tag twice is a no-op.
+	for _ in 0..2 {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/tags", job_id))
+			.method("POST")
+			.json(&serde_json::json!({ "tag": "campaign-q3" }))
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::OK);
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		assert_eq!(body["tags"], serde_json::json!(["campaign-q3"]));
+	}
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/tags", job_id))
+		.method("POST")
+		.json(&serde_json::json!({ "tag": "not a tag" }))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({
+			"input_type": "array",
+			"input": ["foo@bar.baz"],
+			"tags": ["not a tag"],
+		}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+	// Removing a tag the job doesn't have is a 404.
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/tags/signup-list", job_id))
+		.method("DELETE")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(
+		body["message"],
+		format!("job {} has no tag signup-list", job_id)
+	);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/tags/campaign-q3", job_id))
+		.method("DELETE")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["tags"], serde_json::json!([]));
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["tags"], serde_json::json!([]));
+
+	// A tag set before they were checked can still be removed.
+	sqlx::query("UPDATE bulk_jobs SET tags = '{legacy:tag}' WHERE id = $1")
+		.bind(job_id)
+		.execute(&pool)
+		.await
+		.unwrap();
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/tags/legacy:tag", job_id))
+		.method("DELETE")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["tags"], serde_json::json!([]));
+
+	let resp = request()
+		.path("/v0/bulk/2147483647/tags")
+		.method("POST")
+		.json(&serde_json::json!({ "tag": "campaign-q3" }))
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_submitted_task_options() {
+	let pool = pool().await;
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({"input_type": "array", "input": ["foo@bar.baz"]}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let job_id = body["job_id"].as_i64().unwrap() as i32;
+
+	// The options of a task as stored by sqlxmq, the delay being the time
+	// between its creation and its first attempt.
+	let options = r#"
+		SELECT m.attempts, m.retry_backoff::text, m.channel_name, m.channel_args,
+			m.commit_interval::text, m.after_message_id IS NOT NULL,
+			(m.attempt_at - m.created_at)::text
+		FROM mq_msgs m JOIN mq_payloads p ON p.id = m.id
+		"#;
+	let mut tx = pool.begin().await.unwrap();
+	let submitted: (i32, String, String, String, Option<String>, bool, String) = sqlx::query_as(
+		&format!("{} WHERE (p.payload_json->>'job_id')::int = $1", options),
+	)
+	.bind(job_id)
+	.fetch_one(&mut tx)
+	.await
+	.unwrap();
+	let spawned_id = email_verification_task
+		.builder()
+		.set_json(&serde_json::json!({ "job_id": job_id }))
+		.unwrap()
+		.spawn(&mut tx)
+		.await
+		.unwrap();
+	let spawned: (i32, String, String, String, Option<String>, bool, String) =
+		sqlx::query_as(&format!("{} WHERE m.id = $1", options))
+			.bind(spawned_id)
+			.fetch_one(&mut tx)
+			.await
+			.unwrap();
+	tx.rollback().await.unwrap();
+
+	assert_eq!(submitted, spawned);
+}
@@ -0,0 +1,288 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the admin `/v0/bulk` endpoints. They live in their
+//! own file because they sweep across all jobs in the database.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+const ADMIN_KEY: &str = "admin-secret";
+
+/// An `unknown` result with the given SMTP error.
+fn smtp_error_result(input: &str, error_type: &str, message: &str) -> Value {
+	let mut value = result(input, "unknown");
+	value["smtp"] = serde_json::json!({"error": {"type": error_type, "message": message}});
+
+	value
+}
+
+#[tokio::test]
+async fn test_requeue_unknowns_requires_admin_key() {
+	env::set_var("RCH_ADMIN_API_KEY", ADMIN_KEY);
+
+	let resp = request()
+		.path("/v0/bulk/requeue-unknowns")
+		.method("POST")
+		.header("x-reacher-admin-key", "wrong")
+		.json(&serde_json::json!({}))
+		.reply(&create_routes(pool().await))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_requeue_unknowns_only_transient() {
+	env::set_var("RCH_ADMIN_API_KEY", ADMIN_KEY);
+	let pool = pool().await;
+	let created_after: Value = sqlx::query_scalar("SELECT to_jsonb(NOW())")
+		.fetch_one(&pool)
+		.await
+		.unwrap();
+
+	let job_id = insert_job(
+		&pool,
+		&[
+			smtp_error_result("timeout@a.io", "TimeoutError", "future has timed out"),
+			smtp_error_result(
+				"greylisted@a.io",
+				"RcptToError",
+				"transient: try again later",
+			),
+			smtp_error_result("blocked@a.io", "RcptToError", "permanent: blocked"),
+			result("safe@a.io", "safe"),
+		],
+	)
+	.await;
+
+	// All its records are processed, so the job is completed and its results
+	// are only requeued with force.
+	let resp = request()
+		.path("/v0/bulk/requeue-unknowns")
+		.method("POST")
+		.header("x-reacher-admin-key", ADMIN_KEY)
+		.json(&serde_json::json!({ "created_after": created_after }))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+	let resp = request()
+		.path("/v0/bulk/requeue-unknowns")
+		.method("POST")
+		.header("x-reacher-admin-key", ADMIN_KEY)
+		.json(&serde_json::json!({ "created_after": created_after, "force": true }))
+		.reply(&create_routes(pool.clone()))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["total_requeued"], 2);
+
+	let mut remaining: Vec<String> =
+		sqlx::query_scalar("SELECT result->>'input' FROM email_results WHERE job_id = $1")
+			.bind(job_id)
+			.fetch_all(&pool)
+			.await
+			.unwrap();
+	remaining.sort();
+	assert_eq!(remaining, vec!["blocked@a.io", "safe@a.io"]);
+
+	let mut requeued: Vec<String> = sqlx::query_scalar(
+		r#"
+		SELECT payload_json->'input'->'to_emails'->>0 FROM mq_payloads
+		WHERE (payload_json->>'job_id')::int = $1
+		"#,
+	)
+	.bind(job_id)
+	.fetch_all(&pool)
+	.await
+	.unwrap();
+	requeued.sort();
+	assert_eq!(requeued, vec!["greylisted@a.io", "timeout@a.io"]);
+}
+
+#[tokio::test]
+async fn test_requeue_unknowns_retry_info() {
+	env::set_var("RCH_ADMIN_API_KEY", ADMIN_KEY);
+	let pool = pool().await;
+
+	// Created in a window of its own, away from the other requeues.
+	let job_id = insert_job(&pool, &[]).await;
+	let (created_after, created_before): (Value, Value) = sqlx::query_as(
+		r#"
+		UPDATE bulk_jobs SET created_at = TIMESTAMPTZ '2000-01-01' + id * INTERVAL '1 second'
+		WHERE id = $1
+		RETURNING to_jsonb(created_at), to_jsonb(created_at + INTERVAL '1 second')
+		"#,
+	)
+	.bind(job_id)
+	.fetch_one(&pool)
+	.await
+	.unwrap();
+	let timeout = smtp_error_result("timeout@a.io", "TimeoutError", "future has timed out");
+	sqlx::query("INSERT INTO email_results (job_id, result) VALUES ($1, $2)")
+		.bind(job_id)
+		.bind(&timeout)
+		.execute(&pool)
+		.await
+		.unwrap();
+
+	let resp = request()
+		.path("/v0/bulk/requeue-unknowns")
+		.method("POST")
+		.header("x-reacher-admin-key", ADMIN_KEY)
+		.json(&serde_json::json!({
+			"created_after": created_after,
+			"created_before": created_before,
+			"force": true
+		}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["total_requeued"], 1);
+
+	// Simulate the task writing the result of the retry, which timed out
+	// again.
+	sqlx::query(
+		r#"
+		INSERT INTO email_results (job_id, result, retry_count, last_error)
+		SELECT $1, $2, (payload_json->>'retry_count')::int, payload_json->>'last_error'
+		FROM mq_payloads
+		WHERE (payload_json->>'job_id')::int = $1
+		"#,
+	)
+	.bind(job_id)
+	.bind(&timeout)
+	.execute(&pool)
+	.await
+	.unwrap();
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?include_retry_info=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["results"].as_array().unwrap().len(), 1);
+	assert_eq!(body["results"][0]["retry_count"], 1);
+	assert_eq!(
+		body["results"][0]["last_error"],
+		"TimeoutError: future has timed out"
+	);
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&include_retry_info=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body = String::from_utf8(resp.body().to_vec()).unwrap();
+	let lines: Vec<&str> = body.lines().collect();
+	assert!(lines[0].ends_with(",retry_count,last_error"), "{}", body);
+	assert!(
+		lines[1].ends_with(",1,TimeoutError: future has timed out"),
+		"{}",
+		body
+	);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body["results"][0].get("retry_count").is_none());
+}
+
+#[tokio::test]
+async fn test_requeue_unknowns_skips_completed_jobs() {
+	env::set_var("RCH_ADMIN_API_KEY", ADMIN_KEY);
+	let pool = pool().await;
+
+	let timeout = smtp_error_result("timeout@a.io", "TimeoutError", "future has timed out");
+	let completed_id = insert_job(&pool, std::slice::from_ref(&timeout)).await;
+	let running_id = insert_job(&pool, &[timeout]).await;
+	// Created in a window of their own, away from the other requeues.
+	let (created_after, created_before): (Value, Value) = sqlx::query_as(
+		r#"
+		UPDATE bulk_jobs
+		SET created_at = TIMESTAMPTZ '2001-01-01' + $1 * INTERVAL '1 second',
+			total_records = CASE WHEN id = $2 THEN 2 ELSE total_records END
+		WHERE id IN ($1, $2)
+		RETURNING to_jsonb(created_at), to_jsonb(created_at + INTERVAL '1 second')
+		"#,
+	)
+	.bind(completed_id)
+	.bind(running_id)
+	.fetch_one(&pool)
+	.await
+	.unwrap();
+	let routes = create_routes(pool.clone());
+	let requeue = || {
+		request()
+			.path("/v0/bulk/requeue-unknowns")
+			.method("POST")
+			.header("x-reacher-admin-key", ADMIN_KEY)
+			.json(&serde_json::json!({
+				"created_after": created_after,
+				"created_before": created_before
+			}))
+			.reply(&routes)
+	};
+
+	let resp = requeue().await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["total_requeued"], 1);
+	assert_eq!(
+		body["skipped_jobs"],
+		serde_json::json!([{"job_id": completed_id, "total_skipped": 1}])
+	);
+	let remaining: Vec<i32> =
+		sqlx::query_scalar("SELECT job_id FROM email_results WHERE job_id IN ($1, $2)")
+			.bind(completed_id)
+			.bind(running_id)
+			.fetch_all(&pool)
+			.await
+			.unwrap();
+	assert_eq!(remaining, vec![completed_id]);
+
+	// Only the completed job is left, nothing can be requeued.
+	let resp = requeue().await;
+	assert_eq!(resp.status(), StatusCode::CONFLICT);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(
+		body["message"],
+		format!(
+			"jobs {} are completed, their results can only be requeued with force",
+			completed_id
+		)
+	);
+}
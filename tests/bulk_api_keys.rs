@@ -0,0 +1,169 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the API keys of the `api_keys` table. These tests
+//! need a Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they enable API key
+//! auth through the environment.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use warp::http::StatusCode;
+use warp::test::request;
+
+/// Insert an enabled API key with the given scope, and return the key.
+async fn insert_api_key(pool: &Pool<Postgres>, scope: &str) -> String {
+	let nanos = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_nanos();
+	let key = format!("test-{}-{}", scope, nanos);
+	sqlx::query("INSERT INTO api_keys (name, key_hash, scope) VALUES ($1, $2, $3)")
+		.bind(&key)
+		.bind(hex::encode(Sha256::digest(key.as_bytes())))
+		.bind(scope)
+		.execute(pool)
+		.await
+		.unwrap();
+
+	key
+}
+
+#[tokio::test]
+async fn test_disabled_api_key_is_rejected() {
+	env::set_var("RCH_API_KEY_AUTH", "1");
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let key = insert_api_key(&pool, "read_only").await;
+	let routes = create_routes(pool.clone());
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.header("x-reacher-api-key", &key)
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	sqlx::query("UPDATE api_keys SET enabled = false WHERE name = $1")
+		.bind(&key)
+		.execute(&pool)
+		.await
+		.unwrap();
+	// Wait for the cached lookup to expire.
+	tokio::time::sleep(Duration::from_secs(6)).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.header("x-reacher-api-key", &key)
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_api_key_scopes() {
+	env::set_var("RCH_API_KEY_AUTH", "1");
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let read_only = insert_api_key(&pool, "read_only").await;
+	let admin = insert_api_key(&pool, "admin").await;
+	let routes = create_routes(pool);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/export", job_id))
+		.method("POST")
+		.header("x-reacher-api-key", &read_only)
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/export", job_id))
+		.method("POST")
+		.header("x-reacher-api-key", &admin)
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::ACCEPTED);
+
+	// Only the /v0 endpoints need a key.
+	let resp = request()
+		.path("/version")
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_keyless_requests() {
+	env::set_var("RCH_API_KEY_AUTH", "1");
+	env::set_var("RCH_ADMIN_API_KEY", "admin-secret");
+	env::set_var("RCH_URL_SIGNING_KEY", "signing-key");
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let admin = insert_api_key(&pool, "admin").await;
+	let routes = create_routes(pool);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download-url", job_id))
+		.method("POST")
+		.header("x-reacher-api-key", &admin)
+		.header("x-reacher-admin-key", "admin-secret")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+	let url = body["url"].as_str().unwrap();
+
+	// The signed URL stands in for the key.
+	let resp = request().path(url).method("GET").reply(&routes).await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let resp = request()
+		.path(&url.replace("token=", "token=00"))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/progress.svg", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	// The websocket needs a key.
+	assert!(warp::test::ws()
+		.path("/v0/bulk/ws")
+		.handshake(routes)
+		.await
+		.is_err());
+}
@@ -0,0 +1,75 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the body size limits of the POST endpoints. They
+//! live in their own binary, as they set the submission limit through the
+//! environment.
+
+mod common;
+
+use common::pool;
+use reacher_backend::routes::{create_routes, MAX_BODY_BYTES};
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+/// A submission body with `count` emails.
+fn submission(count: usize) -> serde_json::Value {
+	let input: Vec<String> = (0..count).map(|i| format!("foo{}@bar.baz", i)).collect();
+	serde_json::json!({
+		"input_type": "array",
+		"input": input,
+		"draft": true,
+	})
+}
+
+#[tokio::test]
+async fn test_body_limits() {
+	env::set_var("RCH_MAX_SUBMISSION_BYTES", "65536");
+	let pool = pool().await;
+
+	// Larger than the other POST endpoints accept, but within the limit of
+	// submissions.
+	let body = submission(2000);
+	let size = body.to_string().len() as u64;
+	assert!(size > MAX_BODY_BYTES && size < 65536, "{}", size);
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&body)
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&submission(5000))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+	let resp = request()
+		.path("/v0/check_email")
+		.method("POST")
+		.json(&serde_json::json!({
+			"to_email": "foo@bar.baz",
+			"hello_name": "a".repeat(MAX_BODY_BYTES as usize),
+		}))
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
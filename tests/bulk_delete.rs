@@ -0,0 +1,131 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the soft-delete of bulk jobs. These tests need a
+//! Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they configure the
+//! delete mode through the environment.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+const ADMIN_KEY: &str = "admin-secret";
+
+#[tokio::test]
+async fn test_soft_deleted_job_is_hidden_and_purged() {
+	env::set_var("RCH_JOB_DELETE_MODE", "soft");
+	env::set_var("RCH_ADMIN_API_KEY", ADMIN_KEY);
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("DELETE")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["mode"], "soft");
+
+	for path in [
+		format!("/v0/bulk/{}", job_id),
+		format!("/v0/bulk/{}/download", job_id),
+		format!("/v0/bulk/{}/count", job_id),
+		format!("/v0/bulk/{}/result/foo@bar.baz", job_id),
+		format!("/v0/bulk/{}/distribution", job_id),
+	] {
+		let resp = request()
+			.path(&path)
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", path);
+	}
+
+	// Deleted only once.
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("DELETE")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+	let remaining = || {
+		sqlx::query_scalar::<_, i64>(
+			"SELECT (SELECT COUNT(*) FROM bulk_jobs WHERE id = $1 AND deleted_at IS NOT NULL) + (SELECT COUNT(*) FROM email_results WHERE job_id = $1)",
+		)
+		.bind(job_id)
+		.fetch_one(&pool)
+	};
+	assert_eq!(remaining().await.unwrap(), 2);
+
+	let resp = request()
+		.path("/v0/bulk/purge-deleted")
+		.method("POST")
+		.header("x-reacher-admin-key", ADMIN_KEY)
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body["total_purged"].as_u64().unwrap() >= 1);
+	assert_eq!(remaining().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_delete_cancels_tasks() {
+	env::set_var("RCH_JOB_DELETE_MODE", "soft");
+	let pool = pool().await;
+
+	// Nothing processes the tasks, so they stay queued.
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({
+			"input_type": "array",
+			"input": ["foo@bar.baz", "bar@bar.baz"],
+		}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let job_id = body["job_id"].as_i64().unwrap() as i32;
+	let queued = || {
+		sqlx::query_scalar::<_, i64>(
+			r#"
+			SELECT COUNT(*) FROM mq_msgs m
+			JOIN mq_payloads p ON m.id = p.id
+			WHERE (p.payload_json ->> 'job_id')::int = $1
+			"#,
+		)
+		.bind(job_id)
+		.fetch_one(&pool)
+	};
+	assert_eq!(queued().await.unwrap(), 2);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("DELETE")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(queued().await.unwrap(), 0);
+}
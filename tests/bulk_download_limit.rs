@@ -0,0 +1,67 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the per-job cap on concurrent downloads. These
+//! tests need a Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they set the cap
+//! through the environment.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_download_per_job_cap() {
+	env::set_var("RCH_MAX_DOWNLOADS_PER_JOB", "1");
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let other_job_id = insert_job(&pool, &[result("bar@bar.baz", "safe")]).await;
+	let routes = create_routes(pool);
+
+	let download = |job_id: i32| {
+		request()
+			.path(&format!("/v0/bulk/{}/download", job_id))
+			.method("GET")
+			.reply(&routes)
+	};
+	// The first download holds the permit while waiting on the database.
+	let (first, second, other) =
+		tokio::join!(download(job_id), download(job_id), download(other_job_id));
+
+	assert_eq!(first.status(), StatusCode::OK);
+	assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+	assert_eq!(second.headers()["Retry-After"], "2");
+	assert_eq!(other.status(), StatusCode::OK);
+
+	// The permit is released with the response.
+	assert_eq!(download(job_id).await.status(), StatusCode::OK);
+
+	// Once downloads have completed, the delay is estimated from their
+	// duration, well under a second for a single result.
+	let (first, second) = tokio::join!(download(job_id), download(job_id));
+	assert_eq!(first.status(), StatusCode::OK);
+	assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+	let retry_after: u64 = second.headers()["Retry-After"]
+		.to_str()
+		.unwrap()
+		.parse()
+		.unwrap();
+	assert!((1..=2).contains(&retry_after), "{}", retry_after);
+}
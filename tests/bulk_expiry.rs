@@ -0,0 +1,91 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the auto-expiry of bulk jobs. These tests need a
+//! Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they configure the
+//! retention period through the environment.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::{
+	bulk::expiry::{job_retention, purge_expired_jobs},
+	create_routes,
+};
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_expired_job_is_gone_and_purged() {
+	env::set_var("RCH_JOB_RETENTION_DAYS", "30");
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	sqlx::query("UPDATE bulk_jobs SET created_at = NOW() - INTERVAL '31 days' WHERE id = $1")
+		.bind(job_id)
+		.execute(&pool)
+		.await
+		.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::GONE);
+
+	let deleted = purge_expired_jobs(&pool, job_retention().unwrap())
+		.await
+		.unwrap();
+	assert!(deleted >= 1);
+
+	let remaining: i64 = sqlx::query_scalar(
+		"SELECT (SELECT COUNT(*) FROM bulk_jobs WHERE id = $1) + (SELECT COUNT(*) FROM email_results WHERE job_id = $1)",
+	)
+	.bind(job_id)
+	.fetch_one(&pool)
+	.await
+	.unwrap();
+	assert_eq!(remaining, 0);
+}
+
+#[tokio::test]
+async fn test_status_expires_at() {
+	env::set_var("RCH_JOB_RETENTION_DAYS", "30");
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+	let created_at = body["created_at"]
+		.as_str()
+		.unwrap()
+		.parse::<chrono::DateTime<chrono::Utc>>()
+		.unwrap();
+	let expires_at = body["expires_at"]
+		.as_str()
+		.unwrap()
+		.parse::<chrono::DateTime<chrono::Utc>>()
+		.unwrap();
+	assert_eq!(expires_at - created_at, chrono::Duration::days(30));
+}
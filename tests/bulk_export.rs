@@ -0,0 +1,395 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the asynchronous exports. These tests need a
+//! Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they set the export
+//! directory through the environment and install a logger.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+use warp::http::StatusCode;
+use warp::test::request;
+
+fn set_export_dir() {
+	let dir = env::temp_dir().join(format!("reacher-exports-test-{}", std::process::id()));
+	env::set_var("RCH_EXPORT_DIR", dir);
+}
+
+async fn start_export(job_id: i32, format: &str) -> i64 {
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/export?format={}", job_id, format))
+		.method("POST")
+		.reply(&create_routes(pool().await))
+		.await;
+	assert_eq!(resp.status(), StatusCode::ACCEPTED);
+
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	body["export_id"].as_i64().unwrap()
+}
+
+/// Poll the export status until it's not running anymore.
+async fn wait_for_export(job_id: i32, export_id: i64) -> Value {
+	for _ in 0..100 {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/export/{}/status", job_id, export_id))
+			.method("GET")
+			.reply(&create_routes(pool().await))
+			.await;
+		assert_eq!(resp.status(), StatusCode::OK);
+
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		if body["status"] != "running" {
+			return body;
+		}
+		tokio::time::sleep(Duration::from_millis(50)).await;
+	}
+
+	panic!("export {} did not finish", export_id);
+}
+
+#[tokio::test]
+async fn test_export_csv_lifecycle() {
+	set_export_dir();
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+		],
+	)
+	.await;
+
+	let export_id = start_export(job_id, "csv").await;
+	let status = wait_for_export(job_id, export_id).await;
+	assert_eq!(status["status"], "completed");
+	assert_eq!(status["processed_records"], 2);
+	assert_eq!(status["total_records"], 2);
+	assert_eq!(status["error"], Value::Null);
+
+	let resp = request()
+		.path(status["download_url"].as_str().unwrap())
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["Content-Type"], "text/csv");
+	let body = std::str::from_utf8(resp.body()).unwrap();
+	let lines: Vec<&str> = body.lines().collect();
+	assert_eq!(lines.len(), 3);
+	assert!(lines[0].starts_with("input,"));
+	assert!(lines[1].starts_with("foo@bar.baz,"));
+	assert!(lines[2].starts_with("bar@bar.baz,"));
+}
+
+#[tokio::test]
+async fn test_export_json_lifecycle() {
+	set_export_dir();
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+		],
+	)
+	.await;
+
+	let export_id = start_export(job_id, "json").await;
+	let status = wait_for_export(job_id, export_id).await;
+	assert_eq!(status["status"], "completed");
+
+	let resp = request()
+		.path(status["download_url"].as_str().unwrap())
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let inputs: Vec<&str> = body["results"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|r| r["input"].as_str().unwrap())
+		.collect();
+	assert_eq!(inputs, vec!["foo@bar.baz", "bar@bar.baz"]);
+}
+
+#[tokio::test]
+async fn test_export_csv_many_pages() {
+	set_export_dir();
+	let pool = pool().await;
+	// More than a page of results.
+	let results: Vec<Value> = (0..10_001)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+
+	let export_id = start_export(job_id, "csv").await;
+	let status = wait_for_export(job_id, export_id).await;
+	assert_eq!(status["status"], "completed");
+	assert_eq!(status["processed_records"], 10_001);
+
+	let resp = request()
+		.path(status["download_url"].as_str().unwrap())
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body = std::str::from_utf8(resp.body()).unwrap();
+	let lines: Vec<&str> = body.lines().collect();
+	// A single header, and every result once.
+	assert_eq!(lines.len(), 10_002);
+	assert_eq!(lines.iter().filter(|l| l.starts_with("input,")).count(), 1);
+	assert!(lines[10_001].starts_with("user10000@example.com,"));
+}
+
+#[tokio::test]
+async fn test_export_file_removed_with_job() {
+	set_export_dir();
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let export_id = start_export(job_id, "csv").await;
+	assert_eq!(
+		wait_for_export(job_id, export_id).await["status"],
+		"completed"
+	);
+	let path = std::path::PathBuf::from(env::var("RCH_EXPORT_DIR").unwrap())
+		.join(format!("{}.csv", export_id));
+	assert!(path.exists());
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("DELETE")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn test_export_unknown_job() {
+	set_export_dir();
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/export", i32::MAX))
+		.method("POST")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+	// An export is only visible under the job it was created for.
+	let export_id = start_export(job_id, "csv").await;
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/export/{}/status",
+			i32::MAX,
+			export_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_combined_export_csv() {
+	let pool = pool().await;
+	let first_job = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+		],
+	)
+	.await;
+	let second_job = insert_job(&pool, &[result("baz@bar.baz", "risky")]).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/export?ids={},{}&format=csv",
+			first_job, second_job
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(resp.headers()["X-Total-Count"], "3");
+
+	let mut rdr = csv::Reader::from_reader(resp.body().as_ref());
+	assert_eq!(&rdr.headers().unwrap()[0], "job_id");
+	assert_eq!(&rdr.headers().unwrap()[1], "input");
+	let rows: Vec<(String, String)> = rdr
+		.records()
+		.map(|record| {
+			let record = record.unwrap();
+			(record[0].to_string(), record[1].to_string())
+		})
+		.collect();
+	assert_eq!(
+		rows,
+		vec![
+			(first_job.to_string(), "foo@bar.baz".to_string()),
+			(first_job.to_string(), "bar@bar.baz".to_string()),
+			(second_job.to_string(), "baz@bar.baz".to_string()),
+		]
+	);
+}
+
+#[tokio::test]
+async fn test_combined_export_sample() {
+	let pool = pool().await;
+	let results: Vec<Value> = (0..200)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+
+	let export = || async {
+		let resp = request()
+			.path(&format!(
+				"/v0/bulk/export?ids={}&format=csv&sample=0.5",
+				job_id
+			))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::OK);
+		let total: usize = resp.headers()["X-Total-Count"]
+			.to_str()
+			.unwrap()
+			.parse()
+			.unwrap();
+		let rows: Vec<String> = csv::Reader::from_reader(resp.body().as_ref())
+			.records()
+			.map(|record| record.unwrap()[1].to_string())
+			.collect();
+		(total, rows)
+	};
+
+	// The sample is counted, and the same on every request.
+	let (total, rows) = export().await;
+	assert_eq!(total, rows.len());
+	assert!(0 < total && total < 200);
+	assert_eq!(export().await, (total, rows));
+}
+
+#[tokio::test]
+async fn test_combined_export_limits() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let ids: Vec<String> = (0..21).map(|i| (job_id + i).to_string()).collect();
+	let resp = request()
+		.path(&format!("/v0/bulk/export?ids={}", ids.join(",")))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/export?ids={},{}", job_id, i32::MAX))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+/// Keeps the logs of the combined export, to check where it stopped.
+struct CapturingLogger(Mutex<Vec<String>>);
+
+impl log::Log for CapturingLogger {
+	fn enabled(&self, metadata: &log::Metadata) -> bool {
+		metadata.level() <= log::Level::Info
+	}
+
+	fn log(&self, record: &log::Record) {
+		if self.enabled(record.metadata()) && record.target() == "reacher" {
+			self.0.lock().unwrap().push(record.args().to_string());
+		}
+	}
+
+	fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger(Mutex::new(Vec::new()));
+
+#[tokio::test]
+async fn test_combined_export_client_disconnect() {
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	log::set_logger(&LOGGER).unwrap();
+	log::set_max_level(log::LevelFilter::Info);
+	let pool = pool().await;
+	// Many pages of results, more than the socket buffers hold.
+	let results: Vec<Value> = (0..60_000)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+
+	let (addr, server) =
+		warp::serve(create_routes(pool.clone())).bind_ephemeral(([127, 0, 0, 1], 0));
+	tokio::spawn(server);
+
+	let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+	stream
+		.write_all(
+			format!(
+				"GET /v0/bulk/export?ids={}&format=csv HTTP/1.1\r\nHost: localhost\r\n\r\n",
+				job_id
+			)
+			.as_bytes(),
+		)
+		.await
+		.unwrap();
+	// Read the beginning of the response, then abort the download.
+	let mut buf = [0; 1024];
+	assert!(stream.read(&mut buf).await.unwrap() > 0);
+	assert!(buf.starts_with(b"HTTP/1.1 200 OK"));
+	drop(stream);
+
+	// The stream stops before querying the remaining pages, and gives its
+	// connection back to the pool.
+	let disconnect = format!(
+		"Client disconnected from combined export at [job_id={}] after [rows_sent=",
+		job_id
+	);
+	for _ in 0..50 {
+		let rows_sent = LOGGER.0.lock().unwrap().iter().find_map(|log| {
+			log.strip_prefix(&disconnect)
+				.and_then(|rows| rows.trim_end_matches(']').parse::<usize>().ok())
+		});
+		if let Some(rows_sent) = rows_sent {
+			assert!(rows_sent < results.len(), "{}", rows_sent);
+			if pool.num_idle() == pool.size() as usize {
+				return;
+			}
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	}
+	panic!(
+		"The export didn't stop, or {} of the {} pool connections weren't returned",
+		pool.size() as usize - pool.num_idle(),
+		pool.size()
+	);
+}
@@ -0,0 +1,67 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration test for the exports interrupted by a restart. It needs a
+//! Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. It lives in its own binary, as it fails all the running
+//! exports of this instance.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::bulk::export::{fail_interrupted_exports, instance_id};
+use std::env;
+
+#[tokio::test]
+async fn test_fail_interrupted_exports() {
+	let dir = env::temp_dir().join(format!("reacher-exports-test-{}", std::process::id()));
+	env::set_var("RCH_EXPORT_DIR", &dir);
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let insert_export = |instance_id: String| {
+		sqlx::query_scalar(
+			"INSERT INTO bulk_exports (job_id, format, instance_id) VALUES ($1, 'csv', $2) RETURNING id",
+		)
+		.bind(job_id)
+		.bind(instance_id)
+		.fetch_one(&pool)
+	};
+	// An export left running, with its partial file.
+	let export_id: i32 = insert_export(instance_id()).await.unwrap();
+	// An export running on another instance.
+	let other_export_id: i32 = insert_export("other".into()).await.unwrap();
+	std::fs::create_dir_all(&dir).unwrap();
+	let path = dir.join(format!("{}.csv", export_id));
+	std::fs::write(&path, "input,").unwrap();
+
+	assert!(
+		fail_interrupted_exports(&pool, &instance_id())
+			.await
+			.unwrap() >= 1
+	);
+
+	let status = |export_id: i32| {
+		sqlx::query_as("SELECT status, error FROM bulk_exports WHERE id = $1")
+			.bind(export_id)
+			.fetch_one(&pool)
+	};
+	let (status_of_export, error): (String, Option<String>) = status(export_id).await.unwrap();
+	assert_eq!(status_of_export, "failed");
+	assert!(error.unwrap().contains("restart"));
+	assert!(!path.exists());
+	let (status_of_other, _): (String, Option<String>) = status(other_export_id).await.unwrap();
+	assert_eq!(status_of_other, "running");
+}
@@ -0,0 +1,92 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the `GET /v0/bulk` endpoint. They live in their own
+//! file because they look at all the jobs in the database, so they shouldn't
+//! run concurrently with tests creating jobs.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use std::time::Duration;
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_job_list_if_modified_since() {
+	let pool = pool().await;
+	insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let last_modified = resp.headers()["Last-Modified"]
+		.to_str()
+		.unwrap()
+		.to_string();
+
+	// Nothing changed since the last poll.
+	let resp = request()
+		.path("/v0/bulk")
+		.method("GET")
+		.header("If-Modified-Since", &last_modified)
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+	assert!(resp.body().is_empty());
+
+	// HTTP-dates have a one second precision.
+	tokio::time::sleep(Duration::from_millis(1100)).await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("GET")
+		.header("If-Modified-Since", &last_modified)
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["jobs"][0]["id"], job_id);
+	let last_modified = resp.headers()["Last-Modified"]
+		.to_str()
+		.unwrap()
+		.to_string();
+
+	// Tagging a job changes the list too.
+	tokio::time::sleep(Duration::from_millis(1100)).await;
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/tags", job_id))
+		.method("POST")
+		.json(&serde_json::json!({ "tag": "weekly" }))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("GET")
+		.header("If-Modified-Since", &last_modified)
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["jobs"][0]["tags"], serde_json::json!(["weekly"]));
+}
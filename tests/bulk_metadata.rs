@@ -0,0 +1,139 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the result metadata joined into downloads. These
+//! tests need a Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they configure the
+//! metadata table through the environment.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+use warp::http::StatusCode;
+use warp::test::request;
+
+/// Create a job with a result that has a metadata row, and one that doesn't.
+/// Returns the job id and the input of the first result.
+async fn setup(pool: &Pool<Postgres>) -> (i32, String) {
+	env::set_var("RCH_RESULT_METADATA_TABLE", "public.test_result_metadata");
+	env::set_var("RCH_RESULT_METADATA_COLUMNS", "campaign,external_id");
+	// Concurrent CREATE TABLE IF NOT EXISTS can conflict, serialize them.
+	let mut tx = pool.begin().await.unwrap();
+	sqlx::query("SELECT pg_advisory_xact_lock(158)")
+		.execute(&mut tx)
+		.await
+		.unwrap();
+	sqlx::query(
+		r#"
+		CREATE TABLE IF NOT EXISTS test_result_metadata (
+			input TEXT NOT NULL,
+			campaign TEXT,
+			external_id INTEGER,
+			secret TEXT
+		)
+		"#,
+	)
+	.execute(&mut tx)
+	.await
+	.unwrap();
+	tx.commit().await.unwrap();
+
+	let nanos = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_nanos();
+	let input = format!("meta{}@example.com", nanos);
+	sqlx::query(
+		"INSERT INTO test_result_metadata (input, campaign, external_id, secret) VALUES ($1, 'spring', 42, 'hidden')",
+	)
+	.bind(&input)
+	.execute(pool)
+	.await
+	.unwrap();
+
+	let job_id = insert_job(
+		pool,
+		&[
+			result(&input, "safe"),
+			result(&format!("nometa{}@example.com", nanos), "invalid"),
+		],
+	)
+	.await;
+
+	(job_id, input)
+}
+
+#[tokio::test]
+async fn test_download_include_meta() {
+	let pool = pool().await;
+	let (job_id, input) = setup(&pool).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?include_meta=true", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let results = body["results"].as_array().unwrap();
+	assert_eq!(results[0]["input"], input.as_str());
+	// Only the whitelisted columns are exposed.
+	assert_eq!(
+		results[0]["meta"],
+		serde_json::json!({"campaign": "spring", "external_id": 42})
+	);
+	assert_eq!(
+		results[1]["meta"],
+		serde_json::json!({"campaign": null, "external_id": null})
+	);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body["results"][0].get("meta").is_none());
+}
+
+#[tokio::test]
+async fn test_download_csv_include_meta() {
+	let pool = pool().await;
+	let (job_id, input) = setup(&pool).await;
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=csv&include_meta=true",
+			job_id
+		))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body = std::str::from_utf8(resp.body()).unwrap();
+	let lines: Vec<&str> = body.lines().collect();
+	assert!(lines[0].ends_with(",duration_ms,meta.campaign,meta.external_id"));
+	assert!(lines[1].starts_with(&format!("{},", input)));
+	assert!(lines[1].ends_with(",spring,42"));
+	assert!(lines[2].ends_with(",,"));
+}
@@ -0,0 +1,55 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the notification of the submitted jobs. These tests
+//! need a Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they enable the
+//! notifications through the environment.
+
+mod common;
+
+use common::pool;
+use futures::StreamExt;
+use reacher_backend::routes::bulk::notify::subscribe_new_jobs;
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use std::{env, time::Duration};
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_create_job_notifies_workers() {
+	env::set_var("RCH_NOTIFY_NEW_JOBS", "true");
+	let pool = pool().await;
+	let mut new_jobs = Box::pin(subscribe_new_jobs(&pool).await.unwrap());
+
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.json(&serde_json::json!({"input_type": "array", "input": ["foo@bar.baz"]}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let job_id = body["job_id"].as_i64().unwrap() as i32;
+
+	let notified = tokio::time::timeout(Duration::from_secs(5), new_jobs.next())
+		.await
+		.expect("the job should be notified")
+		.unwrap()
+		.unwrap();
+	assert_eq!(notified, job_id);
+}
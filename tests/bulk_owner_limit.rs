@@ -0,0 +1,165 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the limit on the running jobs of an owner. They live
+//! in their own binary, as they set the limit through the environment.
+
+mod common;
+
+use common::pool;
+use reacher_backend::routes::{bulk::owner_limit::start_pending_jobs, create_routes};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_running_jobs_per_owner() {
+	env::set_var("RCH_MAX_RUNNING_JOBS_PER_OWNER", "1");
+	let pool = pool().await;
+	let key = format!(
+		"test-owner-{}",
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_nanos()
+	);
+	sqlx::query("INSERT INTO api_keys (name, key_hash, scope) VALUES ($1, $2, 'admin')")
+		.bind(&key)
+		.bind(hex::encode(Sha256::digest(key.as_bytes())))
+		.execute(&pool)
+		.await
+		.unwrap();
+	let submit = || {
+		request()
+			.path("/v0/bulk")
+			.method("POST")
+			.header("x-reacher-api-key", &key)
+			.json(&serde_json::json!({
+				"input_type": "array",
+				"input": ["foo@bar.baz"],
+			}))
+	};
+
+	// Nothing processes the tasks, so the first job keeps running.
+	let resp = submit().reply(&create_routes(pool.clone())).await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let running_job_id = body["job_id"].as_i64().unwrap() as i32;
+
+	let resp = submit().reply(&create_routes(pool.clone())).await;
+	assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+	assert_eq!(resp.headers()["Retry-After"], "60");
+
+	// A deleted job still counts while its tasks are queued.
+	let set_deleted = |deleted: bool| {
+		sqlx::query("UPDATE bulk_jobs SET deleted_at = CASE WHEN $2 THEN NOW() END WHERE id = $1")
+			.bind(running_job_id)
+			.bind(deleted)
+			.execute(&pool)
+	};
+	set_deleted(true).await.unwrap();
+	let resp = submit().reply(&create_routes(pool.clone())).await;
+	assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+	set_deleted(false).await.unwrap();
+
+	env::set_var("RCH_OWNER_LIMIT_ACTION", "queue");
+	let resp = submit().reply(&create_routes(pool.clone())).await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let pending_job_id = body["job_id"].clone();
+
+	let status = |job_id: &Value| {
+		request()
+			.path(&format!("/v0/bulk/{}", job_id))
+			.method("GET")
+	};
+	let resp = status(&pending_job_id)
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["job_status"], "Pending");
+
+	// Still over the limit.
+	start_pending_jobs(&pool, Some(1)).await.unwrap();
+	let resp = status(&pending_job_id)
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["job_status"], "Pending");
+
+	sqlx::query("UPDATE bulk_jobs SET processed_count = total_records WHERE id = $1")
+		.bind(running_job_id)
+		.execute(&pool)
+		.await
+		.unwrap();
+	start_pending_jobs(&pool, Some(1)).await.unwrap();
+	let resp = status(&pending_job_id)
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["job_status"], "Running");
+	assert_eq!(body["total_records"], 1);
+
+	// A draft isn't limited until it's finalized, like a submission.
+	let resp = request()
+		.path("/v0/bulk")
+		.method("POST")
+		.header("x-reacher-api-key", &key)
+		.json(&serde_json::json!({
+			"input_type": "array",
+			"input": ["foo@bar.baz"],
+			"draft": true,
+		}))
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	let draft_job_id = body["job_id"].clone();
+	let finalize = || {
+		request()
+			.path(&format!("/v0/bulk/{}/finalize", draft_job_id))
+			.method("POST")
+	};
+
+	env::set_var("RCH_OWNER_LIMIT_ACTION", "reject");
+	let resp = finalize().reply(&create_routes(pool.clone())).await;
+	assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+	env::set_var("RCH_OWNER_LIMIT_ACTION", "queue");
+	let resp = finalize().reply(&create_routes(pool.clone())).await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let resp = status(&draft_job_id)
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["job_status"], "Pending");
+
+	sqlx::query("UPDATE bulk_jobs SET processed_count = total_records WHERE id = $1")
+		.bind(pending_job_id.as_i64().unwrap() as i32)
+		.execute(&pool)
+		.await
+		.unwrap();
+	start_pending_jobs(&pool, Some(1)).await.unwrap();
+	let resp = status(&draft_job_id)
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["job_status"], "Running");
+	assert_eq!(body["total_records"], 1);
+}
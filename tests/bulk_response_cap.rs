@@ -0,0 +1,64 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the maximum size of JSON downloads. These tests
+//! need a Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they set the cap
+//! through the environment.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_download_json_exceeds_cap() {
+	// A single result serializes to a few hundred bytes.
+	env::set_var("RCH_MAX_RESPONSE_BYTES", "1000");
+	let pool = pool().await;
+	let results: Vec<Value> = (0..10)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+	// The cap is read when the routes are created, a malformed value set
+	// afterwards doesn't fail the requests.
+	let routes = create_routes(pool);
+	env::set_var("RCH_MAX_RESPONSE_BYTES", "malformed");
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download?format=json&limit=10",
+			job_id
+		))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body["message"].as_str().unwrap().contains("paginate"));
+
+	// A smaller page fits.
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=json&limit=1", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+}
@@ -0,0 +1,136 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for signed download URLs. These tests need a Postgres
+//! database with all migrations applied, reachable at `DATABASE_URL`. They
+//! live in their own binary, as they configure the keys through the
+//! environment.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+const ADMIN_KEY: &str = "admin-key";
+
+async fn mint_url(job_id: i32, expires_in: u64) -> String {
+	env::set_var("RCH_ADMIN_API_KEY", ADMIN_KEY);
+	env::set_var("RCH_URL_SIGNING_KEY", "signing-key");
+
+	let resp = request()
+		.path(&format!(
+			"/v0/bulk/{}/download-url?expires_in={}",
+			job_id, expires_in
+		))
+		.method("POST")
+		.header("x-reacher-admin-key", ADMIN_KEY)
+		.reply(&create_routes(pool().await))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	body["url"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_signed_url_valid() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let url = mint_url(job_id, 3600).await;
+
+	let resp = request()
+		.path(&url)
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_signed_url_expired() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let url = mint_url(job_id, 0).await;
+
+	let resp = request()
+		.path(&url)
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_signed_url_tampered() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let url = mint_url(job_id, 3600).await;
+
+	// Same token for another job.
+	let other_job = url.replace(
+		&format!("/v0/bulk/{}/", job_id),
+		&format!("/v0/bulk/{}/", job_id + 1),
+	);
+	let resp = request()
+		.path(&other_job)
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+	// Extended expiry.
+	let (path, token) = url.split_once("&token=").unwrap();
+	let (path, expires) = path.split_once("expires=").unwrap();
+	let extended = format!(
+		"{}expires={}&token={}",
+		path,
+		expires.parse::<i64>().unwrap() + 1,
+		token
+	);
+	let resp = request()
+		.path(&extended)
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_download_url_unknown_job() {
+	env::set_var("RCH_ADMIN_API_KEY", ADMIN_KEY);
+	env::set_var("RCH_URL_SIGNING_KEY", "signing-key");
+	let pool = pool().await;
+	let deleted_job = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	sqlx::query("UPDATE bulk_jobs SET deleted_at = NOW() WHERE id = $1")
+		.bind(deleted_job)
+		.execute(&pool)
+		.await
+		.unwrap();
+
+	for job_id in [deleted_job, i32::MAX] {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}/download-url", job_id))
+			.method("POST")
+			.header("x-reacher-admin-key", ADMIN_KEY)
+			.reply(&create_routes(pool.clone()))
+			.await;
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", job_id);
+	}
+}
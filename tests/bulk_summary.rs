@@ -0,0 +1,246 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the precomputed summaries of large jobs. These
+//! tests need a Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they set the threshold
+//! through the environment.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::{bulk::summary::refresh_job_summaries, create_routes};
+use serde_json::Value;
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_status_serves_precomputed_summary() {
+	env::set_var("RCH_SUMMARY_MIN_RECORDS", "1");
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[result("foo@bar.baz", "safe"), result("bar@bar.baz", "safe")],
+	)
+	.await;
+	// Differs from the results, to tell whether they were aggregated.
+	sqlx::query(
+		r#"
+		INSERT INTO bulk_job_summaries (job_id, refreshed_at, total_processed, summary)
+		VALUES ($1, '2026-01-01T00:00:00Z', 1, $2)
+		"#,
+	)
+	.bind(job_id)
+	.bind(serde_json::json!({
+		"total_safe": 42,
+		"total_risky": 0,
+		"total_invalid": 0,
+		"total_unknown": 0,
+		"avg_duration_ms": null,
+		"p95_duration_ms": null,
+		"total_transient_errors": 0,
+		"total_permanent_errors": 0,
+		"distinct_domains": 7
+	}))
+	.execute(&pool)
+	.await
+	.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["summary"]["total_safe"], 42);
+	// The progress is read from the counter, not from the summary.
+	assert_eq!(body["total_processed"], 2);
+	assert_eq!(body["job_status"], "Completed");
+	assert_eq!(body["summary_refreshed_at"], "2026-01-01T00:00:00Z");
+	// Only present if requested.
+	assert!(body["summary"].get("distinct_domains").is_none());
+}
+
+#[tokio::test]
+async fn test_status_summary_below_threshold() {
+	env::set_var("RCH_SUMMARY_MIN_RECORDS", "1");
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["summary"]["total_safe"], 1);
+	assert_eq!(body["summary_refreshed_at"], Value::Null);
+	let stored: i64 =
+		sqlx::query_scalar("SELECT COUNT(*) FROM bulk_job_summaries WHERE job_id = $1")
+			.bind(job_id)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+	assert_eq!(stored, 0);
+}
+
+#[tokio::test]
+async fn test_status_missing_summary_pending() {
+	env::set_var("RCH_SUMMARY_MIN_RECORDS", "1");
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[
+			result("foo@bar.baz", "safe"),
+			result("bar@bar.baz", "invalid"),
+		],
+	)
+	.await;
+	let status = || async {
+		let resp = request()
+			.path(&format!("/v0/bulk/{}?distinct_domains=true", job_id))
+			.method("GET")
+			.reply(&create_routes(pool.clone()))
+			.await;
+		serde_json::from_slice::<Value>(resp.body()).unwrap()
+	};
+
+	// The results of a large job aren't aggregated on request.
+	let body = status().await;
+	assert_eq!(body["summary_pending"], true);
+	assert_eq!(body["summary"]["total_safe"], 0);
+	assert_eq!(body["summary_refreshed_at"], Value::Null);
+	let stored: i64 =
+		sqlx::query_scalar("SELECT COUNT(*) FROM bulk_job_summaries WHERE job_id = $1")
+			.bind(job_id)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+	assert_eq!(stored, 0);
+
+	refresh_job_summaries(&pool, 1).await.unwrap();
+	let body = status().await;
+	assert_eq!(body["summary_pending"], false);
+	assert_eq!(body["summary"]["total_safe"], 1);
+	assert_eq!(body["summary"]["total_invalid"], 1);
+	assert_eq!(body["summary"]["distinct_domains"], 1);
+	assert!(body["summary_refreshed_at"].is_string());
+}
+
+#[tokio::test]
+async fn test_refresh_summaries_on_new_results() {
+	env::set_var("RCH_SUMMARY_MIN_RECORDS", "1");
+	let pool = pool().await;
+	// Completed, but its results can still change, e.g. when requeued.
+	let job_id = insert_job(
+		&pool,
+		&[result("foo@bar.baz", "safe"), result("bar@bar.baz", "safe")],
+	)
+	.await;
+	let refreshed_at = || async {
+		sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>(
+			"SELECT refreshed_at FROM bulk_job_summaries WHERE job_id = $1",
+		)
+		.bind(job_id)
+		.fetch_one(&pool)
+		.await
+		.unwrap()
+	};
+
+	refresh_job_summaries(&pool, 1).await.unwrap();
+	let first = refreshed_at().await;
+	refresh_job_summaries(&pool, 1).await.unwrap();
+	assert_eq!(refreshed_at().await, first);
+
+	sqlx::query("INSERT INTO email_results (job_id, result) VALUES ($1, $2)")
+		.bind(job_id)
+		.bind(result("foo@bar.baz", "invalid"))
+		.execute(&pool)
+		.await
+		.unwrap();
+	refresh_job_summaries(&pool, 1).await.unwrap();
+	assert!(refreshed_at().await > first);
+}
+
+#[tokio::test]
+async fn test_recompute_summary() {
+	env::set_var("RCH_SUMMARY_MIN_RECORDS", "1");
+	env::set_var("RCH_ADMIN_API_KEY", "admin-secret");
+	let pool = pool().await;
+	let job_id = insert_job(
+		&pool,
+		&[result("foo@bar.baz", "safe"), result("bar@bar.baz", "safe")],
+	)
+	.await;
+	refresh_job_summaries(&pool, 1).await.unwrap();
+	// The status cache is shared by the requests of the same routes.
+	let routes = create_routes(pool.clone());
+	let status = || {
+		request()
+			.path(&format!("/v0/bulk/{}", job_id))
+			.method("GET")
+	};
+
+	let body: Value = serde_json::from_slice(status().reply(&routes).await.body()).unwrap();
+	assert_eq!(body["summary"]["total_safe"], 2);
+
+	// A manual fix of the results isn't seen by the stored summary.
+	sqlx::query(
+		r#"
+		UPDATE email_results SET result = jsonb_set(result, '{is_reachable}', '"invalid"')
+		WHERE job_id = $1 AND result ->> 'input' = 'bar@bar.baz'
+		"#,
+	)
+	.bind(job_id)
+	.execute(&pool)
+	.await
+	.unwrap();
+	let body: Value = serde_json::from_slice(status().reply(&routes).await.body()).unwrap();
+	assert_eq!(body["summary"]["total_safe"], 2);
+
+	let recompute = |admin_key: &str| {
+		request()
+			.path(&format!("/v0/bulk/{}/recompute-summary", job_id))
+			.method("POST")
+			.header("x-reacher-admin-key", admin_key)
+	};
+	assert_eq!(
+		recompute("wrong").reply(&routes).await.status(),
+		StatusCode::UNAUTHORIZED
+	);
+
+	let resp = recompute("admin-secret").reply(&routes).await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["summary"]["total_safe"], 1);
+	assert_eq!(body["summary"]["total_invalid"], 1);
+
+	let body: Value = serde_json::from_slice(status().reply(&routes).await.body()).unwrap();
+	assert_eq!(body["summary"]["total_safe"], 1);
+	assert_eq!(body["summary"]["total_invalid"], 1);
+
+	let resp = request()
+		.path("/v0/bulk/2147483647/recompute-summary")
+		.method("POST")
+		.header("x-reacher-admin-key", "admin-secret")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,80 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Helpers shared by the integration tests that need a database.
+
+// Not every test binary uses every helper.
+#![allow(dead_code)]
+
+use serde_json::Value;
+use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use std::env;
+
+pub async fn pool() -> Pool<Postgres> {
+	let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+
+	PgPoolOptions::new()
+		.max_connections(5)
+		.connect(&db_url)
+		.await
+		.expect("Failed to connect to DATABASE_URL.")
+}
+
+/// Create a job with the given results already written, and return its id.
+pub async fn insert_job(pool: &Pool<Postgres>, results: &[Value]) -> i32 {
+	let job_id: i32 =
+		sqlx::query_scalar("INSERT INTO bulk_jobs (total_records) VALUES ($1) RETURNING id")
+			.bind(results.len() as i32)
+			.fetch_one(pool)
+			.await
+			.unwrap();
+
+	sqlx::query(
+		"INSERT INTO email_results (job_id, result) SELECT $1, r FROM UNNEST($2::jsonb[]) AS r",
+	)
+	.bind(job_id)
+	.bind(results)
+	.execute(pool)
+	.await
+	.unwrap();
+
+	job_id
+}
+
+/// A minimal `CheckEmailOutput`-like JSON for the given input.
+pub fn result(input: &str, is_reachable: &str) -> Value {
+	let (username, domain) = input.split_once('@').unwrap();
+
+	serde_json::json!({
+		"input": input,
+		"is_reachable": is_reachable,
+		"misc": {"is_disposable": false, "is_role_account": false},
+		"mx": {"accepts_mail": true, "records": []},
+		"smtp": {
+			"can_connect_smtp": true,
+			"has_full_inbox": false,
+			"is_catch_all": false,
+			"is_deliverable": is_reachable == "safe",
+			"is_disabled": false
+		},
+		"syntax": {
+			"address": input,
+			"domain": domain,
+			"is_valid_syntax": true,
+			"username": username
+		}
+	})
+}
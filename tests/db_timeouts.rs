@@ -0,0 +1,88 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the database timeouts. These tests need a Postgres
+//! database with all migrations applied, reachable at `DATABASE_URL`. They
+//! live in their own binary, as they configure the timeouts through the
+//! environment, and lock the `bulk_jobs` table.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::db::{endpoint_pool_options, pool_options};
+use reacher_backend::routes::create_routes;
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_pool_timeout_and_query_timeout() {
+	env::set_var("RCH_DB_ACQUIRE_TIMEOUT_MS", "200");
+	env::set_var("RCH_DB_QUERY_TIMEOUT_MS", "200");
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+	let timed_pool = endpoint_pool_options()
+		.max_connections(1)
+		.connect(&env::var("DATABASE_URL").unwrap())
+		.await
+		.unwrap();
+
+	// Starved pool: the request never gets a connection.
+	let conn = timed_pool.acquire().await.unwrap();
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(timed_pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+	assert_eq!(resp.headers()["Retry-After"], "5");
+	drop(conn);
+
+	// Slow query: the request gets a connection, but its query waits on the
+	// lock past the statement timeout.
+	let mut tx = pool.begin().await.unwrap();
+	sqlx::query("LOCK TABLE bulk_jobs IN ACCESS EXCLUSIVE MODE")
+		.execute(&mut tx)
+		.await
+		.unwrap();
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(timed_pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+	assert!(resp.headers().get("Retry-After").is_none());
+	tx.rollback().await.unwrap();
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.reply(&create_routes(timed_pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	// The pool of the task queue and of the background tasks is not bounded.
+	let task_pool = pool_options()
+		.max_connections(1)
+		.connect(&env::var("DATABASE_URL").unwrap())
+		.await
+		.unwrap();
+	let statement_timeout: String = sqlx::query_scalar("SHOW statement_timeout")
+		.fetch_one(&task_pool)
+		.await
+		.unwrap();
+	assert_eq!(statement_timeout, "0");
+}
@@ -0,0 +1,78 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the configurable default page sizes of downloads.
+//! These tests need a Postgres database with all migrations applied,
+//! reachable at `DATABASE_URL`. They live in their own binary, as they set
+//! the defaults through the environment.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_configured_default_limits() {
+	env::set_var("DEFAULT_JSON_LIMIT", "1");
+	env::set_var("DEFAULT_CSV_LIMIT", "2");
+	let pool = pool().await;
+	let results: Vec<Value> = (0..3)
+		.map(|i| result(&format!("user{}@example.com", i), "safe"))
+		.collect();
+	let job_id = insert_job(&pool, &results).await;
+	// The defaults are read when the routes are created.
+	let routes = create_routes(pool);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["results"].as_array().unwrap().len(), 1);
+
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?format=csv", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	assert_eq!(resp.status(), StatusCode::OK);
+	// The header and two rows.
+	assert_eq!(std::str::from_utf8(resp.body()).unwrap().lines().count(), 3);
+
+	// An explicit limit still wins.
+	let resp = request()
+		.path(&format!("/v0/bulk/{}/download?limit=3", job_id))
+		.method("GET")
+		.reply(&routes)
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["results"].as_array().unwrap().len(), 3);
+
+	let resp = request()
+		.path("/v0/config")
+		.method("GET")
+		.reply(&routes)
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["default_json_limit"], 1);
+	assert_eq!(body["default_csv_limit"], 2);
+}
@@ -0,0 +1,128 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the `GET /health/ready` endpoint.
+
+mod common;
+
+use common::pool;
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use sqlx::{postgres::PgPoolOptions, Executor};
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_ready() {
+	let resp = request()
+		.path("/health/ready")
+		.method("GET")
+		.reply(&create_routes(pool().await))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body, serde_json::json!({"status": "ready"}));
+}
+
+#[tokio::test]
+async fn test_ready_missing_migration() {
+	// A copy of the applied migrations in their own schema, as if the latest
+	// one hadn't run.
+	let schema = format!(
+		"health_{}",
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_nanos()
+	);
+	let (latest, total): (String, i64) = sqlx::query_as(
+		r#"
+		SELECT MAX(version || ' ' || description), COUNT(*) FROM _sqlx_migrations
+		"#,
+	)
+	.fetch_one(&pool().await)
+	.await
+	.unwrap();
+	pool()
+		.await
+		.execute(
+			format!(
+				r#"
+				CREATE SCHEMA {0};
+				CREATE TABLE {0}._sqlx_migrations AS
+				SELECT * FROM public._sqlx_migrations
+				WHERE version < (SELECT MAX(version) FROM public._sqlx_migrations);
+				"#,
+				schema
+			)
+			.as_str(),
+		)
+		.await
+		.unwrap();
+
+	let search_path = format!("SET search_path TO {}", schema);
+	let pool = PgPoolOptions::new()
+		.max_connections(1)
+		.after_connect(move |conn| {
+			let search_path = search_path.clone();
+			Box::pin(async move {
+				conn.execute(search_path.as_str()).await?;
+				Ok(())
+			})
+		})
+		.connect(&env::var("DATABASE_URL").unwrap())
+		.await
+		.unwrap();
+
+	let resp = request()
+		.path("/health/ready")
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(
+		body,
+		serde_json::json!({
+			"status": "migration needed",
+			"missing_migrations": [latest],
+		})
+	);
+
+	// Without the table, no migration was applied with sqlx.
+	pool.execute(format!("DROP TABLE {}._sqlx_migrations", schema).as_str())
+		.await
+		.unwrap();
+	let resp = request()
+		.path("/health/ready")
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(
+		body["missing_migrations"].as_array().unwrap().len() as i64,
+		total
+	);
+
+	pool.execute(format!("DROP SCHEMA {} CASCADE", schema).as_str())
+		.await
+		.unwrap();
+}
@@ -0,0 +1,89 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the logging of slow queries. These tests need a
+//! Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they set the threshold
+//! through the environment and install a logger.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+use warp::http::StatusCode;
+use warp::test::request;
+
+/// Keeps the warnings, to check which queries were logged.
+struct CapturingLogger(Mutex<Vec<String>>);
+
+impl log::Log for CapturingLogger {
+	fn enabled(&self, metadata: &log::Metadata) -> bool {
+		metadata.level() <= log::Level::Warn
+	}
+
+	fn log(&self, record: &log::Record) {
+		if self.enabled(record.metadata()) && record.target() == "reacher" {
+			self.0.lock().unwrap().push(record.args().to_string());
+		}
+	}
+
+	fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger(Mutex::new(Vec::new()));
+
+#[tokio::test]
+async fn test_slow_query_is_logged() {
+	env::set_var("SLOW_QUERY_MS", "50");
+	log::set_logger(&LOGGER).unwrap();
+	log::set_max_level(log::LevelFilter::Warn);
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	// Hold a lock on the results, so that the aggregate of the status waits
+	// for it to be released.
+	let mut tx = pool.begin().await.unwrap();
+	sqlx::query("LOCK TABLE email_results IN ACCESS EXCLUSIVE MODE")
+		.execute(&mut tx)
+		.await
+		.unwrap();
+	let get = |path: String| {
+		let routes = create_routes(pool.clone());
+		tokio::spawn(async move { request().path(&path).method("GET").reply(&routes).await })
+	};
+	let status = get(format!("/v0/bulk/{}", job_id));
+	// The queries of the other endpoints are timed too.
+	let histogram = get(format!("/v0/bulk/{}/histogram", job_id));
+	tokio::time::sleep(Duration::from_millis(200)).await;
+	tx.commit().await.unwrap();
+
+	assert_eq!(status.await.unwrap().status(), StatusCode::OK);
+	assert_eq!(histogram.await.unwrap().status(), StatusCode::OK);
+	let logs = LOGGER.0.lock().unwrap();
+	for query in ["job_aggregate", "job_histogram"] {
+		let slow_query = format!("Slow query [query={}] for [job_id={}]", query, job_id);
+		assert!(
+			logs.iter().any(|log| log.starts_with(&slow_query)),
+			"{:?}",
+			logs
+		);
+	}
+	// The job record isn't locked, its query is fast.
+	assert!(!logs.iter().any(|log| log.contains("[query=job_record]")));
+}
@@ -0,0 +1,64 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for request tracing. These tests need a Postgres
+//! database with all migrations applied, reachable at `DATABASE_URL`.
+
+mod common;
+
+use common::{insert_job, pool, result};
+use reacher_backend::routes::create_routes;
+use std::sync::{Arc, Mutex};
+use tracing::{span, Subscriber};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+use warp::http::StatusCode;
+use warp::test::request;
+
+/// Layer recording the names of all created spans.
+#[derive(Clone, Default)]
+struct SpanRecorder(Arc<Mutex<Vec<&'static str>>>);
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanRecorder {
+	fn on_new_span(&self, attrs: &span::Attributes<'_>, _id: &span::Id, _ctx: Context<'_, S>) {
+		self.0.lock().unwrap().push(attrs.metadata().name());
+	}
+}
+
+#[tokio::test]
+async fn test_status_records_span() {
+	let pool = pool().await;
+	let job_id = insert_job(&pool, &[result("foo@bar.baz", "safe")]).await;
+
+	// `tokio::test` runs on a single thread, so a thread-local default
+	// subscriber sees all spans of the request.
+	let recorder = SpanRecorder::default();
+	let _guard =
+		tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+	let resp = request()
+		.path(&format!("/v0/bulk/{}", job_id))
+		.method("GET")
+		.header(
+			"traceparent",
+			"00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+		)
+		.reply(&create_routes(pool))
+		.await;
+
+	assert_eq!(resp.status(), StatusCode::OK);
+	let spans = recorder.0.lock().unwrap();
+	assert!(spans.contains(&"job_status"), "got spans {:?}", spans);
+	assert!(spans.contains(&"db.query"), "got spans {:?}", spans);
+}
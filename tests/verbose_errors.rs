@@ -0,0 +1,72 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2022 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Integration tests for the verbose errors mode. These tests need a
+//! Postgres database with all migrations applied, reachable at
+//! `DATABASE_URL`. They live in their own binary, as they toggle the mode
+//! through the environment.
+
+mod common;
+
+use common::pool;
+use reacher_backend::routes::create_routes;
+use serde_json::Value;
+use std::env;
+use warp::http::StatusCode;
+use warp::test::request;
+
+#[tokio::test]
+async fn test_verbose_errors() {
+	let pool = pool().await;
+	// The job record query fails with a database error.
+	let path = format!("/v0/bulk/{}", i32::MAX);
+
+	env::remove_var("VERBOSE_ERRORS");
+	let resp = request()
+		.path(&path)
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(
+		body,
+		serde_json::json!({"message": "Internal server error"})
+	);
+
+	env::set_var("VERBOSE_ERRORS", "false");
+	let resp = request()
+		.path(&path)
+		.method("GET")
+		.reply(&create_routes(pool.clone()))
+		.await;
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert!(body.get("detail").is_none());
+
+	env::set_var("VERBOSE_ERRORS", "1");
+	let resp = request()
+		.path(&path)
+		.method("GET")
+		.reply(&create_routes(pool))
+		.await;
+	assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+	let body: Value = serde_json::from_slice(resp.body()).unwrap();
+	assert_eq!(body["message"], "Internal server error");
+	assert!(body["detail"]
+		.as_str()
+		.unwrap()
+		.contains("no rows returned"));
+}